@@ -1,6 +1,21 @@
-use clap::Parser;
+use pngcrypt::chunk_type::Prefix;
+use pngcrypt::contents::MemoryLimit;
+use pngcrypt::crypto::MAX_KDF_ROUNDS;
+use pngcrypt::config::{self, Defaults};
+use pngcrypt::envelope::{self, Padding};
+use pngcrypt::lint::Rule;
+use pngcrypt::lsb::{Channels, LsbOptions, MAX_BITS_PER_CHANNEL};
+use pngcrypt::png::Position;
 
-// make all our arguments positional
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+// one subcommand per operation, taking the image either with -f or as the first positional
 
 #[derive(Debug, Clone, Parser)]
 #[clap(
@@ -9,31 +24,850 @@ use clap::Parser;
     about = "A command line utility for embedding secret messages in PNG images"
 )]
 pub struct Args {
-    // type of operation to perform
-    #[clap(validator(validate_operation))]
-    pub operation: String,
+    // asked for in a terminal when left out, see `wizard`
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// print the result as JSON
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// write the data found after IEND back out when an image is rewritten, which is the default
+    #[clap(long, global = true)]
+    pub keep_trailing: bool,
+
+    /// leave out the data found after IEND when an image is rewritten
+    #[clap(long, global = true, conflicts_with("keep-trailing"))]
+    pub drop_trailing: bool,
+
+    /// replace files that are already there, the image itself when it is rewritten in place
+    #[clap(long, global = true)]
+    pub force: bool,
+
+    /// give a rewritten file the modification time it had before
+    #[clap(long, global = true)]
+    pub preserve_times: bool,
+
+    /// show what a command would write, and how it differs from the input, without writing anything
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+
+    /// report more on stderr: -v for the steps a command takes, -vv for how the files are parsed and the timing
+    #[clap(short, long, global = true, parse(from_occurrences))]
+    pub verbose: u64,
+
+    /// print only the result, such as the token or the message, and no warnings
+    #[clap(short, long, global = true, conflicts_with("verbose"))]
+    pub quiet: bool,
+
+    /// color the output: "auto" when stdout is a terminal and NO_COLOR is not set, "always" or "never"
+    #[clap(long, global = true, default_value = "auto")]
+    pub color: ColorChoice,
+
+    /// use the defaults of this profile of the configuration file
+    #[clap(long, global = true, env = "PNGCRYPT_PROFILE")]
+    pub profile: Option<String>,
+}
+
+// the defaults read from the configuration file, see `config`
+static DEFAULTS: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+
+impl Args {
+    // Parses the command line with the defaults of the configuration file in
+    // place of the built-in ones, exiting on invalid arguments as `parse` does.
+    pub fn parse_configured() -> Result<Args> {
+        let arguments: Vec<OsString> = std::env::args_os().collect();
+
+        let mut defaults = match config::path() {
+            Some(path) => config::load(&path)?,
+            None => Defaults::default(),
+        };
+        if let Some(profile) = config::profile_name(arguments.get(1..).unwrap_or(&[])) {
+            defaults = defaults.with_profile(&profile)?;
+        }
+        DEFAULTS.get_or_init(|| defaults.flags());
+
+        Ok(Args::try_parse_configured(arguments).unwrap_or_else(|error| error.exit()))
+    }
+
+    pub fn try_parse_configured<I, T>(arguments: I) -> Result<Args, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let flags = DEFAULTS.get().map_or(&[][..], Vec::as_slice);
+        let matches = Defaults::apply(Args::command(), flags).try_get_matches_from(arguments)?;
+
+        Args::from_arg_matches(&matches)
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// hide a message in an image
+    #[clap(visible_alias = "enc")]
+    Encode {
+        #[clap(flatten)]
+        input: EncodeInput,
+
+        /// message, asked for in a terminal when left out
+        #[clap(short, long)]
+        message: Option<String>,
+
+        /// a bare file name goes next to the input, any other path is taken as it is;
+        /// <input stem>.<suffix>.<extension> next to the input when left out
+        #[clap(short, long, conflicts_with("in-place"), parse(from_os_str))]
+        output_file: Option<PathBuf>,
+
+        /// what the default output file name adds to the input's, as in photo.pngcrypt.png
+        #[clap(long, env = "PNGCRYPT_SUFFIX", default_value = "pngcrypt")]
+        suffix: String,
+
+        /// write the output to this directory, under the input's name unless --output-file gives one
+        #[clap(long, conflicts_with("in-place"), parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+
+        /// hide the message in the input file itself, after copying it to <file>.bak
+        #[clap(long)]
+        in_place: bool,
+
+        /// do not keep a copy of the input file when encoding in place
+        #[clap(long, requires("in-place"))]
+        no_backup: bool,
+
+        #[clap(flatten)]
+        secret: Secret,
+
+        /// in chunk mode, name the chunk after the password instead of a random token
+        #[clap(long, requires("password"))]
+        derive_token: bool,
+
+        /// told apart from the password, gives another derived chunk type for it
+        #[clap(long, requires("derive-token"))]
+        label: Option<String>,
+
+        /// two lowercase letters every random chunk type starts with, such as "pc"
+        #[clap(long, env = "PNGCRYPT_PREFIX")]
+        prefix: Option<Prefix>,
+
+        #[clap(flatten)]
+        embedding: Embedding,
+
+        /// where the new chunk goes in chunk and text modes: after-ihdr, before-idat, before-iend or random
+        #[clap(long, default_value = "before-iend")]
+        position: Position,
+
+        /// warn when lsb mode leaves the image with a lower PSNR than this, in dB
+        #[clap(long, default_value = "40")]
+        min_psnr: f64,
+    },
+    /// read a hidden message
+    #[clap(visible_alias = "dec")]
+    Decode {
+        #[clap(flatten)]
+        input: Input,
+
+        /// chunk type, the token printed by encode; ? matches any letter and * any run of them
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: Option<String>,
+
+        /// match the chunk type whatever the case of its letters
+        #[clap(short = 'i', long)]
+        match_case_insensitive: bool,
+
+        /// decode every chunk holding a payload instead of the one named by the chunk type
+        #[clap(long, conflicts_with("chunk-type"))]
+        auto: bool,
+
+        /// password the message was encrypted with
+        #[clap(long)]
+        password: Option<String>,
+
+        /// the label given to encode with --derive-token
+        #[clap(long, requires("password"))]
+        label: Option<String>,
+
+        /// where the message is hidden: "chunk", "lsb", "text", "itxt" or "zip"
+        #[clap(long, default_value = "chunk")]
+        mode: Mode,
+
+        /// keyword of the tEXt or iTXt chunk holding the message in text and itxt mode
+        #[clap(long, default_value = "Comment")]
+        keyword: String,
+
+        /// read chunks whose CRC does not match their data instead of failing
+        #[clap(long)]
+        ignore_crc: bool,
+
+        /// fail rather than use more memory than this, in bytes or with a K, M or G after the number;
+        /// large PNG images are then read a chunk at a time
+        #[clap(long)]
+        max_memory: Option<MemoryLimit>,
+    },
+    /// remove a hidden message, printing it
+    #[clap(visible_alias = "rm")]
+    Remove {
+        #[clap(flatten)]
+        input: Input,
+
+        /// chunk type, the token printed by encode; ? matches any letter and * any run of them;
+        /// asked for in a terminal when left out
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: Option<String>,
+
+        /// match the chunk type whatever the case of its letters
+        #[clap(short = 'i', long)]
+        match_case_insensitive: bool,
+
+        /// in a PNG image, only remove the chunk with this index, as print numbers them
+        #[clap(long)]
+        index: Option<usize>,
+
+        /// in a PNG image, remove every chunk of the type instead of the first
+        #[clap(long, conflicts_with("index"))]
+        all: bool,
+
+        /// password the message was encrypted with
+        #[clap(long)]
+        password: Option<String>,
+
+        /// remove a critical chunk such as IHDR or IDAT, after confirming, which leaves the image unreadable
+        #[clap(long)]
+        allow_critical: bool,
+    },
+    /// restore a file changed in place from its latest backup
+    Undo {
+        #[clap(flatten)]
+        input: Input,
+
+        /// list the backups the file can be restored from instead
+        #[clap(long)]
+        list: bool,
+    },
+    /// list the chunks of an image
+    #[clap(visible_alias = "ls")]
+    Print {
+        #[clap(flatten)]
+        input: Input,
+
+        /// only list the chunks of this type; ? matches any letter and * any run of them
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: Option<String>,
+
+        /// match the chunk type whatever the case of its letters
+        #[clap(short = 'i', long)]
+        match_case_insensitive: bool,
+
+        /// read chunks whose CRC does not match their data instead of failing
+        #[clap(long)]
+        ignore_crc: bool,
+
+        /// write the data found after IEND to this file
+        #[clap(long, parse(from_os_str))]
+        extract_trailing: Option<PathBuf>,
+
+        /// remove the data found after IEND from the image
+        #[clap(long)]
+        strip_trailing: bool,
+    },
+    /// tell how much data an image can hide
+    Capacity {
+        #[clap(flatten)]
+        input: Input,
+
+        /// message whose share of the capacity is shown
+        #[clap(short, long)]
+        message: Option<String>,
+
+        #[clap(flatten)]
+        secret: Secret,
+
+        #[clap(flatten)]
+        embedding: Embedding,
+    },
+    /// look through a file or directory for images carrying hidden data
+    Scan {
+        #[clap(flatten)]
+        input: Input,
+
+        /// scan subdirectories too
+        #[clap(short, long)]
+        recursive: bool,
+
+        /// take the CRC of each chunk as stored instead of checking it, faster on files from trusted storage
+        #[clap(long)]
+        no_verify_crc: bool,
+    },
+    /// run steganalysis on the pixel data
+    Analyze {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// write a forensic report on an image
+    Report {
+        #[clap(flatten)]
+        input: Input,
+
+        /// the file or case folder the report is written to
+        #[clap(long, parse(from_os_str))]
+        output_file: PathBuf,
+
+        /// name of the examiner signing off the report
+        #[clap(long)]
+        examiner: Option<String>,
+    },
+    /// list the chunks that may hold a payload, for when the token is lost
+    RecoverToken {
+        #[clap(flatten)]
+        input: Input,
+
+        /// print the contents of the payloads found
+        #[clap(long)]
+        show: bool,
+
+        /// password used to decrypt the payloads shown
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// write a copy of an image with anything hidden removed
+    Sanitize {
+        #[clap(flatten)]
+        input: Input,
+
+        /// the file the cleaned copy is written to
+        #[clap(long, parse(from_os_str))]
+        output_file: PathBuf,
+    },
+    /// compare an image with a suspect copy
+    Diff {
+        /// the original image
+        #[clap(parse(from_os_str))]
+        original: PathBuf,
+
+        /// the suspect copy it is compared against
+        #[clap(parse(from_os_str))]
+        other: PathBuf,
+    },
+    /// check that a file is a well formed PNG
+    Verify {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// check a file against the PNG specification, rule by rule
+    Lint {
+        #[clap(flatten)]
+        input: Input,
+
+        /// lint rules to leave out, by ID or name, comma separated
+        #[clap(long, use_value_delimiter = true)]
+        allow: Vec<Rule>,
+    },
+    /// fix the chunk lengths and CRCs of a damaged file
+    Repair {
+        #[clap(flatten)]
+        input: Input,
+
+        /// the file the repaired image is written to
+        #[clap(long, parse(from_os_str))]
+        output_file: PathBuf,
+    },
+    /// find PNG images inside any binary data
+    Carve {
+        #[clap(flatten)]
+        input: Input,
+
+        /// the folder carved images are written to
+        #[clap(long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+
+        /// run the payload scanner on every image found
+        #[clap(long)]
+        scan: bool,
+    },
+    /// tell whether the payload chunks of an image are likely to survive editing
+    Survivability {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// show the image properties stored in IHDR
+    Info {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// browse the chunks of an image in the terminal, removing, extracting and decoding them
+    Tui {
+        #[clap(flatten)]
+        input: Input,
+
+        /// password the messages were encrypted with
+        #[clap(long)]
+        password: Option<String>,
+
+        /// where to write the image once chunks are removed, the image itself without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// explain what the case of each letter of a chunk type means
+    TypeInfo {
+        /// the chunk type, such as ruSt
+        chunk_type: String,
+    },
+    /// sum up the chunks of an image
+    Stats {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// remove ancillary chunks, by type or by class
+    Strip {
+        #[clap(flatten)]
+        input: Input,
+
+        /// remove every ancillary chunk
+        #[clap(long)]
+        all_ancillary: bool,
+
+        /// remove the chunks of this type, can be repeated
+        #[clap(long = "type", value_name = "TYPE", multiple_occurrences = true)]
+        chunk_types: Vec<String>,
+
+        /// remove the ancillary chunks editors have to drop when they change the image
+        #[clap(long)]
+        unsafe_to_copy: bool,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// put the chunks of an image in the order the specification gives
+    Normalize {
+        #[clap(flatten)]
+        input: Input,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// work on single chunks
+    #[clap(subcommand)]
+    Chunk(ChunkCommand),
+    /// read and edit the metadata of an image
+    #[clap(subcommand)]
+    Meta(MetaCommand),
+    /// answer HTTP requests to encode, decode and scan uploaded images
+    Serve {
+        /// the address and port to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        address: String,
+
+        /// how many requests are answered at a time
+        #[clap(long, default_value = "4")]
+        threads: usize,
+
+        /// the largest upload taken, in bytes or with a K, M or G after the number
+        #[clap(long, default_value = "64M")]
+        max_upload: MemoryLimit,
+
+        /// the most image data a scan inflates, which the size of the upload does not bound
+        #[clap(long, default_value = "256M")]
+        max_decoded: MemoryLimit,
+
+        /// rounds of PBKDF2 the key of encrypted messages is derived with
+        #[clap(long, default_value = "100000", validator(validate_kdf_rounds))]
+        kdf_rounds: u32,
+    },
+    /// write man pages for pngcrypt and each of its subcommands, for packaging
+    #[clap(hide = true)]
+    Mangen {
+        /// the directory to write them to
+        #[clap(parse(from_os_str), default_value = ".")]
+        out_dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ChunkCommand {
+    /// print the data of a chunk
+    Dump {
+        #[clap(flatten)]
+        input: Input,
+
+        /// chunk type, the token printed by encode
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: String,
 
-    // file path
-    #[clap(short, long)]
-    pub file_path: String,
+        /// print an offset, hex and ASCII view instead of the raw data
+        #[clap(long)]
+        hex: bool,
 
-    // chunk type
-    #[clap(short, long)]
-    pub chunk_type: Option<String>,
+        /// only print the first N bytes
+        #[clap(long)]
+        head: Option<usize>,
+    },
+    /// write the data of a chunk to a file
+    Extract {
+        #[clap(flatten)]
+        input: Input,
 
-    // message
-    #[clap(short, long, required_if_eq("operation", "encode"))]
-    pub message: Option<String>,
+        /// chunk type
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: String,
 
-    // output file
+        /// the file the data is written to
+        #[clap(short, long, parse(from_os_str))]
+        output_file: PathBuf,
+    },
+    /// add a chunk holding the contents of a file
+    Inject {
+        #[clap(flatten)]
+        input: Input,
+
+        /// chunk type
+        #[clap(short, long, visible_alias = "type")]
+        chunk_type: String,
+
+        /// the file holding the chunk data
+        #[clap(long, parse(from_os_str))]
+        data: PathBuf,
+
+        /// where the chunk goes: after-ihdr, before-idat, before-iend or random
+        #[clap(long, default_value = "before-iend")]
+        position: Position,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+
+        /// allow critical chunk types and the ones the specification defines
+        #[clap(long)]
+        i_know_what_im_doing: bool,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum MetaCommand {
+    /// list the textual metadata of the tEXt, zTXt and iTXt chunks
+    List {
+        #[clap(flatten)]
+        input: Input,
+    },
+    /// print the text stored under a keyword
+    Get {
+        #[clap(flatten)]
+        input: Input,
+
+        /// keyword, such as Title, Author, Copyright or Software
+        #[clap(short, long)]
+        keyword: String,
+    },
+    /// store a text under a keyword, replacing the one there
+    Set {
+        #[clap(flatten)]
+        input: Input,
+
+        /// keyword, such as Title, Author, Copyright or Software
+        #[clap(short, long)]
+        keyword: String,
+
+        /// the text, written to a tEXt chunk, or an iTXt chunk when it is not Latin-1
+        #[clap(long)]
+        value: String,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// remove the text stored under a keyword
+    Remove {
+        #[clap(flatten)]
+        input: Input,
+
+        /// keyword, such as Title, Author, Copyright or Software
+        #[clap(short, long)]
+        keyword: String,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// the EXIF metadata of the eXIf chunk
+    #[clap(subcommand)]
+    Exif(ExifCommand),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ExifCommand {
+    /// list the EXIF entries
+    Dump {
+        #[clap(flatten)]
+        input: Input,
+
+        /// write the raw EXIF data to this file instead
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// remove the EXIF metadata, leaving every other chunk as it is
+    Strip {
+        #[clap(flatten)]
+        input: Input,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+    /// replace the EXIF metadata with the contents of a file
+    Set {
+        #[clap(flatten)]
+        input: Input,
+
+        /// the file holding the EXIF data, starting with its TIFF header
+        #[clap(long, parse(from_os_str))]
+        from: PathBuf,
+
+        /// output file, the image is changed in place without one
+        #[clap(short, long, parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Input {
+    /// file path
+    #[clap(short, long, required_unless_present("path"), conflicts_with("path"), parse(from_os_str))]
+    pub file_path: Option<PathBuf>,
+
+    /// file path, or the file or directory to look into for scan
+    #[clap(parse(from_os_str))]
+    pub path: Option<PathBuf>,
+}
+
+impl Input {
+    // the file or directory to work on, given either with -f or as a positional
+    pub fn path(&self) -> &Path {
+        self.file_path
+            .as_deref()
+            .or(self.path.as_deref())
+            .expect("A file path is required")
+    }
+}
+
+// The image and message of encode, which can both be given positionally, as
+// in encode photo.png "message". With -f the one positional is the message:
+// clap takes it for the path, which `resolve` sorts out.
+#[derive(Debug, Clone, clap::Args)]
+pub struct EncodeInput {
+    /// file path
+    #[clap(short, long, required_unless_present("path"), parse(from_os_str))]
+    pub file_path: Option<PathBuf>,
+
+    /// file path
+    #[clap(parse(from_os_str))]
+    pub path: Option<PathBuf>,
+
+    /// the message given after the image instead of with --message
+    #[clap(value_name = "MESSAGE", conflicts_with("message"))]
+    pub message_argument: Option<String>,
+}
+
+impl EncodeInput {
+    // the image, and the message when it was given positionally
+    pub fn resolve(self) -> Result<(PathBuf, Option<String>)> {
+        match (self.file_path, self.path, self.message_argument) {
+            (Some(_), Some(_), Some(_)) => bail!("The image is given both with --file-path and positionally"),
+            (Some(file_path), Some(message), None) => {
+                let message = message
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| anyhow!("The message is not valid UTF-8"))?;
+
+                Ok((file_path, Some(message)))
+            }
+            (Some(file_path), None, message) | (None, Some(file_path), message) => Ok((file_path, message)),
+            (None, None, _) => bail!("A file path is required"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Secret {
+    /// password used to encrypt the message
     #[clap(long)]
-    pub output_file: Option<String>,
+    pub password: Option<String>,
+
+    /// pad the payload to a size bucket: a number of bytes, or "auto" for powers of two
+    #[clap(long)]
+    pub pad_to: Option<Padding>,
+
+    /// rounds of PBKDF2 the key is derived from the password with, stored along with the payload
+    #[clap(long, default_value = "100000", validator(validate_kdf_rounds))]
+    pub kdf_rounds: u32,
 }
 
-fn validate_operation(operation: &str) -> Result<(), String> {
-    // check if value is either encode, decode, remove or print
-    match operation {
-        "encode" | "decode" | "remove" | "print" => Ok(()),
-        _ => Err(format!("Invalid operation: {}", operation)),
+impl Secret {
+    pub fn seal(&self, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+        envelope::seal_with_rounds(message, self.password.as_deref(), self.pad_to, self.kdf_rounds)
+    }
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Embedding {
+    /// where to hide the message: "chunk" adds a new chunk, "lsb" hides it in the pixel data,
+    /// "text" and "itxt" write it in the clear to a standard tEXt or iTXt chunk, "zip" to a
+    /// ZIP archive appended to the image
+    #[clap(long, default_value = "chunk")]
+    pub mode: Mode,
+
+    /// keyword of the tEXt or iTXt chunk text and itxt mode write
+    #[clap(long, default_value = "Comment")]
+    pub keyword: String,
+
+    /// language of the message in itxt mode, as a tag like en-GB
+    #[clap(long, default_value = "")]
+    pub language: String,
+
+    /// the keyword translated to the language of the message, in itxt mode
+    #[clap(long, default_value = "")]
+    pub translated_keyword: String,
+
+    /// compress the message in itxt mode
+    #[clap(long)]
+    pub compress: bool,
+
+    /// name of the file holding the message in the archive zip mode appends
+    #[clap(long, default_value = "message.txt")]
+    pub zip_entry: String,
+
+    /// channels used by lsb mode: "all" or a comma separated list of r, g, b, a and gray
+    #[clap(long, default_value = "all")]
+    pub channels: Channels,
+
+    /// number of low bits of each channel used by lsb mode
+    #[clap(long, default_value = "1", validator(validate_bits_per_channel))]
+    pub bits_per_channel: u8,
+
+    /// only embed in textured areas of the image in lsb mode
+    #[clap(long)]
+    pub adaptive: bool,
+
+    /// hide the payload with a Hamming code in lsb mode, changing fewer pixels
+    #[clap(long)]
+    pub matrix: bool,
+}
+
+impl Embedding {
+    pub fn lsb_options(&self) -> LsbOptions {
+        LsbOptions {
+            channels: self.channels,
+            bits_per_channel: self.bits_per_channel,
+            adaptive: self.adaptive,
+            matrix: self.matrix,
+            ..LsbOptions::default()
+        }
+    }
+}
+
+fn validate_kdf_rounds(rounds: &str) -> Result<(), String> {
+    match rounds.parse::<u32>() {
+        Ok(rounds) if (1..=MAX_KDF_ROUNDS).contains(&rounds) => Ok(()),
+        _ => Err(format!(
+            "Invalid number of KDF rounds: {}. Expected a value between 1 and {}",
+            rounds, MAX_KDF_ROUNDS
+        )),
+    }
+}
+
+fn validate_bits_per_channel(bits: &str) -> Result<(), String> {
+    match bits.parse::<u8>() {
+        Ok(bits) if (1..=MAX_BITS_PER_CHANNEL).contains(&bits) => Ok(()),
+        _ => Err(format!(
+            "Invalid bits per channel: {}. Expected a value between 1 and {}",
+            bits, MAX_BITS_PER_CHANNEL
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Chunk,
+    Lsb,
+    Text,
+    Itxt,
+    Zip,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "chunk" => Ok(Mode::Chunk),
+            "lsb" => Ok(Mode::Lsb),
+            "text" => Ok(Mode::Text),
+            "itxt" => Ok(Mode::Itxt),
+            "zip" => Ok(Mode::Zip),
+            _ => Err(format!("Invalid mode: {}", mode)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> Result<Self, Self::Err> {
+        match choice {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Invalid color choice: {}. Expected auto, always or never", choice)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_input(args: &[&str]) -> Result<(PathBuf, Option<String>)> {
+        match Args::try_parse_from(args)?.command {
+            Some(Command::Encode { input, .. }) => input.resolve(),
+            _ => bail!("Not an encode command"),
+        }
+    }
+
+    // every argument of every subcommand, as --help and the man pages show them
+    fn undocumented(command: &clap::Command, found: &mut Vec<String>) {
+        for argument in command.get_arguments().filter(|argument| argument.get_help().is_none()) {
+            found.push(format!("{} {}", command.get_name(), argument.get_id()));
+        }
+        for subcommand in command.get_subcommands() {
+            undocumented(subcommand, found);
+        }
+    }
+
+    #[test]
+    fn test_arguments_have_help() {
+        let mut command = Args::command();
+        command.build();
+
+        let mut found = Vec::new();
+        undocumented(&command, &mut found);
+        assert_eq!(found, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_encode_message_argument() {
+        let expected = (PathBuf::from("in.png"), Some("hello".to_string()));
+
+        assert_eq!(encode_input(&["pngcrypt", "encode", "-f", "in.png", "hello"]).unwrap(), expected);
+        assert_eq!(encode_input(&["pngcrypt", "encode", "in.png", "hello"]).unwrap(), expected);
+        assert_eq!(encode_input(&["pngcrypt", "encode", "-f", "in.png"]).unwrap(), (PathBuf::from("in.png"), None));
+        assert!(encode_input(&["pngcrypt", "encode", "-f", "in.png", "other.png", "hello"]).is_err());
+        assert!(encode_input(&["pngcrypt", "encode", "in.png", "-m", "hi", "hello"]).is_err());
     }
 }
@@ -0,0 +1,374 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::png::{self, Position};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::Range;
+
+use anyhow::Result;
+use rand::seq::SliceRandom;
+
+// A BMP file is a 14 byte file header giving the file size and the offset of
+// the pixel array, an information header with the dimensions and the pixel
+// format, optional color masks and a color table, and the pixel array. Readers
+// go straight to the pixel array, so whatever lies in the gap before it or
+// after it is never looked at.
+//
+// Payloads are records placed in those two regions: the identifier
+// "PNGCrypt", the four letter token decode asks for, the payload length as a
+// little endian u32 and the payload. The pixel array offset, the file size and
+// the offset of an embedded color profile are updated to match.
+
+const IDENTIFIER: &[u8] = b"PNGCrypt";
+const RECORD_HEADER: usize = 8 + 4 + 4;
+
+const FILE_HEADER: usize = 14;
+const CORE_HEADER: usize = 12;
+const INFO_HEADER: usize = 40;
+const V5_HEADER: usize = 124;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+// "MBED", a color profile stored in the file
+const PROFILE_EMBEDDED: u32 = 0x4d42_4544;
+
+#[derive(Debug)]
+pub enum BmpError {
+    InvalidHeader,
+    Truncated,
+}
+
+impl std::error::Error for BmpError {}
+
+impl fmt::Display for BmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BmpError::InvalidHeader => write!(f, "The file does not start with a BMP header"),
+            BmpError::Truncated => write!(f, "The BMP file ends before its pixel array does"),
+        }
+    }
+}
+
+// where the regions of a BMP file start and end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    // the end of the headers, masks and color table, where the gap starts
+    pub header_end: usize,
+    pub pixel_offset: usize,
+    pub pixel_end: usize,
+}
+
+impl Layout {
+    pub fn gap(&self) -> Range<usize> {
+        self.header_end..self.pixel_offset
+    }
+}
+
+struct Record {
+    range: Range<usize>,
+    token: String,
+    payload: Range<usize>,
+}
+
+pub struct Bmp {
+    bytes: Vec<u8>,
+}
+
+impl Bmp {
+    pub fn parse(bytes: &[u8]) -> Result<Bmp> {
+        if !bytes.starts_with(b"BM") || bytes.len() < FILE_HEADER + CORE_HEADER {
+            return Err(BmpError::InvalidHeader.into());
+        }
+
+        let bmp = Bmp { bytes: bytes.to_vec() };
+        bmp.layout()?;
+
+        Ok(bmp)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let bytes = self.bytes.get(offset..offset + 2).ok_or(BmpError::InvalidHeader)?;
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.bytes.get(offset..offset + 4).ok_or(BmpError::InvalidHeader)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn set_u32(&mut self, offset: usize, value: usize) {
+        self.bytes[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    pub fn layout(&self) -> Result<Layout> {
+        let header_size = self.u32(FILE_HEADER)? as usize;
+        let pixel_offset = self.u32(10)? as usize;
+
+        let (width, height, bits, compression, image_size, colors) = if header_size == CORE_HEADER {
+            let (width, height) = (self.u16(18)? as u64, self.u16(20)? as u64);
+            (width, height, self.u16(24)?, BI_RGB, 0, 0)
+        } else {
+            let width = (self.u32(18)? as i32).unsigned_abs() as u64;
+            let height = (self.u32(22)? as i32).unsigned_abs() as u64;
+            (width, height, self.u16(28)?, self.u32(30)?, self.u32(34)?, self.u32(46)? as usize)
+        };
+
+        let masks = match compression {
+            BI_BITFIELDS if header_size == INFO_HEADER => 12,
+            BI_ALPHABITFIELDS if header_size == INFO_HEADER => 16,
+            _ => 0,
+        };
+        let entries = if colors > 0 { colors } else if bits <= 8 { 1 << bits } else { 0 };
+        let entry_size = if header_size == CORE_HEADER { 3 } else { 4 };
+        let header_end = (FILE_HEADER + header_size + masks + entries * entry_size).min(pixel_offset);
+
+        // rows are padded to a multiple of 4 bytes
+        let pixel_size = match compression {
+            BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS => (bits as u64 * width).div_ceil(32) * 4 * height,
+            _ => image_size as u64,
+        };
+        let pixel_end = pixel_offset as u64 + pixel_size;
+
+        if pixel_end > self.bytes.len() as u64 {
+            return Err(BmpError::Truncated.into());
+        }
+
+        Ok(Layout {
+            header_end,
+            pixel_offset,
+            pixel_end: pixel_end as usize,
+        })
+    }
+
+    // the offset of the color profile a V5 header refers to, as stored in the header
+    fn profile_field(&self) -> Option<usize> {
+        let header_size = self.u32(FILE_HEADER).ok()? as usize;
+
+        (header_size >= V5_HEADER && self.u32(FILE_HEADER + 56).ok()? == PROFILE_EMBEDDED)
+            .then_some(FILE_HEADER + 112)
+    }
+
+    // inserts `data` at `at`, moving what the header points to behind it
+    fn insert(&mut self, at: usize, data: &[u8]) -> Result<()> {
+        let pixel_offset = self.layout()?.pixel_offset;
+        let profile = self.profile_field();
+
+        self.bytes.splice(at..at, data.iter().copied());
+
+        if at <= pixel_offset {
+            self.set_u32(10, pixel_offset + data.len());
+        }
+        if let Some(field) = profile {
+            let offset = FILE_HEADER + self.u32(field)? as usize;
+            if offset >= at {
+                self.set_u32(field, offset - FILE_HEADER + data.len());
+            }
+        }
+        self.set_u32(2, self.bytes.len());
+
+        Ok(())
+    }
+
+    fn delete(&mut self, range: Range<usize>) -> Result<()> {
+        let pixel_offset = self.layout()?.pixel_offset;
+        let profile = self.profile_field();
+        let length = range.len();
+
+        self.bytes.drain(range.clone());
+
+        if range.end <= pixel_offset {
+            self.set_u32(10, pixel_offset - length);
+        }
+        if let Some(field) = profile {
+            let offset = FILE_HEADER + self.u32(field)? as usize;
+            if offset >= range.end {
+                self.set_u32(field, offset - FILE_HEADER - length);
+            }
+        }
+        self.set_u32(2, self.bytes.len());
+
+        Ok(())
+    }
+
+    // the payload records in the gap and after the pixel array
+    fn records(&self) -> Vec<Record> {
+        let layout = match self.layout() {
+            Ok(layout) => layout,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut records = Vec::new();
+        for region in [layout.gap(), layout.pixel_end..self.bytes.len()] {
+            let mut offset = region.start;
+
+            while let Some(found) = self.bytes[offset..region.end]
+                .windows(IDENTIFIER.len())
+                .position(|window| window == IDENTIFIER)
+            {
+                let start = offset + found;
+                let payload_start = start + RECORD_HEADER;
+                let length = self.u32(start + 12).unwrap_or(u32::MAX) as usize;
+
+                if payload_start.saturating_add(length) > region.end {
+                    offset = start + 1;
+                    continue;
+                }
+
+                records.push(Record {
+                    range: start..payload_start + length,
+                    token: String::from_utf8_lossy(&self.bytes[start + 8..start + 12]).into_owned(),
+                    payload: payload_start..payload_start + length,
+                });
+                offset = payload_start + length;
+            }
+        }
+
+        records
+    }
+
+    fn record(&self, selector: &str) -> Result<Record> {
+        self.records()
+            .into_iter()
+            .find(|record| record.token == selector)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Carrier for Bmp {
+    fn format(&self) -> &'static str {
+        "BMP"
+    }
+
+    // the file size has to fit in the header
+    fn capacity(&self) -> usize {
+        (u32::MAX as usize).saturating_sub(self.bytes.len() + RECORD_HEADER)
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let records = self.records();
+        let token = std::iter::repeat_with(|| png::new_chunk_type().to_string())
+            .find(|token| records.iter().all(|record| record.token != *token))
+            .unwrap();
+        self.embed_as(payload, &token, position)?;
+
+        Ok(token)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.records().iter().any(|record| record.token == token) {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let layout = self.layout()?;
+        let at = match position {
+            Position::AfterIhdr => layout.header_end,
+            Position::BeforeIdat => layout.pixel_offset,
+            Position::BeforeIend => self.bytes.len(),
+            Position::Random => *[layout.header_end, layout.pixel_offset, self.bytes.len()]
+                .choose(&mut rand::thread_rng())
+                .unwrap(),
+        };
+
+        let mut record = IDENTIFIER.to_vec();
+        record.extend(token.as_bytes());
+        record.extend((payload.len() as u32).to_le_bytes());
+        record.extend(payload);
+
+        self.insert(at, &record)?;
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        Ok(self.bytes[self.record(selector)?.payload].to_vec())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let record = self.record(selector)?;
+        let payload = self.bytes[record.payload].to_vec();
+
+        self.delete(record.range)?;
+        Ok(payload)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.records()
+            .into_iter()
+            .map(|record| Slot {
+                selector: record.token,
+                length: record.payload.len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 2x2 24 bit image with a 4 byte gap before the pixel array
+    fn testing_bmp() -> Vec<u8> {
+        let mut bytes = b"BM".to_vec();
+        bytes.extend(74u32.to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.extend(58u32.to_le_bytes());
+
+        bytes.extend(40u32.to_le_bytes());
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(24u16.to_le_bytes());
+        bytes.extend([0; 24]);
+
+        bytes.extend([0; 4]);
+        bytes.extend((0..16).map(|byte| byte as u8 * 10));
+        bytes
+    }
+
+    #[test]
+    fn test_layout() {
+        let bmp = Bmp::parse(&testing_bmp()).unwrap();
+
+        assert_eq!(
+            bmp.layout().unwrap(),
+            Layout {
+                header_end: 54,
+                pixel_offset: 58,
+                pixel_end: 74,
+            }
+        );
+
+        assert!(Bmp::parse(&testing_bmp()[..70]).is_err());
+        assert!(Bmp::parse(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_bmp_carrier() {
+        let mut bmp = Bmp::parse(&testing_bmp()).unwrap();
+        let pixels = testing_bmp()[58..].to_vec();
+
+        let gap = bmp.embed(b"in the gap", Position::BeforeIdat).unwrap();
+        let appended = bmp.embed(b"appended", Position::BeforeIend).unwrap();
+
+        let mut bmp = Bmp::parse(&bmp.to_bytes()).unwrap();
+        let layout = bmp.layout().unwrap();
+        assert_eq!(&bmp.as_bytes()[layout.pixel_offset..layout.pixel_end], &pixels[..]);
+        assert_eq!(bmp.u32(2).unwrap() as usize, bmp.as_bytes().len());
+        assert_eq!(bmp.list().len(), 2);
+        assert_eq!(bmp.extract(&gap).unwrap(), b"in the gap");
+        assert_eq!(bmp.extract(&appended).unwrap(), b"appended");
+
+        bmp.remove(&gap).unwrap();
+        bmp.remove(&appended).unwrap();
+        assert_eq!(bmp.as_bytes(), testing_bmp());
+    }
+}
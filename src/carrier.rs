@@ -0,0 +1,194 @@
+use super::bmp::Bmp;
+use super::chunk_type::{ChunkType, Prefix};
+use super::gif::Gif;
+use super::ico::Ico;
+use super::jpeg::{self, Jpeg};
+use super::pdf::{self, Pdf};
+use super::png::{self, Png, Position};
+use super::riff::Riff;
+use super::tiff::Tiff;
+use super::wav::{self, Wav};
+use super::webp::{self, WebP};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::Result;
+
+// A container format a payload can be hidden in, image or audio. Each carrier
+// stores payloads in places of its own, such as PNG chunks, and names every
+// place with a selector: the token encode prints and decode asks for. The
+// payload is a sealed envelope, carriers store and return it as it is.
+//
+// `Position` tells where a new payload goes relative to the structure every
+// format shares: a header, the image or sample data and an end marker.
+
+pub trait Carrier {
+    // name of the format, such as "PNG"
+    fn format(&self) -> &'static str;
+
+    // the largest payload a single embed can hold, in bytes
+    fn capacity(&self) -> usize;
+
+    // hides `payload`, returning the selector it can be found under
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String>;
+
+    // hides `payload` under a selector the caller chose, such as one derived
+    // from the password
+    fn embed_as(&mut self, payload: &[u8], selector: &str, position: Position) -> Result<()>;
+
+    // hides `payload` under a new selector starting with `prefix`, returning it
+    fn embed_with_prefix(&mut self, payload: &[u8], prefix: Prefix, position: Position) -> Result<String> {
+        let selector = std::iter::repeat_with(|| png::new_chunk_type_with_prefix(Some(prefix)).to_string())
+            .find(|selector| self.extract(selector).is_err())
+            .unwrap();
+        self.embed_as(payload, &selector, position)?;
+
+        Ok(selector)
+    }
+
+    // the data stored under `selector`
+    fn extract(&self, selector: &str) -> Result<Vec<u8>>;
+
+    // removes the data stored under `selector`, returning it
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>>;
+
+    // the places holding a PNGCrypt payload
+    fn list(&self) -> Vec<Slot>;
+
+    fn to_bytes(&self) -> Vec<u8>;
+
+    // writes the file to `writer`, carriers that can avoid building it in
+    // memory first do so
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+
+    // removes the data found after the end of the container, returning it
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    pub selector: String,
+    pub length: usize,
+}
+
+#[derive(Debug)]
+pub enum CarrierError {
+    UnknownFormat,
+    NotFound(String),
+    SelectorTaken(String),
+}
+
+impl std::error::Error for CarrierError {}
+
+impl fmt::Display for CarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarrierError::UnknownFormat => write!(f, "The file is not in a format PNGCrypt can hide data in"),
+            CarrierError::NotFound(selector) => write!(f, "Nothing is stored under {}", selector),
+            CarrierError::SelectorTaken(selector) => write!(f, "Something is already stored under {}", selector),
+        }
+    }
+}
+
+// Selectors are stored in four bytes, like chunk types, so only four letter
+// ones can be chosen.
+pub fn check_selector(selector: &str) -> Result<()> {
+    ChunkType::from_str(selector)?;
+    Ok(())
+}
+
+// reads `bytes` with the carrier its signature belongs to
+pub fn open(bytes: &[u8]) -> Result<Box<dyn Carrier>> {
+    if bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Ok(Box::new(Png::try_from(bytes)?));
+    }
+
+    if bytes.starts_with(&jpeg::SIGNATURE) {
+        return Ok(Box::new(Jpeg::parse(bytes)?));
+    }
+
+    if bytes.starts_with(b"GIF8") {
+        return Ok(Box::new(Gif::parse(bytes)?));
+    }
+
+    if Riff::is_riff(bytes, webp::FORM) {
+        return Ok(Box::new(WebP::parse(bytes)?));
+    }
+
+    if Riff::is_riff(bytes, wav::FORM) {
+        return Ok(Box::new(Wav::parse(bytes)?));
+    }
+
+    if bytes.starts_with(b"BM") {
+        return Ok(Box::new(Bmp::parse(bytes)?));
+    }
+
+    if Tiff::is_tiff(bytes) {
+        return Ok(Box::new(Tiff::parse(bytes)?));
+    }
+
+    if bytes.starts_with(pdf::SIGNATURE) {
+        return Ok(Box::new(Pdf::parse(bytes)?));
+    }
+
+    // the icon signature is the weakest, so it is tried last
+    if Ico::is_ico(bytes) {
+        return Ok(Box::new(Ico::parse(bytes)?));
+    }
+
+    Err(CarrierError::UnknownFormat.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::envelope;
+    use std::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+        Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]).as_bytes()
+    }
+
+    #[test]
+    fn test_png_carrier() {
+        let mut carrier = open(&testing_png()).unwrap();
+        let payload = envelope::seal(b"secret", None, None).unwrap();
+
+        let selector = carrier.embed(&payload, Position::BeforeIend).unwrap();
+
+        let mut carrier = open(&carrier.to_bytes()).unwrap();
+        assert_eq!(carrier.format(), "PNG");
+        assert_eq!(carrier.list(), vec![Slot { selector: selector.clone(), length: payload.len() }]);
+        assert_eq!(carrier.extract(&selector).unwrap(), payload);
+
+        assert_eq!(carrier.remove(&selector).unwrap(), payload);
+        assert!(carrier.extract(&selector).is_err());
+        assert!(carrier.list().is_empty());
+    }
+
+    #[test]
+    fn test_embed_with_prefix() {
+        let mut carrier = open(&testing_png()).unwrap();
+
+        let selector = carrier.embed_with_prefix(b"first", Prefix(*b"pc"), Position::BeforeIend).unwrap();
+        assert!(selector.starts_with("pc"));
+        assert_eq!(carrier.extract(&selector).unwrap(), b"first");
+
+        assert!(carrier.embed_as(b"second", &selector, Position::BeforeIend).is_err());
+        assert!(carrier.embed_as(b"second", "pcX", Position::BeforeIend).is_err());
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert!(open(b"\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use super::png::{ChunkReader, Png};
+
+// Finds PNG images inside arbitrary data, such as disk images or memory dumps.
+// Every PNG signature starts a candidate, whose chunks are read until IEND or
+// until one can not be read. A candidate without a single chunk is a false
+// match of the signature and is left out.
+
+pub struct Carved {
+    // where the signature starts in the data
+    pub offset: usize,
+    pub length: usize,
+    // whether the image ends with IEND, rather than being cut off or damaged
+    pub complete: bool,
+    pub png: Png,
+}
+
+fn find_signature(bytes: &[u8], from: usize) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(Png::STANDARD_HEADER.len())
+        .position(|window| window == Png::STANDARD_HEADER)
+        .map(|position| from + position)
+}
+
+pub fn carve(bytes: &[u8]) -> Vec<Carved> {
+    let mut carved = Vec::new();
+    let mut from = 0;
+
+    while let Some(offset) = find_signature(bytes, from) {
+        let mut reader = ChunkReader::new(bytes, offset + Png::STANDARD_HEADER.len());
+        let chunks: Vec<_> = reader
+            .by_ref()
+            .map_while(|result| result.ok())
+            .map(|(_, chunk)| chunk.into_chunk())
+            .collect();
+        let end = reader.offset();
+
+        if chunks.is_empty() {
+            from = offset + 1;
+            continue;
+        }
+
+        let complete = chunks
+            .last()
+            .map(|chunk| chunk.chunk_type.bytes == *b"IEND")
+            .unwrap_or(false);
+
+        carved.push(Carved {
+            offset,
+            length: end - offset,
+            complete,
+            png: Png::from_chunks(chunks),
+        });
+
+        from = end;
+    }
+
+    carved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_carve() {
+        let png = testing_png();
+
+        let mut blob = b"disk image header".to_vec();
+        blob.extend_from_slice(&png);
+        blob.extend_from_slice(b"unrelated data");
+        // a lone signature is not an image
+        blob.extend_from_slice(&Png::STANDARD_HEADER);
+        blob.extend_from_slice(b"more data");
+        // cut off in its last chunk
+        blob.extend_from_slice(&png[..png.len() - 3]);
+
+        let carved = carve(&blob);
+
+        assert_eq!(carved.len(), 2);
+
+        assert_eq!(carved[0].offset, 17);
+        assert_eq!(carved[0].length, png.len());
+        assert!(carved[0].complete);
+        assert_eq!(carved[0].png.as_bytes(), png);
+
+        assert!(!carved[1].complete);
+        assert_eq!(carved[1].png.chunks().len(), 2);
+    }
+}
@@ -1,89 +1,252 @@
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::str::Utf8Error;
+use std::io::{self, Write};
 
 use super::chunk_type::ChunkType;
 
-use anyhow::{Context, Error, Result};
-use crc::crc32;
+use crc::{Crc, Table, CRC_32_ISO_HDLC};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// The CRC-32 of PNG chunks, which ZIP archives use as well. The 16 tables of
+// the slice-by-16 variant take 16 KiB and go through the data 16 bytes at a
+// time, several times faster than a single table on large chunks.
+pub static CRC: Crc<u32, Table<16>> = Crc::<u32, Table<16>>::new(&CRC_32_ISO_HDLC);
+
+// the CRC of a chunk, computed over its type and then its data
+pub fn crc(chunk_type: &[u8], data: &[u8]) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(chunk_type);
+    digest.update(data);
+    digest.finalize()
+}
 
-#[allow(dead_code)]
-#[derive(Debug)]
+// With serde, the length and CRC are written for tools reading the output
+// but computed again from the data when read back.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "ChunkFields")]
 pub struct Chunk {
     pub chunk_type: ChunkType,
+    #[serde(with = "data")]
     pub data: Vec<u8>,
     pub length: u32,
     pub crc: u32,
 }
 
+#[derive(Deserialize)]
+struct ChunkFields {
+    chunk_type: ChunkType,
+    #[serde(with = "data")]
+    data: Vec<u8>,
+}
+
+impl From<ChunkFields> for Chunk {
+    fn from(fields: ChunkFields) -> Self {
+        Chunk::new(fields.chunk_type, fields.data)
+    }
+}
+
+// Chunk data for serde: base64 in formats meant to be read, such as JSON, and
+// plain bytes in binary ones such as CBOR.
+pub(crate) mod data {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(data))
+        } else {
+            serializer.serialize_bytes(data)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(DataVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(DataVisitor)
+        }
+    }
+
+    struct DataVisitor;
+
+    impl<'de> Visitor<'de> for DataVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "base64 text or bytes")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+            STANDARD.decode(value).map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(value.to_vec())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut data = Vec::new();
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+}
+
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.data_as_string().expect("Can not construct a valid string from Chunk."))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ChunkError {
+    #[error("Invalid crc: {0}")]
     InvalidCrc(u32),
+    #[error("Invalid length: {0}")]
     InvalidLength(u32),
+    #[error("Invalid chunk type: {0}")]
     InvalidChunkType(String),
+    #[error("Chunk ends before its data and CRC: {needed} bytes needed, {available} available")]
+    Truncated { needed: usize, available: usize },
 }
 
-impl std::error::Error for ChunkError {}
+impl TryFrom<&[u8]> for Chunk {
+    type Error = ChunkError;
 
-impl fmt::Display for ChunkError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ChunkError::InvalidCrc(crc) => write!(f, "Invalid crc: {}", crc),
-            ChunkError::InvalidLength(len) => write!(f, "Invalid length: {}", len),
-            ChunkError::InvalidChunkType(char) => write!(f, "Invalid chunk type: {}", char),
-        }
+    fn try_from(value: &[u8]) -> Result<Self, ChunkError> {
+        Chunk::parse(value, CrcCheck::Verify)
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
+// What reading a chunk does with the CRC stored after its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcCheck {
+    // computes the CRC of the data and fails when the stored one differs
+    Verify,
+    // computes it and keeps the chunk either way, see `Chunk::from_bytes_ignoring_crc`
+    Ignore,
+    // takes the stored CRC as it is without going through the data, for files
+    // from storage that is trusted not to damage them
+    Trust,
+}
+
+impl Chunk {
+    const LENGTH_SIZE: usize = 4;
+    const CRC_SIZE: usize = 4;
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+
+    // Like `try_from`, but accepts a chunk whose stored CRC does not match its
+    // data, for when a flipped bit leaves the rest of the data readable. The
+    // chunk gets the CRC of the data it was read with.
+    pub fn from_bytes_ignoring_crc(value: &[u8]) -> Result<Self, ChunkError> {
+        Chunk::parse(value, CrcCheck::Ignore)
+    }
+
+    pub fn parse(value: &[u8], check: CrcCheck) -> Result<Self, ChunkError> {
+        Ok(ChunkRef::parse(value, check)?.into_chunk())
+    }
+}
+
+// A chunk read in place, its data borrowed from the bytes it was read from
+// rather than copied. Reading a file this way, such as with `ChunkReader`,
+// only copies the chunks that end up kept with `into_chunk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkRef<'a> {
+    pub chunk_type: ChunkType,
+    pub data: &'a [u8],
+    pub crc: u32,
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = ChunkError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, ChunkError> {
+        ChunkRef::parse(value, CrcCheck::Verify)
+    }
+}
+
+// the four bytes at `offset`, a length, chunk type or CRC
+fn field(value: &[u8], offset: usize) -> Result<[u8; 4], ChunkError> {
+    let end = offset.saturating_add(4);
+
+    value
+        .get(offset..end)
+        .and_then(|field| field.try_into().ok())
+        .ok_or(ChunkError::Truncated {
+            needed: end,
+            available: value.len(),
+        })
+}
+
+impl<'a> ChunkRef<'a> {
+    // see `Chunk::from_bytes_ignoring_crc`
+    pub fn from_bytes_ignoring_crc(value: &'a [u8]) -> Result<Self, ChunkError> {
+        ChunkRef::parse(value, CrcCheck::Ignore)
+    }
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let length_data: [u8; 4] = value[0..Chunk::LENGTH_SIZE].try_into()?;
-        let length:usize = u32::from_be_bytes(length_data).try_into()?;
+    pub fn parse(value: &'a [u8], check: CrcCheck) -> Result<Self, ChunkError> {
+        let length_data = field(value, 0)?;
+        let length = u32::from_be_bytes(length_data);
+        let length: usize = length.try_into().map_err(|_| ChunkError::InvalidLength(length))?;
 
-        let chunk_type_data: [u8; 4] = value
-            [Chunk::LENGTH_SIZE..Chunk::LENGTH_SIZE + ChunkType::CHUNK_TYPE_SIZE]
-            .try_into()?;
+        let chunk_type_data = field(value, Chunk::LENGTH_SIZE)?;
+        let chunk_type = ChunkType::try_from(chunk_type_data)
+            .map_err(|_| ChunkError::InvalidChunkType(String::from_utf8_lossy(&chunk_type_data).into_owned()))?;
 
-        let chunk_type = ChunkType::try_from(chunk_type_data).context("Unable to construct chunk type from given data.")?;
+        let data_last_index: usize = length
+            .checked_add(Chunk::LENGTH_SIZE + ChunkType::CHUNK_TYPE_SIZE)
+            .ok_or(ChunkError::InvalidLength(length as u32))?;
 
-        let data_last_index: usize = length + Chunk::LENGTH_SIZE + ChunkType::CHUNK_TYPE_SIZE;
+        // the CRC comes after the data, so when it is there the data is too
+        let stored_crc = field(value, data_last_index)?;
 
-        let crc = crc32::checksum_ieee(&value[Chunk::LENGTH_SIZE..data_last_index]); // we skip the chunk length
-        let data: Vec<u8> = value[8..data_last_index].to_vec();
+        let stored_crc = u32::from_be_bytes(stored_crc);
+        let crc = match check {
+            CrcCheck::Trust => stored_crc,
+            _ => CRC.checksum(&value[Chunk::LENGTH_SIZE..data_last_index]), // we skip the chunk length
+        };
+        let data = &value[8..data_last_index];
 
-        if crc != u32::from_be_bytes(value[data_last_index..data_last_index + Chunk::CRC_SIZE].try_into()?) {
-            return Err(ChunkError::InvalidCrc(crc).into());
+        if check == CrcCheck::Verify && crc != stored_crc {
+            return Err(ChunkError::InvalidCrc(crc));
         }
 
         if data.len() != length {
-            return Err(ChunkError::InvalidLength(length as u32).into());
+            return Err(ChunkError::InvalidLength(length as u32));
         }
 
         if !chunk_type.is_valid() {
-            return Err(ChunkError::InvalidChunkType(chunk_type.to_string()).into());
+            return Err(ChunkError::InvalidChunkType(chunk_type.to_string()));
         }
 
-        Ok(Self {
-            chunk_type: chunk_type,
-            data: data,
-            length: length as u32,
-            crc: crc,
-        })
+        Ok(Self { chunk_type, data, crc })
+    }
+
+    // the length of the data plus the 12 bytes of length, type and CRC around it
+    pub fn size(&self) -> usize {
+        self.data.len() + Chunk::LENGTH_SIZE + ChunkType::CHUNK_TYPE_SIZE + Chunk::CRC_SIZE
+    }
+
+    // copies the data into a chunk of its own
+    pub fn into_chunk(self) -> Chunk {
+        Chunk {
+            chunk_type: self.chunk_type,
+            data: self.data.to_vec(),
+            length: self.data.len() as u32,
+            crc: self.crc,
+        }
     }
 }
 
 impl Chunk {
-    const LENGTH_SIZE: usize = 4;
-    const CRC_SIZE: usize = 4;
-
-    fn length(&self) -> u32 {
+    pub fn length(&self) -> u32 {
         self.length
     }
 
@@ -91,49 +254,84 @@ impl Chunk {
         &self.chunk_type
     }
 
-    fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         &self.data
     }
 
-    fn crc(&self) -> u32 {
+    pub fn crc(&self) -> u32 {
         self.crc
     }
 
-    pub fn data_as_string(&self) -> Result<String> {
+    pub fn data_as_string(&self) -> Result<String, Utf8Error> {
         Ok(std::str::from_utf8(&self.data)?.to_string())
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    // see `ChunkRef::size`
+    pub fn size(&self) -> usize {
+        self.data.len() + Chunk::LENGTH_SIZE + ChunkType::CHUNK_TYPE_SIZE + Chunk::CRC_SIZE
+    }
 
-        bytes.extend_from_slice(&self.length.to_be_bytes());
-        bytes.extend_from_slice(&self.chunk_type.bytes);
-        bytes.extend_from_slice(&self.data);
-        bytes.extend_from_slice(&self.crc.to_be_bytes());
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        self.write_to(&mut bytes).expect("Writing to a Vec can not fail.");
 
         bytes
     }
 
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes)?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())
+    }
+
+    // starts a chunk of `chunk_type` whose data is given piece by piece, see `ChunkBuilder`
+    pub fn builder(chunk_type: ChunkType) -> ChunkBuilder {
+        ChunkBuilder {
+            chunk_type,
+            data: Vec::new(),
+        }
+    }
+
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
         let length = data.len() as u32;
-        // join chunk type and data to calculate crc
-        let chained = chunk_type
-            .bytes
-            .iter()
-            .chain(&data)
-            .cloned()
-            .collect::<Vec<u8>>();
-        let crc = crc32::checksum_ieee(&chained);
+        let crc = crc(&chunk_type.bytes, &data);
 
         Self {
-            chunk_type: chunk_type,
-            data: data,
-            length: length,
-            crc: crc,
+            chunk_type,
+            data,
+            length,
+            crc,
         }
     }
 }
 
+// Collects the data of a chunk, whose length and CRC are computed from it
+// when built:
+//
+//     let text = Chunk::builder(chunk_type).data(b"Title".to_vec()).extend(b"\0Dice").build();
+pub struct ChunkBuilder {
+    chunk_type: ChunkType,
+    data: Vec<u8>,
+}
+
+impl ChunkBuilder {
+    // replaces the data collected so far
+    pub fn data(mut self, data: Vec<u8>) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn extend(mut self, data: &[u8]) -> Self {
+        self.data.extend_from_slice(data);
+        self
+    }
+
+    pub fn build(self) -> Chunk {
+        Chunk::new(self.chunk_type, self.data)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,6 +378,8 @@ mod tests {
     fn test_chunk_crc() {
         let chunk = testing_chunk();
         assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(crc(b"RuSt", chunk.data()), 2882656334);
+        assert_eq!(CRC.checksum(b"RuStThis is where your secret message will be!"), 2882656334);
     }
 
     #[test]
@@ -230,6 +430,17 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let chunk = testing_chunk().as_bytes();
+
+        assert!(matches!(
+            Chunk::try_from(&chunk[..chunk.len() - 1]),
+            Err(ChunkError::Truncated { needed: 54, available: 53 })
+        ));
+        assert!(matches!(Chunk::try_from(&chunk[..6]), Err(ChunkError::Truncated { needed: 8, available: 6 })));
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -250,4 +461,39 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_chunk_ref() {
+        let bytes = testing_chunk().as_bytes();
+        let chunk = ChunkRef::try_from(bytes.as_ref()).unwrap();
+
+        assert_eq!(chunk.chunk_type.to_string(), "RuSt");
+        assert_eq!(chunk.data.as_ptr(), bytes[8..].as_ptr());
+        assert_eq!(chunk.size(), bytes.len());
+        assert_eq!(chunk.into_chunk().size(), bytes.len());
+        assert_eq!(chunk.into_chunk().as_bytes(), bytes);
+
+        assert!(ChunkRef::try_from(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_clone() {
+        let chunk = testing_chunk();
+        let mut copy = chunk.clone();
+        assert_eq!(copy, chunk);
+
+        copy.data.push(0);
+        assert_ne!(copy, chunk);
+        assert_eq!(std::collections::HashSet::from([chunk.clone(), chunk]).len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_builder() {
+        let chunk = Chunk::builder(ChunkType::try_from(*b"RuSt").unwrap())
+            .data(b"This is where your secret".to_vec())
+            .extend(b" message will be!")
+            .build();
+
+        assert_eq!(chunk.as_bytes(), testing_chunk().as_bytes());
+    }
 }
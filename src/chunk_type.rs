@@ -1,88 +1,197 @@
-use std::cmp::{Eq, PartialEq};
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
 
-use anyhow::{Error, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
 
-#[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ChunkType {
     pub bytes: [u8; 4],
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum ChunkTypeError {
+    #[error("Inconsistent byte length: {0}")]
     InconsistentByteLength(usize),
+    #[error("Invalid chunk type: {0}")]
     InvalidChunkType(String),
+    #[error("{0} is a critical chunk type, viewers that do not know it refuse to show the image")]
+    Critical(String),
+    #[error("{0} is defined by the PNG specification, viewers would read the payload as its data")]
+    Standard(String),
+    #[error("Invalid prefix: {0}, expected two lowercase letters so that the chunks stay ancillary and private")]
+    InvalidPrefix(String),
 }
 
-impl std::error::Error for ChunkTypeError {}
+// The first two letters of every payload chunk type, which an organization can
+// reserve so that its own scanners tell its payloads apart. They have to be
+// lowercase, the case of those letters marks the chunk ancillary and private.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix(pub [u8; 2]);
 
-impl fmt::Display for ChunkTypeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ChunkTypeError::InconsistentByteLength(len) => {
-                write!(f, "Inconsistent byte length: {}", len)
-            }
-            ChunkTypeError::InvalidChunkType(char) => write!(f, "Invalid chunk type: {}", char),
+impl FromStr for Prefix {
+    type Err = ChunkTypeError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.as_bytes() {
+            [first, second] if first.is_ascii_lowercase() && second.is_ascii_lowercase() => Ok(Prefix([*first, *second])),
+            _ => Err(ChunkTypeError::InvalidPrefix(value.to_string())),
         }
     }
 }
 
+// four ASCII letters, see `unchecked` for any other bytes
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = Error;
+    type Error = ChunkTypeError;
 
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
+        if !value.iter().all(|elem| elem.is_ascii_alphabetic()) {
+            return Err(ChunkTypeError::InvalidChunkType(String::from_utf8_lossy(&value).into_owned()));
+        }
+
         Ok(Self { bytes: value })
     }
 }
 
  impl FromStr for ChunkType {
-    type Err = Error;
+    type Err = ChunkTypeError;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         let values = value.as_bytes();
 
-        let is_valid = &values
-            .iter()
-            .all(|&elem| (elem >= 65 && elem <= 90) || (elem >= 97 && elem <= 122));
+        let is_valid = values.iter().all(|elem| elem.is_ascii_alphabetic());
 
         if !is_valid {
-            return Err(ChunkTypeError::InvalidChunkType(value.to_string()).into());
+            return Err(ChunkTypeError::InvalidChunkType(value.to_string()));
         }
 
         if values.len() != 4 {
             return Err(ChunkTypeError::InconsistentByteLength(
                 values.len(),
-            ).into());
+            ));
         }
 
-        Ok(ChunkType::try_from([
-            values[0], values[1], values[2], values[3],
-        ])?)
+        ChunkType::try_from([values[0], values[1], values[2], values[3]])
     }
 }
 
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let _str = std::str::from_utf8(&self.bytes).unwrap();
-        write!(f, "{}", _str)
+        // bytes read from a damaged file need not be text
+        write!(f, "{}", String::from_utf8_lossy(&self.bytes))
     }
 }
 
-impl Eq for ChunkType {}
+// written as its four letters, such as "IHDR"
+impl Serialize for ChunkType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
 
-impl PartialEq for ChunkType {
-    fn eq(&self, other: &Self) -> bool {
-        self.bytes == other.bytes
+impl<'de> Deserialize<'de> for ChunkType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        ChunkType::from_str(&value).map_err(de::Error::custom)
     }
 }
 
+// the chunk types the PNG specification defines, with what they hold
+pub const STANDARD: [(ChunkType, &str); 25] = [
+    (ChunkType::IHDR, "image header"),
+    (ChunkType::PLTE, "palette"),
+    (ChunkType::IDAT, "image data"),
+    (ChunkType::IEND, "image end"),
+    (ChunkType::TRNS, "transparency"),
+    (ChunkType::CHRM, "primary chromaticities and white point"),
+    (ChunkType::GAMA, "image gamma"),
+    (ChunkType::ICCP, "embedded ICC profile"),
+    (ChunkType::SBIT, "significant bits"),
+    (ChunkType::SRGB, "standard RGB color space"),
+    (ChunkType::CICP, "coding-independent code points"),
+    (ChunkType::MDCV, "mastering display color volume"),
+    (ChunkType::CLLI, "content light level"),
+    (ChunkType::TEXT, "Latin-1 text"),
+    (ChunkType::ZTXT, "compressed Latin-1 text"),
+    (ChunkType::ITXT, "international text"),
+    (ChunkType::BKGD, "background color"),
+    (ChunkType::HIST, "palette histogram"),
+    (ChunkType::PHYS, "physical pixel dimensions"),
+    (ChunkType::SPLT, "suggested palette"),
+    (ChunkType::EXIF, "EXIF metadata"),
+    (ChunkType::TIME, "last modification time"),
+    (ChunkType::ACTL, "animation control"),
+    (ChunkType::FCTL, "frame control"),
+    (ChunkType::FDAT, "frame data"),
+];
+
 impl ChunkType {
    pub  const CHUNK_TYPE_SIZE:usize = 4;
+
+    pub const IHDR: ChunkType = ChunkType { bytes: *b"IHDR" };
+    pub const PLTE: ChunkType = ChunkType { bytes: *b"PLTE" };
+    pub const IDAT: ChunkType = ChunkType { bytes: *b"IDAT" };
+    pub const IEND: ChunkType = ChunkType { bytes: *b"IEND" };
+    pub const TRNS: ChunkType = ChunkType { bytes: *b"tRNS" };
+    pub const CHRM: ChunkType = ChunkType { bytes: *b"cHRM" };
+    pub const GAMA: ChunkType = ChunkType { bytes: *b"gAMA" };
+    pub const ICCP: ChunkType = ChunkType { bytes: *b"iCCP" };
+    pub const SBIT: ChunkType = ChunkType { bytes: *b"sBIT" };
+    pub const SRGB: ChunkType = ChunkType { bytes: *b"sRGB" };
+    pub const CICP: ChunkType = ChunkType { bytes: *b"cICP" };
+    pub const MDCV: ChunkType = ChunkType { bytes: *b"mDCV" };
+    pub const CLLI: ChunkType = ChunkType { bytes: *b"cLLI" };
+    pub const TEXT: ChunkType = ChunkType { bytes: *b"tEXt" };
+    pub const ZTXT: ChunkType = ChunkType { bytes: *b"zTXt" };
+    pub const ITXT: ChunkType = ChunkType { bytes: *b"iTXt" };
+    pub const BKGD: ChunkType = ChunkType { bytes: *b"bKGD" };
+    pub const HIST: ChunkType = ChunkType { bytes: *b"hIST" };
+    pub const PHYS: ChunkType = ChunkType { bytes: *b"pHYs" };
+    pub const SPLT: ChunkType = ChunkType { bytes: *b"sPLT" };
+    pub const EXIF: ChunkType = ChunkType { bytes: *b"eXIf" };
+    pub const TIME: ChunkType = ChunkType { bytes: *b"tIME" };
+    pub const ACTL: ChunkType = ChunkType { bytes: *b"acTL" };
+    pub const FCTL: ChunkType = ChunkType { bytes: *b"fcTL" };
+    pub const FDAT: ChunkType = ChunkType { bytes: *b"fdAT" };
+
+    // what a chunk of a standard type holds, None for any other type
+    pub fn description(&self) -> Option<&'static str> {
+        STANDARD
+            .iter()
+            .find(|(chunk_type, _)| chunk_type == self)
+            .map(|(_, description)| *description)
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.description().is_some()
+    }
+
+    // Whether data of our own may go in a chunk of this type: only valid,
+    // ancillary types the specification does not define, so that viewers skip
+    // the chunk rather than fail on it or misread it.
+    pub fn check_payload_type(&self) -> Result<(), ChunkTypeError> {
+        if !self.is_valid() {
+            return Err(ChunkTypeError::InvalidChunkType(self.to_string()));
+        }
+        if self.is_standard() {
+            return Err(ChunkTypeError::Standard(self.to_string()));
+        }
+        if self.is_critical() {
+            return Err(ChunkTypeError::Critical(self.to_string()));
+        }
+
+        Ok(())
+    }
+
+    // Any four bytes, letters or not, for tools that have to keep the chunk
+    // types of damaged files as they are. `is_valid` tells whether it is one.
+    pub fn unchecked(bytes: [u8; 4]) -> ChunkType {
+        ChunkType { bytes }
+    }
+
     pub fn bytes(&self) -> [u8; 4] {
         self.bytes
     }
@@ -90,23 +199,23 @@ impl ChunkType {
     pub fn is_valid(&self) -> bool {
         self.bytes
             .iter()
-            .all(|&elem| (elem >= 65 && elem <= 90) || (elem >= 97 && elem <= 122))
+            .all(|elem| elem.is_ascii_alphabetic())
             && self.is_reserved_bit_valid() // check if the chunk type conforms with the png standard.
     }
 
-    fn is_critical(&self) -> bool {
+    pub fn is_critical(&self) -> bool {
         self.bytes[0] >= 65 && self.bytes[0] <= 90
     }
 
-    fn is_public(&self) -> bool {
+    pub fn is_public(&self) -> bool {
         self.bytes[1] >= 65 && self.bytes[1] <= 90
     }
 
-    fn is_reserved_bit_valid(&self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         self.bytes[2] >= 65 && self.bytes[2] <= 90
     }
 
-    fn is_safe_to_copy(&self) -> bool {
+    pub fn is_safe_to_copy(&self) -> bool {
         self.bytes[3] >= 97 && self.bytes[3] <= 122
     }
 }
@@ -125,6 +234,46 @@ mod tests {
         assert_eq!(expected, actual.bytes());
     }
 
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        assert!(ChunkType::try_from([82, 117, 0xff, 116]).is_err());
+        assert!(ChunkType::try_from([82, 117, b'1', 116]).is_err());
+
+        let chunk_type = ChunkType::unchecked([82, 117, 0xff, 116]);
+        assert!(!chunk_type.is_valid());
+        assert_eq!(chunk_type.bytes(), [82, 117, 0xff, 116]);
+    }
+
+    #[test]
+    pub fn test_standard_chunk_types() {
+        assert_eq!(ChunkType::from_str("tEXt").unwrap(), ChunkType::TEXT);
+        assert_eq!(ChunkType::TEXT.description(), Some("Latin-1 text"));
+        assert!(STANDARD.iter().all(|(chunk_type, _)| chunk_type.is_valid()));
+
+        assert!(!ChunkType::from_str("ruSt").unwrap().is_standard());
+        assert_eq!(ChunkType::from_str("ruSt").unwrap().description(), None);
+    }
+
+    #[test]
+    pub fn test_check_payload_type() {
+        assert!(ChunkType::from_str("ruSt").unwrap().check_payload_type().is_ok());
+        assert!(matches!(ChunkType::IDAT.check_payload_type(), Err(ChunkTypeError::Standard(_))));
+        assert!(matches!(ChunkType::from_str("RuSt").unwrap().check_payload_type(), Err(ChunkTypeError::Critical(_))));
+        assert!(matches!(ChunkType::TEXT.check_payload_type(), Err(ChunkTypeError::Standard(_))));
+        assert!(matches!(
+            ChunkType::from_str("rust").unwrap().check_payload_type(),
+            Err(ChunkTypeError::InvalidChunkType(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_prefix_from_str() {
+        assert_eq!(Prefix::from_str("pc").unwrap(), Prefix(*b"pc"));
+        assert!(Prefix::from_str("Pc").is_err());
+        assert!(Prefix::from_str("pcx").is_err());
+        assert!(Prefix::from_str("p1").is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_from_str() {
         let expected = ChunkType::try_from([82, 117, 83, 116]).unwrap();
@@ -208,5 +357,27 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
-}
 
+    #[test]
+    pub fn test_chunk_type_serde() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        assert_eq!(serde_json::to_string(&chunk_type).unwrap(), "\"RuSt\"");
+        assert_eq!(serde_json::from_str::<ChunkType>("\"RuSt\"").unwrap(), chunk_type);
+        assert!(serde_json::from_str::<ChunkType>("\"Ru5t\"").is_err());
+    }
+
+    #[test]
+    pub fn test_chunk_type_as_key() {
+        let ihdr = ChunkType::from_str("IHDR").unwrap();
+        let idat = ChunkType::from_str("IDAT").unwrap();
+
+        let mut counts = std::collections::HashMap::new();
+        for chunk_type in [ihdr, idat, idat] {
+            *counts.entry(chunk_type).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts[&idat], 2);
+        assert_eq!(counts[&ihdr], 1);
+        assert!(idat < ihdr);
+    }
+}
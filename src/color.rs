@@ -0,0 +1,24 @@
+use super::args::ColorChoice;
+
+use std::io::IsTerminal;
+
+// Whether what is printed with `colored`, on stdout and stderr alike, carries
+// ANSI color codes. With "auto", it does when stdout is a terminal and
+// NO_COLOR is not set to anything (see https://no-color.org), so output that
+// is piped or logged is plain text; "always" and "never" override both.
+
+pub fn enabled(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            !no_color && std::io::stdout().is_terminal()
+        }
+    }
+}
+
+// called once the arguments are read, before anything is printed
+pub fn init(choice: ColorChoice) {
+    colored::control::set_override(enabled(choice));
+}
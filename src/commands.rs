@@ -1,155 +1,2998 @@
 use super::args;
-use super::chunk;
-use super::chunk_type;
-use super::png;
+use super::args::{ChunkCommand, Command, ExifCommand, MetaCommand, Mode};
+use super::color;
+use super::logging;
+use super::man;
+use super::progress;
+use super::serve;
+use super::tui;
+use super::wizard;
+
+use pngcrypt::carrier;
+use pngcrypt::carrier::Carrier;
+use pngcrypt::carve;
+use pngcrypt::chunk;
+use pngcrypt::chunk_type;
+use pngcrypt::contents::{self, Contents, MemoryLimit};
+use pngcrypt::diff;
+use pngcrypt::envelope;
+use pngcrypt::inspect;
+use pngcrypt::journal;
+use pngcrypt::lint;
+use pngcrypt::lsb;
+use pngcrypt::png;
+use pngcrypt::png::exif;
+use pngcrypt::png::index::ChunkIndex;
+use pngcrypt::png::order;
+use pngcrypt::png::pixels::Pixels;
+use pngcrypt::png::repair;
+use pngcrypt::png::text;
+use pngcrypt::png::text::{InternationalText, Text};
+use pngcrypt::png::validate;
+use pngcrypt::quality;
+use pngcrypt::report;
+use pngcrypt::riff::Riff;
+use pngcrypt::sanitize;
+use pngcrypt::scan;
+use pngcrypt::selector::Selector;
+use pngcrypt::stats;
+use pngcrypt::steganalysis;
+use pngcrypt::survivability;
+use pngcrypt::wav;
+use pngcrypt::zip;
 
 use std::convert::TryFrom;
-use std::fs::File;
-use std::io::Read;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Instant;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::CommandFactory;
 use colored::*;
-use rand::Rng;
+use tracing::{debug, info, trace, warn};
 
 pub struct Commands {}
 
+// how encode names the payload in chunk mode: a random token, starting with the
+// prefix when one is set, or the one derived from the password
+pub enum Naming {
+    Random(Option<chunk_type::Prefix>),
+    Derived(String),
+}
+
+// How commands write files: `drop_trailing` leaves out the data found after
+// IEND, `force` lets them replace a file that is already there, the input
+// image they rewrite in place included, and `preserve_times` gives the new file
+// the modification time of the one it replaces. With `dry_run` nothing is
+// written, what would have been is described instead.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    pub drop_trailing: bool,
+    pub force: bool,
+    pub preserve_times: bool,
+    pub dry_run: bool,
+}
+
 impl Commands {
-    pub fn encode(input_path: &Path, message: String, output_file_path: String) -> Result<()> {
+    fn open(input_path: &Path) -> Result<(PathBuf, File)> {
         let path = input_path
             .canonicalize()
-            .context(format!("Invalid path given {input_path:?}. File not found."))?
-            .into_os_string()
-            .into_string()
-            .unwrap();
+            .context(format!("Invalid path given {input_path:?}. File not found."))?;
 
         let file = File::open(&path).context(format!(
-            "Invalid input file. Can not find file to be encoded. {path}"
+            "Invalid input file. Can not find file to be encoded. {}",
+            path.display()
         ))?;
 
-        let bytes: Vec<u8> = file.bytes().map(|b| b.unwrap()).collect();
+        Ok((path, file))
+    }
+
+    // large files are mapped rather than read, see `contents`
+    fn read_bytes(input_path: &Path) -> Result<(PathBuf, Contents)> {
+        let (path, file) = Commands::open(input_path)?;
 
-        let mut png = png::Png::try_from(bytes.as_ref())
-            .context("Failed to reconstruct a valid png struct from the given file.")?;
+        let bytes = contents::read(file).context(format!("Unable to read {}.", path.display()))?;
+        debug!(path = %path.display(), length = bytes.len(), mapped = bytes.is_mapped(), "Read the file");
 
-        let chunk_type_str = Commands::new_chunk_type();
-        let chunk_type = chunk_type::ChunkType::from_str(&chunk_type_str).unwrap();
-        let chunk_bytes = chunk::Chunk::new(chunk_type, message.bytes().collect::<Vec<u8>>());
+        Ok((path, bytes))
+    }
 
-        png.append_chunk(chunk_bytes);
+    // Reads the chunks straight from the file, see `Png::from_reader`, or from
+    // its mapping when it is large enough to be mapped.
+    fn read_png(input_path: &Path) -> Result<(PathBuf, png::Png)> {
+        let (path, file) = Commands::open(input_path)?;
+        let started = Instant::now();
 
-        let bytes = png.as_bytes();
+        let png = if file.metadata()?.len() >= contents::MAP_THRESHOLD {
+            let bytes = contents::read(file).context(format!("Unable to read {}.", path.display()))?;
 
-        let output_parent = input_path.parent().unwrap().to_str().unwrap();
+            // read as a stream from the mapping, so the bar follows how far it got
+            let bar = progress::bytes(bytes.len() as u64, format!("Reading {}", path.display()));
+            let png = png::Png::from_reader(bar.wrap_read(&*bytes));
+            bar.finish_and_clear();
 
-        let output_path = if !output_parent.is_empty() {
-            format!("{}/{}", output_parent, output_file_path)
+            png
         } else {
-            output_file_path
+            png::Png::from_reader(BufReader::new(file))
+        }
+        .context("Failed to reconstruct a valid png struct from the given file.")?;
+        Commands::trace_png(&path, &png, started);
+
+        Ok((path, png))
+    }
+
+    // what parsing the image at `path` found, and how long it took since `started`
+    fn trace_png(path: &Path, png: &png::Png, started: Instant) {
+        let chunks = png.chunks().len();
+        debug!(path = %path.display(), chunks, trailing = png.trailing().len(), "Parsed the image");
+
+        for (index, (chunk, offset)) in png.chunks().zip(png.chunk_offsets()).enumerate() {
+            trace!(index, chunk_type = %chunk.chunk_type, offset, length = chunk.data().len(), "Chunk");
+        }
+        trace!(elapsed = ?started.elapsed(), "Parsing took");
+    }
+
+    // The image read a chunk at a time, as `read_png` reads it, when the file
+    // is a PNG image below the size files are mapped from and reads without
+    // error. Nothing is held twice then, while the bytes of the whole file and
+    // the chunks copied out of them would be. Commands that take other
+    // formats or salvage damaged files go on from the bytes of the file
+    // otherwise.
+    fn read_png_streamed(input_path: &Path) -> Option<(PathBuf, png::Png)> {
+        let (path, file) = Commands::open(input_path).ok()?;
+        if file.metadata().ok()?.len() >= contents::MAP_THRESHOLD {
+            return None;
+        }
+
+        let started = Instant::now();
+        let png = png::Png::from_reader(BufReader::new(file)).ok()?;
+        Commands::trace_png(&path, &png, started);
+
+        Some((path, png))
+    }
+
+    // the chunks of the file without their data, see `ChunkIndex`
+    fn read_index(input_path: &Path) -> Result<ChunkIndex<BufReader<File>>> {
+        let (path, file) = Commands::open(input_path)?;
+
+        let index = ChunkIndex::read(BufReader::new(file))
+            .context("Failed to reconstruct a valid png struct from the given file.")?;
+
+        debug!(path = %path.display(), chunks = index.entries().len(), "Indexed the image");
+        for (number, entry) in index.entries().iter().enumerate() {
+            let (offset, length) = (entry.offset, entry.length);
+            trace!(index = number, chunk_type = %entry.chunk_type, offset, length, "Chunk");
+        }
+
+        Ok(index)
+    }
+
+    // Like `read_png`, but reads the chunks with a CRC mismatch too, warning
+    // about each of them.
+    fn read_png_ignoring_crc(input_path: &Path) -> Result<png::Png> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+
+        let (png, mismatches) = png::Png::try_from_ignoring_crc(&bytes)
+            .context("Failed to reconstruct a valid png struct from the given file.")?;
+
+        Commands::warn_crc_mismatches(&mismatches);
+
+        Ok(png)
+    }
+
+    fn warn_crc_mismatches(mismatches: &[validate::Violation]) {
+        for mismatch in mismatches {
+            warn!("{}. Its data may be corrupted and is used as is.", mismatch);
+        }
+    }
+
+    // Like `read_png`, but keeps the chunks that could be read from a damaged
+    // or truncated file, so payloads that survived can still be decoded. With
+    // `ignore_crc`, chunks with a CRC mismatch are kept as well.
+    fn salvage_png(input_path: &Path, ignore_crc: bool) -> Result<png::Png> {
+        // a chunk with a CRC mismatch stops a streamed read, those are read from the bytes
+        if !ignore_crc {
+            if let Some((_, png)) = Commands::read_png_streamed(input_path) {
+                return Ok(png);
+            }
+        }
+
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+
+        Commands::salvage_bytes(&bytes, ignore_crc)
+    }
+
+    fn salvage_bytes(bytes: &[u8], ignore_crc: bool) -> Result<png::Png> {
+        if ignore_crc {
+            if let Ok((png, mismatches)) = png::Png::try_from_ignoring_crc(bytes) {
+                Commands::warn_crc_mismatches(&mismatches);
+                return Ok(png);
+            }
+        } else if let Ok(png) = png::Png::try_from(bytes) {
+            return Ok(png);
+        }
+
+        let (png, damaged_at) = png::Png::salvage(bytes, ignore_crc)
+            .context("Failed to reconstruct a valid png struct from the given file.")?;
+
+        if let Some(offset) = damaged_at {
+            warn!(
+                "The file is truncated or damaged at offset {}, only the {} chunks before it were read.",
+                offset,
+                png.chunks().len()
+            );
+        }
+
+        Ok(png)
+    }
+
+    fn read_carrier(input_path: &Path) -> Result<(PathBuf, Box<dyn Carrier>)> {
+        if let Some((path, png)) = Commands::read_png_streamed(input_path) {
+            return Ok((path, Box::new(png)));
+        }
+
+        let (path, bytes) = Commands::read_bytes(input_path)?;
+
+        let carrier = carrier::open(&bytes).context("Failed to read the given file.")?;
+
+        Ok((path, carrier))
+    }
+
+    // Like `read_carrier`, but salvages what it can of a damaged PNG file, as
+    // `salvage_png` does.
+    fn salvage_carrier(input_path: &Path, ignore_crc: bool) -> Result<Box<dyn Carrier>> {
+        // a chunk with a CRC mismatch stops a streamed read, those are read from the bytes
+        if !ignore_crc {
+            if let Some((_, png)) = Commands::read_png_streamed(input_path) {
+                return Ok(Box::new(png));
+            }
+        }
+
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+
+        if bytes.starts_with(&png::Png::STANDARD_HEADER) {
+            return Ok(Box::new(Commands::salvage_bytes(&bytes, ignore_crc)?));
+        }
+
+        carrier::open(&bytes).context("Failed to read the given file.")
+    }
+
+    // A bare file name is written next to the input, any other path, absolute
+    // or relative to the working directory, is taken as it is.
+    fn output_path(input_path: &Path, output_file_path: PathBuf) -> PathBuf {
+        match input_path.parent() {
+            Some(parent) if output_file_path.parent() == Some(Path::new("")) => parent.join(output_file_path),
+            _ => output_file_path,
+        }
+    }
+
+    // Writes the image made from the file at `input_path`, along with the data
+    // found after its IEND chunk unless `drop_trailing` is set.
+    fn write_png(input_path: &Path, output_path: &Path, png: &png::Png, writing: WriteOptions) -> Result<()> {
+        Commands::write_atomically(Some(input_path), output_path, writing, false, |writer| {
+            if writing.drop_trailing && !png.trailing().is_empty() {
+                png.write_chunks_to(writer)?;
+                info!("Dropped {} bytes found after IEND", png.trailing().len());
+            } else {
+                png.write_to(writer)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn write_carrier(
+        input_path: &Path,
+        output_path: &Path,
+        carrier: &mut dyn Carrier,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        if writing.drop_trailing {
+            let dropped = carrier.strip_trailing();
+            if !dropped.is_empty() {
+                info!("Dropped {} bytes found after the end of the {} file", dropped.len(), carrier.format());
+            }
+        }
+
+        Commands::write_atomically(Some(input_path), output_path, writing, false, |writer| Ok(carrier.write_to(writer)?))
+    }
+
+    // <input stem>.<suffix>.<extension>, as in photo.pngcrypt.png
+    fn default_output_name(input_path: &Path, suffix: &str) -> PathBuf {
+        let mut name = input_path.file_stem().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(suffix);
+
+        if let Some(extension) = input_path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+
+        PathBuf::from(name)
+    }
+
+    // Describes the file a dry run would have written: its size and, when it
+    // is an image made from `input_path`, its chunks and how they differ from
+    // the input's, or the payloads it holds for the other formats.
+    fn preview(input_path: Option<&Path>, output_path: &Path, bytes: &[u8]) -> Result<()> {
+        let summary = format!("Dry run, nothing was written. {} would hold {} bytes", output_path.display(), bytes.len());
+
+        let input_path = match input_path {
+            Some(input_path) => input_path,
+            None => {
+                println!("{}.", summary);
+                return Ok(());
+            }
         };
+        let (_, before) = Commands::read_bytes(input_path)?;
+        println!("{}, {} holds {}.", summary, input_path.display(), before.len());
 
-        let mut output_file = File::create(&output_path)
-            .context(format!("Unable to create output file at {output_path}."))?;
+        match (png::Png::try_from(&*before), png::Png::try_from(bytes)) {
+            (Ok(before), Ok(after)) => {
+                for (index, chunk) in after.chunks().enumerate() {
+                    println!("{:>4}  {}  {:>10}", index, chunk.chunk_type, chunk.length);
+                }
 
-        output_file.write_all(bytes.as_ref())?;
-        output_file.flush()?;
+                let diff = diff::diff(&before, &after);
+                for change in diff.changes.iter() {
+                    let line = change.to_string();
 
-        println!("Secret encoded successfully The token is {}, please keep it a secret. It will be used for decoding your message.", &chunk_type_str.white().bold());
+                    match change {
+                        diff::Change::Added(_) => println!("{}", line.green()),
+                        diff::Change::Removed(_) => println!("{}", line.red()),
+                        diff::Change::Modified { .. } => println!("{}", line.yellow()),
+                    }
+                }
+                println!("{} chunks unchanged", diff.unchanged);
+            }
+            _ => {
+                if let Ok(carrier) = carrier::open(bytes) {
+                    for slot in carrier.list() {
+                        println!("{} payload {}: {} bytes", carrier.format(), slot.selector, slot.length);
+                    }
+                }
+            }
+        }
 
         Ok(())
     }
 
-    pub fn decode(input_path: &Path, chunk_type: String) -> Result<String> {
-        let path = input_path
-            .canonicalize()
-            .context(format!("Invalid path given {input_path:?}. File not found."))?
-            .into_os_string()
-            .into_string()
-            .unwrap();
+    // Reads the file before it is changed in place, returning what copies it,
+    // permissions included, to a new backup recorded in its journal, see
+    // `journal`. That is only called once the change succeeded, so a failed
+    // one leaves neither a backup nor a journal entry behind.
+    fn backup(input_path: &Path, writing: WriteOptions) -> Result<impl FnOnce() -> Result<()>> {
+        let (path, bytes) = Commands::read_bytes(input_path)?;
+        let permissions = std::fs::metadata(&path)?.permissions();
 
-        let file = File::open(&path).context(format!(
-            "Invalid input file. Can not find file to be encoded. {path}"
-        ))?;
-        let bytes: Vec<u8> = file.bytes().map(|b| b.unwrap()).collect();
+        Ok(move || {
+            let backup = journal::next_backup(&path)?;
+            let backup_path = journal::backup_path(&path, backup);
+
+            Commands::write_file(&backup_path, &bytes, writing)?;
+            if writing.dry_run {
+                return Ok(());
+            }
+            std::fs::set_permissions(&backup_path, permissions)?;
+            journal::record(&path, &journal::Entry::new(journal::Action::Backup, backup))?;
+            info!("Backed up {} to {}", path.display(), backup_path.display());
+
+            Ok(())
+        })
+    }
+
+    // Takes an advisory lock on the file a command changes in place, held
+    // until the returned handle is dropped, so that commands changing the same
+    // file at once can not interleave. Fails at once when it is taken.
+    fn lock(input_path: &Path) -> Result<File> {
+        let (path, file) = Commands::open(input_path)?;
+
+        let busy = || format!("{} is busy, another command is changing it. Try again once it is done", path.display());
+        match fs2::FileExt::try_lock_exclusive(&file) {
+            Ok(()) => {}
+            Err(error) if error.raw_os_error() == fs2::lock_contended_error().raw_os_error() => bail!(busy()),
+            Err(error) => return Err(error).context(format!("Unable to lock {path:?}.")),
+        }
 
-        let png = png::Png::try_from(bytes.as_ref())?;
+        // the command that held the lock may have replaced the file since it was opened
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
 
-        let chunk = png.chunk_by_type(&chunk_type).context("Can not decode. Critical chunk not found!!")?;
+            let (locked, current) = (file.metadata()?, std::fs::metadata(&path)?);
+            if (locked.dev(), locked.ino()) != (current.dev(), current.ino()) {
+                bail!(busy());
+            }
+        }
 
-        chunk.data_as_string()
+        Ok(file)
     }
 
-    pub fn remove(input_path: &Path, chunk_type: String) -> Result<String> {
-        let path = input_path
-            .canonicalize()
-            .context(format!("Invalid path given {input_path:?}. File not found."))?
-            .into_os_string()
-            .into_string()
-            .unwrap();
+    // fails when `output_path` is already there, unless `force` is set
+    fn check_overwrite(output_path: &Path, force: bool) -> Result<()> {
+        if !force && output_path.exists() {
+            bail!("{} already exists. Pass --force to overwrite it", output_path.display());
+        }
 
-        let file = File::open(&path).context(format!(
-            "Invalid input file. Can not find file to be encoded. {path}"
-        ))?;
-        let bytes: Vec<u8> = file.bytes().map(|b| b.unwrap()).collect();
+        Ok(())
+    }
+
+    fn write_file(output_path: &Path, contents: &[u8], writing: WriteOptions) -> Result<()> {
+        Commands::write_atomically(None, output_path, writing, false, |writer| Ok(writer.write_all(contents)?))
+    }
+
+    // Like `write_file`, for data taken out of a payload, which on Unix only
+    // the owner of the file can read.
+    fn write_secret_file(output_path: &Path, contents: &[u8], writing: WriteOptions) -> Result<()> {
+        Commands::write_atomically(None, output_path, writing, true, |writer| Ok(writer.write_all(contents)?))
+    }
+
+    // Writes to a temporary file next to `output_path` and renames it over the
+    // target once it is on disk, so a command killed halfway leaves the old
+    // file, the image it was rewriting in place included, as it was. The new
+    // file keeps the permissions of the one it replaces, unless it is `secret`.
+    // A dry run only previews the file, against `input_path` when it is made
+    // from one.
+    fn write_atomically(
+        input_path: Option<&Path>,
+        output_path: &Path,
+        writing: WriteOptions,
+        secret: bool,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        Commands::check_overwrite(output_path, writing.force)?;
+
+        if writing.dry_run {
+            let mut bytes = Vec::new();
+            write(&mut bytes)?;
+
+            return Commands::preview(input_path, output_path, &bytes);
+        }
+        let replaced = std::fs::metadata(output_path).ok();
+
+        let file_name = output_path.file_name().context(format!("Invalid output file {output_path:?}."))?;
+        let mut temporary_name = OsString::from(".");
+        temporary_name.push(file_name);
+        temporary_name.push(format!(".{}.tmp", std::process::id()));
+        let temporary_path = output_path.with_file_name(temporary_name);
+
+        let written = (|| {
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            if secret {
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            }
 
-        let mut png = png::Png::try_from(bytes.as_ref())?;
+            let output_file = options
+                .open(&temporary_path)
+                .context(format!("Unable to create output file at {output_path:?}."))?;
 
-        let chunk = png.remove_chunk(&chunk_type).context("Can not remove message. Critical chunk not found!!")?;
+            let mut writer = BufWriter::new(output_file);
+            write(&mut writer)?;
 
-        let bytes = png.as_bytes();
+            let output_file = writer.into_inner().map_err(|error| error.into_error())?;
+            if let Some(replaced) = &replaced {
+                if !secret {
+                    output_file.set_permissions(replaced.permissions())?;
+                }
+                if writing.preserve_times {
+                    output_file.set_modified(replaced.modified()?)?;
+                }
+            }
+            output_file.sync_all()?;
+
+            std::fs::rename(&temporary_path, output_path)
+                .context(format!("Unable to replace {output_path:?}."))
+        })();
 
-        let mut output_file = File::create(&path).context("Unable to create file at {path}")?;
-        output_file.write_all(bytes.as_ref())?;
-        output_file.flush()?;
+        if written.is_err() {
+            let _ = std::fs::remove_file(&temporary_path);
+        }
 
-        chunk.data_as_string()
+        written
     }
 
-    pub fn new_chunk_type() -> String {
-        let mut rng = rand::thread_rng();
-        let mut chunk_type = String::new();
+    pub fn encode(
+        input_path: &Path,
+        message: String,
+        output_file_path: PathBuf,
+        secret: args::Secret,
+        position: png::Position,
+        naming: Naming,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let payload = secret.seal(message.as_bytes())?;
+        let output_path = Commands::output_path(input_path, output_file_path);
+
+        let token = if Commands::is_large_png(input_path)? {
+            Commands::splice_payload(input_path, &output_path, &payload, position, &naming, writing)
+        } else {
+            Commands::embed_payload(input_path, &output_path, &payload, position, &naming, writing)
+        };
+
+        if let Naming::Derived(_) = naming {
+            token.map_err(|error| match error.downcast_ref::<carrier::CarrierError>() {
+                Some(carrier::CarrierError::SelectorTaken(_)) => {
+                    error.context("Another payload uses the chunk type of this password, pass a --label to get a new one.")
+                }
+                _ => error,
+            })?;
+
+            // the password is all decoding needs
+            if !logging::quiet() {
+                println!("Secret encoded successfully under a chunk type derived from your password. It can be decoded with your password, no token is needed.");
+            }
 
-        for _ in 0..2 {
-            chunk_type.push(rng.gen_range(b'a'..b'z') as char);
+            return Ok(());
         }
-        for _ in 0..2 {
-            chunk_type.push(rng.gen_range(b'A'..b'Z') as char);
+
+        let token = token?;
+        if logging::quiet() {
+            println!("{}", token);
+            return Ok(());
         }
 
-        chunk_type
+        println!("Secret encoded successfully The token is {}, please keep it a secret. It will be used for decoding your message.", &token.white().bold());
+
+        Ok(())
     }
 
-    pub fn from_args(args: args::Args) -> Result<()> {
-        match args.operation.as_str() {
-            "encode" => {
-                let path = Path::new(&args.file_path);
+    // Adds the payload to the carrier read from the file and writes it out,
+    // returning the token it is stored under.
+    fn embed_payload(
+        input_path: &Path,
+        output_path: &Path,
+        payload: &[u8],
+        position: png::Position,
+        naming: &Naming,
+        writing: WriteOptions,
+    ) -> Result<String> {
+        let (_, mut carrier) = Commands::read_carrier(input_path)?;
 
-                let message = args.message.expect("Message is required");
-                let output_file = args.output_file.expect("Output file is required");
+        let token = match naming {
+            Naming::Derived(token) => {
+                carrier.embed_as(payload, token, position)?;
+                token.clone()
+            }
+            Naming::Random(Some(prefix)) => carrier.embed_with_prefix(payload, *prefix, position)?,
+            Naming::Random(None) => carrier.embed(payload, position)?,
+        };
+        debug!(chunk_type = %token, length = payload.len(), "Added the payload");
 
-                Commands::encode(path, message, output_file)?;
+        Commands::write_carrier(input_path, output_path, carrier.as_mut(), writing)?;
 
-                Ok(())
+        Ok(token)
+    }
+
+    // whether the file is a PNG image large enough to be mapped, see `contents`
+    fn is_large_png(input_path: &Path) -> Result<bool> {
+        let (_, mut file) = Commands::open(input_path)?;
+        let mut signature = [0; 8];
+
+        Ok(file.metadata()?.len() >= contents::MAP_THRESHOLD
+            && file.read_exact(&mut signature).is_ok()
+            && signature == png::Png::STANDARD_HEADER)
+    }
+
+    // Like `embed_payload`, for large PNG images: the payload chunk is written
+    // at its place while the rest of the file is copied over as it is, see
+    // `ChunkIndex`, rather than every chunk being read in and written back out.
+    // Only the framing of the chunks is checked, not their CRCs.
+    fn splice_payload(
+        input_path: &Path,
+        output_path: &Path,
+        payload: &[u8],
+        position: png::Position,
+        naming: &Naming,
+        writing: WriteOptions,
+    ) -> Result<String> {
+        let mut indexed = Commands::read_index(input_path)?;
+
+        let chunk_type = match naming {
+            Naming::Derived(token) => {
+                let chunk_type = chunk_type::ChunkType::from_str(token)?;
+                chunk_type.check_payload_type()?;
+                if indexed.has_chunk(chunk_type) {
+                    return Err(carrier::CarrierError::SelectorTaken(token.clone()).into());
+                }
+                chunk_type
             }
-            "decode" => {
-                let chunk_type = args.chunk_type.expect("Chunk type is required");
-                let path = Path::new(&args.file_path);
+            Naming::Random(prefix) => std::iter::repeat_with(|| png::new_chunk_type_with_prefix(*prefix))
+                .find(|chunk_type| !indexed.has_chunk(*chunk_type))
+                .unwrap(),
+        };
 
-                println!("{}", Commands::decode(path, chunk_type)?);
+        let chunk = chunk::Chunk::new(chunk_type, payload.to_vec());
+        let offset = indexed.insertion_offset(position);
+        debug!(chunk_type = %chunk_type, length = payload.len(), offset, "Splicing in the payload");
 
-                Ok(())
+        Commands::write_atomically(Some(input_path), output_path, writing, false, |writer| {
+            let bar = progress::bytes(indexed.file_length(), format!("Writing {}", output_path.display()));
+            indexed.write_with_chunk(&chunk, offset, !writing.drop_trailing, bar.wrap_write(writer))?;
+            bar.finish_and_clear();
+
+            if writing.drop_trailing && indexed.trailing_length() > 0 {
+                info!("Dropped {} bytes found after IEND", indexed.trailing_length());
             }
-            "remove" => {
-                let path = Path::new(&args.file_path);
-                let chunk_type = args.chunk_type.expect("Chunk type is required");
 
-                println!("{}", Commands::remove(path, chunk_type)?);
+            Ok(())
+        })?;
 
-                Ok(())
+        Ok(chunk_type.to_string())
+    }
+
+    // Writes the message in the clear to a standard tEXt or iTXt chunk, which
+    // any PNG tool shows, so it is not hidden at all.
+    pub fn encode_text(
+        input_path: &Path,
+        output_file_path: PathBuf,
+        keyword: &str,
+        text_chunk: chunk::Chunk,
+        position: png::Position,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let (_, mut png) = Commands::read_png(input_path)?;
+
+        let chunk_type = text_chunk.chunk_type.to_string();
+        png.insert_chunk_at(text_chunk, position);
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+        if logging::quiet() {
+            return Ok(());
+        }
+
+        println!(
+            "Message written to a new {} chunk with the keyword {}. It is not encrypted and shows in any PNG viewer.",
+            chunk_type,
+            keyword.white().bold()
+        );
+
+        Ok(())
+    }
+
+    // Appends a ZIP archive holding the message as `entry_name` to the image,
+    // so the file also opens as an archive. The message is not encrypted.
+    pub fn encode_zip(
+        input_path: &Path,
+        message: String,
+        output_file_path: PathBuf,
+        entry_name: &str,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let (_, mut png) = Commands::read_png(input_path)?;
+
+        if writing.drop_trailing {
+            png.strip_trailing();
+        }
+
+        // the offsets in the archive count from the start of the file
+        let start = png.trailing_offset() + png.trailing().len();
+        png.append_trailing(&zip::archive(entry_name, message.as_bytes(), start)?);
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, WriteOptions { drop_trailing: false, ..writing })?;
+        if logging::quiet() {
+            return Ok(());
+        }
+
+        println!(
+            "Message written to {} in a ZIP archive after the image. Renamed to .zip, {} opens as an archive; the message is not encrypted.",
+            entry_name.white().bold(),
+            output_path.display()
+        );
+
+        Ok(())
+    }
+
+    // the colors of indexed frames, so quality is measured on them rather than on palette indices
+    fn frame_colors(png: &png::Png, frames: &[Pixels]) -> Result<Vec<Pixels>> {
+        let palette = png.palette()?;
+        frames.iter().map(|frame| palette.expand(frame)).collect()
+    }
+
+    pub fn encode_lsb(
+        input_path: &Path,
+        message: String,
+        output_file_path: PathBuf,
+        secret: args::Secret,
+        mut options: lsb::LsbOptions,
+        writing: WriteOptions,
+    ) -> Result<(PathBuf, quality::Quality)> {
+        let (_, mut png) = Commands::read_png(input_path)?;
+
+        let payload = secret.seal(message.as_bytes())?;
+
+        options.seed = secret.password.as_deref().map(lsb::seed_from_password);
+
+        let spinner = progress::spinner("Decoding the image");
+        let original = png.frames().context("Unable to decode the image data.")?;
+        let indexed = png.ihdr()?.is_indexed();
+
+        let (mut frames, reference) = if indexed {
+            let reference = Commands::frame_colors(&png, &original)?;
+            (png.sort_palette(&original)?, reference)
+        } else {
+            (original.clone(), original)
+        };
+
+        spinner.set_message("Embedding the message");
+        lsb::embed_frames(&mut frames, &payload, &options)?;
+        spinner.set_message("Compressing the image");
+        png.set_frames(&frames)?;
+        spinner.finish_and_clear();
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        let spinner = progress::spinner("Measuring the quality of the image");
+        let result = if indexed { Commands::frame_colors(&png, &frames)? } else { frames };
+        let quality = quality::compare_frames(&reference, &result);
+        spinner.finish_and_clear();
+
+        Ok((output_path, quality))
+    }
+
+    fn is_wav(input_path: &Path) -> bool {
+        Commands::read_bytes(input_path)
+            .map(|(_, bytes)| Riff::is_riff(&bytes, wav::FORM))
+            .unwrap_or(false)
+    }
+
+    // Hides the payload in the lowest bit of the samples of a WAV file, in an
+    // order derived from the password when there is one.
+    pub fn encode_wav_lsb(
+        input_path: &Path,
+        message: String,
+        output_file_path: PathBuf,
+        secret: args::Secret,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+        let mut wav = wav::Wav::parse(&bytes).context("Failed to read the given file.")?;
+
+        let payload = secret.seal(message.as_bytes())?;
+        let seed = secret.password.as_deref().map(lsb::seed_from_password);
+        wav.embed_lsb(&payload, seed.as_ref())?;
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_carrier(input_path, &output_path, &mut wav, writing)?;
+
+        if !logging::quiet() {
+            println!("Secret embedded successfully in the audio samples. It can be decoded with your password, no token is needed.");
+        }
+
+        Ok(())
+    }
+
+    pub fn decode_wav_lsb(input_path: &Path, password: Option<String>) -> Result<String> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+        let wav = wav::Wav::parse(&bytes).context("Failed to read the given file.")?;
+
+        let seed = password.as_deref().map(lsb::seed_from_password);
+        let payload = wav.extract_lsb(seed.as_ref())?;
+
+        let message = envelope::open(&payload, password.as_deref())?;
+
+        Ok(String::from_utf8(message)?)
+    }
+
+    fn report_quality(output_path: &Path, quality: quality::Quality, min_psnr: f64, json: bool) {
+        if json {
+            let report = serde_json::json!({
+                "output": output_path.display().to_string(),
+                "mode": "lsb",
+                "psnr": quality.psnr,
+                "ssim": quality.ssim,
+                "below_min_psnr": quality.psnr < min_psnr,
+            });
+            println!("{}", report);
+
+            return;
+        }
+        if logging::quiet() {
+            return;
+        }
+
+        println!("Secret embedded successfully in the pixel data. It can be decoded with your password, no token is needed.");
+        println!("Image quality: PSNR {:.2} dB, SSIM {:.4}", quality.psnr, quality.ssim);
+
+        if quality.psnr < min_psnr {
+            warn!(
+                "PSNR is below {} dB, the changes may be noticeable. Consider fewer bits per channel or a shorter message.",
+                min_psnr
+            );
+        }
+    }
+
+    // The name `selector` stands for: itself when it is an exact name, otherwise
+    // the one payload it matches.
+    fn resolve(carrier: &dyn Carrier, selector: &Selector) -> Result<String> {
+        Commands::resolve_among(selector, || Ok(carrier.list()))
+    }
+
+    // like `resolve`, with the places holding a payload listed by `slots`
+    fn resolve_among(selector: &Selector, slots: impl FnOnce() -> Result<Vec<carrier::Slot>>) -> Result<String> {
+        if selector.is_exact() {
+            return Ok(selector.pattern.clone());
+        }
+
+        let names: Vec<String> = slots()?.into_iter().map(|slot| slot.selector).collect();
+
+        Ok(selector.find_one(&names)?)
+    }
+
+    // Whether decoding goes through the chunks of the file one at a time, see
+    // `ChunkIndex`, rather than reading all of it: for PNG images large enough
+    // to be mapped, and for those that do not fit in `max_memory`. Any other
+    // file is read whole, so it has to fit.
+    fn decodes_indexed(input_path: &Path, max_memory: Option<MemoryLimit>) -> Result<bool> {
+        let (_, mut file) = Commands::open(input_path)?;
+        let length = file.metadata()?.len();
+        let mut signature = [0; 8];
+        let is_png = file.read_exact(&mut signature).is_ok() && signature == png::Png::STANDARD_HEADER;
+
+        let fits = match max_memory {
+            Some(limit) => limit.check(length).is_ok(),
+            None => true,
+        };
+        if is_png && (!fits || length >= contents::MAP_THRESHOLD) {
+            return Ok(true);
+        }
+
+        if !fits {
+            Commands::check_memory(input_path, max_memory)?;
+        }
+
+        Ok(false)
+    }
+
+    // reading the whole file has to fit in `max_memory`
+    fn check_memory(input_path: &Path, max_memory: Option<MemoryLimit>) -> Result<()> {
+        if let Some(limit) = max_memory {
+            let (_, file) = Commands::open(input_path)?;
+            limit
+                .check(file.metadata()?.len())
+                .context("The whole file has to be read, raise --max-memory")?;
+        }
+
+        Ok(())
+    }
+
+    // Reads the chunk at `position` of the index, warning about a CRC mismatch
+    // when `ignore_crc` lets it through.
+    fn read_indexed_chunk(
+        index: &mut ChunkIndex<BufReader<File>>,
+        position: usize,
+        ignore_crc: bool,
+    ) -> Result<chunk::Chunk> {
+        let entry = index.entries()[position];
+        let chunk = index
+            .chunk(position, if ignore_crc { chunk::CrcCheck::Ignore } else { chunk::CrcCheck::Verify })
+            .context(format!("Failed to read chunk {} of the given file.", position))?;
+
+        if chunk.crc != entry.crc {
+            Commands::warn_crc_mismatches(&[validate::Violation::InvalidCrc {
+                offset: entry.offset as usize,
+                chunk_type: entry.chunk_type.to_string(),
+                stored: entry.crc,
+                computed: chunk.crc,
+            }]);
+        }
+
+        Ok(chunk)
+    }
+
+    // The data of the `chunk_type` chunk, read on its own. It and the message
+    // opened from it have to fit in `max_memory`.
+    fn extract_indexed(
+        index: &mut ChunkIndex<BufReader<File>>,
+        chunk_type: &str,
+        ignore_crc: bool,
+        max_memory: Option<MemoryLimit>,
+    ) -> Result<Vec<u8>> {
+        let position = chunk_type::ChunkType::from_str(chunk_type)
+            .ok()
+            .and_then(|chunk_type| index.position(chunk_type))
+            .ok_or_else(|| carrier::CarrierError::NotFound(chunk_type.to_string()))
+            .context("Can not decode. Critical chunk not found!!")?;
+
+        if let Some(limit) = max_memory {
+            let entry = index.entries()[position];
+            limit
+                .check(2 * (entry.end() - entry.offset))
+                .context("The chunk holding the payload does not fit in --max-memory")?;
+        }
+
+        Ok(Commands::read_indexed_chunk(index, position, ignore_crc)?.data().to_vec())
+    }
+
+    // The index of the chunks of the file when decoding goes through it, see
+    // `decodes_indexed`. A file it can not be read from, such as a truncated
+    // one, is salvaged the usual way when the whole of it fits in memory.
+    fn payload_index(
+        input_path: &Path,
+        max_memory: Option<MemoryLimit>,
+    ) -> Result<Option<ChunkIndex<BufReader<File>>>> {
+        if !Commands::decodes_indexed(input_path, max_memory)? {
+            return Ok(None);
+        }
+
+        match Commands::read_index(input_path) {
+            Ok(index) => Ok(Some(index)),
+            Err(error) => match Commands::check_memory(input_path, max_memory) {
+                Ok(()) => Ok(None),
+                Err(_) => Err(error.context("The file can not be salvaged within --max-memory")),
+            },
+        }
+    }
+
+    // With `max_memory`, large PNG images are decoded holding no more than the
+    // chunk with the payload and the message in memory, and other files fail
+    // up front when they do not fit.
+    pub fn decode(
+        input_path: &Path,
+        selector: &Selector,
+        password: Option<String>,
+        ignore_crc: bool,
+        max_memory: Option<MemoryLimit>,
+    ) -> Result<String> {
+        let (chunk_type, data) = match Commands::payload_index(input_path, max_memory)? {
+            Some(mut index) => {
+                let chunk_type = Commands::resolve_among(selector, || Ok(index.slots()?))?;
+                let data = Commands::extract_indexed(&mut index, &chunk_type, ignore_crc, max_memory)?;
+
+                (chunk_type, data)
+            }
+            None => {
+                let carrier = Commands::salvage_carrier(input_path, ignore_crc)?;
+                let chunk_type = Commands::resolve(carrier.as_ref(), selector)?;
+                let data = carrier.extract(&chunk_type).context("Can not decode. Critical chunk not found!!")?;
+
+                (chunk_type, data)
             }
-            _ => panic!("Invalid operation"),
+        };
+        debug!(chunk_type = %chunk_type, length = data.len(), "Found the payload");
+
+        // standard text chunks are not sealed, they hold a keyword and the text
+        match chunk_type.as_str() {
+            "tEXt" => return Ok(Text::from_data(&data)?.to_string()),
+            "iTXt" => return Ok(InternationalText::from_data(&data)?.to_string()),
+            _ => {}
         }
+
+        let message = envelope::open(&data, password.as_deref())?;
+
+        Ok(String::from_utf8(message)?)
+    }
+
+    // the first file of the ZIP archive following the image, whose offsets count from the start of the file
+    pub fn decode_zip(input_path: &Path) -> Result<String> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+
+        let entry = zip::entries(&bytes)?
+            .into_iter()
+            .next()
+            .context("The ZIP archive after the image is empty.")?;
+
+        Ok(String::from_utf8(entry.data)?)
+    }
+
+    pub fn decode_text(input_path: &Path, keyword: String, ignore_crc: bool) -> Result<String> {
+        let png = Commands::salvage_png(input_path, ignore_crc)?;
+
+        let text = png
+            .texts()
+            .into_iter()
+            .find(|text| text.keyword == keyword)
+            .context(format!("No tEXt or iTXt chunk with the keyword {keyword} found."))?;
+
+        Ok(text.text)
+    }
+
+    // Tries every ancillary chunk holding a PNGCrypt payload, for when the token
+    // is lost. Returns the chunk type and message of each one that opens.
+    pub fn decode_auto(
+        input_path: &Path,
+        password: Option<String>,
+        ignore_crc: bool,
+        max_memory: Option<MemoryLimit>,
+    ) -> Result<Vec<(String, String)>> {
+        let open = |data: &[u8]| {
+            let message = envelope::open(data, password.as_deref()).ok()?;
+            String::from_utf8(message).ok()
+        };
+
+        let mut messages: Vec<(String, String)> = Vec::new();
+        match Commands::payload_index(input_path, max_memory)? {
+            // the payloads are read one at a time, each has to fit in memory
+            Some(mut index) => {
+                for slot in index.slots()? {
+                    let data = Commands::extract_indexed(&mut index, &slot.selector, ignore_crc, max_memory)?;
+                    if let Some(message) = open(&data) {
+                        messages.push((slot.selector, message));
+                    }
+                }
+            }
+            None => {
+                let carrier = Commands::salvage_carrier(input_path, ignore_crc)?;
+
+                messages.extend(carrier.list().into_iter().filter_map(|slot| {
+                    let message = open(&carrier.extract(&slot.selector).ok()?)?;
+                    Some((slot.selector, message))
+                }));
+            }
+        }
+
+        if messages.is_empty() {
+            match password {
+                Some(_) => bail!("No chunk holds a payload that opens with this password"),
+                None => bail!("No chunk holds an unencrypted payload. Encrypted payloads need --password"),
+            }
+        }
+
+        Ok(messages)
+    }
+
+    // Lists the private ancillary chunks, where chunk mode stores its payloads,
+    // and which of them hold something that looks like a message: a PNGCrypt
+    // envelope or UTF-8 text. With `show` the messages are printed too.
+    pub fn recover_token(input_path: &Path, show: bool, password: Option<String>) -> Result<()> {
+        let png = Commands::salvage_png(input_path, false)?;
+
+        let candidates: Vec<&chunk::Chunk> = png
+            .chunks()
+            .filter(|chunk| !chunk.chunk_type.is_critical() && !chunk.chunk_type.is_public())
+            .collect();
+
+        if candidates.is_empty() {
+            println!("No private ancillary chunks found.");
+            return Ok(());
+        }
+
+        for chunk in candidates {
+            let data = chunk.data();
+
+            let payload = if envelope::is_encrypted(data) {
+                Some("PNGCrypt payload, encrypted")
+            } else if envelope::is_envelope(data) {
+                Some("PNGCrypt payload")
+            } else if !data.is_empty() && std::str::from_utf8(data).is_ok() {
+                Some("UTF-8 text")
+            } else {
+                None
+            };
+
+            let line = format!(
+                "{}  {:>10} bytes  {}",
+                chunk.chunk_type.to_string().bold(),
+                data.len(),
+                payload.unwrap_or("no payload")
+            );
+
+            if payload.is_none() {
+                println!("{}", line.dimmed());
+                continue;
+            }
+
+            println!("{}", line.green());
+
+            if show {
+                match envelope::open(data, password.as_deref()) {
+                    Ok(message) => println!("    {}", String::from_utf8_lossy(&message)),
+                    Err(e) => println!("    {}", e.to_string().red()),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn decode_lsb(input_path: &Path, password: Option<String>, ignore_crc: bool) -> Result<String> {
+        let png = if ignore_crc {
+            Commands::read_png_ignoring_crc(input_path)?
+        } else {
+            Commands::read_png(input_path)?.1
+        };
+
+        let seed = password.as_deref().map(lsb::seed_from_password);
+
+        // an animation carries the payload across its frames
+        let frames = png.frames().context("Unable to decode the image data.")?;
+        let payload = match frames.as_slice() {
+            [still] => lsb::extract(still, seed.as_ref())?,
+            _ => lsb::extract_frames(&frames, seed.as_ref())?,
+        };
+
+        let message = envelope::open(&payload, password.as_deref())?;
+
+        Ok(String::from_utf8(message)?)
+    }
+
+    // asks on the terminal before something that can not be undone
+    fn confirm(question: &str) -> Result<bool> {
+        print!("{} Type yes to go on: ", question);
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+
+        Ok(answer.trim() == "yes")
+    }
+
+    // Without a critical chunk such as IHDR or IDAT the image does not open
+    // anymore, so removing one takes `allow_critical` and a confirmation.
+    // Tells whether the chunk type is critical.
+    fn check_critical(chunk_type: &str, allow_critical: bool) -> Result<bool> {
+        let critical = chunk_type::ChunkType::from_str(chunk_type).is_ok_and(|chunk_type| chunk_type.is_critical());
+        if critical {
+            if !allow_critical {
+                bail!("{} is a critical chunk, the image can not be shown without it. Pass --allow-critical to remove it anyway", chunk_type);
+            }
+            if !Commands::confirm(&format!("Removing {} leaves an image viewers can not show.", chunk_type))? {
+                bail!("Nothing was removed");
+            }
+        }
+
+        Ok(critical)
+    }
+
+    // Removes the chunks of a type `occurrence` picks from a PNG image,
+    // printing the message each one holds, or its size when it holds none.
+    pub fn remove_chunks(
+        input_path: &Path,
+        selector: &Selector,
+        occurrence: png::Occurrence,
+        password: Option<String>,
+        allow_critical: bool,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+        let chunk_type = Commands::resolve(&png, selector)?;
+        Commands::check_critical(&chunk_type, allow_critical)?;
+
+        let removed = png
+            .remove_chunks(&chunk_type, occurrence)
+            .context(format!("Can not remove message. No {} chunk found there.", chunk_type))?;
+
+        Commands::write_png(input_path, &path, &png, writing)?;
+
+        for (index, chunk) in removed {
+            let message = envelope::open(chunk.data(), password.as_deref())
+                .ok()
+                .and_then(|message| String::from_utf8(message).ok())
+                .unwrap_or_else(|| format!("{} bytes", chunk.data().len()));
+
+            println!("Removed {} chunk #{}: {}", chunk_type, index, message);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(
+        input_path: &Path,
+        selector: &Selector,
+        password: Option<String>,
+        allow_critical: bool,
+        writing: WriteOptions,
+    ) -> Result<String> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut carrier) = Commands::read_carrier(input_path)?;
+        let chunk_type = Commands::resolve(carrier.as_ref(), selector)?;
+        let critical = Commands::check_critical(&chunk_type, allow_critical)?;
+
+        let data = carrier.remove(&chunk_type).context("Can not remove message. Critical chunk not found!!")?;
+
+        // opened before the file is rewritten, so a wrong password leaves the payload in it
+        let removed = match critical {
+            true => format!("Removed the {} chunk of {} bytes", chunk_type, data.len()),
+            false => String::from_utf8(envelope::open(&data, password.as_deref())?)?,
+        };
+
+        Commands::write_carrier(input_path, &path, carrier.as_mut(), writing)?;
+
+        Ok(removed)
+    }
+
+    // Puts the latest backup of a file changed in place back, recording it in
+    // the journal, or lists the backups it can be restored from with `list`.
+    pub fn undo(input_path: &Path, list: bool, writing: WriteOptions, json: bool) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, _) = Commands::open(input_path)?;
+        let restore_points = journal::restore_points(&path)?;
+
+        if list {
+            if json {
+                let restore_points: Vec<serde_json::Value> = restore_points
+                    .iter()
+                    .rev()
+                    .map(|entry| {
+                        serde_json::json!({
+                            "backup": journal::backup_path(&path, entry.backup).display().to_string(),
+                            "created": entry.time,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::Value::Array(restore_points));
+
+                return Ok(());
+            }
+
+            if restore_points.is_empty() {
+                println!("{} has no backups to restore.", path.display());
+            }
+            for entry in restore_points.iter().rev() {
+                let backup_path = journal::backup_path(&path, entry.backup);
+                let length = std::fs::metadata(&backup_path)?.len();
+                println!("{}  {} bytes, made at {} (Unix time)", backup_path.display(), length, entry.time);
+            }
+
+            return Ok(());
+        }
+
+        let entry = restore_points
+            .last()
+            .context(format!("{} has no backups to restore.", path.display()))?;
+        let backup_path = journal::backup_path(&path, entry.backup);
+        let bytes = std::fs::read(&backup_path).context(format!("Unable to read {backup_path:?}."))?;
+
+        // undoing asks for the file to be replaced
+        Commands::write_file(&path, &bytes, WriteOptions { force: true, ..writing })?;
+        if writing.dry_run {
+            return Ok(());
+        }
+        std::fs::remove_file(&backup_path).context(format!("Unable to remove {backup_path:?}."))?;
+        journal::record(&path, &journal::Entry::new(journal::Action::Undo, entry.backup))?;
+
+        println!("Restored {} from {}", path.display(), backup_path.display());
+
+        Ok(())
+    }
+
+    pub fn capacity(
+        input_path: &Path,
+        message: Option<String>,
+        password: Option<String>,
+        padding: Option<envelope::Padding>,
+        mut options: lsb::LsbOptions,
+    ) -> Result<()> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+        let payload_size = message
+            .map(|message| envelope::sealed_size(message.len(), password.is_some(), padding))
+            .transpose()?;
+
+        // other formats only have room for payloads outside the image
+        if !bytes.starts_with(&png::Png::STANDARD_HEADER) {
+            let carrier = carrier::open(&bytes).context("Failed to read the given file.")?;
+            let capacity = carrier.capacity();
+
+            println!("Format: {}", carrier.format());
+            println!("Chunk mode: up to {} bytes", capacity);
+
+            if let Some(size) = payload_size {
+                println!("Payload: {} bytes ({:.2}% of chunk mode capacity)", size, size as f64 * 100.0 / capacity as f64);
+            }
+
+            if Riff::is_riff(&bytes, wav::FORM) {
+                let wav = wav::Wav::parse(&bytes)?;
+                let capacity = wav.lsb_capacity()?;
+
+                println!("LSB mode ({} samples): {} bytes", wav.sample_count()?, capacity);
+
+                if let Some(size) = payload_size {
+                    let usage = format!("Payload: {} bytes ({:.2}% of LSB mode capacity)", size, size as f64 * 100.0 / capacity as f64);
+
+                    if size > capacity {
+                        println!("{}", format!("{}. It does not fit in the samples.", usage).red());
+                    } else {
+                        println!("{}", usage);
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+
+        let (_, png) = Commands::read_png(input_path)?;
+        let header = png.ihdr()?;
+
+        // adaptive capacity depends on where the seeded order puts the header
+        if options.adaptive {
+            options.seed = password.as_deref().map(lsb::seed_from_password);
+        }
+
+        println!(
+            "Image: {}x{} {}, {} bit",
+            header.width,
+            header.height,
+            header.color_type_name(),
+            header.bit_depth
+        );
+
+        let chunk_capacity = png.capacity();
+        println!("Chunk mode: up to {} bytes", chunk_capacity);
+
+        if let (Some(size), None) = (payload_size, padding) {
+            warn!(
+                "Chunk mode grows the file by {} bytes, which gives away the message length. Consider --pad-to.",
+                size + 12
+            );
+        }
+
+        let lsb_capacity = if options.adaptive || png.is_animated() {
+            png.frames().and_then(|frames| lsb::frames_capacity(&frames, &options))
+        } else {
+            lsb::capacity(&header, &options)
+        };
+
+        match &lsb_capacity {
+            Ok(capacity) => println!(
+                "LSB mode ({} channels, {} bits per channel{}): {} bytes",
+                options.channels,
+                options.bits_per_channel,
+                if options.adaptive { ", adaptive" } else { "" },
+                capacity
+            ),
+            Err(e) => println!("LSB mode: not supported. {}", e),
+        }
+
+        if let Some(size) = payload_size {
+            println!(
+                "Payload: {} bytes ({:.2}% of chunk mode capacity)",
+                size,
+                size as f64 * 100.0 / chunk_capacity as f64
+            );
+
+            if let Ok(capacity) = lsb_capacity {
+                let usage = format!("Payload: {} bytes ({:.2}% of LSB mode capacity)", size, size as f64 * 100.0 / capacity as f64);
+
+                if size > capacity {
+                    println!("{}", format!("{}. It does not fit in the pixel data.", usage).red());
+                } else {
+                    println!("{}", usage);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The image properties stored in IHDR, along with the file size.
+    pub fn info(input_path: &Path, json: bool) -> Result<()> {
+        let mut index = Commands::read_index(input_path)?;
+        let header = index.ihdr()?;
+        let pixels = header.width as u64 * header.height as u64;
+
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "width": header.width,
+                    "height": header.height,
+                    "bit_depth": header.bit_depth,
+                    "color_type": header.color_type,
+                    "color_type_name": header.color_type_name(),
+                    "interlace": header.interlace,
+                    "interlace_name": header.interlace_name(),
+                    "pixels": pixels,
+                    "file_size": index.file_length(),
+                })
+            );
+
+            return Ok(());
+        }
+
+        println!("Dimensions: {}x{}", header.width, header.height);
+        println!("Bit depth: {}", header.bit_depth);
+        println!("Color type: {} ({})", header.color_type, header.color_type_name());
+        println!("Interlace: {} ({})", header.interlace, header.interlace_name());
+        println!("Pixels: {}", pixels);
+        println!("File size: {} bytes", index.file_length());
+
+        Ok(())
+    }
+
+    // Writes the man pages of pngcrypt to `out_dir`, with the defaults built
+    // into it rather than those of the configuration file.
+    pub fn mangen(out_dir: &Path, writing: WriteOptions) -> Result<()> {
+        let mut command = args::Args::command();
+        command.build();
+
+        std::fs::create_dir_all(out_dir).context(format!("Unable to create {out_dir:?}."))?;
+        for (name, page) in man::pages(&command) {
+            let path = out_dir.join(name);
+            Commands::write_file(&path, page.as_bytes(), writing)?;
+            println!("Wrote {}", path.display());
+        }
+
+        Ok(())
+    }
+
+    // Browses the chunks of the image in the terminal, see `tui`. Chunks are
+    // extracted next to the image, and the image is written, without the
+    // chunks removed, to `output_file_path` or in place.
+    pub fn browse(
+        input_path: &Path,
+        password: Option<String>,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        if !wizard::is_interactive() {
+            bail!("The chunk browser needs a terminal");
+        }
+        if writing.dry_run {
+            bail!("The chunk browser can not be run with --dry-run, use print to list the chunks");
+        }
+
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+        let output_path = Commands::edited_path(input_path, path.clone(), output_file_path);
+
+        let mut extract = |index: usize, chunk: &chunk::Chunk| {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = format!("{}.{}.{}.bin", stem, index, chunk.chunk_type);
+            let extracted_path = Commands::output_path(input_path, PathBuf::from(name));
+            Commands::write_secret_file(&extracted_path, chunk.data(), writing)?;
+
+            Ok(format!("Wrote the {} bytes of the chunk to {}", chunk.data().len(), extracted_path.display()))
+        };
+        let mut save = |png: &png::Png| {
+            Commands::write_png(input_path, &output_path, png, writing)?;
+
+            Ok(format!("Written to {}", output_path.display()))
+        };
+
+        tui::browse(
+            &mut png,
+            password,
+            tui::Actions {
+                extract: &mut extract,
+                save: &mut save,
+            },
+        )
+    }
+
+    pub fn type_info(chunk_type: &str, json: bool) -> Result<()> {
+        let chunk_type = chunk_type::ChunkType::from_str(chunk_type)?;
+
+        if json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "chunk_type": chunk_type,
+                    "critical": chunk_type.is_critical(),
+                    "public": chunk_type.is_public(),
+                    "reserved_bit_valid": chunk_type.is_reserved_bit_valid(),
+                    "safe_to_copy": chunk_type.is_safe_to_copy(),
+                    "valid": chunk_type.is_valid(),
+                })
+            );
+
+            return Ok(());
+        }
+
+        let letters: Vec<char> = chunk_type.to_string().chars().collect();
+        let bit = |uppercase: bool, yes: &str, no: &str| {
+            if uppercase {
+                format!("uppercase, {}", yes)
+            } else {
+                format!("lowercase, {}", no)
+            }
+        };
+
+        println!("Chunk type: {}", chunk_type);
+        println!(
+            "{}: {}",
+            letters[0],
+            bit(
+                chunk_type.is_critical(),
+                "critical, decoders that do not know it can not display the image",
+                "ancillary, decoders that do not know it skip it"
+            )
+        );
+        println!(
+            "{}: {}",
+            letters[1],
+            bit(
+                chunk_type.is_public(),
+                "public, defined by the specification or registered",
+                "private, free for applications to use"
+            )
+        );
+        println!(
+            "{}: {}",
+            letters[2],
+            bit(
+                chunk_type.is_reserved_bit_valid(),
+                "reserved bit unset, as it has to be",
+                "reserved bit set, which no valid chunk type has"
+            )
+        );
+        println!(
+            "{}: {}",
+            letters[3],
+            bit(
+                !chunk_type.is_safe_to_copy(),
+                "unsafe to copy, editors that change the image have to drop it",
+                "safe to copy, editors may keep it whatever they change"
+            )
+        );
+
+        if !chunk_type.is_valid() {
+            println!("{}", "Not a valid chunk type: the third letter has to be uppercase".yellow());
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(input_path: &Path, json: bool) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+        let stats = stats::stats(&png);
+
+        if json {
+            println!("{}", stats.to_json());
+            return Ok(());
+        }
+
+        println!("{:<4}  {:>5}  {:>10}", "Type", "Count", "Bytes");
+        for types in stats.types.iter() {
+            println!("{:<4}  {:>5}  {:>10}", types.chunk_type, types.count, types.bytes);
+        }
+
+        println!();
+        println!("File size: {} bytes", stats.file_size);
+        println!("Critical chunks: {} bytes", stats.critical_bytes);
+        println!("Ancillary chunks: {} bytes", stats.ancillary_bytes);
+        println!("Image data: {} bytes", stats.idat_bytes);
+
+        let payloads = format!(
+            "PNGCrypt payloads: {} chunks, {} bytes ({:.2}% of the file)",
+            stats.payload_chunks,
+            stats.payload_bytes,
+            stats.payload_share()
+        );
+        println!("{}", if stats.payload_chunks > 0 { payloads.yellow() } else { payloads.normal() });
+
+        if stats.trailing_bytes > 0 {
+            println!("{}", format!("Data after IEND: {} bytes", stats.trailing_bytes).red());
+        }
+
+        println!();
+        println!("Largest chunks:");
+        for chunk in stats.largest.iter() {
+            println!("  {} at offset {}: {} bytes", chunk.chunk_type, chunk.offset, chunk.length);
+        }
+
+        Ok(())
+    }
+
+    // Prints the data of the first chunk of `chunk_type`, raw or as a hex
+    // view, limited to the first `head` bytes when given.
+    pub fn dump_chunk(input_path: &Path, chunk_type: String, hex: bool, head: Option<usize>) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let chunk = png
+            .chunk_by_type(&chunk_type)
+            .context(format!("No {} chunk found.", chunk_type))?;
+        let data = &chunk.data()[..head.unwrap_or(usize::MAX).min(chunk.data().len())];
+
+        if hex {
+            println!("{}", inspect::hexdump(data));
+        } else {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(data)?;
+            stdout.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn extract_chunk(
+        input_path: &Path,
+        chunk_type: String,
+        output_file_path: PathBuf,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let chunk = png
+            .chunk_by_type(&chunk_type)
+            .context(format!("No {} chunk found.", chunk_type))?;
+
+        Commands::write_secret_file(&output_file_path, chunk.data(), writing)?;
+
+        println!(
+            "Wrote the {} bytes of the {} chunk to {}",
+            chunk.data().len(),
+            chunk_type,
+            output_file_path.display()
+        );
+
+        Ok(())
+    }
+
+    // Adds a chunk of `chunk_type` holding the contents of `data_path`, its
+    // length and CRC computed from them.
+    pub fn inject_chunk(
+        input_path: &Path,
+        chunk_type: String,
+        data_path: &Path,
+        position: png::Position,
+        output_file_path: Option<PathBuf>,
+        forced: bool,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        let data = std::fs::read(data_path).context(format!("Unable to read {data_path:?}."))?;
+        let chunk_type = chunk_type::ChunkType::from_str(&chunk_type)?;
+        if !chunk_type.is_valid() {
+            bail!("{} is not a valid chunk type, its third letter has to be uppercase", chunk_type);
+        }
+        if !forced {
+            chunk_type
+                .check_payload_type()
+                .context("Pass --i-know-what-im-doing to add the chunk anyway.")?;
+        }
+        let chunk = chunk::Chunk::new(chunk_type, data);
+
+        println!(
+            "Added a {} chunk of {} bytes, CRC {:#010x}",
+            chunk.chunk_type,
+            chunk.length,
+            chunk.crc
+        );
+
+        png.insert_chunk_at(chunk, position);
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        println!("Written to {}", output_path.display());
+
+        Ok(())
+    }
+
+    // where an edited image goes: `output_file_path` next to the input, or the input itself
+    fn edited_path(input_path: &Path, path: PathBuf, output_file_path: Option<PathBuf>) -> PathBuf {
+        match output_file_path {
+            Some(output_file_path) => Commands::output_path(input_path, output_file_path),
+            None => path,
+        }
+    }
+
+    pub fn meta_list(input_path: &Path, json: bool) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let texts = png.texts();
+
+        if json {
+            let texts: Vec<serde_json::Value> = texts.iter().map(Text::to_json).collect();
+            println!("{}", serde_json::Value::Array(texts));
+
+            return Ok(());
+        }
+
+        if texts.is_empty() {
+            println!("The image has no textual metadata.");
+        }
+
+        for text in texts {
+            println!("{}: {}", text.keyword.bold(), text.text);
+        }
+
+        Ok(())
+    }
+
+    pub fn meta_get(input_path: &Path, keyword: String) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let texts: Vec<Text> = png.texts().into_iter().filter(|text| text.keyword == keyword).collect();
+        if texts.is_empty() {
+            bail!("The image has no text under the keyword {}", keyword);
+        }
+
+        for text in texts {
+            println!("{}", text.text);
+        }
+
+        Ok(())
+    }
+
+    pub fn meta_set(
+        input_path: &Path,
+        keyword: String,
+        value: String,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        // keywords are case sensitive, a typo makes a keyword no tool shows
+        if !text::STANDARD_KEYWORDS.contains(&keyword.as_str()) {
+            warn!("{} is not a standard keyword ({}).", keyword, text::STANDARD_KEYWORDS.join(", "));
+        }
+
+        png.set_text(&keyword, &value)?;
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        println!("Set {}. Written to {}", keyword.bold(), output_path.display());
+
+        Ok(())
+    }
+
+    pub fn meta_remove(
+        input_path: &Path,
+        keyword: String,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        if png.remove_texts(&keyword).is_empty() {
+            bail!("The image has no text under the keyword {}", keyword);
+        }
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        println!("Removed {}. Written to {}", keyword.bold(), output_path.display());
+
+        Ok(())
+    }
+
+    pub fn exif_dump(
+        input_path: &Path,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+        json: bool,
+    ) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let data = png.exif().context("The image has no eXIf chunk.")?;
+
+        if let Some(output_file_path) = output_file_path {
+            Commands::write_file(&output_file_path, data, writing)?;
+            println!("Wrote {} bytes of EXIF data to {}", data.len(), output_file_path.display());
+
+            return Ok(());
+        }
+
+        let entries = exif::entries(data)?;
+
+        if json {
+            let entries: Vec<serde_json::Value> = entries.iter().map(exif::Entry::to_json).collect();
+            println!("{}", serde_json::Value::Array(entries));
+
+            return Ok(());
+        }
+
+        for entry in entries {
+            // the location the picture was taken at is what usually has to go
+            if entry.ifd == exif::Ifd::Gps {
+                println!("{}", entry.to_string().yellow());
+            } else {
+                println!("{}", entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn exif_strip(input_path: &Path, output_file_path: Option<PathBuf>, writing: WriteOptions) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        let removed = png.strip_exif();
+        if removed.is_empty() {
+            println!("The image has no eXIf chunk.");
+            return Ok(());
+        }
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        let bytes: usize = removed.iter().map(|chunk| chunk.data().len()).sum();
+        println!("Removed {} bytes of EXIF data. Written to {}", bytes, output_path.display());
+
+        Ok(())
+    }
+
+    pub fn exif_set(
+        input_path: &Path,
+        exif_path: &Path,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+    ) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        let data = std::fs::read(exif_path).context(format!("Unable to read {exif_path:?}."))?;
+        let length = data.len();
+        png.set_exif(data)?;
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        println!("Set {} bytes of EXIF data. Written to {}", length, output_path.display());
+
+        Ok(())
+    }
+
+    // list the chunks of the image, with the entropy of their data, and any data after IEND
+    pub fn print(input_path: &Path, selector: Option<Selector>, json: bool, ignore_crc: bool) -> Result<()> {
+        let mut indexed = Commands::read_index(input_path)?;
+        let trailing = match indexed.trailing_length() {
+            0 => Vec::new(),
+            _ => indexed.trailing()?,
+        };
+        let trailing_offset = indexed.trailing_offset();
+        // chunks past it were appended by earlier versions and are flagged as such
+        let iend = indexed.entries().iter().position(|entry| entry.chunk_type == chunk_type::ChunkType::IEND);
+        let after_iend = |index: usize| iend.is_some_and(|iend| index > iend);
+
+        // The chunks the selector matches, with their index among all chunks.
+        // Only their data is read, one chunk at a time.
+        let selected: Vec<usize> = indexed
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| selector.as_ref().is_none_or(|selector| selector.matches(&entry.chunk_type.to_string())))
+            .map(|(position, _)| position)
+            .collect();
+        let selected = selected.into_iter().map(|position| {
+            let chunk = Commands::read_indexed_chunk(&mut indexed, position, ignore_crc)?;
+
+            Ok::<_, anyhow::Error>((position, chunk))
+        });
+
+        if json {
+            let chunks = selected
+                .map(|result| {
+                    let (index, chunk) = result?;
+
+                    Ok(serde_json::json!({
+                        "index": index,
+                        "chunk_type": chunk.chunk_type,
+                        "length": chunk.length,
+                        "crc": chunk.crc,
+                        "entropy": inspect::entropy(chunk.data()),
+                        "content": inspect::classify(chunk.data()).to_string(),
+                        "description": chunk.chunk_type.description(),
+                        "text": Commands::text_of(&chunk).map(|(_, json)| json),
+                        "after_iend": after_iend(index),
+                    }))
+                })
+                .collect::<Result<Vec<serde_json::Value>>>()?;
+
+            let trailing = (!trailing.is_empty()).then(|| {
+                serde_json::json!({
+                    "offset": trailing_offset,
+                    "length": trailing.len(),
+                    "entropy": inspect::entropy(&trailing),
+                })
+            });
+
+            println!("{}", serde_json::json!({ "chunks": chunks, "trailing": trailing }));
+
+            return Ok(());
+        }
+
+        println!(
+            "{:>4}  {:<4}  {:>10}  {:<10}  {:>7}  {:<12}  Description",
+            "#", "Type", "Length", "CRC", "Entropy", "Content"
+        );
+
+        for result in selected {
+            let (index, chunk) = result?;
+            let content = inspect::classify(chunk.data());
+            let line = format!(
+                "{:>4}  {:<4}  {:>10}  {:<10}  {:>7.3}  {:<12}  {}",
+                index,
+                chunk.chunk_type.to_string(),
+                chunk.length,
+                format!("{:#010x}", chunk.crc),
+                inspect::entropy(chunk.data()),
+                content.to_string(),
+                chunk.chunk_type.description().unwrap_or("")
+            );
+
+            // image data is compressed, random looking data elsewhere is worth a look
+            if after_iend(index) {
+                println!("{}", format!("{} (after IEND)", line).red());
+            } else if content == inspect::Content::HighEntropy && !chunk.chunk_type.is_critical() {
+                println!("{}", line.red());
+            } else {
+                println!("{}", line);
+            }
+
+            if let Some((text, _)) = Commands::text_of(&chunk) {
+                println!("      {}", text);
+            }
+        }
+
+        if !trailing.is_empty() {
+            println!(
+                "{}",
+                format!(
+                    "{} bytes after IEND at offset {}, entropy {:.3}",
+                    trailing.len(),
+                    trailing_offset,
+                    inspect::entropy(&trailing)
+                )
+                .red()
+            );
+        }
+
+        Ok(())
+    }
+
+    // the text of a tEXt or iTXt chunk, to show and as JSON
+    fn text_of(chunk: &chunk::Chunk) -> Option<(String, serde_json::Value)> {
+        match &chunk.chunk_type.bytes {
+            b"tEXt" => Text::from_chunk(chunk).ok().map(|text| (text.to_string(), text.to_json())),
+            b"iTXt" => InternationalText::from_chunk(chunk).ok().map(|text| (text.to_string(), text.to_json())),
+            _ => None,
+        }
+    }
+
+    // write the data after IEND to `extract_to`, and drop it from the image when `strip` is set
+    pub fn trailing(input_path: &Path, extract_to: Option<PathBuf>, strip: bool, writing: WriteOptions) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        if png.trailing().is_empty() {
+            println!("There is no data after IEND.");
+            return Ok(());
+        }
+
+        if let Some(extract_to) = extract_to {
+            Commands::write_secret_file(&extract_to, png.trailing(), writing)?;
+            println!("Wrote {} bytes found after IEND to {}", png.trailing().len(), extract_to.display());
+        }
+
+        if strip {
+            let stripped = png.strip_trailing();
+            Commands::write_png(input_path, &path, &png, WriteOptions { drop_trailing: false, ..writing })?;
+            println!("Removed {} bytes found after IEND", stripped.len());
+        }
+
+        Ok(())
+    }
+
+    pub fn scan(root: &Path, recursive: bool, options: png::ParseOptions, json: bool) -> Result<()> {
+        let files = scan::find_pngs(root, recursive).context(format!("Unable to read {root:?}."))?;
+
+        // the files done, and the bytes read of the current one under it
+        let bars = progress::stack();
+        let bar = bars.add(progress::files(files.len() as u64));
+        let reports: Vec<scan::FileReport> = files
+            .iter()
+            .map(|file| {
+                bar.set_message(file.display().to_string());
+                let size = std::fs::metadata(file).map(|metadata| metadata.len()).unwrap_or_default();
+                let reading = bars.add(progress::bytes(size, "Reading".to_string()));
+                let report = scan::scan_file_with(file, options, |read| reading.inc(read as u64));
+                reading.finish_and_clear();
+                bars.remove(&reading);
+                bar.inc(1);
+
+                report
+            })
+            .collect();
+        bar.finish_and_clear();
+
+        if json {
+            let reports: Vec<serde_json::Value> = reports.iter().map(scan::FileReport::to_json).collect();
+            println!("{}", serde_json::Value::Array(reports));
+
+            return Ok(());
+        }
+
+        let flagged: Vec<&scan::FileReport> = reports.iter().filter(|report| report.is_flagged()).collect();
+
+        for report in flagged.iter() {
+            println!("{} ({} bytes)", report.path.display().to_string().white().bold(), report.size);
+
+            if let Some(error) = &report.error {
+                println!("  {}", error.red());
+            }
+            for finding in report.findings.iter() {
+                println!("  {}", finding.to_string().yellow());
+            }
+        }
+
+        println!("Scanned {} PNG files, {} flagged.", reports.len(), flagged.len());
+
+        Ok(())
+    }
+
+    pub fn analyze(input_path: &Path, json: bool) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let mut pixels = png.pixels().context("Unable to decode the image data.")?;
+        if pixels.header.is_indexed() {
+            pixels = pixels.unpack()?;
+        }
+
+        let analysis = steganalysis::analyze(&pixels);
+
+        if json {
+            let report = serde_json::json!({
+                "chi_square": analysis.chi_square,
+                "rs": analysis.rs,
+                "suspicious": analysis.is_suspicious(),
+            });
+            println!("{}", report);
+
+            return Ok(());
+        }
+
+        let chi_square = format!(
+            "Chi-square: {:.2}% probability that the lowest bits were replaced",
+            analysis.chi_square * 100.0
+        );
+
+        if analysis.chi_square > steganalysis::CHI_SQUARE_THRESHOLD {
+            println!("{}", chi_square.yellow());
+        } else {
+            println!("{}", chi_square);
+        }
+        println!("RS analysis: an estimated {:.2}% of the samples carry a payload", analysis.rs * 100.0);
+
+        if analysis.is_suspicious() {
+            println!("{}", "LSB embedding is likely present.".red());
+        } else {
+            println!("{}", "No sign of LSB embedding.".green());
+        }
+
+        Ok(())
+    }
+
+    // Writes the report on the image to `output`, as HTML when it ends in .html
+    // and as JSON otherwise. A directory gets a JSON report named after the image.
+    pub fn report(input_path: &Path, output: &Path, examiner: Option<String>, writing: WriteOptions) -> Result<String> {
+        let bytes = contents::read_path(input_path).context(format!("Unable to read {input_path:?}."))?;
+
+        let name = input_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let report = report::Report::new(&name, &bytes, examiner);
+
+        let output = if output.is_dir() {
+            output.join(format!("{}.report.json", name))
+        } else {
+            output.to_path_buf()
+        };
+
+        let html = output
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"))
+            .unwrap_or(false);
+
+        let contents = if html {
+            report.to_html()
+        } else {
+            serde_json::to_string_pretty(&report.to_json())?
+        };
+
+        Commands::write_file(&output, contents.as_bytes(), writing)?;
+
+        Ok(output.display().to_string())
+    }
+
+    pub fn sanitize(
+        input_path: &Path,
+        output_file_path: PathBuf,
+        writing: WriteOptions,
+    ) -> Result<(PathBuf, sanitize::Summary)> {
+        let (_, mut png) = Commands::read_png(input_path)?;
+
+        let summary = sanitize::sanitize(&mut png).context("Unable to re-encode the image data.")?;
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, WriteOptions { drop_trailing: false, ..writing })?;
+
+        Ok((output_path, summary))
+    }
+
+    // Removes the ancillary chunks selected by class or type. Critical chunks are
+    // needed to display the image and are never removed.
+    pub fn strip(
+        input_path: &Path,
+        all_ancillary: bool,
+        chunk_types: Vec<String>,
+        unsafe_to_copy: bool,
+        output_file_path: Option<PathBuf>,
+        writing: WriteOptions,
+        json: bool,
+    ) -> Result<()> {
+        if !all_ancillary && chunk_types.is_empty() && !unsafe_to_copy {
+            bail!("Nothing to strip, pass --all-ancillary, --type or --unsafe-to-copy");
+        }
+
+        let chunk_types = chunk_types
+            .iter()
+            .map(|chunk_type| chunk_type::ChunkType::from_str(chunk_type))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(critical) = chunk_types.iter().find(|chunk_type| chunk_type.is_critical()) {
+            bail!("{} is a critical chunk, the image can not be displayed without it", critical);
+        }
+
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        let removed = png.retain_chunks(|chunk| {
+            let chunk_type = &chunk.chunk_type;
+            let selected = all_ancillary || chunk_types.contains(chunk_type) || (unsafe_to_copy && !chunk_type.is_safe_to_copy());
+
+            chunk_type.is_critical() || !selected
+        });
+
+        let saved: usize = removed.iter().map(|chunk| 12 + chunk.data().len()).sum();
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        if !removed.is_empty() {
+            Commands::write_png(input_path, &output_path, &png, writing)?;
+        }
+
+        if json {
+            let removed: Vec<serde_json::Value> = removed
+                .iter()
+                .map(|chunk| serde_json::json!({ "chunk_type": chunk.chunk_type, "length": chunk.length }))
+                .collect();
+            let output = output_path.display().to_string();
+            println!("{}", serde_json::json!({ "output": output, "removed": removed, "bytes_saved": saved }));
+
+            return Ok(());
+        }
+
+        if removed.is_empty() {
+            println!("No chunk matched, the image was left as it is.");
+            return Ok(());
+        }
+
+        for chunk in removed.iter() {
+            println!("Removed {} ({} bytes)", chunk.chunk_type.to_string().bold(), chunk.length);
+        }
+        println!("Saved {} bytes. Written to {}", saved, output_path.display());
+
+        Ok(())
+    }
+
+    pub fn normalize(input_path: &Path, output_file_path: Option<PathBuf>, writing: WriteOptions) -> Result<()> {
+        let _lock = Commands::lock(input_path)?;
+        let (path, mut png) = Commands::read_png(input_path)?;
+
+        let moved = order::normalize(&mut png)?;
+        if moved == 0 {
+            println!("The chunks are already in order.");
+            return Ok(());
+        }
+
+        let output_path = Commands::edited_path(input_path, path, output_file_path);
+        Commands::write_png(input_path, &output_path, &png, writing)?;
+
+        println!("Moved {} chunks. Written to {}", moved, output_path.display());
+
+        Ok(())
+    }
+
+    pub fn diff(original_path: &Path, suspect_path: &Path, json: bool) -> Result<()> {
+        let (_, original) = Commands::read_png(original_path)?;
+        let (_, suspect) = Commands::read_png(suspect_path)?;
+
+        let diff = diff::diff(&original, &suspect);
+
+        if json {
+            println!("{}", diff.to_json());
+            return Ok(());
+        }
+
+        for change in diff.changes.iter() {
+            let line = change.to_string();
+
+            match change {
+                diff::Change::Added(_) => println!("{}", line.green()),
+                diff::Change::Removed(_) => println!("{}", line.red()),
+                diff::Change::Modified { .. } => println!("{}", line.yellow()),
+            }
+        }
+        println!("{} chunks unchanged", diff.unchanged);
+
+        println!("IDAT: {} -> {} bytes compressed", diff.idat_before, diff.idat_after);
+
+        match diff.pixels {
+            Some(pixels) => println!(
+                "Pixels: {} of {} samples changed, {} only in the lowest bit, largest change {}",
+                pixels.changed, pixels.samples, pixels.low_bit_only, pixels.max_difference
+            ),
+            None => println!("Pixels: not comparable, the image data can not be decoded or the sizes differ"),
+        }
+
+        Ok(())
+    }
+
+    // Checks the structure and every CRC of the file, listing each problem.
+    // Returns whether the file is valid.
+    pub fn verify(input_path: &Path, json: bool) -> Result<bool> {
+        let bytes = contents::read_path(input_path).context(format!("Unable to read {input_path:?}."))?;
+        let violations = validate::validate_bytes(&bytes);
+
+        if json {
+            let violations: Vec<String> = violations.iter().map(validate::Violation::to_string).collect();
+            println!("{}", serde_json::json!({ "valid": violations.is_empty(), "violations": violations }));
+        } else if violations.is_empty() {
+            println!("{}", "The file is a valid PNG.".green());
+        } else {
+            for violation in violations.iter() {
+                println!("{}", violation.to_string().red());
+            }
+        }
+
+        Ok(violations.is_empty())
+    }
+
+    // Lists the lint issues of the file, leaving out the rules in `allow`.
+    // Returns whether none are left.
+    pub fn lint(input_path: &Path, allow: &[lint::Rule], json: bool) -> Result<bool> {
+        let bytes = contents::read_path(input_path).context(format!("Unable to read {input_path:?}."))?;
+
+        let (allowed, issues): (Vec<lint::Issue>, Vec<lint::Issue>) = lint::lint_bytes(&bytes)
+            .into_iter()
+            .partition(|issue| allow.contains(&issue.rule));
+
+        if json {
+            let issues: Vec<serde_json::Value> = issues.iter().map(lint::Issue::to_json).collect();
+            println!("{}", serde_json::json!({ "issues": issues, "allowed": allowed.len() }));
+        } else {
+            for issue in issues.iter() {
+                println!("{}", issue.to_string().red());
+            }
+
+            let summary = format!("{} issues, {} allowed", issues.len(), allowed.len());
+            println!("{}", if issues.is_empty() { summary.green() } else { summary.normal() });
+        }
+
+        Ok(issues.is_empty())
+    }
+
+    pub fn repair(input_path: &Path, output_file_path: PathBuf, writing: WriteOptions, json: bool) -> Result<()> {
+        let bytes = contents::read_path(input_path).context(format!("Unable to read {input_path:?}."))?;
+        let mut repaired = repair::repair(&bytes).context("Unable to repair the file.")?;
+
+        // what could not be read is left out rather than written after the repaired chunks
+        if repaired.unrecoverable.is_some() {
+            repaired.png.strip_trailing();
+        }
+
+        let output_path = Commands::output_path(input_path, output_file_path);
+        Commands::write_png(input_path, &output_path, &repaired.png, writing)?;
+
+        if json {
+            let report = serde_json::json!({
+                "output": output_path.display().to_string(),
+                "repairs": repaired.repairs.iter().map(repair::Repair::to_string).collect::<Vec<_>>(),
+                "unrecoverable": repaired.unrecoverable,
+            });
+            println!("{}", report);
+
+            return Ok(());
+        }
+
+        for change in repaired.repairs.iter() {
+            println!("{}", change.to_string().yellow());
+        }
+        if let Some(offset) = repaired.unrecoverable {
+            println!(
+                "{}",
+                format!("The chunks from offset {} on could not be recovered and were left out.", offset).red()
+            );
+        }
+
+        println!("{} repairs, written to {}", repaired.repairs.len(), output_path.display());
+
+        Ok(())
+    }
+
+    // Lists the PNG images found in `input_path`, writing each one to
+    // `output_dir` when it is given and scanning them when `scan` is set.
+    pub fn carve(input_path: &Path, output_dir: Option<PathBuf>, scan: bool, writing: WriteOptions, json: bool) -> Result<()> {
+        let (_, bytes) = Commands::read_bytes(input_path)?;
+        let carved = carve::carve(&bytes);
+
+        if let Some(output_dir) = &output_dir {
+            std::fs::create_dir_all(output_dir).context(format!("Unable to create {output_dir:?}."))?;
+        }
+
+        let mut results = Vec::new();
+
+        for image in carved.iter() {
+            let written = match &output_dir {
+                Some(output_dir) => {
+                    let path = Path::new(output_dir).join(format!("carved-{:08x}.png", image.offset));
+                    Commands::write_png(input_path, &path, &image.png, writing)?;
+                    Some(path.display().to_string())
+                }
+                None => None,
+            };
+
+            let findings = if scan { scan::scan_png(&image.png) } else { Vec::new() };
+
+            if !json {
+                let line = format!(
+                    "offset {:#x}: {} bytes{}",
+                    image.offset,
+                    image.length,
+                    if image.complete { "" } else { ", cut off or damaged" }
+                );
+                println!("{}", if image.complete { line.normal() } else { line.yellow() });
+
+                if let Some(written) = &written {
+                    println!("  written to {}", written);
+                }
+                for finding in findings.iter() {
+                    println!("  {}", finding.to_string().yellow());
+                }
+            }
+
+            results.push(serde_json::json!({
+                "offset": image.offset,
+                "length": image.length,
+                "complete": image.complete,
+                "output": written,
+                "findings": findings.iter().map(scan::Finding::to_json).collect::<Vec<_>>(),
+            }));
+        }
+
+        if json {
+            println!("{}", serde_json::Value::Array(results));
+        } else {
+            println!("Found {} PNG images.", carved.len());
+        }
+
+        Ok(())
+    }
+
+    pub fn survivability(input_path: &Path, json: bool) -> Result<()> {
+        let (_, png) = Commands::read_png(input_path)?;
+
+        let payloads = survivability::payload_chunks(&png);
+
+        if json {
+            let payloads: Vec<serde_json::Value> = payloads.iter().map(survivability::PayloadChunk::to_json).collect();
+            println!("{}", serde_json::Value::Array(payloads));
+
+            return Ok(());
+        }
+
+        if payloads.is_empty() {
+            println!("No chunk holds a PNGCrypt payload.");
+        }
+
+        for payload in payloads.iter() {
+            if payload.safe_to_copy {
+                println!("{}", payload.to_string().green());
+            } else {
+                println!("{}", format!("Warning: {}", payload).yellow());
+            }
+        }
+
+        if payloads.iter().any(|payload| !payload.safe_to_copy) {
+            println!("Encode the message again to move it to a chunk that is safe to copy.");
+        }
+
+        Ok(())
+    }
+
+    pub fn from_args(args: args::Args) -> Result<()> {
+        let json = args.json;
+        color::init(args.color);
+        logging::init(args.verbose, args.quiet);
+        progress::init(json || args.quiet);
+        let writing = WriteOptions {
+            drop_trailing: args.drop_trailing,
+            force: args.force,
+            preserve_times: args.preserve_times,
+            dry_run: args.dry_run,
+        };
+
+        let command = match args.command {
+            Some(command) => command,
+            None => wizard::run()?,
+        };
+
+        match command {
+            Command::Encode {
+                input,
+                message,
+                output_file,
+                output_dir,
+                suffix,
+                in_place,
+                no_backup,
+                secret,
+                derive_token,
+                label,
+                prefix,
+                embedding,
+                position,
+                min_psnr,
+            } => {
+                let (path, message_argument) = input.resolve()?;
+                if message.is_some() && message_argument.is_some() {
+                    bail!("The message is given both with --message and positionally");
+                }
+                let path = path.as_path();
+                let message = match message.or(message_argument) {
+                    Some(message) => message,
+                    None => wizard::message()?,
+                };
+                let mut writing = writing;
+                let _lock = if in_place { Some(Commands::lock(path)?) } else { None };
+                let mut backup = None;
+
+                let output_file = match (output_file, output_dir) {
+                    (output_file, Some(output_dir)) => {
+                        std::fs::create_dir_all(&output_dir).context(format!("Unable to create {output_dir:?}."))?;
+
+                        // the input keeps its name unless --output-file gives another
+                        let name = output_file.unwrap_or_else(|| path.file_name().unwrap_or_default().into());
+                        output_dir.join(name)
+                    }
+                    (Some(output_file), None) => output_file,
+                    (None, None) if !in_place => {
+                        let output_file = Commands::default_output_name(path, &suffix);
+                        info!("Writing to {}", output_file.display());
+
+                        output_file
+                    }
+                    (None, None) => {
+                        if !no_backup {
+                            backup = Some(Commands::backup(path, writing)?);
+                        }
+                        // --in-place already asks for the input to be replaced
+                        writing.force = true;
+
+                        path.file_name().unwrap_or_default().into()
+                    }
+                };
+
+                if derive_token && embedding.mode != Mode::Chunk {
+                    bail!("Only chunk mode names its payloads, --derive-token does not apply to other modes");
+                }
+                let naming = match &secret.password {
+                    Some(password) if derive_token => {
+                        Naming::Derived(png::derived_chunk_type(password, label.as_deref()).to_string())
+                    }
+                    _ => Naming::Random(prefix),
+                };
+
+                match embedding.mode {
+                    Mode::Chunk => Commands::encode(
+                        path,
+                        message,
+                        output_file,
+                        secret,
+                        position,
+                        naming,
+                        writing,
+                    )?,
+                    Mode::Lsb if Commands::is_wav(path) => {
+                        Commands::encode_wav_lsb(path, message, output_file, secret, writing)?
+                    }
+                    Mode::Lsb => {
+                        let (output_path, quality) = Commands::encode_lsb(
+                            path,
+                            message,
+                            output_file,
+                            secret,
+                            embedding.lsb_options(),
+                            writing,
+                        )?;
+
+                        Commands::report_quality(&output_path, quality, min_psnr, json);
+                    }
+                    Mode::Text | Mode::Itxt => {
+                        if secret.password.is_some() {
+                            bail!("Text modes store the message in the clear, they can not be used with --password");
+                        }
+
+                        let keyword = &embedding.keyword;
+                        let text_chunk = match embedding.mode {
+                            Mode::Text => Text::new(keyword, &message)?.to_chunk()?,
+                            _ => InternationalText::new(
+                                keyword,
+                                &embedding.language,
+                                &embedding.translated_keyword,
+                                &message,
+                                embedding.compress,
+                            )?
+                            .to_chunk()?,
+                        };
+
+                        Commands::encode_text(path, output_file, keyword, text_chunk, position, writing)?
+                    }
+                    Mode::Zip => {
+                        if secret.password.is_some() {
+                            bail!("Zip mode stores the message in the clear, it can not be used with --password");
+                        }
+
+                        Commands::encode_zip(path, message, output_file, &embedding.zip_entry, writing)?
+                    }
+                }
+
+                // the input is only backed up once it was encoded
+                if let Some(backup) = backup {
+                    backup()?;
+                }
+
+                Ok(())
+            }
+            Command::Decode {
+                input,
+                chunk_type,
+                match_case_insensitive,
+                auto,
+                password,
+                label,
+                mode,
+                keyword,
+                ignore_crc,
+                max_memory,
+            } => {
+                let path = input.path();
+
+                // only chunk mode can read a file a chunk at a time
+                if mode != Mode::Chunk {
+                    Commands::check_memory(path, max_memory)?;
+                }
+
+                let message = match mode {
+                    Mode::Chunk if auto => {
+                        for (chunk_type, message) in Commands::decode_auto(path, password, ignore_crc, max_memory)? {
+                            if logging::quiet() {
+                                println!("{}", message);
+                            } else {
+                                println!("{}: {}", chunk_type.white().bold(), message);
+                            }
+                        }
+
+                        return Ok(());
+                    }
+                    Mode::Chunk => match chunk_type {
+                        Some(chunk_type) => {
+                            let selector = Selector::new(&chunk_type, match_case_insensitive);
+                            Commands::decode(path, &selector, password, ignore_crc, max_memory)?
+                        }
+                        // images encoded with --derive-token are named after the password
+                        None => match &password {
+                            Some(secret) => {
+                                let chunk_type = png::derived_chunk_type(secret, label.as_deref()).to_string();
+                                let selector = Selector::new(&chunk_type, false);
+                                Commands::decode(path, &selector, password, ignore_crc, max_memory).context(
+                                    "Nothing is stored under the chunk type of this password, pass the token with -c or use --auto",
+                                )?
+                            }
+                            None => {
+                                let selector = Selector::new(&wizard::chunk_type()?, match_case_insensitive);
+                                Commands::decode(path, &selector, password, ignore_crc, max_memory)?
+                            }
+                        },
+                    },
+                    Mode::Lsb if Commands::is_wav(path) => Commands::decode_wav_lsb(path, password)?,
+                    Mode::Lsb => Commands::decode_lsb(path, password, ignore_crc)?,
+                    Mode::Text | Mode::Itxt => Commands::decode_text(path, keyword, ignore_crc)?,
+                    Mode::Zip => Commands::decode_zip(path)?,
+                };
+
+                println!("{}", message);
+
+                Ok(())
+            }
+            Command::Remove {
+                input,
+                chunk_type,
+                match_case_insensitive,
+                index,
+                all,
+                password,
+                allow_critical,
+            } => {
+                let chunk_type = match chunk_type {
+                    Some(chunk_type) => chunk_type,
+                    None => wizard::chunk_type()?,
+                };
+                let selector = Selector::new(&chunk_type, match_case_insensitive);
+
+                let occurrence = match index {
+                    Some(index) => Some(png::Occurrence::At(index)),
+                    None if all => Some(png::Occurrence::All),
+                    None => None,
+                };
+                if let Some(occurrence) = occurrence {
+                    let path = input.path();
+                    return Commands::remove_chunks(path, &selector, occurrence, password, allow_critical, writing);
+                }
+
+                println!("{}", Commands::remove(input.path(), &selector, password, allow_critical, writing)?);
+
+                Ok(())
+            }
+            Command::Undo { input, list } => Commands::undo(input.path(), list, writing, json),
+            Command::Capacity {
+                input,
+                message,
+                secret,
+                embedding,
+            } => Commands::capacity(
+                input.path(),
+                message,
+                secret.password,
+                secret.pad_to,
+                embedding.lsb_options(),
+            ),
+            Command::Print {
+                input,
+                chunk_type,
+                match_case_insensitive,
+                ignore_crc,
+                extract_trailing,
+                strip_trailing,
+            } => {
+                if extract_trailing.is_some() || strip_trailing {
+                    return Commands::trailing(input.path(), extract_trailing, strip_trailing, writing);
+                }
+
+                let selector = chunk_type.map(|chunk_type| Selector::new(&chunk_type, match_case_insensitive));
+                Commands::print(input.path(), selector, json, ignore_crc)
+            }
+            Command::Scan {
+                input,
+                recursive,
+                no_verify_crc,
+            } => {
+                let options = png::ParseOptions { verify_crc: !no_verify_crc };
+                Commands::scan(input.path(), recursive, options, json)
+            }
+            Command::Analyze { input } => Commands::analyze(input.path(), json),
+            Command::Sanitize { input, output_file } => {
+                let (output_path, summary) = Commands::sanitize(input.path(), output_file, writing)?;
+
+                if json {
+                    let report = serde_json::json!({
+                        "output": output_path.display().to_string(),
+                        "removed_chunks": summary.removed_chunks,
+                        "trailing": summary.trailing,
+                        "randomized": summary.randomized,
+                    });
+                    println!("{}", report);
+
+                    return Ok(());
+                }
+
+                println!("Clean copy written to {}", output_path.display());
+
+                if !summary.removed_chunks.is_empty() {
+                    println!("Removed chunks: {}", summary.removed_chunks.join(", "));
+                }
+                if summary.trailing > 0 {
+                    println!("Removed {} bytes found after IEND", summary.trailing);
+                }
+                if summary.randomized {
+                    println!("Replaced the lowest bit of every sample");
+                } else {
+                    println!("{}", "The image is grayscale with less than 8 bits, the lowest bits were kept.".yellow());
+                }
+
+                Ok(())
+            }
+            Command::Carve {
+                input,
+                output_file,
+                scan,
+            } => Commands::carve(input.path(), output_file, scan, writing, json),
+            Command::Repair { input, output_file } => Commands::repair(input.path(), output_file, writing, json),
+            Command::Verify { input } => {
+                if !Commands::verify(input.path(), json)? {
+                    std::process::exit(1);
+                }
+
+                Ok(())
+            }
+            Command::Survivability { input } => Commands::survivability(input.path(), json),
+            Command::Info { input } => Commands::info(input.path(), json),
+            Command::Tui {
+                input,
+                password,
+                output_file,
+            } => Commands::browse(input.path(), password, output_file, writing),
+            Command::Serve {
+                address,
+                threads,
+                max_upload,
+                max_decoded,
+                kdf_rounds,
+            } => serve::run(&address, threads, max_upload, max_decoded, kdf_rounds),
+            Command::Mangen { out_dir } => Commands::mangen(&out_dir, writing),
+            Command::TypeInfo { chunk_type } => Commands::type_info(&chunk_type, json),
+            Command::Stats { input } => Commands::stats(input.path(), json),
+            Command::Lint { input, allow } => {
+                if !Commands::lint(input.path(), &allow, json)? {
+                    std::process::exit(1);
+                }
+
+                Ok(())
+            }
+            Command::Diff { original, other } => Commands::diff(&original, &other, json),
+            Command::RecoverToken { input, show, password } => Commands::recover_token(input.path(), show, password),
+            Command::Report {
+                input,
+                output_file,
+                examiner,
+            } => {
+                let written = Commands::report(input.path(), &output_file, examiner, writing)?;
+
+                println!("Report written to {}", written);
+
+                Ok(())
+            }
+            Command::Chunk(ChunkCommand::Dump {
+                input,
+                chunk_type,
+                hex,
+                head,
+            }) => Commands::dump_chunk(input.path(), chunk_type, hex, head),
+            Command::Chunk(ChunkCommand::Extract {
+                input,
+                chunk_type,
+                output_file,
+            }) => Commands::extract_chunk(input.path(), chunk_type, output_file, writing),
+            Command::Chunk(ChunkCommand::Inject {
+                input,
+                chunk_type,
+                data,
+                position,
+                output_file,
+                i_know_what_im_doing,
+            }) => Commands::inject_chunk(
+                input.path(),
+                chunk_type,
+                &data,
+                position,
+                output_file,
+                i_know_what_im_doing,
+                writing,
+            ),
+            Command::Strip {
+                input,
+                all_ancillary,
+                chunk_types,
+                unsafe_to_copy,
+                output_file,
+            } => Commands::strip(
+                input.path(),
+                all_ancillary,
+                chunk_types,
+                unsafe_to_copy,
+                output_file,
+                writing,
+                json,
+            ),
+            Command::Normalize { input, output_file } => Commands::normalize(input.path(), output_file, writing),
+            Command::Meta(MetaCommand::List { input }) => Commands::meta_list(input.path(), json),
+            Command::Meta(MetaCommand::Get { input, keyword }) => Commands::meta_get(input.path(), keyword),
+            Command::Meta(MetaCommand::Set {
+                input,
+                keyword,
+                value,
+                output_file,
+            }) => Commands::meta_set(input.path(), keyword, value, output_file, writing),
+            Command::Meta(MetaCommand::Remove {
+                input,
+                keyword,
+                output_file,
+            }) => Commands::meta_remove(input.path(), keyword, output_file, writing),
+            Command::Meta(MetaCommand::Exif(ExifCommand::Dump { input, output_file })) => {
+                Commands::exif_dump(input.path(), output_file, writing, json)
+            }
+            Command::Meta(MetaCommand::Exif(ExifCommand::Strip { input, output_file })) => {
+                Commands::exif_strip(input.path(), output_file, writing)
+            }
+            Command::Meta(MetaCommand::Exif(ExifCommand::Set {
+                input,
+                from,
+                output_file,
+            })) => Commands::exif_set(input.path(), &from, output_file, writing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngcrypt::png::builder::PngBuilder;
+
+    const WRITING: WriteOptions = WriteOptions {
+        drop_trailing: false,
+        force: false,
+        preserve_times: false,
+        dry_run: false,
+    };
+
+    // an empty directory of its own for each test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngcrypt-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // the paths commands report are canonical
+        dir.canonicalize().unwrap()
+    }
+
+    // the names of the files in `dir`, sorted
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+
+        names
+    }
+
+    fn run(args: &[&str]) -> Result<()> {
+        Commands::from_args(args::Args::try_parse_configured(args)?)
+    }
+
+    // an image at `path` holding `message` sealed with `password`, and its token
+    fn encoded_image(path: &Path, message: &str, password: &str) -> String {
+        let payload = envelope::seal_with_rounds(message.as_bytes(), Some(password), None, 10).unwrap();
+        let mut carrier = carrier::open(&PngBuilder::new(2, 2).build().unwrap().as_bytes()).unwrap();
+        let token = carrier.embed(&payload, png::Position::BeforeIend).unwrap();
+        std::fs::write(path, carrier.to_bytes()).unwrap();
+
+        token
+    }
+
+    #[test]
+    fn test_remove_wrong_password() {
+        let path = scratch("remove").join("image.png");
+        let token = encoded_image(&path, "Message", "hunter2");
+        let before = std::fs::read(&path).unwrap();
+        let selector = Selector::new(&token, false);
+        let writing = WriteOptions { force: true, ..WRITING };
+
+        assert!(Commands::remove(&path, &selector, Some("wrong".to_string()), false, writing).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        let message = Commands::remove(&path, &selector, Some("hunter2".to_string()), false, writing).unwrap();
+        assert_eq!(message, "Message");
+        assert_ne!(std::fs::read(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn test_default_output_name() {
+        let name = Commands::default_output_name;
+
+        assert_eq!(name(Path::new("dir/photo.png"), "pngcrypt"), PathBuf::from("photo.pngcrypt.png"));
+        assert_eq!(name(Path::new("photo"), "secret"), PathBuf::from("photo.secret"));
+    }
+
+    #[test]
+    fn test_check_overwrite() {
+        let path = scratch("overwrite").join("out.txt");
+        std::fs::write(&path, "before").unwrap();
+
+        assert!(Commands::write_file(&path, b"after", WRITING).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"before");
+
+        Commands::write_file(&path, b"after", WriteOptions { force: true, ..WRITING }).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"after");
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = scratch("atomically");
+        let path = dir.join("out.txt");
+        std::fs::write(&path, "before").unwrap();
+        let writing = WriteOptions { force: true, ..WRITING };
+
+        let failed = Commands::write_atomically(None, &path, writing, false, |writer| {
+            writer.write_all(b"half")?;
+            bail!("Failed halfway")
+        });
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"before");
+        assert_eq!(listing(&dir), ["out.txt"]);
+
+        Commands::write_file(&path, b"after", writing).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"after");
+        assert_eq!(listing(&dir), ["out.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch("permissions");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let writing = WriteOptions { force: true, ..WRITING };
+
+        let path = dir.join("out.txt");
+        std::fs::write(&path, "before").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        Commands::write_file(&path, b"after", writing).unwrap();
+        assert_eq!(mode(&path), 0o640);
+
+        let secret = dir.join("secret.txt");
+        std::fs::write(&secret, "before").unwrap();
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o644)).unwrap();
+        Commands::write_secret_file(&secret, b"after", writing).unwrap();
+        assert_eq!(mode(&secret), 0o600);
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let dir = scratch("dry-run");
+        let path = dir.join("image.png");
+        encoded_image(&path, "Message", "hunter2");
+        let before = std::fs::read(&path).unwrap();
+        let path = path.to_str().unwrap();
+
+        run(&["pngcrypt", "--dry-run", "encode", path, "Other", "--kdf-rounds", "10"]).unwrap();
+        run(&["pngcrypt", "--dry-run", "encode", path, "Other", "--in-place", "--kdf-rounds", "10"]).unwrap();
+        assert_eq!(listing(&dir), ["image.png"]);
+        assert_eq!(std::fs::read(path).unwrap(), before);
+    }
+
+    #[test]
+    fn test_encode_in_place_backup() {
+        let dir = scratch("in-place");
+        let path = dir.join("image.png");
+        encoded_image(&path, "Message", "hunter2");
+        let before = std::fs::read(&path).unwrap();
+        let path = path.to_str().unwrap();
+
+        // Text mode refuses a password only once the image is read. The failed
+        // encode leaves the image as it was, with neither a backup nor a journal.
+        let text = ["--mode", "text", "--password", "hunter2"];
+        assert!(run(&[&["pngcrypt", "encode", path, "Other", "--in-place"][..], &text].concat()).is_err());
+        assert_eq!(listing(&dir), ["image.png"]);
+        assert_eq!(std::fs::read(path).unwrap(), before);
+
+        run(&["pngcrypt", "encode", path, "Other", "--in-place", "--kdf-rounds", "10"]).unwrap();
+        assert_eq!(listing(&dir), ["image.png", "image.png.bak", "image.png.journal"]);
+        assert_eq!(std::fs::read(journal::backup_path(Path::new(path), 0)).unwrap(), before);
+        assert_eq!(journal::restore_points(Path::new(path)).unwrap().len(), 1);
+        assert_ne!(std::fs::read(path).unwrap(), before);
+
+        run(&["pngcrypt", "encode", path, "Third", "--in-place", "--no-backup", "--kdf-rounds", "10"]).unwrap();
+        assert_eq!(listing(&dir), ["image.png", "image.png.bak", "image.png.journal"]);
+    }
+
+    #[test]
+    fn test_lock() {
+        let path = scratch("lock").join("image.png");
+        encoded_image(&path, "Message", "hunter2");
+
+        let lock = Commands::lock(&path).unwrap();
+        let busy = Commands::lock(&path).unwrap_err();
+        assert!(busy.to_string().contains("is busy"));
+        assert!(run(&["pngcrypt", "encode", path.to_str().unwrap(), "Other", "--in-place"]).is_err());
+
+        drop(lock);
+        Commands::lock(&path).unwrap();
     }
 }
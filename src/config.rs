@@ -0,0 +1,298 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::chunk_type::Prefix;
+use super::crypto::MAX_KDF_ROUNDS;
+use super::envelope::Padding;
+use super::png::Position;
+
+use clap::Command;
+use serde::Deserialize;
+use thiserror::Error;
+
+// Defaults for the flags of the commands, read from a TOML file:
+// $PNGCRYPT_CONFIG when it is set, otherwise pngcrypt/config.toml in
+// $XDG_CONFIG_HOME or ~/.config. Each key is the long name of a flag and sets
+// the value it takes when it is not given, on every command that has it:
+//
+//     suffix = "hidden"
+//     position = "after-ihdr"
+//     kdf-rounds = 200000
+//
+// A flag given on the command line, or through its environment variable,
+// always wins over the file, which wins over the built-in default. Passwords
+// are not read from the file.
+//
+// Sections of the file are named profiles, chosen with --profile or
+// $PNGCRYPT_PROFILE. The keys of the profile chosen replace the ones above
+// the sections, the others still apply:
+//
+//     [profile.work]
+//     prefix = "wk"
+//     pad-to = "auto"
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Defaults {
+    // the name encode gives the output, as in photo.<suffix>.png
+    pub suffix: Option<String>,
+    // where new chunks go: after-ihdr, before-idat, before-iend or random
+    pub position: Option<String>,
+    // the two letters random chunk types start with
+    pub prefix: Option<String>,
+    // the size bucket payloads are padded to, a number of bytes or "auto"
+    pub pad_to: Option<String>,
+    // the rounds of PBKDF2 keys are derived with
+    pub kdf_rounds: Option<u32>,
+    // the label told apart from the password for derived chunk types
+    pub label: Option<String>,
+    // auto, always or never
+    pub color: Option<String>,
+    // the defaults of each profile, only at the top of the file
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, Defaults>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Unable to read {0}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("Invalid configuration in {0}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+    #[error("Invalid configuration in {0}: {1}")]
+    Invalid(PathBuf, String),
+    #[error("No profile named {0} in the configuration")]
+    UnknownProfile(String),
+}
+
+impl Defaults {
+    pub fn parse(text: &str) -> Result<Defaults, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    // Checks the values as the flags they stand for check them, since clap
+    // takes a default it can not parse for a mistake in the program.
+    pub fn check(&self) -> Result<(), String> {
+        if let Some(position) = &self.position {
+            Position::from_str(position)?;
+        }
+        if let Some(prefix) = &self.prefix {
+            Prefix::from_str(prefix).map_err(|error| error.to_string())?;
+        }
+        if let Some(padding) = &self.pad_to {
+            Padding::from_str(padding).map_err(|error| error.to_string())?;
+        }
+        for (name, profile) in &self.profiles {
+            if !profile.profiles.is_empty() {
+                return Err(format!("Profile {} has profiles of its own", name));
+            }
+            profile.check().map_err(|error| format!("{} in profile {}", error, name))?;
+        }
+        if let Some(rounds) = self.kdf_rounds.filter(|rounds| !(1..=MAX_KDF_ROUNDS).contains(rounds)) {
+            return Err(format!(
+                "Invalid number of KDF rounds: {}. Expected a value between 1 and {}",
+                rounds, MAX_KDF_ROUNDS
+            ));
+        }
+        if let Some(color) = &self.color {
+            if !["auto", "always", "never"].contains(&color.as_str()) {
+                return Err(format!("Invalid color choice: {}. Expected auto, always or never", color));
+            }
+        }
+
+        Ok(())
+    }
+
+    // the defaults with the ones of the profile `name` in place of them
+    pub fn with_profile(mut self, name: &str) -> Result<Defaults, ConfigError> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?;
+
+        Ok(Defaults {
+            suffix: profile.suffix.or(self.suffix),
+            position: profile.position.or(self.position),
+            prefix: profile.prefix.or(self.prefix),
+            pad_to: profile.pad_to.or(self.pad_to),
+            kdf_rounds: profile.kdf_rounds.or(self.kdf_rounds),
+            label: profile.label.or(self.label),
+            color: profile.color.or(self.color),
+            profiles: BTreeMap::new(),
+        })
+    }
+
+    // the defaults as the flags they stand for and their values, as they would be typed
+    pub fn flags(&self) -> Vec<(&'static str, String)> {
+        let flags = vec![
+            ("suffix", self.suffix.clone()),
+            ("position", self.position.clone()),
+            ("prefix", self.prefix.clone()),
+            ("pad-to", self.pad_to.clone()),
+            ("kdf-rounds", self.kdf_rounds.map(|rounds| rounds.to_string())),
+            ("label", self.label.clone()),
+            ("color", self.color.clone()),
+        ];
+
+        flags.into_iter().filter_map(|(flag, value)| Some((flag, value?))).collect()
+    }
+
+    // Makes the values of `flags` the defaults of the arguments with those
+    // names, in `command` and all its subcommands. The values are checked as
+    // typed ones are once the command line is parsed.
+    pub fn apply<'a>(command: Command<'a>, flags: &'a [(&'static str, String)]) -> Command<'a> {
+        let mut command = flags.iter().fold(command, |command, (flag, value)| {
+            // mut_arg adds the argument when the command does not have it
+            if command.get_arguments().any(|argument| argument.get_id() == *flag) {
+                command.mut_arg(*flag, |argument| argument.default_value(value.as_str()))
+            } else {
+                command
+            }
+        });
+
+        let names: Vec<String> = command.get_subcommands().map(|command| command.get_name().to_string()).collect();
+        for name in names {
+            command = command.mut_subcommand(name.as_str(), |subcommand| Defaults::apply(subcommand, flags));
+        }
+
+        command
+    }
+}
+
+// where the configuration is read from, see above
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PNGCRYPT_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let directory = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => PathBuf::from(directory),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(directory.join("pngcrypt").join("config.toml"))
+}
+
+// The profile --profile names in `arguments`, the command line without the
+// program, or $PNGCRYPT_PROFILE otherwise. It has to be known before the
+// command line is parsed, since the defaults it gives take part in parsing.
+pub fn profile_name(arguments: &[OsString]) -> Option<String> {
+    let mut arguments = arguments.iter().map(|argument| argument.to_string_lossy());
+
+    while let Some(argument) = arguments.next() {
+        if argument == "--" {
+            break;
+        }
+        if argument == "--profile" {
+            return arguments.next().map(|name| name.into_owned());
+        }
+        if let Some(name) = argument.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+
+    std::env::var("PNGCRYPT_PROFILE").ok().filter(|name| !name.is_empty())
+}
+
+// the defaults in the file at `path`, none when there is no file there
+pub fn load(path: &Path) -> Result<Defaults, ConfigError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Defaults::default()),
+        Err(error) => return Err(ConfigError::Read(path.to_path_buf(), error)),
+    };
+
+    let defaults = Defaults::parse(&text).map_err(|error| ConfigError::Parse(path.to_path_buf(), error))?;
+    defaults.check().map_err(|error| ConfigError::Invalid(path.to_path_buf(), error))?;
+
+    Ok(defaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command<'a>() -> Command<'a> {
+        Command::new("pngcrypt")
+            .arg(Arg::new("color").long("color").global(true).default_value("auto"))
+            .subcommand(
+                Command::new("encode")
+                    .arg(Arg::new("suffix").long("suffix").default_value("pngcrypt"))
+                    .arg(Arg::new("prefix").long("prefix").takes_value(true)),
+            )
+    }
+
+    fn encode_value(flags: &[(&'static str, String)], arguments: &[&str], id: &str) -> Option<String> {
+        let matches = Defaults::apply(command(), flags).try_get_matches_from(arguments).unwrap();
+        let (_, encode) = matches.subcommand().unwrap();
+
+        encode.get_one::<String>(id).cloned()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let defaults = Defaults::parse("suffix = \"hidden\"\nkdf-rounds = 200000\n").unwrap();
+
+        assert_eq!(defaults.suffix.as_deref(), Some("hidden"));
+        assert_eq!(
+            defaults.flags(),
+            vec![("suffix", "hidden".to_string()), ("kdf-rounds", "200000".to_string())]
+        );
+        assert!(Defaults::parse("sufix = \"hidden\"").is_err());
+        assert!(Defaults::parse("kdf-rounds = \"many\"").is_err());
+        assert!(Defaults::parse("position = \"middle\"").unwrap().check().is_err());
+        assert!(Defaults::parse("pad-to = \"auto\"\ncolor = \"never\"").unwrap().check().is_ok());
+    }
+
+    #[test]
+    fn test_config_over_builtin_default() {
+        let flags = Defaults::parse("suffix = \"hidden\"\nprefix = \"pc\"\ncolor = \"never\"").unwrap().flags();
+
+        assert_eq!(encode_value(&flags, &["pngcrypt", "encode"], "suffix").as_deref(), Some("hidden"));
+        assert_eq!(encode_value(&flags, &["pngcrypt", "encode"], "prefix").as_deref(), Some("pc"));
+        assert_eq!(encode_value(&flags, &["pngcrypt", "encode"], "color").as_deref(), Some("never"));
+        assert_eq!(encode_value(&[], &["pngcrypt", "encode"], "suffix").as_deref(), Some("pngcrypt"));
+        assert_eq!(encode_value(&[], &["pngcrypt", "encode"], "prefix"), None);
+    }
+
+    #[test]
+    fn test_command_line_over_config() {
+        let flags = Defaults::parse("suffix = \"hidden\"\ncolor = \"never\"").unwrap().flags();
+        let arguments = ["pngcrypt", "--color", "always", "encode", "--suffix", "mine"];
+
+        assert_eq!(encode_value(&flags, &arguments, "suffix").as_deref(), Some("mine"));
+        assert_eq!(encode_value(&flags, &arguments, "color").as_deref(), Some("always"));
+    }
+
+    #[test]
+    fn test_profile_over_config() {
+        let text = "suffix = \"hidden\"\nprefix = \"pc\"\n[profile.work]\nprefix = \"wk\"\npad-to = \"auto\"\n";
+        let defaults = Defaults::parse(text).unwrap();
+        assert!(defaults.check().is_ok());
+
+        let work = defaults.clone().with_profile("work").unwrap();
+        assert_eq!(work.suffix.as_deref(), Some("hidden"));
+        assert_eq!(work.prefix.as_deref(), Some("wk"));
+        assert_eq!(work.pad_to.as_deref(), Some("auto"));
+        assert!(matches!(defaults.with_profile("home"), Err(ConfigError::UnknownProfile(_))));
+
+        let arguments = ["pngcrypt", "encode", "--suffix", "mine"];
+        assert_eq!(encode_value(&work.flags(), &arguments, "suffix").as_deref(), Some("mine"));
+        assert_eq!(encode_value(&work.flags(), &arguments, "prefix").as_deref(), Some("wk"));
+
+        let arguments = ["encode", "--profile", "work", "--", "--profile=home"].map(OsString::from);
+        assert_eq!(profile_name(&arguments).as_deref(), Some("work"));
+        assert_eq!(profile_name(&[OsString::from("--profile=home")]).as_deref(), Some("home"));
+        assert!(Defaults::parse("[profile.work.profile.home]").unwrap().check().is_err());
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        assert_eq!(load(Path::new("does/not/exist.toml")).unwrap(), Defaults::default());
+        assert!(matches!(load(Path::new("src")), Err(ConfigError::Read(..))));
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+
+use memmap2::Mmap;
+
+// The contents of an input file. Small files are read into memory; large ones
+// are mapped read-only instead, so their bytes are paged in as the parser
+// reaches them and a multi-hundred-MB image is not held twice, once as read
+// and once as parsed. A mapping sees changes other programs make to the file
+// while it is open; the commands changing a file in place lock it first.
+
+// the size from which files are mapped rather than read
+pub const MAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+pub enum Contents {
+    Read(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Contents {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Contents::Mapped(_))
+    }
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Contents::Read(bytes) => bytes,
+            Contents::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Contents {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+// How much memory a command may use for the data it reads in, such as the
+// chunk holding a payload and the message opened from it. Commands that can
+// not stay under it fail before reading rather than running out of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryLimit {
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum ContentsError {
+    InvalidLimit(String),
+    OverLimit { needed: u64, limit: u64 },
+}
+
+impl std::error::Error for ContentsError {}
+
+impl fmt::Display for ContentsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentsError::InvalidLimit(value) => write!(
+                f,
+                "Invalid memory limit: {}. Expected a number of bytes, optionally followed by K, M or G",
+                value
+            ),
+            ContentsError::OverLimit { needed, limit } => write!(
+                f,
+                "This needs about {} bytes of memory, above the limit of {} bytes",
+                needed, limit
+            ),
+        }
+    }
+}
+
+impl MemoryLimit {
+    pub fn check(&self, needed: u64) -> Result<(), ContentsError> {
+        if needed > self.bytes {
+            return Err(ContentsError::OverLimit {
+                needed,
+                limit: self.bytes,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// a number of bytes, or of kibibytes, mebibytes or gibibytes with a K, M or G
+// after it, such as 512M
+impl FromStr for MemoryLimit {
+    type Err = ContentsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || ContentsError::InvalidLimit(value.to_string());
+
+        let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let unit: u64 = match value[digits.len()..].to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            _ => return Err(invalid()),
+        };
+
+        let bytes = digits.parse::<u64>().ok().and_then(|count| count.checked_mul(unit));
+        match bytes {
+            Some(bytes) if bytes > 0 => Ok(MemoryLimit { bytes }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+pub fn read(file: File) -> io::Result<Contents> {
+    read_with_threshold(file, MAP_THRESHOLD)
+}
+
+pub fn read_with_threshold(mut file: File, threshold: u64) -> io::Result<Contents> {
+    let length = file.metadata()?.len();
+
+    // an empty file can not be mapped
+    if length >= threshold && length > 0 {
+        // the mapping stays valid as long as the file is not truncated, see above
+        let map = unsafe { Mmap::map(&file)? };
+        return Ok(Contents::Mapped(map));
+    }
+
+    // sized up front, so the file is read in one go rather than in growing steps
+    let mut bytes = Vec::with_capacity(length as usize);
+    file.read_to_end(&mut bytes)?;
+
+    Ok(Contents::Read(bytes))
+}
+
+pub fn read_path(path: &Path) -> io::Result<Contents> {
+    read(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_small_file() {
+        let contents = read_path(Path::new("Cargo.toml")).unwrap();
+
+        assert!(!contents.is_mapped());
+        assert_eq!(&*contents, std::fs::read("Cargo.toml").unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_map_large_file() {
+        let contents = read_with_threshold(File::open("Cargo.toml").unwrap(), 1).unwrap();
+
+        assert!(contents.is_mapped());
+        assert_eq!(&*contents, std::fs::read("Cargo.toml").unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        assert_eq!(MemoryLimit::from_str("4096").unwrap().bytes, 4096);
+        assert_eq!(MemoryLimit::from_str("512M").unwrap().bytes, 512 << 20);
+        assert_eq!(MemoryLimit::from_str("2gib").unwrap().bytes, 2 << 30);
+        assert!(MemoryLimit::from_str("0").is_err());
+        assert!(MemoryLimit::from_str("12T").is_err());
+        assert!(MemoryLimit::from_str("M").is_err());
+
+        let limit = MemoryLimit::from_str("1K").unwrap();
+        assert!(limit.check(1024).is_ok());
+        assert!(matches!(limit.check(1025), Err(ContentsError::OverLimit { needed: 1025, limit: 1024 })));
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::Result;
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+pub const DEFAULT_KDF_ROUNDS: u32 = 100_000;
+// The most rounds a payload may ask for. The count is read from the payload,
+// so without a bound a crafted one keeps whoever opens it busy for hours.
+pub const MAX_KDF_ROUNDS: u32 = 10_000_000;
+
+#[derive(Debug)]
+pub enum CryptoError {
+    EncryptionFailed,
+    DecryptionFailed,
+}
+
+impl std::error::Error for CryptoError {}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::EncryptionFailed => write!(f, "Unable to encrypt payload"),
+            CryptoError::DecryptionFailed => {
+                write!(f, "Unable to decrypt payload. Wrong password or corrupted data")
+            }
+        }
+    }
+}
+
+// stretch a password into a 256 bit key with PBKDF2-HMAC-SHA256
+pub fn derive_key(password: &str, salt: &[u8], rounds: u32) -> [u8; KEY_SIZE] {
+    let mut key = [0u8; KEY_SIZE];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+// Fills `out` with HKDF-SHA256 output keyed by the password, for values other
+// than keys that have to follow from it. `info` keeps values for different
+// uses apart. Unlike `derive_key` this is fast, so anything derived this way
+// and written in the clear lets password guesses be checked quickly.
+pub fn expand_password(password: &str, info: &[u8], out: &mut [u8]) {
+    Hkdf::<Sha256>::new(Some(b"PNGCrypt"), password.as_bytes())
+        .expand(info, out)
+        .expect("The output is far shorter than HKDF allows.");
+}
+
+pub fn random_bytes(buf: &mut [u8]) {
+    OsRng.fill_bytes(buf);
+}
+
+pub fn encrypt(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    cipher
+        .encrypt(Nonce::from_slice(nonce), plaintext)
+        .map_err(|_| CryptoError::EncryptionFailed.into())
+}
+
+pub fn decrypt(key: &[u8; KEY_SIZE], nonce: &[u8; NONCE_SIZE], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError::DecryptionFailed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_key_is_deterministic() {
+        let salt = [7u8; SALT_SIZE];
+        assert_eq!(derive_key("hunter2", &salt, 10), derive_key("hunter2", &salt, 10));
+        assert_ne!(derive_key("hunter2", &salt, 10), derive_key("hunter3", &salt, 10));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let key = derive_key("hunter2", &[1u8; SALT_SIZE], 10);
+        let nonce = [2u8; NONCE_SIZE];
+
+        let ciphertext = encrypt(&key, &nonce, b"attack at dawn").unwrap();
+        assert_eq!(ciphertext.len(), 14 + TAG_SIZE);
+
+        let plaintext = decrypt(&key, &nonce, &ciphertext).unwrap();
+        assert_eq!(plaintext, b"attack at dawn");
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key() {
+        let key = derive_key("hunter2", &[1u8; SALT_SIZE], 10);
+        let wrong_key = derive_key("hunter3", &[1u8; SALT_SIZE], 10);
+        let nonce = [2u8; NONCE_SIZE];
+
+        let ciphertext = encrypt(&key, &nonce, b"attack at dawn").unwrap();
+
+        assert!(decrypt(&wrong_key, &nonce, &ciphertext).is_err());
+    }
+}
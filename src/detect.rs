@@ -0,0 +1,228 @@
+use super::inspect;
+use super::png::pixels::Pixels;
+use super::png::Png;
+
+// Fingerprints of other ways to hide data in a PNG, so findings can be
+// attributed to a tool or technique instead of being reported as unknown.
+
+// chunk types used by other tools to carry their payload
+const KNOWN_CHUNKS: [(&str, &str); 2] = [
+    // the chunk type used throughout the "pngme" tutorial
+    ("ruSt", "pngme"),
+    ("RuSt", "pngme"),
+];
+
+// magic bytes of files commonly appended after IEND
+const APPENDED_FORMATS: [(&[u8], &str); 6] = [
+    (b"PK\x03\x04", "ZIP archive"),
+    (b"Rar!\x1a\x07", "RAR archive"),
+    (b"7z\xbc\xaf\x27\x1c", "7z archive"),
+    (b"%PDF", "PDF document"),
+    (b"\x1f\x8b", "gzip stream"),
+    (b"\x89PNG\r\n\x1a\n", "PNG image"),
+];
+
+// how many bytes of sequential LSB data to look at for magics
+const LSB_PREFIX: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub tool: &'static str,
+    pub detail: String,
+}
+
+// data appended after IEND, which viewers ignore
+pub fn trailing_data(png: &Png) -> Option<Detection> {
+    let trailing = png.trailing();
+    let offset = png.trailing_offset();
+
+    if trailing.is_empty() {
+        return None;
+    }
+
+    let format = APPENDED_FORMATS
+        .iter()
+        .find(|(magic, _)| trailing.starts_with(magic))
+        .map(|(_, format)| *format);
+
+    // cropping tools that overwrite a file without truncating it leave the
+    // end of the original image behind (aCropalypse)
+    let leftover = trailing.windows(4).any(|window| window == b"IEND");
+
+    let size = format!(
+        "{} bytes (entropy {:.3}) after IEND at offset {}",
+        trailing.len(),
+        inspect::entropy(trailing),
+        offset
+    );
+
+    let detection = match format {
+        Some(format) => Detection {
+            tool: "appended file",
+            detail: format!("{} of {}", format, size),
+        },
+        None if leftover => Detection {
+            tool: "aCropalypse",
+            detail: format!("{} of an earlier image, left behind by a cropping tool", size),
+        },
+        None => Detection {
+            tool: "appended data",
+            detail: size,
+        },
+    };
+
+    Some(detection)
+}
+
+// well formed chunks after IEND, which viewers ignore as they do any other data there
+pub fn chunks_after_iend(png: &Png) -> Option<Detection> {
+    let chunks = png.chunks_after_iend();
+    let offset = *png.chunk_offsets().get(png.chunks().len() - chunks.len())?;
+
+    let types: Vec<String> = chunks.iter().map(|chunk| chunk.chunk_type.to_string()).collect();
+    let size: usize = chunks.iter().map(|chunk| chunk.size()).sum();
+
+    Some(Detection {
+        tool: "chunks after IEND",
+        detail: format!("{} ({} bytes) after IEND at offset {}", types.join(", "), size, offset),
+    })
+}
+
+pub fn known_chunk(chunk_type: &str) -> Option<Detection> {
+    KNOWN_CHUNKS
+        .iter()
+        .find(|(known, _)| *known == chunk_type)
+        .map(|(_, tool)| Detection {
+            tool,
+            detail: format!("{} chunk", chunk_type),
+        })
+}
+
+// The lowest bits of the red, green and blue samples in plain order, as bytes.
+// This is how most simple LSB tools lay out their data.
+fn sequential_rgb_lsb(pixels: &Pixels, len: usize) -> Option<Vec<u8>> {
+    if pixels.header.bit_depth != 8 || !matches!(pixels.header.color_type, 2 | 6) {
+        return None;
+    }
+
+    let channels = pixels.header.channels().ok()?;
+
+    let bits: Vec<u8> = pixels
+        .data
+        .chunks_exact(channels)
+        .flat_map(|pixel| pixel[..3].iter().map(|sample| sample & 1))
+        .take(len * 8)
+        .collect();
+
+    if bits.len() < len * 8 {
+        return None;
+    }
+
+    Some(
+        bits.chunks_exact(8)
+            .map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | bit))
+            .collect(),
+    )
+}
+
+// the "length:message" prefix the Python Stegano library writes
+fn stegano_prefix(data: &[u8]) -> Option<usize> {
+    let colon = data.iter().position(|&byte| byte == b':')?;
+    let digits = &data[..colon];
+
+    if digits.is_empty() || digits.len() > 9 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+pub fn lsb_signatures(pixels: &Pixels) -> Vec<Detection> {
+    let mut detections = Vec::new();
+
+    if let Some(prefix) = sequential_rgb_lsb(pixels, LSB_PREFIX) {
+        let capacity = pixels.data.len() / 8;
+
+        if let Some(length) = stegano_prefix(&prefix).filter(|&length| length > 0 && length <= capacity) {
+            detections.push(Detection {
+                tool: "Stegano",
+                detail: format!("LSB message of {} characters", length),
+            });
+        }
+    }
+
+    detections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::pixels::Header;
+    use std::convert::TryFrom;
+
+    fn testing_png(trailing: &[u8]) -> Png {
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(b"IEND");
+        bytes.extend_from_slice(&[0xae, 0x42, 0x60, 0x82]);
+        bytes.extend_from_slice(trailing);
+
+        Png::try_from(bytes.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        assert_eq!(trailing_data(&testing_png(b"")), None);
+        assert_eq!(trailing_data(&testing_png(b"PK\x03\x04rest")).unwrap().tool, "appended file");
+        assert_eq!(trailing_data(&testing_png(b"xxxxIEND\xae\x42\x60\x82")).unwrap().tool, "aCropalypse");
+
+        let detection = trailing_data(&testing_png(b"hello")).unwrap();
+        assert_eq!(detection.tool, "appended data");
+        assert!(detection.detail.ends_with("offset 20"));
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        assert_eq!(chunks_after_iend(&testing_png(b"")), None);
+
+        // a tEXt chunk, which no other check looks at
+        let png = testing_png(b"\0\0\0\x02tEXtA\0\x05\x41\xa0\xc8");
+        assert!(png.trailing().is_empty());
+        let detection = chunks_after_iend(&png).unwrap();
+        assert_eq!(detection.tool, "chunks after IEND");
+        assert_eq!(detection.detail, "tEXt (14 bytes) after IEND at offset 20");
+    }
+
+    #[test]
+    fn test_known_chunk() {
+        assert_eq!(known_chunk("ruSt").unwrap().tool, "pngme");
+        assert_eq!(known_chunk("abCD"), None);
+    }
+
+    #[test]
+    fn test_stegano_signature() {
+        let header = Header {
+            width: 64,
+            height: 1,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+        let message = b"5:hello";
+        let bits: Vec<u8> = message
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+            .collect();
+
+        let mut data = vec![100u8; 64 * 3];
+        for (sample, bit) in data.iter_mut().zip(&bits) {
+            *sample |= bit;
+        }
+
+        let detections = lsb_signatures(&Pixels::new(header, data));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].tool, "Stegano");
+
+        assert!(lsb_signatures(&Pixels::new(header, vec![100u8; 64 * 3])).is_empty());
+    }
+}
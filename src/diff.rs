@@ -0,0 +1,326 @@
+use super::png::pixels::Pixels;
+use super::png::Png;
+
+use std::fmt;
+
+// Compares the chunk layout of two images, typically an original and a copy
+// suspected of carrying a payload, and how far their pixel data differs.
+//
+// Chunks are matched on their type and CRC, keeping their order. Unmatched
+// chunks of the same type in the same stretch of the file are reported as
+// modified, the others as added or removed.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkInfo {
+    pub offset: usize,
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(ChunkInfo),
+    Removed(ChunkInfo),
+    Modified { before: ChunkInfo, after: ChunkInfo },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelDiff {
+    pub samples: usize,
+    pub changed: usize,
+    // samples that only differ in their lowest bit, the mark of LSB embedding
+    pub low_bit_only: usize,
+    pub max_difference: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+    pub unchanged: usize,
+    // compressed size of the image data
+    pub idat_before: usize,
+    pub idat_after: usize,
+    // missing when the image data can not be decoded or the sizes differ
+    pub pixels: Option<PixelDiff>,
+}
+
+impl fmt::Display for ChunkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} bytes, CRC {:#010x}, offset {})",
+            self.chunk_type, self.length, self.crc, self.offset
+        )
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(chunk) => write!(f, "+ {}", chunk),
+            Change::Removed(chunk) => write!(f, "- {}", chunk),
+            Change::Modified { before, after } => write!(
+                f,
+                "~ {} ({} -> {} bytes, CRC {:#010x} -> {:#010x}, offset {} -> {})",
+                before.chunk_type, before.length, after.length, before.crc, after.crc, before.offset, after.offset
+            ),
+        }
+    }
+}
+
+impl ChunkInfo {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "offset": self.offset,
+            "chunk_type": self.chunk_type,
+            "length": self.length,
+            "crc": self.crc,
+        })
+    }
+}
+
+impl Change {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Change::Added(chunk) => serde_json::json!({ "kind": "added", "chunk": chunk.to_json() }),
+            Change::Removed(chunk) => serde_json::json!({ "kind": "removed", "chunk": chunk.to_json() }),
+            Change::Modified { before, after } => serde_json::json!({
+                "kind": "modified",
+                "before": before.to_json(),
+                "after": after.to_json(),
+            }),
+        }
+    }
+}
+
+impl Diff {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "changes": self.changes.iter().map(Change::to_json).collect::<Vec<_>>(),
+            "unchanged": self.unchanged,
+            "idat_before": self.idat_before,
+            "idat_after": self.idat_after,
+            "pixels": self.pixels.map(|pixels| serde_json::json!({
+                "samples": pixels.samples,
+                "changed": pixels.changed,
+                "low_bit_only": pixels.low_bit_only,
+                "max_difference": pixels.max_difference,
+            })),
+        })
+    }
+}
+
+fn chunk_infos(png: &Png) -> Vec<ChunkInfo> {
+    png.chunks()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| ChunkInfo {
+            offset,
+            chunk_type: chunk.chunk_type.to_string(),
+            length: chunk.length,
+            crc: chunk.crc,
+        })
+        .collect()
+}
+
+// pairs of indices of identical chunks, longest common subsequence
+fn matches(before: &[ChunkInfo], after: &[ChunkInfo]) -> Vec<(usize, usize)> {
+    let same = |a: &ChunkInfo, b: &ChunkInfo| a.chunk_type == b.chunk_type && a.crc == b.crc && a.length == b.length;
+
+    // lengths[i][j]: longest common subsequence of before[i..] and after[j..]
+    let mut lengths = vec![vec![0usize; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            lengths[i][j] = if same(&before[i], &after[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() && j < after.len() {
+        if same(&before[i], &after[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    pairs
+}
+
+// the changes between two matched chunks, pairing chunks of the same type
+fn gap_changes(removed: &[ChunkInfo], added: &[ChunkInfo], changes: &mut Vec<Change>) {
+    let mut added: Vec<Option<&ChunkInfo>> = added.iter().map(Some).collect();
+
+    for before in removed {
+        let counterpart = added
+            .iter_mut()
+            .find(|chunk| chunk.map(|chunk| chunk.chunk_type == before.chunk_type).unwrap_or(false))
+            .and_then(Option::take);
+
+        match counterpart {
+            Some(after) => changes.push(Change::Modified {
+                before: before.clone(),
+                after: after.clone(),
+            }),
+            None => changes.push(Change::Removed(before.clone())),
+        }
+    }
+
+    changes.extend(added.into_iter().flatten().cloned().map(Change::Added));
+}
+
+// sample values of the image, colors for indexed images
+fn sample_values(png: &Png) -> Option<Vec<u16>> {
+    let mut pixels: Pixels = png.pixels().ok()?;
+
+    if pixels.header.is_indexed() {
+        pixels = png.palette().ok()?.expand(&pixels).ok()?;
+    } else {
+        pixels = pixels.unpack().ok()?;
+    }
+
+    if pixels.header.bit_depth == 16 {
+        Some(
+            pixels
+                .data
+                .chunks_exact(2)
+                .map(|sample| u16::from_be_bytes([sample[0], sample[1]]))
+                .collect(),
+        )
+    } else {
+        Some(pixels.data.iter().map(|&sample| sample as u16).collect())
+    }
+}
+
+fn pixel_diff(before: &Png, after: &Png) -> Option<PixelDiff> {
+    let a = sample_values(before)?;
+    let b = sample_values(after)?;
+
+    if a.len() != b.len() {
+        return None;
+    }
+
+    let mut diff = PixelDiff {
+        samples: a.len(),
+        changed: 0,
+        low_bit_only: 0,
+        max_difference: 0,
+    };
+
+    for (a, b) in a.iter().zip(&b) {
+        if a == b {
+            continue;
+        }
+
+        diff.changed += 1;
+        if a ^ b == 1 {
+            diff.low_bit_only += 1;
+        }
+        diff.max_difference = diff.max_difference.max(a.abs_diff(*b));
+    }
+
+    Some(diff)
+}
+
+fn idat_size(png: &Png) -> usize {
+    png.chunks()
+        .filter(|chunk| chunk.chunk_type.bytes == *b"IDAT")
+        .map(|chunk| chunk.data().len())
+        .sum()
+}
+
+pub fn diff(before: &Png, after: &Png) -> Diff {
+    let before_chunks = chunk_infos(before);
+    let after_chunks = chunk_infos(after);
+    let pairs = matches(&before_chunks, &after_chunks);
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    for &(next_i, next_j) in pairs.iter().chain(std::iter::once(&(before_chunks.len(), after_chunks.len()))) {
+        gap_changes(&before_chunks[i..next_i], &after_chunks[j..next_j], &mut changes);
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+
+    Diff {
+        changes,
+        unchanged: pairs.len(),
+        idat_before: idat_size(before),
+        idat_after: idat_size(after),
+        pixels: pixel_diff(before, after),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::builder::PngBuilder;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png(pixels: &[u8], extra: Vec<Chunk>) -> Png {
+        let mut png = PngBuilder::new(4, 1)
+            .pixels(pixels.to_vec())
+            .chunk(chunk("tEXt", b"Title\0Dice".to_vec()))
+            .build()
+            .unwrap();
+
+        for chunk in extra {
+            png.insert_chunk(chunk);
+        }
+
+        png
+    }
+
+    #[test]
+    fn test_identical() {
+        let png = testing_png(&[10, 20, 30, 40], Vec::new());
+        let diff = diff(&png, &png);
+
+        assert!(diff.changes.is_empty());
+        assert_eq!(diff.unchanged, 4);
+        assert_eq!(diff.pixels.unwrap().changed, 0);
+    }
+
+    #[test]
+    fn test_changes() {
+        let before = testing_png(&[10, 20, 30, 40], Vec::new());
+        let after = testing_png(&[11, 20, 30, 48], vec![chunk("abCD", b"payload".to_vec())]);
+
+        let diff = diff(&before, &after);
+
+        assert_eq!(diff.unchanged, 3);
+        assert_eq!(diff.changes.len(), 2);
+        assert!(matches!(&diff.changes[0], Change::Modified { before, .. } if before.chunk_type == "IDAT"));
+        assert!(matches!(&diff.changes[1], Change::Added(chunk) if chunk.chunk_type == "abCD"));
+
+        let pixels = diff.pixels.unwrap();
+        assert_eq!(pixels.samples, 4);
+        assert_eq!(pixels.changed, 2);
+        assert_eq!(pixels.low_bit_only, 1);
+        assert_eq!(pixels.max_difference, 8);
+    }
+
+    #[test]
+    fn test_removed() {
+        let before = testing_png(&[10, 20, 30, 40], vec![chunk("abCD", b"payload".to_vec())]);
+        let after = testing_png(&[10, 20, 30, 40], Vec::new());
+
+        assert!(matches!(&diff(&before, &after).changes[..], [Change::Removed(chunk)] if chunk.chunk_type == "abCD"));
+    }
+}
@@ -0,0 +1,343 @@
+use super::crypto;
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+
+// Every payload written by PNGCrypt is wrapped in an envelope:
+//
+//   magic "PNGC" | version | flags | [rounds | salt | nonce] | body
+//
+// The body is `message length | message | padding`, encrypted as a whole when a
+// password is given, so neither the real length nor the padding leak.
+
+pub const MAGIC: [u8; 4] = *b"PNGC";
+pub const VERSION: u8 = 1;
+
+const FLAG_ENCRYPTED: u8 = 0b0000_0001;
+const FLAG_PADDED: u8 = 0b0000_0010;
+
+const PREAMBLE_SIZE: usize = 6;
+const KDF_HEADER_SIZE: usize = 4 + crypto::SALT_SIZE + crypto::NONCE_SIZE;
+const LENGTH_SIZE: usize = 4;
+
+// the largest size bucket payloads can be padded to, 16 MiB
+pub const MAX_BUCKET: usize = 1 << 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    // pad to the next power of two
+    Auto,
+    // pad to the next multiple of the given number of bytes
+    Bucket(usize),
+}
+
+impl Padding {
+    fn target_size(&self, size: usize) -> Result<usize, EnvelopeError> {
+        let target = match self {
+            Padding::Auto => size.checked_next_power_of_two(),
+            Padding::Bucket(bucket) if *bucket > MAX_BUCKET => return Err(EnvelopeError::PaddingTooLarge(*bucket)),
+            Padding::Bucket(bucket) => size.div_ceil(*bucket).checked_mul(*bucket),
+        };
+
+        target.ok_or(EnvelopeError::TooLong(size))
+    }
+}
+
+impl FromStr for Padding {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "auto" {
+            return Ok(Padding::Auto);
+        }
+
+        match value.parse::<usize>() {
+            Ok(bucket) if bucket > MAX_BUCKET => Err(EnvelopeError::PaddingTooLarge(bucket).into()),
+            Ok(bucket) if bucket > 0 => Ok(Padding::Bucket(bucket)),
+            _ => Err(EnvelopeError::InvalidPadding(value.to_string()).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    InvalidPadding(String),
+    PaddingTooLarge(usize),
+    TooLong(usize),
+    TooManyRounds(u32),
+    UnsupportedVersion(u8),
+    Truncated,
+    PasswordRequired,
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::InvalidPadding(value) => write!(
+                f,
+                "Invalid padding: {}. Expected a number of bytes or \"auto\"",
+                value
+            ),
+            EnvelopeError::PaddingTooLarge(bucket) => write!(
+                f,
+                "Invalid padding: {} bytes. Size buckets can be at most {} bytes",
+                bucket, MAX_BUCKET
+            ),
+            EnvelopeError::TooLong(size) => write!(f, "A payload of {} bytes is too long for an envelope", size),
+            EnvelopeError::TooManyRounds(rounds) => write!(
+                f,
+                "The payload asks for {} KDF rounds, more than the {} allowed",
+                rounds,
+                crypto::MAX_KDF_ROUNDS
+            ),
+            EnvelopeError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported envelope version: {}", version)
+            }
+            EnvelopeError::Truncated => write!(f, "Envelope is truncated"),
+            EnvelopeError::PasswordRequired => {
+                write!(f, "Payload is encrypted. A password is required to decode it")
+            }
+        }
+    }
+}
+
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    is_envelope(data) && data.get(5).is_some_and(|flags| flags & FLAG_ENCRYPTED != 0)
+}
+
+// size of the envelope `seal` produces for a message of `message_len` bytes
+pub fn sealed_size(message_len: usize, encrypted: bool, padding: Option<Padding>) -> Result<usize> {
+    let mut size = PREAMBLE_SIZE + LENGTH_SIZE + message_len;
+
+    if encrypted {
+        size += KDF_HEADER_SIZE + crypto::TAG_SIZE;
+    }
+
+    match padding {
+        Some(padding) => Ok(padding.target_size(size)?),
+        None => Ok(size),
+    }
+}
+
+pub fn seal(message: &[u8], password: Option<&str>, padding: Option<Padding>) -> Result<Vec<u8>> {
+    seal_with_rounds(message, password, padding, crypto::DEFAULT_KDF_ROUNDS)
+}
+
+pub fn seal_with_rounds(
+    message: &[u8],
+    password: Option<&str>,
+    padding: Option<Padding>,
+    rounds: u32,
+) -> Result<Vec<u8>> {
+    if rounds > crypto::MAX_KDF_ROUNDS {
+        return Err(EnvelopeError::TooManyRounds(rounds).into());
+    }
+
+    let mut flags = 0;
+    let mut header_size = PREAMBLE_SIZE;
+    let mut overhead = 0;
+
+    if password.is_some() {
+        flags |= FLAG_ENCRYPTED;
+        header_size += KDF_HEADER_SIZE;
+        overhead += crypto::TAG_SIZE;
+    }
+    if padding.is_some() {
+        flags |= FLAG_PADDED;
+    }
+
+    let mut body = Vec::new();
+    let length = u32::try_from(message.len()).map_err(|_| EnvelopeError::TooLong(message.len()))?;
+    body.extend_from_slice(&length.to_be_bytes());
+    body.extend_from_slice(message);
+
+    if let Some(padding) = padding {
+        let size = header_size + body.len() + overhead;
+        let mut pad = vec![0u8; padding.target_size(size)? - size];
+        crypto::random_bytes(&mut pad);
+        body.extend_from_slice(&pad);
+    }
+
+    let mut bytes = Vec::with_capacity(header_size + body.len() + overhead);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.push(flags);
+
+    match password {
+        Some(password) => {
+            let mut salt = [0u8; crypto::SALT_SIZE];
+            let mut nonce = [0u8; crypto::NONCE_SIZE];
+            crypto::random_bytes(&mut salt);
+            crypto::random_bytes(&mut nonce);
+
+            let key = crypto::derive_key(password, &salt, rounds);
+
+            bytes.extend_from_slice(&rounds.to_be_bytes());
+            bytes.extend_from_slice(&salt);
+            bytes.extend_from_slice(&nonce);
+            bytes.extend_from_slice(&crypto::encrypt(&key, &nonce, &body)?);
+        }
+        None => bytes.extend_from_slice(&body),
+    }
+
+    Ok(bytes)
+}
+
+// Returns the message stored in `data`. Chunks written before envelopes existed
+// hold the raw message, so anything without the magic is passed through as is.
+pub fn open(data: &[u8], password: Option<&str>) -> Result<Vec<u8>> {
+    if !is_envelope(data) {
+        return Ok(data.to_vec());
+    }
+
+    if data.len() < PREAMBLE_SIZE {
+        return Err(EnvelopeError::Truncated.into());
+    }
+
+    let version = data[4];
+    if version != VERSION {
+        return Err(EnvelopeError::UnsupportedVersion(version).into());
+    }
+
+    let flags = data[5];
+    let rest = &data[PREAMBLE_SIZE..];
+
+    let body = if flags & FLAG_ENCRYPTED != 0 {
+        let password = password.ok_or(EnvelopeError::PasswordRequired)?;
+
+        if rest.len() < KDF_HEADER_SIZE {
+            return Err(EnvelopeError::Truncated.into());
+        }
+
+        let rounds = u32::from_be_bytes(rest[0..4].try_into()?);
+        if rounds > crypto::MAX_KDF_ROUNDS {
+            return Err(EnvelopeError::TooManyRounds(rounds).into());
+        }
+        let salt = &rest[4..4 + crypto::SALT_SIZE];
+        let nonce: [u8; crypto::NONCE_SIZE] = rest[4 + crypto::SALT_SIZE..KDF_HEADER_SIZE].try_into()?;
+
+        let key = crypto::derive_key(password, salt, rounds);
+        crypto::decrypt(&key, &nonce, &rest[KDF_HEADER_SIZE..])?
+    } else {
+        rest.to_vec()
+    };
+
+    if body.len() < LENGTH_SIZE {
+        return Err(EnvelopeError::Truncated.into());
+    }
+
+    let length = u32::from_be_bytes(body[0..LENGTH_SIZE].try_into()?) as usize;
+
+    // anything past the declared length is padding
+    body.get(LENGTH_SIZE..LENGTH_SIZE + length)
+        .map(|message| message.to_vec())
+        .ok_or_else(|| EnvelopeError::Truncated.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUNDS: u32 = 10;
+
+    #[test]
+    fn test_padding_from_str() {
+        assert_eq!(Padding::from_str("auto").unwrap(), Padding::Auto);
+        assert_eq!(Padding::from_str("512").unwrap(), Padding::Bucket(512));
+        assert!(Padding::from_str("0").is_err());
+        assert!(Padding::from_str("big").is_err());
+        assert!(Padding::from_str("4000000000").is_err());
+        assert!(seal_with_rounds(b"Message", None, Some(Padding::Bucket(usize::MAX)), ROUNDS).is_err());
+    }
+
+    #[test]
+    fn test_plain_roundtrip() {
+        let sealed = seal_with_rounds(b"Message", None, None, ROUNDS).unwrap();
+
+        assert!(is_envelope(&sealed));
+        assert!(!is_encrypted(&sealed));
+        assert_eq!(open(&sealed, None).unwrap(), b"Message");
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let sealed = seal_with_rounds(b"Message", Some("hunter2"), None, ROUNDS).unwrap();
+
+        assert!(!sealed.windows(7).any(|window| window == b"Message"));
+        assert!(is_encrypted(&sealed));
+        assert_eq!(open(&sealed, Some("hunter2")).unwrap(), b"Message");
+    }
+
+    #[test]
+    fn test_encrypted_requires_password() {
+        let sealed = seal_with_rounds(b"Message", Some("hunter2"), None, ROUNDS).unwrap();
+
+        assert!(open(&sealed, None).is_err());
+        assert!(open(&sealed, Some("hunter3")).is_err());
+    }
+
+    #[test]
+    fn test_auto_padding() {
+        let short = seal_with_rounds(b"Hi", Some("hunter2"), Some(Padding::Auto), ROUNDS).unwrap();
+        let long = seal_with_rounds(b"Hello", Some("hunter2"), Some(Padding::Auto), ROUNDS).unwrap();
+
+        assert_eq!(short.len(), 64);
+        assert_eq!(short.len(), long.len());
+        assert_eq!(open(&short, Some("hunter2")).unwrap(), b"Hi");
+        assert_eq!(open(&long, Some("hunter2")).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn test_bucket_padding() {
+        let sealed = seal_with_rounds(b"Message", None, Some(Padding::Bucket(100)), ROUNDS).unwrap();
+
+        assert_eq!(sealed.len(), 100);
+        assert_eq!(open(&sealed, None).unwrap(), b"Message");
+    }
+
+    #[test]
+    fn test_sealed_size() {
+        for &(password, padding) in &[
+            (None, None),
+            (Some("hunter2"), None),
+            (Some("hunter2"), Some(Padding::Auto)),
+            (None, Some(Padding::Bucket(100))),
+        ] {
+            let sealed = seal_with_rounds(b"Message", password, padding, ROUNDS).unwrap();
+            assert_eq!(sealed.len(), sealed_size(7, password.is_some(), padding).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_open_too_many_rounds() {
+        let mut sealed = seal_with_rounds(b"Message", Some("hunter2"), None, ROUNDS).unwrap();
+        sealed[PREAMBLE_SIZE..PREAMBLE_SIZE + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        // refused before the key is derived, which would take hours
+        let error = open(&sealed, Some("hunter2")).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(EnvelopeError::TooManyRounds(u32::MAX))));
+        assert!(seal_with_rounds(b"Message", Some("hunter2"), None, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_open_legacy_payload() {
+        assert_eq!(open(b"I am a raw message", None).unwrap(), b"I am a raw message");
+    }
+
+    #[test]
+    fn test_open_truncated_envelope() {
+        let sealed = seal_with_rounds(b"Message", None, None, ROUNDS).unwrap();
+
+        assert!(open(&sealed[..sealed.len() - 2], None).is_err());
+        assert!(open(&sealed[..5], None).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::png::{self, Position};
+
+use std::fmt;
+
+use anyhow::Result;
+use rand::Rng;
+
+// A GIF file is a header with the screen size and the global color table,
+// followed by blocks: extensions (0x21, a label and data sub-blocks), images
+// (0x2C, a descriptor, an optional color table and LZW data sub-blocks) and
+// the trailer (0x3B). Sub-blocks are a length byte followed by up to 255
+// bytes of data, ending with an empty one.
+//
+// Payloads go in application extensions, the kind animation loop counts are
+// stored in, with the identifier "PNGCrypt" and authentication code "1.0".
+// Their data is the four letter token decode asks for followed by the
+// payload. Decoders skip application extensions they do not know.
+
+const SIGNATURES: [&[u8]; 2] = [b"GIF87a", b"GIF89a"];
+const SCREEN_DESCRIPTOR_END: usize = 13;
+
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+const APPLICATION: u8 = 0xff;
+const GRAPHIC_CONTROL: u8 = 0xf9;
+
+// the block size, identifier and authentication code starting a payload extension
+const APPLICATION_HEADER: &[u8] = b"\x0bPNGCrypt1.0";
+const MAX_SUB_BLOCK: usize = 255;
+
+#[derive(Debug)]
+pub enum GifError {
+    InvalidSignature,
+    Truncated(usize),
+    InvalidBlock(usize),
+}
+
+impl std::error::Error for GifError {}
+
+impl fmt::Display for GifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GifError::InvalidSignature => write!(f, "The file does not start with the GIF signature"),
+            GifError::Truncated(offset) => write!(f, "The GIF file is cut off in the block at offset {}", offset),
+            GifError::InvalidBlock(offset) => write!(f, "Unknown block introducer at offset {}", offset),
+        }
+    }
+}
+
+// the bytes of an extension or an image, kept as they are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    bytes: Vec<u8>,
+}
+
+impl Block {
+    pub fn is_image(&self) -> bool {
+        self.bytes[0] == IMAGE
+    }
+
+    // the label of an extension
+    pub fn label(&self) -> Option<u8> {
+        (self.bytes[0] == EXTENSION).then_some(self.bytes[1])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // the token and the payload of a payload extension
+    fn payload(&self) -> Option<(String, Vec<u8>)> {
+        let sub_blocks = self.bytes.strip_prefix(&[EXTENSION, APPLICATION])?.strip_prefix(APPLICATION_HEADER)?;
+
+        let mut data = Vec::new();
+        let mut offset = 0;
+        while let Some(&length) = sub_blocks.get(offset).filter(|&&length| length > 0) {
+            data.extend(sub_blocks.get(offset + 1..offset + 1 + length as usize)?);
+            offset += 1 + length as usize;
+        }
+
+        if data.len() < 4 {
+            return None;
+        }
+
+        let payload = data.split_off(4);
+        Some((String::from_utf8_lossy(&data).into_owned(), payload))
+    }
+
+    fn new_payload(token: &str, payload: &[u8]) -> Block {
+        let mut data = token.as_bytes().to_vec();
+        data.extend(payload);
+
+        let mut bytes = vec![EXTENSION, APPLICATION];
+        bytes.extend(APPLICATION_HEADER);
+        for sub_block in data.chunks(MAX_SUB_BLOCK) {
+            bytes.push(sub_block.len() as u8);
+            bytes.extend(sub_block);
+        }
+        bytes.push(0);
+
+        Block { bytes }
+    }
+}
+
+pub struct Gif {
+    // the signature, the logical screen descriptor and the global color table
+    header: Vec<u8>,
+    blocks: Vec<Block>,
+    // bytes found after the trailer
+    trailing: Vec<u8>,
+}
+
+// the offset after the sub-blocks starting at `offset`
+fn skip_sub_blocks(bytes: &[u8], mut offset: usize, block: usize) -> Result<usize> {
+    loop {
+        let length = *bytes.get(offset).ok_or(GifError::Truncated(block))? as usize;
+        offset += 1 + length;
+
+        if length == 0 {
+            return Ok(offset);
+        }
+    }
+}
+
+// the size of the color table a packed fields byte announces
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 0x07) + 1)
+    }
+}
+
+impl Gif {
+    pub fn parse(bytes: &[u8]) -> Result<Gif> {
+        if !SIGNATURES.iter().any(|signature| bytes.starts_with(signature)) {
+            return Err(GifError::InvalidSignature.into());
+        }
+
+        let packed = *bytes.get(SCREEN_DESCRIPTOR_END - 3).ok_or(GifError::Truncated(0))?;
+        let header_end = SCREEN_DESCRIPTOR_END + color_table_size(packed);
+        let header = bytes.get(..header_end).ok_or(GifError::Truncated(0))?.to_vec();
+
+        let mut blocks = Vec::new();
+        let mut offset = header_end;
+
+        loop {
+            let end = match bytes.get(offset) {
+                Some(&EXTENSION) => skip_sub_blocks(bytes, offset + 2, offset)?,
+                Some(&IMAGE) => {
+                    let packed = *bytes.get(offset + 9).ok_or(GifError::Truncated(offset))?;
+                    // the descriptor, the local color table and the LZW minimum code size
+                    skip_sub_blocks(bytes, offset + 11 + color_table_size(packed), offset)?
+                }
+                Some(&TRAILER) => {
+                    return Ok(Gif {
+                        header,
+                        blocks,
+                        trailing: bytes[offset + 1..].to_vec(),
+                    })
+                }
+                Some(_) => return Err(GifError::InvalidBlock(offset).into()),
+                None => return Err(GifError::Truncated(offset).into()),
+            };
+
+            let block = bytes.get(offset..end).ok_or(GifError::Truncated(offset))?;
+            blocks.push(Block { bytes: block.to_vec() });
+            offset = end;
+        }
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+
+        for block in self.blocks.iter() {
+            bytes.extend(&block.bytes);
+        }
+
+        bytes.push(TRAILER);
+        bytes.extend(&self.trailing);
+        bytes
+    }
+
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    // whether a block can go at `index`, which is not the case between a
+    // graphic control extension and the image it applies to
+    fn is_insertable(&self, index: usize) -> bool {
+        index == 0 || self.blocks[index - 1].label() != Some(GRAPHIC_CONTROL)
+    }
+
+    fn payloads(&self) -> impl Iterator<Item = (usize, String, Vec<u8>)> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| block.payload().map(|(token, payload)| (index, token, payload)))
+    }
+}
+
+impl Carrier for Gif {
+    fn format(&self) -> &'static str {
+        "GIF"
+    }
+
+    // sub-blocks can be chained without limit
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let token = std::iter::repeat_with(|| png::new_chunk_type().to_string())
+            .find(|token| self.payloads().all(|(_, used, _)| used != *token))
+            .unwrap();
+        self.embed_as(payload, &token, position)?;
+
+        Ok(token)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.payloads().any(|(_, used, _)| used == token) {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let first_image = self.blocks.iter().position(Block::is_image).unwrap_or(self.blocks.len());
+        let mut index = match position {
+            Position::AfterIhdr => 0,
+            Position::BeforeIdat => first_image,
+            Position::BeforeIend => self.blocks.len(),
+            Position::Random => rand::thread_rng().gen_range(0..=self.blocks.len()),
+        };
+
+        while !self.is_insertable(index) {
+            index -= 1;
+        }
+
+        self.blocks.insert(index, Block::new_payload(token, payload));
+
+        // extensions were introduced with version 89a
+        self.header[..6].copy_from_slice(SIGNATURES[1]);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        self.payloads()
+            .find(|(_, token, _)| token == selector)
+            .map(|(_, _, payload)| payload)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let (index, _, payload) = self
+            .payloads()
+            .find(|(_, token, _)| token == selector)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        self.blocks.remove(index);
+        Ok(payload)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.payloads()
+            .map(|(_, selector, payload)| Slot {
+                selector,
+                length: payload.len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 1x1 GIF with a two color global table, a graphic control extension, an image and the trailer
+    fn testing_gif() -> Vec<u8> {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend([1, 0, 1, 0, 0x80, 0, 0]);
+        bytes.extend([0, 0, 0, 255, 255, 255]);
+        bytes.extend([EXTENSION, GRAPHIC_CONTROL, 4, 0, 10, 0, 0, 0]);
+        bytes.extend([IMAGE, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+        bytes.extend([2, 2, 0x4c, 0x01, 0]);
+        bytes.push(TRAILER);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_gif() {
+        let mut bytes = testing_gif();
+        let gif = Gif::parse(&bytes).unwrap();
+
+        assert_eq!(gif.blocks().len(), 2);
+        assert_eq!(gif.blocks()[0].label(), Some(GRAPHIC_CONTROL));
+        assert!(gif.blocks()[1].is_image());
+        assert_eq!(gif.as_bytes(), bytes);
+
+        bytes.extend(b"appended");
+        assert_eq!(Gif::parse(&bytes).unwrap().trailing(), b"appended");
+
+        assert!(Gif::parse(&bytes[..20]).is_err());
+        assert!(Gif::parse(b"\x89PNG").is_err());
+    }
+
+    #[test]
+    fn test_gif_carrier() {
+        let mut gif = Gif::parse(&testing_gif()).unwrap();
+        let payload: Vec<u8> = (0..600).map(|byte| byte as u8).collect();
+
+        let token = gif.embed(&payload, Position::BeforeIdat).unwrap();
+
+        // the payload does not come between the graphic control extension and its image
+        let mut gif = Gif::parse(&gif.as_bytes()).unwrap();
+        assert_eq!(gif.blocks()[0].label(), Some(APPLICATION));
+        assert_eq!(gif.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+        assert_eq!(gif.extract(&token).unwrap(), payload);
+
+        gif.remove(&token).unwrap();
+        assert_eq!(gif.as_bytes(), testing_gif());
+    }
+}
@@ -0,0 +1,271 @@
+use super::carrier::{Carrier, CarrierError, Slot};
+use super::chunk::Chunk;
+use super::chunk_type::ChunkType;
+use super::png::{self, Png, Position};
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use rand::seq::SliceRandom;
+
+// An ICO (or CUR) file bundles several sizes of the same icon: a 6 byte header
+// with the image count, a 16 byte directory entry per image giving its size
+// and offset, and the images, each a complete PNG file or a BMP without its
+// file header.
+//
+// Payloads go in the PNG members, as a chunk whose type is the token, like in
+// any PNG file. `--position` tells where in the member the chunk goes; the
+// first PNG member gets it, or a random one with `random`. The images are
+// written back one after the other with the directory updated to match.
+
+const HEADER_LENGTH: usize = 6;
+const ENTRY_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum IcoError {
+    InvalidHeader,
+    Truncated(usize),
+    NoPngMember,
+}
+
+impl std::error::Error for IcoError {}
+
+impl fmt::Display for IcoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IcoError::InvalidHeader => write!(f, "The file does not start with an icon or cursor header"),
+            IcoError::Truncated(image) => write!(f, "Image {} of the icon lies outside the file", image),
+            IcoError::NoPngMember => write!(f, "The icon has no PNG images to hide data in, only BMP ones"),
+        }
+    }
+}
+
+pub enum Image {
+    Png(Png),
+    Bmp(Vec<u8>),
+}
+
+impl Image {
+    fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Image::Png(png) => png.as_bytes(),
+            Image::Bmp(bytes) => bytes.clone(),
+        }
+    }
+}
+
+pub struct Member {
+    // the directory entry up to the image size: dimensions, colors, planes and bit depth
+    pub entry: [u8; 8],
+    pub image: Image,
+}
+
+pub struct Ico {
+    // 1 for icons, 2 for cursors
+    kind: u16,
+    members: Vec<Member>,
+}
+
+impl Ico {
+    pub fn is_ico(bytes: &[u8]) -> bool {
+        bytes.starts_with(&[0, 0, 1, 0]) || bytes.starts_with(&[0, 0, 2, 0])
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Ico> {
+        if !Ico::is_ico(bytes) || bytes.len() < HEADER_LENGTH {
+            return Err(IcoError::InvalidHeader.into());
+        }
+
+        let u16_at = |offset: usize| u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap());
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+
+        let count = u16_at(4) as usize;
+        if bytes.len() < HEADER_LENGTH + count * ENTRY_LENGTH {
+            return Err(IcoError::InvalidHeader.into());
+        }
+
+        let mut members = Vec::new();
+        for index in 0..count {
+            let entry = HEADER_LENGTH + index * ENTRY_LENGTH;
+            let (size, offset) = (u32_at(entry + 8), u32_at(entry + 12));
+
+            let data = bytes
+                .get(offset..offset.saturating_add(size))
+                .ok_or(IcoError::Truncated(index))?;
+
+            // a PNG member that does not read is kept as it is, like a BMP one
+            let image = if data.starts_with(&Png::STANDARD_HEADER) {
+                Png::try_from(data).map(Image::Png).unwrap_or_else(|_| Image::Bmp(data.to_vec()))
+            } else {
+                Image::Bmp(data.to_vec())
+            };
+
+            members.push(Member {
+                entry: bytes[entry..entry + 8].try_into().unwrap(),
+                image,
+            });
+        }
+
+        Ok(Ico {
+            kind: u16_at(2),
+            members,
+        })
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    fn pngs(&self) -> impl Iterator<Item = &Png> {
+        self.members.iter().filter_map(|member| match &member.image {
+            Image::Png(png) => Some(png),
+            Image::Bmp(_) => None,
+        })
+    }
+
+    fn pngs_mut(&mut self) -> impl Iterator<Item = &mut Png> {
+        self.members.iter_mut().filter_map(|member| match &mut member.image {
+            Image::Png(png) => Some(png),
+            Image::Bmp(_) => None,
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let images: Vec<Vec<u8>> = self.members.iter().map(|member| member.image.as_bytes()).collect();
+
+        let mut bytes = vec![0, 0];
+        bytes.extend(self.kind.to_le_bytes());
+        bytes.extend((self.members.len() as u16).to_le_bytes());
+
+        let mut offset = HEADER_LENGTH + self.members.len() * ENTRY_LENGTH;
+        for (member, image) in self.members.iter().zip(images.iter()) {
+            bytes.extend(member.entry);
+            bytes.extend((image.len() as u32).to_le_bytes());
+            bytes.extend((offset as u32).to_le_bytes());
+            offset += image.len();
+        }
+
+        bytes.extend(images.concat());
+        bytes
+    }
+}
+
+impl Carrier for Ico {
+    fn format(&self) -> &'static str {
+        "ICO"
+    }
+
+    fn capacity(&self) -> usize {
+        self.pngs().map(Carrier::capacity).max().unwrap_or(0)
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        // a chunk type any member already has would make the token ambiguous
+        let chunk_type = std::iter::repeat_with(png::new_chunk_type)
+            .find(|chunk_type| {
+                self.pngs()
+                    .all(|png| png.chunks().all(|chunk| chunk.chunk_type != *chunk_type))
+            })
+            .unwrap();
+        let selector = chunk_type.to_string();
+        self.embed_as(payload, &selector, position)?;
+
+        Ok(selector)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        let chunk_type = ChunkType::from_str(token)?;
+        chunk_type.check_payload_type()?;
+        if self.pngs().any(|png| png.chunks().any(|chunk| chunk.chunk_type == chunk_type)) {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let mut pngs: Vec<&mut Png> = self.pngs_mut().collect();
+        let png = match position {
+            Position::Random => pngs.choose_mut(&mut rand::thread_rng()),
+            _ => pngs.first_mut(),
+        }
+        .ok_or(IcoError::NoPngMember)?;
+
+        png.insert_chunk_at(Chunk::new(chunk_type, payload.to_vec()), position);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        self.pngs()
+            .find_map(|png| png.extract(selector).ok())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        self.pngs_mut()
+            .find_map(|png| png.remove(selector).ok())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.pngs().flat_map(Carrier::list).collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::envelope;
+    use std::str::FromStr;
+
+    // an icon with a BMP image and a PNG image
+    fn testing_ico() -> Vec<u8> {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![1, 2, 3]);
+        let png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]).as_bytes();
+        let bmp = vec![40, 0, 0, 0, 9, 9, 9, 9];
+
+        let mut bytes = vec![0, 0, 1, 0, 2, 0];
+        bytes.extend([16, 16, 0, 0, 1, 0, 32, 0]);
+        bytes.extend((bmp.len() as u32).to_le_bytes());
+        bytes.extend(38u32.to_le_bytes());
+        bytes.extend([0, 0, 0, 0, 1, 0, 32, 0]);
+        bytes.extend((png.len() as u32).to_le_bytes());
+        bytes.extend((38 + bmp.len() as u32).to_le_bytes());
+        bytes.extend(bmp);
+        bytes.extend(png);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_ico() {
+        let ico = Ico::parse(&testing_ico()).unwrap();
+
+        assert_eq!(ico.members().len(), 2);
+        assert!(matches!(ico.members()[0].image, Image::Bmp(_)));
+        assert!(matches!(ico.members()[1].image, Image::Png(_)));
+        assert_eq!(ico.as_bytes(), testing_ico());
+
+        assert!(Ico::parse(&testing_ico()[..40]).is_err());
+        assert!(Ico::parse(b"BM").is_err());
+    }
+
+    #[test]
+    fn test_ico_carrier() {
+        let mut ico = Ico::parse(&testing_ico()).unwrap();
+        let payload = envelope::seal(b"secret", None, None).unwrap();
+
+        let token = ico.embed(&payload, Position::BeforeIend).unwrap();
+
+        let mut ico = Ico::parse(&ico.as_bytes()).unwrap();
+        assert!(matches!(&ico.members()[1].image, Image::Png(png) if png.chunk_by_type(&token).is_some()));
+        assert_eq!(ico.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+        assert_eq!(ico.extract(&token).unwrap(), payload);
+
+        assert_eq!(ico.remove(&token).unwrap(), payload);
+        assert_eq!(ico.as_bytes(), testing_ico());
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+// Above this share of the highest entropy a chunk of its size can reach, its
+// data looks random: encrypted or compressed.
+const HIGH_ENTROPY_RATIO: f64 = 0.9;
+// too few bytes to tell random data from anything else
+const MIN_ENTROPY_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    Empty,
+    Text,
+    Binary,
+    HighEntropy,
+}
+
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Content::Empty => write!(f, "empty"),
+            Content::Text => write!(f, "text"),
+            Content::Binary => write!(f, "binary"),
+            Content::HighEntropy => write!(f, "high entropy"),
+        }
+    }
+}
+
+// Shannon entropy of `data`, in bits per byte (0 to 8)
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// printable ASCII and whitespace, plus the NUL separating tEXt keywords from their text
+fn is_text(data: &[u8]) -> bool {
+    data.iter()
+        .all(|&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace() || byte == 0)
+}
+
+pub fn classify(data: &[u8]) -> Content {
+    if data.is_empty() {
+        return Content::Empty;
+    }
+
+    if is_text(data) {
+        return Content::Text;
+    }
+
+    // short data can not reach 8 bits per byte, compare against what it could reach
+    let max_entropy = (data.len().min(256) as f64).log2();
+
+    if data.len() >= MIN_ENTROPY_LENGTH && entropy(data) > max_entropy * HIGH_ENTROPY_RATIO {
+        Content::HighEntropy
+    } else {
+        Content::Binary
+    }
+}
+
+// An offset, hex and ASCII view of `data`, 16 bytes a line, like `hexdump -C`.
+pub fn hexdump(data: &[u8]) -> String {
+    let mut lines = Vec::new();
+
+    for (index, row) in data.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for position in 0..16 {
+            match row.get(position) {
+                Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                None => hex.push_str("   "),
+            }
+            // a wider gap between the two halves
+            if position == 7 {
+                hex.push(' ');
+            }
+        }
+
+        let ascii: String = row
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+
+        lines.push(format!("{:08x}  {} |{}|", index * 16, hex, ascii));
+    }
+
+    lines.push(format!("{:08x}", data.len()));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&[0, 1, 0, 1]), 1.0);
+
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(entropy(&all), 8.0);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(b""), Content::Empty);
+        assert_eq!(classify(b"Title\0A picture of dice"), Content::Text);
+        assert_eq!(classify(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]), Content::Binary);
+
+        let random: Vec<u8> = (0..64u32).map(|i| (i * 97 + 13) as u8 ^ 0x5a).collect();
+        assert_eq!(classify(&random), Content::HighEntropy);
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let dump = hexdump(b"Title\0A picture of dice");
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "00000000  54 69 74 6c 65 00 41 20  70 69 63 74 75 72 65 20  |Title.A picture |",
+                "00000010  6f 66 20 64 69 63 65                              |of dice|",
+                "00000017",
+            ]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// The backups kept of a file changed in place, so it can be restored. Each
+// backup is a copy of the file as it was before a change: <file>.bak for the
+// first one, then <file>.bak.1, <file>.bak.2 and so on. The journal,
+// <file>.journal, has a line of JSON for every backup made and every backup
+// restored. The backups made and not restored yet whose copy is still there
+// are the restore points, the latest one last.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Backup,
+    Undo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    // Unix time
+    pub time: u64,
+    pub action: Action,
+    // the number of the backup, see `backup_path`
+    pub backup: u32,
+}
+
+impl Entry {
+    pub fn new(action: Action, backup: u32) -> Entry {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Entry { time, action, backup }
+    }
+}
+
+fn with_suffix(file: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(file.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+pub fn backup_path(file: &Path, backup: u32) -> PathBuf {
+    match backup {
+        0 => with_suffix(file, ".bak"),
+        _ => with_suffix(file, &format!(".bak.{}", backup)),
+    }
+}
+
+pub fn journal_path(file: &Path) -> PathBuf {
+    with_suffix(file, ".journal")
+}
+
+// the entries of the journal of `file`, none when it has no journal yet
+pub fn read(file: &Path) -> Result<Vec<Entry>> {
+    let path = journal_path(file);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = std::fs::read_to_string(&path).context(format!("Unable to read {path:?}."))?;
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).context(format!("{path:?} holds an entry that can not be read.")))
+        .collect()
+}
+
+pub fn record(file: &Path, entry: &Entry) -> Result<()> {
+    let path = journal_path(file);
+
+    let mut journal = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("Unable to write to {path:?}."))?;
+    writeln!(journal, "{}", serde_json::to_string(entry)?)?;
+
+    Ok(())
+}
+
+// the backups made and not restored yet, in the order they were made
+pub fn pending(entries: &[Entry]) -> Vec<&Entry> {
+    let mut pending: Vec<&Entry> = Vec::new();
+
+    for entry in entries {
+        match entry.action {
+            Action::Backup => pending.push(entry),
+            Action::Undo => pending.retain(|backup| backup.backup != entry.backup),
+        }
+    }
+
+    pending
+}
+
+// the pending backups of `file` that are still there, the latest one last
+pub fn restore_points(file: &Path) -> Result<Vec<Entry>> {
+    let entries = read(file)?;
+
+    Ok(pending(&entries)
+        .into_iter()
+        .filter(|entry| backup_path(file, entry.backup).exists())
+        .cloned()
+        .collect())
+}
+
+// the first backup number neither the journal nor a file on disk uses
+pub fn next_backup(file: &Path) -> Result<u32> {
+    let entries = read(file)?;
+
+    Ok((0..)
+        .find(|&backup| {
+            entries.iter().all(|entry| entry.backup != backup) && !backup_path(file, backup).exists()
+        })
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_path() {
+        let file = Path::new("photos/cover.png");
+
+        assert_eq!(backup_path(file, 0), PathBuf::from("photos/cover.png.bak"));
+        assert_eq!(backup_path(file, 2), PathBuf::from("photos/cover.png.bak.2"));
+        assert_eq!(journal_path(file), PathBuf::from("photos/cover.png.journal"));
+    }
+
+    #[test]
+    fn test_pending() {
+        let entry = |action, backup| Entry { time: 0, action, backup };
+        let entries = vec![
+            entry(Action::Backup, 0),
+            entry(Action::Backup, 1),
+            entry(Action::Undo, 1),
+            entry(Action::Backup, 2),
+        ];
+
+        let pending: Vec<u32> = pending(&entries).iter().map(|entry| entry.backup).collect();
+        assert_eq!(pending, vec![0, 2]);
+
+        let line = serde_json::to_string(&entries[2]).unwrap();
+        assert_eq!(line, r#"{"time":0,"action":"undo","backup":1}"#);
+        assert_eq!(serde_json::from_str::<Entry>(&line).unwrap(), entries[2]);
+    }
+}
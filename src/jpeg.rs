@@ -0,0 +1,336 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::png::{self, Position};
+
+use std::fmt;
+
+use anyhow::Result;
+use rand::Rng;
+
+// A JPEG file is a list of marker segments: a 0xFF byte, a marker byte and,
+// for most markers, a two byte length followed by the segment data. The
+// entropy coded image data follows the first SOS (start of scan) segment and
+// runs up to EOI (end of image); it is kept as it is.
+//
+// Payloads go in APP15 segments, which no common application uses, starting
+// with an identifier and the four letter token decode asks for. A segment
+// holds at most 64 KiB, so larger payloads are split over several segments
+// numbered in order. Payloads written to COM (comment) segments the same way
+// are read as well.
+
+pub const SIGNATURE: [u8; 2] = [0xff, 0xd8];
+
+const SOS: u8 = 0xda;
+const EOI: [u8; 2] = [0xff, 0xd9];
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP15: u8 = 0xef;
+const COM: u8 = 0xfe;
+
+const IDENTIFIER: &[u8] = b"PNGCrypt\0";
+// identifier, token and part number
+const PART_HEADER: usize = 9 + 4 + 1;
+// the length field counts itself
+const MAX_PART: usize = u16::MAX as usize - 2 - PART_HEADER;
+const MAX_PARTS: usize = 256;
+
+#[derive(Debug)]
+pub enum JpegError {
+    InvalidSignature,
+    Truncated(usize),
+    InvalidMarker(usize),
+    TooLarge(usize),
+}
+
+impl std::error::Error for JpegError {}
+
+impl fmt::Display for JpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JpegError::InvalidSignature => write!(f, "The file does not start with the JPEG signature"),
+            JpegError::Truncated(offset) => write!(f, "The JPEG file is cut off in the segment at offset {}", offset),
+            JpegError::InvalidMarker(offset) => write!(f, "Expected a marker at offset {}", offset),
+            JpegError::TooLarge(length) => write!(
+                f,
+                "A payload of {} bytes does not fit in a JPEG file, at most {} bytes do",
+                length,
+                MAX_PART * MAX_PARTS
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub marker: u8,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    // the token and part number of a segment holding a payload part, with the part
+    fn part(&self) -> Option<(&[u8], u8, &[u8])> {
+        if self.marker != APP15 && self.marker != COM {
+            return None;
+        }
+
+        let rest = self.data.strip_prefix(IDENTIFIER)?;
+        if rest.len() < 5 {
+            return None;
+        }
+
+        Some((&rest[..4], rest[4], &rest[5..]))
+    }
+}
+
+pub struct Jpeg {
+    // the segments before the first scan
+    segments: Vec<Segment>,
+    // the first SOS segment and everything after it up to and including EOI
+    scan: Vec<u8>,
+    // bytes found after EOI
+    trailing: Vec<u8>,
+}
+
+impl Jpeg {
+    pub fn parse(bytes: &[u8]) -> Result<Jpeg> {
+        if !bytes.starts_with(&SIGNATURE) {
+            return Err(JpegError::InvalidSignature.into());
+        }
+
+        let mut segments = Vec::new();
+        let mut offset = SIGNATURE.len();
+
+        loop {
+            if bytes.get(offset) != Some(&0xff) {
+                return Err(JpegError::InvalidMarker(offset).into());
+            }
+
+            // any number of 0xFF bytes may pad the space before a marker
+            let marker_offset = offset;
+            while bytes.get(offset) == Some(&0xff) {
+                offset += 1;
+            }
+            let marker = *bytes.get(offset).ok_or(JpegError::Truncated(marker_offset))?;
+
+            if marker == SOS {
+                let scan = &bytes[marker_offset..];
+                let end = scan
+                    .windows(2)
+                    .position(|window| window == EOI)
+                    .map(|position| position + 2)
+                    .unwrap_or(scan.len());
+
+                return Ok(Jpeg {
+                    segments,
+                    scan: scan[..end].to_vec(),
+                    trailing: scan[end..].to_vec(),
+                });
+            }
+
+            let length = bytes
+                .get(offset + 1..offset + 3)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                .ok_or(JpegError::Truncated(marker_offset))?;
+            let data = bytes
+                .get(offset + 3..offset + 1 + length.max(2))
+                .ok_or(JpegError::Truncated(marker_offset))?;
+
+            segments.push(Segment {
+                marker,
+                data: data.to_vec(),
+            });
+            offset += 1 + length.max(2);
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+
+        for segment in self.segments.iter() {
+            bytes.extend([0xff, segment.marker]);
+            bytes.extend(((segment.data.len() + 2) as u16).to_be_bytes());
+            bytes.extend(&segment.data);
+        }
+
+        bytes.extend(&self.scan);
+        bytes.extend(&self.trailing);
+        bytes
+    }
+
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    // the index new segments go at: JFIF and EXIF segments have to come first
+    fn first_index(&self) -> usize {
+        self.segments
+            .iter()
+            .take_while(|segment| segment.marker == APP0 || segment.marker == APP1)
+            .count()
+    }
+
+    // the parts stored under `token`, in order
+    fn parts(&self, token: &str) -> Vec<(u8, &[u8])> {
+        let mut parts: Vec<(u8, &[u8])> = self
+            .segments
+            .iter()
+            .filter_map(Segment::part)
+            .filter(|(part_token, _, _)| *part_token == token.as_bytes())
+            .map(|(_, number, data)| (number, data))
+            .collect();
+
+        parts.sort_by_key(|(number, _)| *number);
+        parts
+    }
+}
+
+impl Carrier for Jpeg {
+    fn format(&self) -> &'static str {
+        "JPEG"
+    }
+
+    fn capacity(&self) -> usize {
+        MAX_PART * MAX_PARTS
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let token = std::iter::repeat_with(|| png::new_chunk_type().to_string())
+            .find(|token| self.parts(token).is_empty())
+            .unwrap();
+        self.embed_as(payload, &token, position)?;
+
+        Ok(token)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if payload.len() > self.capacity() {
+            return Err(JpegError::TooLarge(payload.len()).into());
+        }
+        if !self.parts(token).is_empty() {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let first = self.first_index();
+        let index = match position {
+            Position::AfterIhdr => first,
+            Position::BeforeIdat | Position::BeforeIend => self.segments.len(),
+            Position::Random => rand::thread_rng().gen_range(first..=self.segments.len()),
+        };
+
+        // an empty payload still takes a segment, so it can be found
+        let parts: Vec<&[u8]> = if payload.is_empty() { vec![payload] } else { payload.chunks(MAX_PART).collect() };
+
+        let segments: Vec<Segment> = parts
+            .into_iter()
+            .enumerate()
+            .map(|(number, part)| {
+                let mut data = IDENTIFIER.to_vec();
+                data.extend(token.as_bytes());
+                data.push(number as u8);
+                data.extend(part);
+
+                Segment { marker: APP15, data }
+            })
+            .collect();
+
+        self.segments.splice(index..index, segments);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        let parts = self.parts(selector);
+        if parts.is_empty() {
+            return Err(CarrierError::NotFound(selector.to_string()).into());
+        }
+
+        Ok(parts.into_iter().flat_map(|(_, data)| data.to_vec()).collect())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let payload = self.extract(selector)?;
+
+        self.segments
+            .retain(|segment| segment.part().map(|(token, _, _)| token != selector.as_bytes()).unwrap_or(true));
+
+        Ok(payload)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        let mut slots: Vec<Slot> = Vec::new();
+
+        for (token, _, data) in self.segments.iter().filter_map(Segment::part) {
+            let selector = String::from_utf8_lossy(token).into_owned();
+
+            match slots.iter_mut().find(|slot| slot.selector == selector) {
+                Some(slot) => slot.length += data.len(),
+                None => slots.push(Slot {
+                    selector,
+                    length: data.len(),
+                }),
+            }
+        }
+
+        slots
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SOI, a JFIF APP0 segment, a quantization table, a scan and EOI
+    fn testing_jpeg() -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend([0xff, APP0, 0, 7]);
+        bytes.extend(b"JFIF\0");
+        bytes.extend([0xff, 0xdb, 0, 4, 1, 2]);
+        bytes.extend([0xff, SOS, 0, 3, 9, 0x12, 0xff, 0x00, 0x34]);
+        bytes.extend(EOI);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_jpeg() {
+        let mut bytes = testing_jpeg();
+        let jpeg = Jpeg::parse(&bytes).unwrap();
+
+        assert_eq!(jpeg.segments().len(), 2);
+        assert_eq!(jpeg.as_bytes(), bytes);
+
+        bytes.extend(b"appended");
+        assert_eq!(Jpeg::parse(&bytes).unwrap().trailing(), b"appended");
+
+        assert!(Jpeg::parse(&bytes[..8]).is_err());
+        assert!(Jpeg::parse(b"\x89PNG").is_err());
+    }
+
+    #[test]
+    fn test_jpeg_carrier() {
+        let mut jpeg = Jpeg::parse(&testing_jpeg()).unwrap();
+        let payload: Vec<u8> = (0..MAX_PART + 10).map(|byte| byte as u8).collect();
+
+        let token = jpeg.embed(&payload, Position::AfterIhdr).unwrap();
+
+        let mut jpeg = Jpeg::parse(&jpeg.as_bytes()).unwrap();
+        assert_eq!(jpeg.segments()[0].marker, APP0);
+        assert_eq!(jpeg.segments()[1].marker, APP15);
+        assert_eq!(jpeg.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+        assert_eq!(jpeg.extract(&token).unwrap(), payload);
+
+        jpeg.remove(&token).unwrap();
+        assert_eq!(jpeg.as_bytes(), testing_jpeg());
+    }
+}
@@ -0,0 +1,34 @@
+pub mod bmp;
+pub mod carrier;
+pub mod carve;
+pub mod chunk;
+pub mod chunk_type;
+pub mod config;
+pub mod contents;
+pub mod crypto;
+pub mod detect;
+pub mod diff;
+pub mod envelope;
+pub mod gif;
+pub mod ico;
+pub mod inspect;
+pub mod journal;
+pub mod jpeg;
+pub mod lint;
+pub mod lsb;
+pub mod multipart;
+pub mod pdf;
+pub mod png;
+pub mod quality;
+pub mod report;
+pub mod riff;
+pub mod sanitize;
+pub mod scan;
+pub mod selector;
+pub mod stats;
+pub mod steganalysis;
+pub mod survivability;
+pub mod tiff;
+pub mod wav;
+pub mod webp;
+pub mod zip;
@@ -0,0 +1,455 @@
+use super::png::order::{AFTER_PLTE, BEFORE_IDAT, BEFORE_PLTE};
+use super::png::validate::{self, Violation};
+use super::png::Png;
+
+use std::fmt;
+use std::str::FromStr;
+
+// Checks a PNG for compliance with the specification beyond what `verify`
+// covers: unknown critical chunks, ancillary chunks out of place or repeated,
+// IHDR field combinations the specification forbids, empty IDAT chunks and
+// palettes or transparency that do not fit the image.
+//
+// Every issue carries a rule with a stable ID, so a pipeline can allow the
+// ones it does not care about.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    InvalidSignature,
+    Truncated,
+    LengthOutOfBounds,
+    InvalidChunkType,
+    CrcMismatch,
+    IhdrNotFirst,
+    IendNotLast,
+    DuplicateChunk,
+    MissingIdat,
+    IdatNotContiguous,
+    MissingPalette,
+    TrailingData,
+    InvalidIhdr,
+    UnknownCriticalChunk,
+    ChunkOrder,
+    EmptyIdat,
+    InvalidPalette,
+    UnexpectedChunk,
+    InvalidTransparency,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 19] = [
+        Rule::InvalidSignature,
+        Rule::Truncated,
+        Rule::LengthOutOfBounds,
+        Rule::InvalidChunkType,
+        Rule::CrcMismatch,
+        Rule::IhdrNotFirst,
+        Rule::IendNotLast,
+        Rule::DuplicateChunk,
+        Rule::MissingIdat,
+        Rule::IdatNotContiguous,
+        Rule::MissingPalette,
+        Rule::TrailingData,
+        Rule::InvalidIhdr,
+        Rule::UnknownCriticalChunk,
+        Rule::ChunkOrder,
+        Rule::EmptyIdat,
+        Rule::InvalidPalette,
+        Rule::UnexpectedChunk,
+        Rule::InvalidTransparency,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::InvalidSignature => "PNG001",
+            Rule::Truncated => "PNG002",
+            Rule::LengthOutOfBounds => "PNG003",
+            Rule::InvalidChunkType => "PNG004",
+            Rule::CrcMismatch => "PNG005",
+            Rule::IhdrNotFirst => "PNG006",
+            Rule::IendNotLast => "PNG007",
+            Rule::DuplicateChunk => "PNG008",
+            Rule::MissingIdat => "PNG009",
+            Rule::IdatNotContiguous => "PNG010",
+            Rule::MissingPalette => "PNG011",
+            Rule::TrailingData => "PNG012",
+            Rule::InvalidIhdr => "PNG013",
+            Rule::UnknownCriticalChunk => "PNG014",
+            Rule::ChunkOrder => "PNG015",
+            Rule::EmptyIdat => "PNG016",
+            Rule::InvalidPalette => "PNG017",
+            Rule::UnexpectedChunk => "PNG018",
+            Rule::InvalidTransparency => "PNG019",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::InvalidSignature => "invalid-signature",
+            Rule::Truncated => "truncated",
+            Rule::LengthOutOfBounds => "length-out-of-bounds",
+            Rule::InvalidChunkType => "invalid-chunk-type",
+            Rule::CrcMismatch => "crc-mismatch",
+            Rule::IhdrNotFirst => "ihdr-not-first",
+            Rule::IendNotLast => "iend-not-last",
+            Rule::DuplicateChunk => "duplicate-chunk",
+            Rule::MissingIdat => "missing-idat",
+            Rule::IdatNotContiguous => "idat-not-contiguous",
+            Rule::MissingPalette => "missing-palette",
+            Rule::TrailingData => "trailing-data",
+            Rule::InvalidIhdr => "invalid-ihdr",
+            Rule::UnknownCriticalChunk => "unknown-critical-chunk",
+            Rule::ChunkOrder => "chunk-order",
+            Rule::EmptyIdat => "empty-idat",
+            Rule::InvalidPalette => "invalid-palette",
+            Rule::UnexpectedChunk => "unexpected-chunk",
+            Rule::InvalidTransparency => "invalid-transparency",
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    // a rule is named by its ID or its name
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        Rule::ALL
+            .iter()
+            .find(|candidate| candidate.id().eq_ignore_ascii_case(rule) || candidate.name() == rule)
+            .copied()
+            .ok_or(format!("Unknown lint rule: {}", rule))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.rule.id(), self.rule.name(), self.message)
+    }
+}
+
+impl Issue {
+    fn new(rule: Rule, message: String) -> Issue {
+        Issue { rule, message }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.rule.id(),
+            "rule": self.rule.name(),
+            "message": self.message,
+        })
+    }
+}
+
+const CRITICAL_CHUNKS: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+// ancillary chunks that may appear at most once
+const UNIQUE_CHUNKS: [&str; 15] = [
+    "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs", "tIME", "acTL", "eXIf", "oFFs", "pCAL",
+    "sCAL",
+];
+
+fn rule_for(violation: &Violation) -> Option<Rule> {
+    match violation {
+        Violation::InvalidSignature => Some(Rule::InvalidSignature),
+        Violation::Truncated { .. } => Some(Rule::Truncated),
+        Violation::LengthOutOfBounds { .. } => Some(Rule::LengthOutOfBounds),
+        Violation::InvalidChunkType { .. } => Some(Rule::InvalidChunkType),
+        Violation::InvalidCrc { .. } => Some(Rule::CrcMismatch),
+        Violation::FirstChunkNotIhdr => Some(Rule::IhdrNotFirst),
+        Violation::LastChunkNotIend => Some(Rule::IendNotLast),
+        Violation::DuplicateChunk(_) => Some(Rule::DuplicateChunk),
+        Violation::MissingIdat => Some(Rule::MissingIdat),
+        Violation::IdatNotContiguous => Some(Rule::IdatNotContiguous),
+        Violation::MissingPalette => Some(Rule::MissingPalette),
+        Violation::TrailingData { .. } => Some(Rule::TrailingData),
+        Violation::ChunkAfterIend { .. } => Some(Rule::TrailingData),
+        // the IHDR checks below tell what exactly is wrong
+        Violation::InvalidHeader => None,
+    }
+}
+
+fn check_ihdr(data: &[u8]) -> Vec<Issue> {
+    if data.len() != 13 {
+        return vec![Issue::new(
+            Rule::InvalidIhdr,
+            format!("IHDR holds {} bytes instead of 13", data.len()),
+        )];
+    }
+
+    let mut issues = Vec::new();
+    let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let (bit_depth, color_type, compression, filter, interlace) = (data[8], data[9], data[10], data[11], data[12]);
+
+    for (name, value) in [("width", width), ("height", height)] {
+        if value == 0 || value > i32::MAX as u32 {
+            issues.push(Issue::new(
+                Rule::InvalidIhdr,
+                format!("The {} is {}, it has to be between 1 and {}", name, value, i32::MAX),
+            ));
+        }
+    }
+
+    let allowed_depths: &[u8] = match color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        _ => &[],
+    };
+    if allowed_depths.is_empty() {
+        issues.push(Issue::new(Rule::InvalidIhdr, format!("Color type {} does not exist", color_type)));
+    } else if !allowed_depths.contains(&bit_depth) {
+        issues.push(Issue::new(
+            Rule::InvalidIhdr,
+            format!("A bit depth of {} is not allowed with color type {}", bit_depth, color_type),
+        ));
+    }
+
+    if compression != 0 {
+        issues.push(Issue::new(Rule::InvalidIhdr, format!("Compression method {} does not exist", compression)));
+    }
+    if filter != 0 {
+        issues.push(Issue::new(Rule::InvalidIhdr, format!("Filter method {} does not exist", filter)));
+    }
+    if interlace > 1 {
+        issues.push(Issue::new(Rule::InvalidIhdr, format!("Interlace method {} does not exist", interlace)));
+    }
+
+    issues
+}
+
+fn check_chunk_types(types: &[String]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    for (index, chunk_type) in types.iter().enumerate() {
+        let critical = chunk_type.as_bytes()[0].is_ascii_uppercase();
+        let reported = types[..index].contains(chunk_type);
+
+        if critical && !reported && !CRITICAL_CHUNKS.contains(&chunk_type.as_str()) {
+            issues.push(Issue::new(
+                Rule::UnknownCriticalChunk,
+                format!("{} is marked critical but is not defined, decoders have to reject the image", chunk_type),
+            ));
+        }
+    }
+
+    for unique in UNIQUE_CHUNKS {
+        let count = types.iter().filter(|chunk_type| *chunk_type == unique).count();
+        if count > 1 {
+            issues.push(Issue::new(
+                Rule::DuplicateChunk,
+                format!("There are {} {} chunks, only one is allowed", count, unique),
+            ));
+        }
+    }
+
+    let first = |name: &str| types.iter().position(|chunk_type| chunk_type == name);
+    let plte = first("PLTE");
+    let idat = first("IDAT");
+
+    for (index, chunk_type) in types.iter().enumerate() {
+        let name = chunk_type.as_str();
+        let after = |position: Option<usize>| position.map(|position| index > position).unwrap_or(false);
+
+        let misplaced = if BEFORE_PLTE.contains(&name) {
+            (after(plte) || after(idat)).then_some("before PLTE and IDAT")
+        } else if AFTER_PLTE.contains(&name) {
+            (plte.map(|plte| index < plte).unwrap_or(false) || after(idat)).then_some("after PLTE and before IDAT")
+        } else if BEFORE_IDAT.contains(&name) || name == "PLTE" {
+            after(idat).then_some("before IDAT")
+        } else {
+            None
+        };
+
+        if let Some(place) = misplaced {
+            issues.push(Issue::new(Rule::ChunkOrder, format!("Chunk {} ({}) has to come {}", index, name, place)));
+        }
+    }
+
+    if first("iCCP").is_some() && first("sRGB").is_some() {
+        issues.push(Issue::new(
+            Rule::UnexpectedChunk,
+            "Both iCCP and sRGB are present, only one of them is allowed".to_string(),
+        ));
+    }
+
+    issues
+}
+
+fn check_palette(png: &Png, bit_depth: u8, color_type: u8) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let plte = png.chunk_by_type("PLTE").map(|chunk| chunk.data());
+    let trns = png.chunk_by_type("tRNS").map(|chunk| chunk.data());
+
+    if let Some(plte) = plte {
+        if color_type == 0 || color_type == 4 {
+            issues.push(Issue::new(
+                Rule::UnexpectedChunk,
+                format!("PLTE is not allowed in images of color type {}", color_type),
+            ));
+        }
+
+        let entries = plte.len() / 3;
+        let limit = if color_type == 3 { 1usize << bit_depth.min(8) } else { 256 };
+
+        if plte.is_empty() || !plte.len().is_multiple_of(3) {
+            issues.push(Issue::new(
+                Rule::InvalidPalette,
+                format!("PLTE holds {} bytes, which is not a whole number of colors", plte.len()),
+            ));
+        } else if entries > limit {
+            issues.push(Issue::new(
+                Rule::InvalidPalette,
+                format!("PLTE holds {} colors, at most {} are allowed", entries, limit),
+            ));
+        }
+    }
+
+    if png.chunk_by_type("hIST").is_some() && plte.is_none() {
+        issues.push(Issue::new(Rule::UnexpectedChunk, "hIST is only allowed with a PLTE chunk".to_string()));
+    }
+
+    if let Some(trns) = trns {
+        let expected = match color_type {
+            0 => Some(trns.len() == 2),
+            2 => Some(trns.len() == 6),
+            3 => Some(trns.len() <= plte.map(|plte| plte.len() / 3).unwrap_or(0)),
+            _ => None,
+        };
+
+        match expected {
+            None => issues.push(Issue::new(
+                Rule::UnexpectedChunk,
+                format!("tRNS is not allowed in images of color type {}, which have an alpha channel", color_type),
+            )),
+            Some(false) => issues.push(Issue::new(
+                Rule::InvalidTransparency,
+                format!("tRNS holds {} bytes, which does not fit color type {}", trns.len(), color_type),
+            )),
+            Some(true) => {}
+        }
+    }
+
+    issues
+}
+
+fn check_png(png: &Png) -> Vec<Issue> {
+    let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+    let mut issues = check_chunk_types(&types);
+
+    for (chunk, offset) in png.chunks().zip(png.chunk_offsets()) {
+        if chunk.chunk_type.bytes == *b"IDAT" && chunk.data().is_empty() {
+            issues.push(Issue::new(Rule::EmptyIdat, format!("The IDAT chunk at offset {} is empty", offset)));
+        }
+    }
+
+    if let Some(ihdr) = png.chunks().next().filter(|chunk| chunk.chunk_type.bytes == *b"IHDR") {
+        let data = ihdr.data();
+        issues.extend(check_ihdr(data));
+
+        if data.len() == 13 {
+            issues.extend(check_palette(png, data[8], data[9]));
+        }
+    }
+
+    issues
+}
+
+pub fn lint_bytes(bytes: &[u8]) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = validate::validate_bytes(bytes)
+        .iter()
+        .filter_map(|violation| Some(Issue::new(rule_for(violation)?, violation.to_string())))
+        .collect();
+
+    // problems with the chunks themselves are reported above, the rules below
+    // look at whatever chunks can be read
+    let png = Png::try_from_ignoring_crc(bytes)
+        .map(|(png, _)| png)
+        .or_else(|_| Png::salvage(bytes, true).map(|(png, _)| png));
+
+    if let Ok(png) = png {
+        issues.extend(check_png(&png));
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn rules(chunks: Vec<Chunk>) -> Vec<Rule> {
+        lint_bytes(&Png::from_chunks(chunks).as_bytes())
+            .iter()
+            .map(|issue| issue.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_png() {
+        let chunks = vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ];
+
+        assert!(rules(chunks).is_empty());
+    }
+
+    #[test]
+    fn test_lint_rules() {
+        let chunks = vec![
+            // a bit depth of 4 is not allowed for RGB
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0]),
+            chunk("IDAT", &[]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("ABCD", &[]),
+            chunk("tIME", &[0; 7]),
+            chunk("tIME", &[0; 7]),
+            chunk("IEND", &[]),
+        ];
+
+        let rules = rules(chunks);
+
+        assert!(rules.contains(&Rule::InvalidIhdr));
+        assert!(rules.contains(&Rule::EmptyIdat));
+        assert!(rules.contains(&Rule::ChunkOrder));
+        assert!(rules.contains(&Rule::UnknownCriticalChunk));
+        assert!(rules.contains(&Rule::DuplicateChunk));
+    }
+
+    #[test]
+    fn test_palette_rules() {
+        let chunks = vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 1, 3, 0, 0, 0]),
+            // three colors for a 1 bit image, and a transparency entry for a fourth
+            chunk("PLTE", &[0; 9]),
+            chunk("tRNS", &[0; 4]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ];
+
+        assert_eq!(rules(chunks), vec![Rule::InvalidPalette, Rule::InvalidTransparency]);
+    }
+
+    #[test]
+    fn test_rule_from_str() {
+        assert_eq!(Rule::from_str("PNG005"), Ok(Rule::CrcMismatch));
+        assert_eq!(Rule::from_str("trailing-data"), Ok(Rule::TrailingData));
+        assert!(Rule::from_str("PNG999").is_err());
+    }
+}
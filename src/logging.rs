@@ -0,0 +1,80 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use colored::*;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+// What commands report besides their result, through `tracing`, all of it on
+// stderr. By default that is the warnings and what was written where, as
+// before; -v adds the steps a command takes, -vv how the files are parsed,
+// chunk by chunk with their offsets, and how long it took. With --quiet only
+// errors are reported, and commands print their bare result, such as the
+// token encode gives or the message decode finds.
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// Events as lines of their message and fields, colored by level. The level is
+// written in front from -v on, and the time since the start from -vv on.
+struct Lines {
+    verbose: u64,
+    started: Instant,
+}
+
+impl<S, N> FormatEvent<S, N> for Lines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, context: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        if self.verbose >= 2 {
+            write!(writer, "[{:>9.3}s] ", self.started.elapsed().as_secs_f64())?;
+        }
+
+        let level = *event.metadata().level();
+        if self.verbose >= 1 {
+            write!(writer, "{:<5} ", level)?;
+        }
+
+        let mut line = String::new();
+        context.format_fields(Writer::new(&mut line), event)?;
+
+        match level {
+            Level::ERROR => writeln!(writer, "{}", line.red().bold()),
+            Level::WARN => writeln!(writer, "{}", line.yellow()),
+            Level::DEBUG | Level::TRACE => writeln!(writer, "{}", line.dimmed()),
+            _ => writeln!(writer, "{}", line),
+        }
+    }
+}
+
+// called once the arguments are read; a second call, as when tests run
+// several commands, keeps the subscriber the first one set
+pub fn init(verbose: u64, quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level)
+        .event_format(Lines {
+            verbose,
+            started: Instant::now(),
+        })
+        .try_init()
+        .ok();
+}
@@ -0,0 +1,1081 @@
+use super::crypto;
+use super::envelope;
+use super::png::pixels::{Header, PixelError, Pixels};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+// Hides a payload in the least significant bit of the samples of the image.
+// The payload is preceded by a small header so extraction knows where to stop.
+// When a seed is given the samples are visited in a seeded pseudo-random order
+// instead of sequentially, scattering the payload over the whole image.
+//
+// The header always uses the lowest bit of its samples; it records how many
+// low bits of each following sample carry the payload, and whether adaptive
+// embedding skipped the flat areas of the image.
+//
+// With matrix embedding the payload is split into groups of k bits, each
+// hidden in the lowest bits of 2^k - 1 carriers with a Hamming code: the
+// group is the syndrome of the carriers, and setting it takes at most one
+// flipped bit. The header records k.
+//
+// Indexed images carry the payload in the lowest bit of their palette indices,
+// one bit per pixel. Sorting the palette by luminance first (see
+// `Png::sort_palette`) keeps the color each flip lands on close to the
+// original. Images with less than 8 bits per sample are unpacked to a byte per
+// sample while embedding.
+//
+// Animated images get a header and a slice of the payload in every frame, in
+// frame order, each frame scattered with its own seed. Frames too small for
+// the header are left as they are. Extraction finds the channel selection on
+// the first frame holding a payload and reassembles the slices.
+//
+// Only the selected channels are touched. The selection is not stored anywhere:
+// extraction tries every channel combination and keeps the one that yields a
+// valid envelope.
+
+pub type Seed = [u8; 32];
+
+// fixed salt so the order can be derived again from nothing but the password
+const ORDER_SALT: &[u8] = b"pngcrypt lsb pixel order";
+
+pub const MAX_BITS_PER_CHANNEL: u8 = 4;
+
+// Pixels whose neighbourhood varies less than this (in squared 8 bit levels)
+// count as flat and are skipped by adaptive embedding.
+const MIN_VARIANCE: f64 = 9.0;
+
+const FLAG_ADAPTIVE: u8 = 0b0000_0001;
+
+// largest Hamming code tried, 7 payload bits in 127 carriers
+const MAX_MATRIX_BITS: u8 = 7;
+
+#[derive(Debug)]
+pub enum LsbError {
+    IndexedBitsPerChannel(u8),
+    UnsupportedBitDepth(u8),
+    InvalidChannel(String),
+    InvalidBitsPerChannel(u8),
+    MatrixNeedsOneBit(u8),
+    UnavailableChannels(Channels),
+    PayloadTooLarge { needed: usize, available: usize },
+    NoPayload,
+}
+
+impl std::error::Error for LsbError {}
+
+impl fmt::Display for LsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LsbError::IndexedBitsPerChannel(bits) => write!(
+                f,
+                "Indexed-color images only support 1 bit per channel in lsb mode, got {}",
+                bits
+            ),
+            LsbError::UnsupportedBitDepth(depth) => {
+                write!(f, "Unsupported bit depth for lsb mode: {}", depth)
+            }
+            LsbError::InvalidChannel(channel) => write!(
+                f,
+                "Invalid channel: {}. Expected \"all\" or a comma separated list of r, g, b, a and gray",
+                channel
+            ),
+            LsbError::InvalidBitsPerChannel(bits) => write!(
+                f,
+                "Invalid bits per channel: {}. Expected a value between 1 and {}",
+                bits, MAX_BITS_PER_CHANNEL
+            ),
+            LsbError::MatrixNeedsOneBit(bits) => write!(
+                f,
+                "Matrix embedding only works with 1 bit per channel, got {}",
+                bits
+            ),
+            LsbError::UnavailableChannels(channels) => {
+                write!(f, "The image does not have the requested channels: {}", channels)
+            }
+            LsbError::PayloadTooLarge { needed, available } => write!(
+                f,
+                "Payload too large: {} bytes needed but the image can only hide {} bytes",
+                needed, available
+            ),
+            LsbError::NoPayload => write!(f, "No hidden payload found in the pixel data"),
+        }
+    }
+}
+
+// A set of named channels. Which sample of a pixel each name refers to depends
+// on the color type of the image the set is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels(u8);
+
+impl Channels {
+    pub const RED: Channels = Channels(0b00001);
+    pub const GREEN: Channels = Channels(0b00010);
+    pub const BLUE: Channels = Channels(0b00100);
+    pub const ALPHA: Channels = Channels(0b01000);
+    pub const GRAY: Channels = Channels(0b10000);
+    pub const ALL: Channels = Channels(0b11111);
+
+    const NAMES: [(Channels, &'static str); 5] = [
+        (Channels::RED, "r"),
+        (Channels::GREEN, "g"),
+        (Channels::BLUE, "b"),
+        (Channels::ALPHA, "a"),
+        (Channels::GRAY, "gray"),
+    ];
+
+    fn contains(&self, other: Channels) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // the channels of each pixel, in sample order
+    fn layout(header: &Header) -> &'static [Channels] {
+        match header.color_type {
+            2 => &[Channels::RED, Channels::GREEN, Channels::BLUE],
+            4 => &[Channels::GRAY, Channels::ALPHA],
+            6 => &[Channels::RED, Channels::GREEN, Channels::BLUE, Channels::ALPHA],
+            _ => &[Channels::GRAY],
+        }
+    }
+
+    // for each sample of a pixel, whether it belongs to the set
+    fn select(&self, header: &Header) -> Result<Vec<bool>> {
+        let layout = Channels::layout(header);
+        let available = layout.iter().fold(0, |acc, channel| acc | channel.0);
+
+        if *self != Channels::ALL && self.0 & !available != 0 {
+            return Err(LsbError::UnavailableChannels(*self).into());
+        }
+
+        Ok(layout.iter().map(|&channel| self.contains(channel)).collect())
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels::ALL
+    }
+}
+
+impl FromStr for Channels {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "all" {
+            return Ok(Channels::ALL);
+        }
+
+        value.split(',').try_fold(Channels(0), |acc, name| {
+            let channel = match name.trim() {
+                "r" | "red" => Channels::RED,
+                "g" | "green" => Channels::GREEN,
+                "b" | "blue" => Channels::BLUE,
+                "a" | "alpha" => Channels::ALPHA,
+                "gray" | "grey" => Channels::GRAY,
+                _ => return Err(LsbError::InvalidChannel(name.to_string()).into()),
+            };
+
+            Ok(Channels(acc.0 | channel.0))
+        })
+    }
+}
+
+impl fmt::Display for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Channels::ALL {
+            return write!(f, "all");
+        }
+
+        let names: Vec<&str> = Channels::NAMES
+            .iter()
+            .filter(|(channel, _)| self.contains(*channel))
+            .map(|(_, name)| *name)
+            .collect();
+
+        write!(f, "{}", names.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LsbOptions {
+    pub seed: Option<Seed>,
+    pub channels: Channels,
+    pub bits_per_channel: u8,
+    pub adaptive: bool,
+    pub matrix: bool,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            seed: None,
+            channels: Channels::ALL,
+            bits_per_channel: 1,
+            adaptive: false,
+            matrix: false,
+        }
+    }
+}
+
+struct StegoHeader {
+    length: u32,
+    bits_per_channel: u8,
+    flags: u8,
+    // payload bits per Hamming code group, 0 without matrix embedding
+    matrix_bits: u8,
+}
+
+impl StegoHeader {
+    const SIZE: usize = 7;
+    const BITS: usize = StegoHeader::SIZE * 8;
+
+    fn to_bytes(&self) -> [u8; StegoHeader::SIZE] {
+        let mut bytes = [0u8; StegoHeader::SIZE];
+        bytes[..4].copy_from_slice(&self.length.to_be_bytes());
+        bytes[4] = self.bits_per_channel;
+        bytes[5] = self.flags;
+        bytes[6] = self.matrix_bits;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<StegoHeader> {
+        Ok(StegoHeader {
+            length: u32::from_be_bytes(bytes[..4].try_into()?),
+            bits_per_channel: bytes[4],
+            flags: bytes[5],
+            matrix_bits: bytes[6],
+        })
+    }
+}
+
+fn check_bits_per_channel(bits: u8) -> Result<()> {
+    if bits == 0 || bits > MAX_BITS_PER_CHANNEL {
+        return Err(LsbError::InvalidBitsPerChannel(bits).into());
+    }
+
+    Ok(())
+}
+
+fn check_supported(header: &Header) -> Result<()> {
+    if header.interlace != 0 {
+        return Err(PixelError::UnsupportedInterlace.into());
+    }
+
+    match (header.is_indexed(), header.bit_depth) {
+        (true, 1 | 2 | 4 | 8) | (false, 8 | 16) => Ok(()),
+        (_, depth) => Err(LsbError::UnsupportedBitDepth(depth).into()),
+    }
+}
+
+// indices into the pixel data of the bytes whose lowest bit carries the payload
+fn carrier_indices(header: &Header, data_len: usize, selected: &[bool]) -> Result<Vec<usize>> {
+    check_supported(header)?;
+
+    // samples are big endian, so only touch the low byte of each
+    let sample_size = header.bit_depth as usize / 8;
+
+    Ok((0..data_len / sample_size)
+        .filter(|sample| selected[sample % selected.len()])
+        .map(|sample| sample * sample_size + sample_size - 1)
+        .collect())
+}
+
+pub fn seed_from_password(password: &str) -> Seed {
+    crypto::derive_key(password, ORDER_SALT, crypto::DEFAULT_KDF_ROUNDS)
+}
+
+// uniform integer below `bound`, rejecting the values that would bias the modulo
+fn uniform(rng: &mut ChaCha20Rng, bound: u64) -> u64 {
+    let zone = u64::MAX - (u64::MAX % bound);
+
+    loop {
+        let value = rng.next_u64();
+        if value < zone {
+            return value % bound;
+        }
+    }
+}
+
+// Fisher-Yates shuffle, spelled out so the order never changes with the rand version
+pub(crate) fn shuffle(indices: &mut [usize], seed: &Seed) {
+    let mut rng = ChaCha20Rng::from_seed(*seed);
+
+    for i in (1..indices.len()).rev() {
+        let j = uniform(&mut rng, i as u64 + 1) as usize;
+        indices.swap(i, j);
+    }
+}
+
+fn carrier_order(pixels: &Pixels, seed: Option<&Seed>, selected: &[bool]) -> Result<Vec<usize>> {
+    let mut indices = carrier_indices(&pixels.header, pixels.data.len(), selected)?;
+
+    if let Some(seed) = seed {
+        shuffle(&mut indices, seed);
+    }
+
+    Ok(indices)
+}
+
+// Number of samples of an image described by `header` that `selected` picks.
+// Saturating, since the header may describe an image far too large to decode.
+fn carrier_count(header: &Header, selected: &[bool]) -> usize {
+    let samples = selected.iter().filter(|&&selected| selected).count();
+
+    (header.width as usize)
+        .saturating_mul(header.height as usize)
+        .saturating_mul(samples)
+}
+
+// whether an image described by `header` has enough `selected` samples for
+// the header, which frames of an animation that are too small do not get
+fn holds_header(header: &Header, selected: &[bool]) -> bool {
+    carrier_count(header, selected) >= StegoHeader::BITS
+}
+
+fn selected_capacity(header: &Header, selected: &[bool], bits_per_channel: u8) -> usize {
+    let carriers = carrier_count(header, selected).saturating_sub(StegoHeader::BITS);
+
+    carriers.saturating_mul(bits_per_channel as usize) / 8
+}
+
+// number of payload bytes an image described by `header` can hide with `options`
+pub fn capacity(header: &Header, options: &LsbOptions) -> Result<usize> {
+    check_supported(header)?;
+    check_bits_per_channel(options.bits_per_channel)?;
+
+    // a higher bit of an index can jump to any color of the palette
+    if header.is_indexed() && options.bits_per_channel != 1 {
+        return Err(LsbError::IndexedBitsPerChannel(options.bits_per_channel).into());
+    }
+
+    Ok(selected_capacity(
+        header,
+        &options.channels.select(header)?,
+        options.bits_per_channel,
+    ))
+}
+
+// For every pixel, whether its 3x3 neighbourhood is textured enough to hide
+// data in. Only the bits above `bits_per_channel` are looked at, so embedding
+// does not change the map and extraction computes the same one.
+fn texture_map(pixels: &Pixels, bits_per_channel: u8) -> Result<Vec<bool>> {
+    let header = &pixels.header;
+    let channels = header.channels()?;
+    let sample_size = header.bit_depth as usize / 8;
+    let width = header.width as usize;
+    let height = header.height as usize;
+
+    // 16 bit samples only ever change in their low byte, so the high byte is stable
+    let mask = if sample_size == 2 { 0xff } else { 0xff << bits_per_channel };
+    let value = |x: usize, y: usize, channel: usize| {
+        (pixels.data[((y * width + x) * channels + channel) * sample_size] & mask) as f64
+    };
+
+    let mut map = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let rows = y.saturating_sub(1)..=(y + 1).min(height - 1);
+            let columns = x.saturating_sub(1)..=(x + 1).min(width - 1);
+
+            let textured = (0..channels).any(|channel| {
+                let neighbours: Vec<f64> = rows
+                    .clone()
+                    .flat_map(|ny| columns.clone().map(move |nx| value(nx, ny, channel)))
+                    .collect();
+
+                let count = neighbours.len() as f64;
+                let mean = neighbours.iter().sum::<f64>() / count;
+                let variance = neighbours.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+
+                variance >= MIN_VARIANCE
+            });
+
+            map.push(textured);
+        }
+    }
+
+    Ok(map)
+}
+
+// the carriers left for the payload once the header took its share
+fn payload_carriers(pixels: &Pixels, indices: &[usize], bits_per_channel: u8, adaptive: bool) -> Result<Vec<usize>> {
+    let rest = indices.get(StegoHeader::BITS..).unwrap_or(&[]);
+
+    if !adaptive {
+        return Ok(rest.to_vec());
+    }
+
+    let map = texture_map(pixels, bits_per_channel)?;
+    let bytes_per_pixel = pixels.header.channels()? * pixels.header.bit_depth as usize / 8;
+
+    Ok(rest
+        .iter()
+        .copied()
+        .filter(|index| map[index / bytes_per_pixel])
+        .collect())
+}
+
+// Number of payload bytes `pixels` can hide with `options`. Unlike `capacity`
+// this accounts for adaptive embedding, which depends on the image content.
+pub fn pixel_capacity(pixels: &Pixels, options: &LsbOptions) -> Result<usize> {
+    if !options.adaptive {
+        return capacity(&pixels.header, options);
+    }
+
+    if pixels.header.bit_depth < 8 {
+        return pixel_capacity(&pixels.unpack()?, options);
+    }
+
+    check_supported(&pixels.header)?;
+    check_bits_per_channel(options.bits_per_channel)?;
+
+    let selected = options.channels.select(&pixels.header)?;
+    // the header takes the first carriers of the seeded order, whether textured or not
+    let indices = carrier_order(pixels, options.seed.as_ref(), &selected)?;
+    let carriers = payload_carriers(pixels, &indices, options.bits_per_channel, true)?;
+
+    Ok(carriers.len() * options.bits_per_channel as usize / 8)
+}
+
+// Spread `bytes` over the lowest `depth` bits of the carriers, most significant
+// bit first. The last carrier may only be partially used.
+fn write_bits(pixels: &mut Pixels, indices: &[usize], bytes: &[u8], depth: u8) {
+    let bits: Vec<u8> = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+        .collect();
+
+    for (&index, carried) in indices.iter().zip(bits.chunks(depth as usize)) {
+        for (i, bit) in carried.iter().enumerate() {
+            let shift = depth as usize - 1 - i;
+            pixels.data[index] = (pixels.data[index] & !(1 << shift)) | (bit << shift);
+        }
+    }
+}
+
+fn read_bits(pixels: &Pixels, indices: &[usize], depth: u8, len: usize) -> Vec<u8> {
+    let bits: Vec<u8> = indices
+        .iter()
+        .flat_map(|&index| (0..depth).rev().map(move |shift| (pixels.data[index] >> shift) & 1))
+        .take(len * 8)
+        .collect();
+
+    bits.chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | bit))
+        .collect()
+}
+
+// number of carriers the Hamming code with `k` bits per group needs for `len` bytes
+fn matrix_carriers(len: usize, k: u8) -> usize {
+    (len * 8).div_ceil(k as usize) * ((1 << k) - 1)
+}
+
+// the largest code whose groups still fit `len` bytes in `carriers`
+fn matrix_bits_for(len: usize, carriers: usize) -> Option<u8> {
+    (1..=MAX_MATRIX_BITS)
+        .rev()
+        .find(|&k| matrix_carriers(len, k) <= carriers)
+}
+
+// position (1 based) of the set lowest bits in a group, xor-ed together
+fn syndrome(pixels: &Pixels, group: &[usize]) -> usize {
+    group
+        .iter()
+        .enumerate()
+        .filter(|(_, &index)| pixels.data[index] & 1 == 1)
+        .fold(0, |acc, (position, _)| acc ^ (position + 1))
+}
+
+fn write_matrix(pixels: &mut Pixels, indices: &[usize], bytes: &[u8], k: u8) {
+    let bits: Vec<u8> = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+        .collect();
+
+    for (group, carried) in indices.chunks_exact((1 << k) - 1).zip(bits.chunks(k as usize)) {
+        // a short last group is padded with zero bits
+        let wanted = (0..k as usize).fold(0, |acc, i| (acc << 1) | *carried.get(i).unwrap_or(&0) as usize);
+        let flip = syndrome(pixels, group) ^ wanted;
+
+        if flip != 0 {
+            pixels.data[group[flip - 1]] ^= 1;
+        }
+    }
+}
+
+fn read_matrix(pixels: &Pixels, indices: &[usize], k: u8, len: usize) -> Vec<u8> {
+    let bits: Vec<u8> = indices
+        .chunks_exact((1 << k) - 1)
+        .flat_map(|group| {
+            let value = syndrome(pixels, group);
+            (0..k).rev().map(move |shift| ((value >> shift) & 1) as u8)
+        })
+        .take(len * 8)
+        .collect();
+
+    bits.chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | bit))
+        .collect()
+}
+
+pub fn embed(pixels: &mut Pixels, payload: &[u8], options: &LsbOptions) -> Result<()> {
+    if pixels.header.bit_depth < 8 {
+        let mut unpacked = pixels.unpack()?;
+        embed(&mut unpacked, payload, options)?;
+        *pixels = unpacked.repack(pixels.header.bit_depth)?;
+
+        return Ok(());
+    }
+
+    let available = pixel_capacity(pixels, options)?;
+
+    if payload.len() > available {
+        return Err(LsbError::PayloadTooLarge {
+            needed: payload.len(),
+            available,
+        }
+        .into());
+    }
+
+    if options.matrix && options.bits_per_channel != 1 {
+        return Err(LsbError::MatrixNeedsOneBit(options.bits_per_channel).into());
+    }
+
+    let selected = options.channels.select(&pixels.header)?;
+    let indices = carrier_order(pixels, options.seed.as_ref(), &selected)?;
+    if indices.len() < StegoHeader::BITS {
+        return Err(LsbError::PayloadTooLarge {
+            needed: payload.len(),
+            available: 0,
+        }
+        .into());
+    }
+
+    let carriers = payload_carriers(pixels, &indices, options.bits_per_channel, options.adaptive)?;
+
+    // k = 1 is plain embedding, so a payload that fits always gets a code
+    let matrix_bits = if options.matrix {
+        matrix_bits_for(payload.len(), carriers.len()).unwrap_or(0)
+    } else {
+        0
+    };
+
+    let header = StegoHeader {
+        length: payload.len() as u32,
+        bits_per_channel: options.bits_per_channel,
+        flags: if options.adaptive { FLAG_ADAPTIVE } else { 0 },
+        matrix_bits,
+    };
+
+    write_bits(pixels, &indices[..StegoHeader::BITS], &header.to_bytes(), 1);
+
+    if matrix_bits > 0 {
+        write_matrix(pixels, &carriers, payload, matrix_bits);
+    } else {
+        write_bits(pixels, &carriers, payload, options.bits_per_channel);
+    }
+
+    Ok(())
+}
+
+fn extract_selected(pixels: &Pixels, seed: Option<&Seed>, selected: &[bool]) -> Result<Vec<u8>> {
+    let indices = carrier_order(pixels, seed, selected)?;
+
+    if indices.len() < StegoHeader::BITS {
+        return Err(LsbError::NoPayload.into());
+    }
+
+    let header = StegoHeader::from_bytes(&read_bits(
+        pixels,
+        &indices[..StegoHeader::BITS],
+        1,
+        StegoHeader::SIZE,
+    ))?;
+    let length = header.length as usize;
+
+    if check_bits_per_channel(header.bits_per_channel).is_err()
+        || header.matrix_bits > MAX_MATRIX_BITS
+        || length > selected_capacity(&pixels.header, selected, header.bits_per_channel)
+    {
+        return Err(LsbError::NoPayload.into());
+    }
+
+    let adaptive = header.flags & FLAG_ADAPTIVE != 0;
+    let carriers = payload_carriers(pixels, &indices, header.bits_per_channel, adaptive)?;
+
+    if header.matrix_bits > 0 {
+        if matrix_carriers(length, header.matrix_bits) > carriers.len() {
+            return Err(LsbError::NoPayload.into());
+        }
+
+        return Ok(read_matrix(pixels, &carriers, header.matrix_bits, length));
+    }
+
+    if carriers.len() * (header.bits_per_channel as usize) < length * 8 {
+        return Err(LsbError::NoPayload.into());
+    }
+
+    Ok(read_bits(pixels, &carriers, header.bits_per_channel, length))
+}
+
+// Every non-empty combination of the image's channels, all of them first since
+// that is the default when embedding.
+fn candidate_selections(header: &Header) -> Vec<Vec<bool>> {
+    let count = Channels::layout(header).len();
+
+    (1..1usize << count)
+        .rev()
+        .map(|mask| (0..count).map(|channel| mask & (1 << channel) != 0).collect())
+        .collect()
+}
+
+// the channel selection that yields an envelope, along with the payload
+fn find_selection(pixels: &Pixels, seed: Option<&Seed>) -> Result<(Vec<bool>, Vec<u8>)> {
+    candidate_selections(&pixels.header)
+        .into_iter()
+        .filter_map(|selected| {
+            extract_selected(pixels, seed, &selected)
+                .ok()
+                .map(|payload| (selected, payload))
+        })
+        .find(|(_, payload)| envelope::is_envelope(payload))
+        .ok_or_else(|| LsbError::NoPayload.into())
+}
+
+pub fn extract(pixels: &Pixels, seed: Option<&Seed>) -> Result<Vec<u8>> {
+    check_supported(&pixels.header)?;
+
+    find_selection(&pixels.unpack()?, seed).map(|(_, payload)| payload)
+}
+
+// the seed of frame `index` of an animation; the first frame uses the seed as is
+fn frame_seed(seed: &Seed, index: usize) -> Seed {
+    if index == 0 {
+        return *seed;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update((index as u32).to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn frame_options(options: &LsbOptions, index: usize) -> LsbOptions {
+    LsbOptions {
+        seed: options.seed.map(|seed| frame_seed(&seed, index)),
+        ..*options
+    }
+}
+
+// number of payload bytes all `frames` together can hide with `options`
+pub fn frames_capacity(frames: &[Pixels], options: &LsbOptions) -> Result<usize> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| pixel_capacity(frame, &frame_options(options, index)))
+        .sum()
+}
+
+// Like `embed`, splitting the payload over the frames of an animation. Frames
+// are filled in order, so later ones may only hold an empty slice, and frames
+// too small for the header are skipped.
+pub fn embed_frames(frames: &mut [Pixels], payload: &[u8], options: &LsbOptions) -> Result<()> {
+    let capacities = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| pixel_capacity(frame, &frame_options(options, index)))
+        .collect::<Result<Vec<usize>>>()?;
+
+    let available = capacities.iter().sum();
+    if payload.len() > available {
+        return Err(LsbError::PayloadTooLarge {
+            needed: payload.len(),
+            available,
+        }
+        .into());
+    }
+
+    let mut rest = payload;
+
+    for (index, (frame, capacity)) in frames.iter_mut().zip(capacities).enumerate() {
+        if !holds_header(&frame.header, &options.channels.select(&frame.header)?) {
+            continue;
+        }
+
+        let (slice, remaining) = rest.split_at(std::cmp::min(rest.len(), capacity));
+        embed(frame, slice, &frame_options(options, index))?;
+        rest = remaining;
+    }
+
+    Ok(())
+}
+
+pub fn extract_frames(frames: &[Pixels], seed: Option<&Seed>) -> Result<Vec<u8>> {
+    let first = frames.first().ok_or(LsbError::NoPayload)?;
+    check_supported(&first.header)?;
+
+    // the payload starts in the first frame large enough for a header with the
+    // selection, as the frames before it were skipped
+    let (selected, start, mut payload) = candidate_selections(&first.header)
+        .into_iter()
+        .find_map(|selected| {
+            let start = frames.iter().position(|frame| holds_header(&frame.header, &selected))?;
+            let seed = seed.map(|seed| frame_seed(seed, start));
+            let payload = extract_selected(&frames[start].unpack().ok()?, seed.as_ref(), &selected).ok()?;
+
+            envelope::is_envelope(&payload).then_some((selected, start, payload))
+        })
+        .ok_or(LsbError::NoPayload)?;
+
+    for (index, frame) in frames.iter().enumerate().skip(start + 1) {
+        if !holds_header(&frame.header, &selected) {
+            continue;
+        }
+
+        let seed = seed.map(|seed| frame_seed(seed, index));
+        payload.extend(extract_selected(&frame.unpack()?, seed.as_ref(), &selected)?);
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_pixels(bit_depth: u8, color_type: u8) -> Pixels {
+        let header = Header {
+            width: 16,
+            height: 16,
+            bit_depth,
+            color_type,
+            interlace: 0,
+        };
+        let len = header.stride().unwrap() * 16;
+
+        Pixels::new(header, (0..len).map(|i| (i * 31 % 256) as u8).collect())
+    }
+
+    fn testing_payload(message: &[u8]) -> Vec<u8> {
+        envelope::seal(message, None, None).unwrap()
+    }
+
+    fn seeded(seed: Seed) -> LsbOptions {
+        LsbOptions {
+            seed: Some(seed),
+            ..LsbOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_channels_from_str() {
+        assert_eq!(Channels::from_str("all").unwrap(), Channels::ALL);
+        assert_eq!(Channels::from_str("alpha").unwrap(), Channels::ALPHA);
+        assert_eq!(Channels::from_str("r,b").unwrap().to_string(), "r,b");
+        assert!(Channels::from_str("r,x").is_err());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let alpha = LsbOptions {
+            channels: Channels::ALPHA,
+            ..LsbOptions::default()
+        };
+        let deep = LsbOptions {
+            bits_per_channel: 3,
+            ..LsbOptions::default()
+        };
+
+        assert_eq!(capacity(&testing_pixels(8, 6).header, &LsbOptions::default()).unwrap(), (16 * 16 * 4 - 56) / 8);
+        assert_eq!(capacity(&testing_pixels(16, 2).header, &LsbOptions::default()).unwrap(), (16 * 16 * 3 - 56) / 8);
+        assert_eq!(capacity(&testing_pixels(8, 6).header, &alpha).unwrap(), (16 * 16 - 56) / 8);
+        assert_eq!(capacity(&testing_pixels(8, 6).header, &deep).unwrap(), (16 * 16 * 4 - 56) * 3 / 8);
+    }
+
+    #[test]
+    fn test_embed_extract() {
+        let mut pixels = testing_pixels(8, 6);
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"This is a secret");
+
+        embed(&mut pixels, &payload, &LsbOptions::default()).unwrap();
+
+        assert!(pixels.data.iter().zip(&original).all(|(a, b)| (a ^ b) <= 1));
+        assert_eq!(extract(&pixels, None).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_16_bit() {
+        let mut pixels = testing_pixels(16, 0);
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"Deep");
+
+        embed(&mut pixels, &payload, &LsbOptions::default()).unwrap();
+
+        assert!(pixels.data.iter().step_by(2).eq(original.iter().step_by(2)));
+        assert_eq!(extract(&pixels, None).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_seeded() {
+        let mut pixels = testing_pixels(8, 2);
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"Scattered");
+
+        embed(&mut pixels, &payload, &seeded([42u8; 32])).unwrap();
+
+        assert_eq!(extract(&pixels, Some(&[42u8; 32])).unwrap(), payload);
+        assert!(extract(&pixels, Some(&[43u8; 32])).is_err());
+
+        // the changes are not confined to the start of the image
+        let last_change = pixels
+            .data
+            .iter()
+            .zip(&original)
+            .rposition(|(a, b)| a != b)
+            .unwrap();
+        assert!(last_change > pixels.data.len() / 2);
+    }
+
+    #[test]
+    fn test_embed_extract_alpha_only() {
+        let mut pixels = testing_pixels(8, 6);
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"Transparent");
+        let options = LsbOptions {
+            channels: Channels::ALPHA,
+            ..seeded([1u8; 32])
+        };
+
+        embed(&mut pixels, &payload, &options).unwrap();
+
+        let color_untouched = pixels
+            .data
+            .iter()
+            .zip(&original)
+            .enumerate()
+            .all(|(i, (a, b))| i % 4 == 3 || a == b);
+        assert!(color_untouched);
+        assert_eq!(extract(&pixels, Some(&[1u8; 32])).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_bits_per_channel() {
+        for bits_per_channel in 1..=MAX_BITS_PER_CHANNEL {
+            let mut pixels = testing_pixels(8, 2);
+            let original = pixels.data.clone();
+            let payload = testing_payload(b"More bits, fewer pixels");
+            let options = LsbOptions {
+                bits_per_channel,
+                ..seeded([9u8; 32])
+            };
+
+            embed(&mut pixels, &payload, &options).unwrap();
+
+            let max_change = (1u8 << bits_per_channel) - 1;
+            assert!(pixels.data.iter().zip(&original).all(|(a, b)| (a ^ b) <= max_change));
+            assert_eq!(extract(&pixels, Some(&[9u8; 32])).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_embed_extract_adaptive() {
+        // left half flat, right half noisy
+        let mut pixels = testing_pixels(8, 0);
+        for (i, value) in pixels.data.iter_mut().enumerate() {
+            *value = if i % 16 < 8 { 128 } else { (i * 97 % 256) as u8 };
+        }
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"In the noise");
+        let options = LsbOptions {
+            adaptive: true,
+            bits_per_channel: 2,
+            ..seeded([5u8; 32])
+        };
+
+        assert!(pixel_capacity(&pixels, &options).unwrap() < capacity(&pixels.header, &options).unwrap());
+
+        embed(&mut pixels, &payload, &options).unwrap();
+
+        // apart from the few header bits, nothing lands away from the texture
+        let flat_changes = pixels
+            .data
+            .iter()
+            .zip(&original)
+            .enumerate()
+            .filter(|(i, (a, b))| i % 16 < 7 && a != b)
+            .count();
+        assert!(flat_changes <= StegoHeader::BITS);
+        assert_eq!(extract(&pixels, Some(&[5u8; 32])).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_matrix() {
+        let mut pixels = testing_pixels(8, 6);
+        let original = pixels.data.clone();
+        let payload = testing_payload(b"Hi");
+        let options = LsbOptions {
+            matrix: true,
+            ..seeded([6u8; 32])
+        };
+
+        embed(&mut pixels, &payload, &options).unwrap();
+
+        // groups of 5 bits fit here, each costing at most one change besides the header
+        let changes = pixels.data.iter().zip(&original).filter(|(a, b)| a != b).count();
+        assert_eq!(matrix_bits_for(payload.len(), 1024 - StegoHeader::BITS), Some(5));
+        assert!(changes <= StegoHeader::BITS + (payload.len() * 8).div_ceil(5));
+        assert_eq!(extract(&pixels, Some(&[6u8; 32])).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_matrix_bits_for() {
+        assert_eq!(matrix_bits_for(1, 8), Some(1));
+        assert_eq!(matrix_bits_for(1, 12), Some(2));
+        assert_eq!(matrix_bits_for(1, 1000), Some(7));
+        assert_eq!(matrix_bits_for(2, 8), None);
+    }
+
+    #[test]
+    fn test_matrix_needs_one_bit() {
+        let mut pixels = testing_pixels(8, 6);
+        let options = LsbOptions {
+            matrix: true,
+            bits_per_channel: 2,
+            ..LsbOptions::default()
+        };
+
+        assert!(embed(&mut pixels, &testing_payload(b"Hi"), &options).is_err());
+    }
+
+    #[test]
+    fn test_invalid_bits_per_channel() {
+        let options = LsbOptions {
+            bits_per_channel: 5,
+            ..LsbOptions::default()
+        };
+
+        assert!(capacity(&testing_pixels(8, 2).header, &options).is_err());
+    }
+
+    #[test]
+    fn test_unavailable_channels() {
+        let mut pixels = testing_pixels(8, 2);
+        let options = LsbOptions {
+            channels: Channels::ALPHA,
+            ..LsbOptions::default()
+        };
+
+        assert!(embed(&mut pixels, &testing_payload(b"Nope"), &options).is_err());
+    }
+
+    #[test]
+    fn test_shuffle_is_a_stable_permutation() {
+        let mut first: Vec<usize> = (0..1000).collect();
+        let mut second = first.clone();
+
+        shuffle(&mut first, &[7u8; 32]);
+        shuffle(&mut second, &[7u8; 32]);
+
+        assert_eq!(first, second);
+        assert_ne!(first, (0..1000).collect::<Vec<usize>>());
+
+        first.sort_unstable();
+        assert_eq!(first, (0..1000).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let mut pixels = testing_pixels(8, 0);
+        let payload = vec![0u8; capacity(&pixels.header, &LsbOptions::default()).unwrap() + 1];
+
+        assert!(embed(&mut pixels, &payload, &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_embed_extract_indexed() {
+        let mut pixels = testing_pixels(4, 3);
+        let original = pixels.unpack().unwrap();
+        let payload = testing_payload(b"Hi");
+
+        assert_eq!(capacity(&pixels.header, &LsbOptions::default()).unwrap(), (16 * 16 - 56) / 8);
+
+        embed(&mut pixels, &payload, &seeded([7u8; 32])).unwrap();
+
+        // indices only ever move to their neighbour
+        let unpacked = pixels.unpack().unwrap();
+        assert!(unpacked.data.iter().zip(&original.data).all(|(a, b)| a >> 1 == b >> 1));
+        assert_eq!(pixels.header.bit_depth, 4);
+        assert_eq!(extract(&pixels, Some(&[7u8; 32])).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_frames() {
+        let mut frames = vec![testing_pixels(8, 2), testing_pixels(8, 2), testing_pixels(8, 2)];
+        let first = capacity(&frames[0].header, &LsbOptions::default()).unwrap();
+        let payload = testing_payload(&vec![b'x'; first + 10]);
+        let options = seeded([8u8; 32]);
+
+        assert_eq!(frames_capacity(&frames, &options).unwrap(), 3 * first);
+
+        embed_frames(&mut frames, &payload, &options).unwrap();
+
+        // the first frame is full and only holds the start of the payload
+        assert_eq!(extract(&frames[0], Some(&[8u8; 32])).unwrap().len(), first);
+        assert_eq!(extract_frames(&frames, Some(&[8u8; 32])).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_embed_extract_small_frames() {
+        let mut small = testing_pixels(8, 2);
+        small.header.width = 2;
+        small.header.height = 2;
+        small.data.truncate(small.header.stride().unwrap() * 2);
+
+        let mut frames = vec![small.clone(), testing_pixels(8, 2), small.clone()];
+        let payload = testing_payload(b"Message");
+        let options = seeded([8u8; 32]);
+
+        embed_frames(&mut frames, &payload, &options).unwrap();
+
+        // the frames too small for the header are left as they are
+        assert_eq!(frames[0].data, small.data);
+        assert_eq!(frames[2].data, small.data);
+        assert_eq!(extract_frames(&frames, Some(&[8u8; 32])).unwrap(), payload);
+
+        assert!(embed(&mut small, &[], &LsbOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_capacity_of_huge_images() {
+        let header = Header {
+            width: u32::MAX,
+            height: u32::MAX,
+            ..testing_pixels(16, 6).header
+        };
+        let options = LsbOptions {
+            bits_per_channel: 4,
+            ..LsbOptions::default()
+        };
+
+        assert_eq!(capacity(&header, &options).unwrap(), usize::MAX / 8);
+    }
+
+    #[test]
+    fn test_unsupported_images() {
+        let deep = LsbOptions {
+            bits_per_channel: 2,
+            ..LsbOptions::default()
+        };
+
+        assert!(capacity(&testing_pixels(8, 3).header, &deep).is_err());
+        assert!(capacity(&testing_pixels(4, 0).header, &LsbOptions::default()).is_err());
+    }
+}
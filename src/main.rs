@@ -1,16 +1,23 @@
 mod args;
-mod chunk;
-mod chunk_type;
+mod color;
 mod commands;
-mod png;
+mod logging;
+mod man;
+mod progress;
+mod serve;
+mod tui;
+mod wizard;
+
+use std::time::Instant;
 
 use anyhow::{Result};
-use clap::Parser;
 
 fn main() -> Result<()> {
-    let arguments = args::Args::parse();
+    let started = Instant::now();
+    let arguments = args::Args::parse_configured()?;
 
     commands::Commands::from_args(arguments)?;
+    tracing::trace!(elapsed = ?started.elapsed(), "Done");
 
     Ok(())
 }
@@ -0,0 +1,165 @@
+use clap::{Arg, Command};
+
+// Man pages in roff for pngcrypt and each of its subcommands, as packages
+// install them: pngcrypt.1, pngcrypt-encode.1, pngcrypt-chunk-list.1 and so
+// on. They are written from what clap tells of the built command: its usage,
+// its arguments with their help, defaults and environment variables, and its
+// subcommands. The help is the doc comments of `args`, so it is the same in
+// --help and in the pages.
+
+// Text as roff reads it as text: a line starting with . or ' would be a
+// control line, a backslash starts an escape and - is a hyphen rather than
+// the minus of options.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+
+    match text.starts_with('.') || text.starts_with('\'') {
+        true => format!("\\&{}", text),
+        false => text,
+    }
+}
+
+fn bold(text: &str) -> String {
+    format!("\\fB{}\\fR", escape(text))
+}
+
+fn italic(text: &str) -> String {
+    format!("\\fI{}\\fR", escape(text))
+}
+
+// the name of the page of `command`, as in pngcrypt-chunk-list
+fn page_name(command: &Command) -> String {
+    command.get_bin_name().unwrap_or_else(|| command.get_name()).replace(' ', "-")
+}
+
+// the forms an argument is given in, as in -o, --output-file <OUTPUT_FILE>
+fn argument_forms(argument: &Arg) -> String {
+    let values: Vec<String> = match argument.get_value_names() {
+        Some(names) => names.iter().map(|name| italic(&format!("<{}>", name))).collect(),
+        None if argument.is_takes_value_set() => {
+            vec![italic(&format!("<{}>", argument.get_id().to_uppercase()))]
+        }
+        None => Vec::new(),
+    };
+
+    if argument.is_positional() {
+        return values.join(" ");
+    }
+
+    let mut forms = Vec::new();
+    if let Some(short) = argument.get_short() {
+        forms.push(bold(&format!("-{}", short)));
+    }
+    if let Some(long) = argument.get_long() {
+        forms.push(bold(&format!("--{}", long)));
+    }
+
+    let mut forms = forms.join(", ");
+    for value in values {
+        forms.push(' ');
+        forms.push_str(&value);
+    }
+
+    forms
+}
+
+fn argument_description(argument: &Arg) -> String {
+    let mut notes = Vec::new();
+    if let Some(help) = argument.get_long_help().or_else(|| argument.get_help()) {
+        notes.push(escape(help));
+    }
+
+    let defaults: Vec<String> = argument
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().into_owned())
+        .collect();
+    if !defaults.is_empty() {
+        notes.push(format!("[default: {}]", escape(&defaults.join(", "))));
+    }
+    if let Some(variable) = argument.get_env() {
+        notes.push(format!("[env: {}]", escape(&variable.to_string_lossy())));
+    }
+
+    notes.join(" ")
+}
+
+fn is_listed(command: &Command) -> bool {
+    !command.is_hide_set() && command.get_name() != "help"
+}
+
+// the page of a built `command`, see `Command::build`, from `source`, the
+// program and its version
+fn page(command: &Command, source: &str) -> String {
+    let name = page_name(command);
+    let mut page = String::new();
+
+    page.push_str(&format!(".TH {} 1 \"\" \"{}\"\n", escape(&name.to_uppercase()), escape(source)));
+
+    page.push_str(".SH NAME\n");
+    match command.get_about() {
+        Some(about) => page.push_str(&format!("{} \\- {}\n", escape(&name), escape(about))),
+        None => page.push_str(&format!("{}\n", escape(&name))),
+    }
+
+    // the usage rendered for --help, without its title
+    let usage = command.clone().render_usage();
+    page.push_str(".SH SYNOPSIS\n");
+    let usage: Vec<String> = usage.lines().skip(1).map(|line| escape(line.trim())).collect();
+    page.push_str(&format!("{}\n", usage.join("\n.br\n")));
+
+    if let Some(about) = command.get_long_about() {
+        page.push_str(&format!(".SH DESCRIPTION\n{}\n", escape(about)));
+    }
+
+    let (positionals, options): (Vec<&Arg>, Vec<&Arg>) = command
+        .get_arguments()
+        .filter(|argument| !argument.is_hide_set())
+        .partition(|argument| argument.is_positional());
+    for (title, arguments) in [("ARGUMENTS", positionals), ("OPTIONS", options)] {
+        if arguments.is_empty() {
+            continue;
+        }
+
+        page.push_str(&format!(".SH {}\n", title));
+        for argument in arguments {
+            page.push_str(&format!(".TP\n{}\n", argument_forms(argument)));
+
+            let description = argument_description(argument);
+            if !description.is_empty() {
+                page.push_str(&format!("{}\n", description));
+            }
+        }
+    }
+
+    let subcommands: Vec<&Command> = command.get_subcommands().filter(|subcommand| is_listed(subcommand)).collect();
+    if !subcommands.is_empty() {
+        page.push_str(".SH SUBCOMMANDS\n");
+        for subcommand in subcommands {
+            page.push_str(&format!(".TP\n{}(1)\n", bold(&page_name(subcommand))));
+            if let Some(about) = subcommand.get_about() {
+                page.push_str(&format!("{}\n", escape(about)));
+            }
+        }
+    }
+
+    page
+}
+
+// The pages of a built `command` and of all its subcommands, but for hidden
+// ones, each with the name of its file.
+pub fn pages(command: &Command) -> Vec<(String, String)> {
+    let source = format!("{} {}", command.get_name(), command.get_version().unwrap_or_default());
+    let mut pages = Vec::new();
+    add_pages(command, source.trim(), &mut pages);
+
+    pages
+}
+
+fn add_pages(command: &Command, source: &str, pages: &mut Vec<(String, String)>) {
+    pages.push((format!("{}.1", page_name(command)), page(command, source)));
+
+    for subcommand in command.get_subcommands().filter(|subcommand| is_listed(subcommand)) {
+        add_pages(subcommand, source, pages);
+    }
+}
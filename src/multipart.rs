@@ -0,0 +1,155 @@
+use thiserror::Error;
+
+// The parts of a multipart/form-data body, as browsers and HTTP clients send
+// forms with files in them (RFC 7578): each part is a field of the form, with
+// its name, the name of the file for uploads, and its bytes as they were
+// sent. Parts are separated by a boundary line the Content-Type header of the
+// request gives.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum MultipartError {
+    #[error("Expected a multipart/form-data body, got {0}")]
+    NotMultipart(String),
+    #[error("The Content-Type of the body has no boundary")]
+    MissingBoundary,
+    #[error("Malformed multipart body: {0}")]
+    Malformed(&'static str),
+}
+
+// the boundary a Content-Type header of multipart/form-data gives, as in
+// multipart/form-data; boundary="----1234"
+pub fn boundary(content_type: &str) -> Result<String, MultipartError> {
+    let mut parameters = content_type.split(';').map(str::trim);
+
+    let media_type = parameters.next().unwrap_or_default();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(MultipartError::NotMultipart(media_type.to_string()));
+    }
+
+    parameters
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or(MultipartError::MissingBoundary)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// the value of a parameter of a Content-Disposition header, as name in
+// form-data; name="image"; filename="photo.png"
+fn parameter(header: &str, name: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        match key.trim().eq_ignore_ascii_case(name) {
+            true => Some(value.trim().trim_matches('"').to_string()),
+            false => None,
+        }
+    })
+}
+
+fn parse_part(bytes: &[u8]) -> Result<Part, MultipartError> {
+    let end = find(bytes, b"\r\n\r\n").ok_or(MultipartError::Malformed("a part has no end of its headers"))?;
+    let headers = std::str::from_utf8(&bytes[..end]).map_err(|_| MultipartError::Malformed("headers are not UTF-8"))?;
+
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+    for header in headers.split("\r\n") {
+        let Some((key, value)) = header.split_once(':') else { continue };
+
+        if key.trim().eq_ignore_ascii_case("content-disposition") {
+            name = parameter(value, "name");
+            filename = parameter(value, "filename");
+        } else if key.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+
+    Ok(Part {
+        name: name.ok_or(MultipartError::Malformed("a part has no name"))?,
+        filename,
+        content_type,
+        data: bytes[end + 4..].to_vec(),
+    })
+}
+
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, MultipartError> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    // parts end with a line break before the next delimiter, which is not theirs
+    let separator = [b"\r\n".as_slice(), &delimiter].concat();
+
+    let start = find(body, &delimiter).ok_or(MultipartError::Malformed("the boundary is not in the body"))?;
+    let mut rest = &body[start + delimiter.len()..];
+    let mut parts = Vec::new();
+
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or(MultipartError::Malformed("a boundary is not followed by a line break"))?;
+
+        let end = find(rest, &separator).ok_or(MultipartError::Malformed("the body ends before its last boundary"))?;
+        parts.push(parse_part(&rest[..end])?);
+        rest = &rest[end + separator.len()..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body() -> Vec<u8> {
+        let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"message\"\r\n\r\nMeet at noon\r\n".to_vec();
+        body.extend_from_slice(
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"image\"; filename=\"photo.png\"\r\n\
+            Content-Type: image/png\r\n\r\n",
+        );
+        body.extend_from_slice(b"\x89PNG\r\n--Xy\r\n");
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        body
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(boundary("multipart/form-data; boundary=XyZ").unwrap(), "XyZ");
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b\"").unwrap(), "a b");
+        assert!(matches!(boundary("application/json"), Err(MultipartError::NotMultipart(_))));
+        assert!(matches!(boundary("multipart/form-data"), Err(MultipartError::MissingBoundary)));
+    }
+
+    #[test]
+    fn test_parse() {
+        let parts = parse(&body(), "XyZ").unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "message");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(parts[0].data, b"Meet at noon");
+        assert_eq!(parts[1].name, "image");
+        assert_eq!(parts[1].filename.as_deref(), Some("photo.png"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("image/png"));
+        // line breaks and the start of the boundary in the data are kept
+        assert_eq!(parts[1].data, b"\x89PNG\r\n--Xy\r\n");
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let body = body();
+
+        assert!(parse(&body[..body.len() - 12], "XyZ").is_err());
+        assert!(parse(&body, "Other").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::png::{self, Position};
+
+use std::fmt;
+use std::ops::Range;
+
+use anyhow::Result;
+
+// A PDF file is a header, numbered objects, a cross-reference table giving the
+// offset of every object and a trailer pointing to the document catalog. A
+// file can be changed by an incremental update: new objects, a table listing
+// just those and a trailer pointing back to the previous table are appended,
+// and everything before is left as it was.
+//
+// Payloads go in a stream object added by such an update. Nothing in the
+// document refers to it, so viewers never show it; its dictionary carries the
+// marker /PNGCrypt with the four letter token decode asks for as value. Only
+// what is needed for this is parsed: the trailer of the last update and the
+// payload objects. `--position` does not apply, updates always go at the end.
+
+pub const SIGNATURE: &[u8] = b"%PDF-";
+
+const MARKER: &[u8] = b"/PNGCrypt /";
+
+#[derive(Debug)]
+pub enum PdfError {
+    InvalidHeader,
+    MissingTrailer,
+    MissingEntry(&'static str),
+}
+
+impl std::error::Error for PdfError {}
+
+impl fmt::Display for PdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdfError::InvalidHeader => write!(f, "The file does not start with a PDF header"),
+            PdfError::MissingTrailer => write!(f, "The PDF file has no trailer at the offset startxref gives"),
+            PdfError::MissingEntry(key) => write!(f, "The PDF trailer has no {} entry", key),
+        }
+    }
+}
+
+struct Record {
+    // the object, from its number to the end of "endobj"
+    object: Range<usize>,
+    token: String,
+    data: Range<usize>,
+}
+
+fn find(bytes: &[u8], pattern: &[u8], from: usize) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .map(|position| from + position)
+}
+
+fn rfind(bytes: &[u8], pattern: &[u8], before: usize) -> Option<usize> {
+    bytes[..before.min(bytes.len())].windows(pattern.len()).rposition(|window| window == pattern)
+}
+
+// the dictionary starting at `start`, up to the matching ">>"
+fn dictionary(bytes: &[u8], start: usize) -> Option<&[u8]> {
+    let mut depth = 0;
+    let mut offset = start;
+
+    while offset + 1 < bytes.len() {
+        match &bytes[offset..offset + 2] {
+            b"<<" => {
+                depth += 1;
+                offset += 2;
+            }
+            b">>" => {
+                depth -= 1;
+                offset += 2;
+                if depth == 0 {
+                    return Some(&bytes[start..offset]);
+                }
+            }
+            _ => offset += 1,
+        }
+    }
+
+    None
+}
+
+// The value of `key` in a dictionary as written, for the kinds of values a
+// trailer holds: numbers, references such as "1 0 R" and arrays.
+fn entry(dictionary: &[u8], key: &str) -> Option<String> {
+    let text = String::from_utf8_lossy(dictionary);
+    let start = text
+        .match_indices(key)
+        .map(|(index, _)| index + key.len())
+        .find(|&end| !text[end..].starts_with(|char: char| char.is_ascii_alphanumeric()))?;
+    let rest = text[start..].trim_start();
+
+    if rest.starts_with('[') {
+        return Some(rest[..rest.find(']')? + 1].to_string());
+    }
+
+    let tokens: Vec<&str> = rest.split(|char: char| char.is_whitespace() || char == '/' || char == '>').take(3).collect();
+    if tokens.len() == 3 && tokens[2] == "R" {
+        Some(tokens.join(" "))
+    } else {
+        tokens.first().filter(|token| !token.is_empty()).map(|token| token.to_string())
+    }
+}
+
+pub struct Pdf {
+    bytes: Vec<u8>,
+}
+
+impl Pdf {
+    pub fn parse(bytes: &[u8]) -> Result<Pdf> {
+        if !bytes.starts_with(SIGNATURE) {
+            return Err(PdfError::InvalidHeader.into());
+        }
+
+        let pdf = Pdf { bytes: bytes.to_vec() };
+        pdf.trailer()?;
+
+        Ok(pdf)
+    }
+
+    // the offset of the last cross-reference table
+    fn startxref(&self) -> Result<usize> {
+        let keyword = rfind(&self.bytes, b"startxref", self.bytes.len()).ok_or(PdfError::MissingTrailer)?;
+        let value = String::from_utf8_lossy(&self.bytes[keyword + 9..]);
+
+        Ok(value
+            .split_whitespace()
+            .next()
+            .and_then(|offset| offset.parse().ok())
+            .ok_or(PdfError::MissingTrailer)?)
+    }
+
+    // The trailer dictionary of the last update. Files using a cross-reference
+    // stream keep it in the dictionary of that stream.
+    pub fn trailer(&self) -> Result<&[u8]> {
+        let xref = self.startxref()?;
+        if xref >= self.bytes.len() {
+            return Err(PdfError::MissingTrailer.into());
+        }
+
+        let keyword: &[u8] = if self.bytes[xref..].starts_with(b"xref") { b"trailer" } else { b"obj" };
+        let start = find(&self.bytes, keyword, xref)
+            .and_then(|keyword| find(&self.bytes, b"<<", keyword))
+            .ok_or(PdfError::MissingTrailer)?;
+
+        Ok(dictionary(&self.bytes, start).ok_or(PdfError::MissingTrailer)?)
+    }
+
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+
+        while let Some(marker) = find(&self.bytes, MARKER, offset) {
+            offset = marker + MARKER.len();
+
+            let record = (|| {
+                let start = rfind(&self.bytes, b"<<", marker)?;
+                let dictionary = dictionary(&self.bytes, start)?;
+                let length: usize = entry(dictionary, "/Length")?.parse().ok()?;
+
+                let stream = find(&self.bytes, b"stream", start + dictionary.len())? + 6;
+                let data = stream + if self.bytes[stream..].starts_with(b"\r\n") { 2 } else { 1 };
+                let end = find(&self.bytes, b"endobj", data + length)? + 6;
+
+                // the object starts on the line holding "N G obj"
+                let obj = rfind(&self.bytes, b"obj", start)?;
+                let object = rfind(&self.bytes, b"\n", obj).map(|newline| newline + 1).unwrap_or(0);
+
+                Some(Record {
+                    object: object..end,
+                    token: String::from_utf8_lossy(self.bytes.get(offset..offset + 4)?).into_owned(),
+                    data: data..self.bytes.get(data..data + length).map(|_| data + length)?,
+                })
+            })();
+
+            records.extend(record);
+        }
+
+        records
+    }
+
+    fn record(&self, selector: &str) -> Result<Record> {
+        self.records()
+            .into_iter()
+            .find(|record| record.token == selector)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Carrier for Pdf {
+    fn format(&self) -> &'static str {
+        "PDF"
+    }
+
+    // offsets in the cross-reference table have 10 digits
+    fn capacity(&self) -> usize {
+        9_999_999_999usize.saturating_sub(self.bytes.len() + 256)
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let records = self.records();
+        let token = std::iter::repeat_with(|| png::new_chunk_type().to_string())
+            .find(|token| records.iter().all(|record| record.token != *token))
+            .unwrap();
+        self.embed_as(payload, &token, position)?;
+
+        Ok(token)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, _position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.records().iter().any(|record| record.token == token) {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let trailer = self.trailer()?;
+        let size: usize = entry(trailer, "/Size")
+            .and_then(|size| size.parse().ok())
+            .ok_or(PdfError::MissingEntry("/Size"))?;
+        let root = entry(trailer, "/Root").ok_or(PdfError::MissingEntry("/Root"))?;
+
+        // the entries the new trailer has to repeat
+        let mut entries = format!("/Size {} /Prev {} /Root {}", size + 1, self.startxref()?, root);
+        for key in ["/Info", "/ID", "/Encrypt"] {
+            if let Some(value) = entry(trailer, key) {
+                entries.push_str(&format!(" {} {}", key, value));
+            }
+        }
+
+        if !self.bytes.ends_with(b"\n") {
+            self.bytes.push(b'\n');
+        }
+
+        let object = self.bytes.len();
+        self.bytes.extend(format!("{} 0 obj\n<< /Length {} /PNGCrypt /{} >>\nstream\n", size, payload.len(), token).as_bytes());
+        self.bytes.extend(payload);
+        self.bytes.extend(b"\nendstream\nendobj\n");
+
+        let xref = self.bytes.len();
+        self.bytes.extend(format!("xref\n{} 1\n{:010} 00000 n \n", size, object).as_bytes());
+        self.bytes.extend(format!("trailer\n<< {} >>\nstartxref\n{}\n%%EOF\n", entries, xref).as_bytes());
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        Ok(self.bytes[self.record(selector)?.data].to_vec())
+    }
+
+    // The update adding the payload is cut off when it is the last one. When
+    // other updates followed, the object stays and only its data is cleared.
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let record = self.record(selector)?;
+        let payload = self.bytes[record.data.clone()].to_vec();
+
+        if find(&self.bytes, b" obj", record.object.end).is_none() {
+            self.bytes.truncate(record.object.start);
+        } else {
+            self.bytes[record.data].fill(0);
+        }
+
+        Ok(payload)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.records()
+            .into_iter()
+            .map(|record| Slot {
+                selector: record.token,
+                length: record.data.len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a one page document with a classic cross-reference table
+    fn testing_pdf() -> Vec<u8> {
+        let objects = [
+            "1 0 obj\n<< /Type /Catalog /Pages 2 0 R >>\nendobj\n",
+            "2 0 obj\n<< /Type /Pages /Kids [3 0 R] /Count 1 >>\nendobj\n",
+            "3 0 obj\n<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] >>\nendobj\n",
+        ];
+
+        let mut pdf = "%PDF-1.4\n".to_string();
+        let mut xref = "xref\n0 4\n0000000000 65535 f \n".to_string();
+        for object in objects {
+            xref.push_str(&format!("{:010} 00000 n \n", pdf.len()));
+            pdf.push_str(object);
+        }
+
+        let offset = pdf.len();
+        pdf.push_str(&xref);
+        pdf.push_str(&format!("trailer\n<< /Size 4 /Root 1 0 R /ID [<01> <02>] >>\nstartxref\n{}\n%%EOF\n", offset));
+        pdf.into_bytes()
+    }
+
+    #[test]
+    fn test_trailer_entries() {
+        let pdf = Pdf::parse(&testing_pdf()).unwrap();
+        let trailer = pdf.trailer().unwrap();
+
+        assert_eq!(entry(trailer, "/Size"), Some("4".to_string()));
+        assert_eq!(entry(trailer, "/Root"), Some("1 0 R".to_string()));
+        assert_eq!(entry(trailer, "/ID"), Some("[<01> <02>]".to_string()));
+        assert_eq!(entry(trailer, "/Info"), None);
+
+        assert!(Pdf::parse(b"%PDF-1.4\n").is_err());
+    }
+
+    #[test]
+    fn test_pdf_carrier() {
+        let mut pdf = Pdf::parse(&testing_pdf()).unwrap();
+        let payload = b"binary \x00\xff endstream payload".to_vec();
+
+        let token = pdf.embed(&payload, Position::BeforeIend).unwrap();
+
+        let mut pdf = Pdf::parse(pdf.as_bytes()).unwrap();
+        let trailer = pdf.trailer().unwrap();
+        assert_eq!(entry(trailer, "/Size"), Some("5".to_string()));
+        assert_eq!(entry(trailer, "/Prev"), Some("186".to_string()));
+        assert_eq!(pdf.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+        assert_eq!(pdf.extract(&token).unwrap(), payload);
+
+        assert_eq!(pdf.remove(&token).unwrap(), payload);
+        assert_eq!(pdf.as_bytes(), testing_pdf());
+    }
+}
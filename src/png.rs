@@ -1,122 +1,1042 @@
-use super::chunk::Chunk;
-use super::chunk_type::ChunkType;
+pub mod apng;
+pub mod builder;
+pub mod exif;
+pub mod index;
+pub mod order;
+pub mod palette;
+pub mod pixels;
+pub mod repair;
+pub mod text;
+pub mod validate;
+
+use super::carrier::{Carrier, CarrierError, Slot};
+use super::chunk::{self, Chunk, ChunkError, ChunkRef, CrcCheck};
+use super::chunk_type::{ChunkType, ChunkTypeError, Prefix};
+use super::crypto;
+use super::envelope;
+use apng::FrameControl;
+use palette::Palette;
+use pixels::{Header, Pixels};
+use text::{InternationalText, Text};
+use validate::Violation;
 
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::Index;
 use std::str::FromStr;
 
-use anyhow::{Error, Result};
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[allow(dead_code)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Png {
     chunks: Vec<Chunk>,
+    // bytes found after IEND, which viewers ignore
+    #[serde(with = "chunk::data")]
+    trailing: Vec<u8>,
+}
+
+// where a new chunk goes, relative to the chunks the specification places
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    AfterIhdr,
+    // before the first IDAT, or before IEND when there is none
+    BeforeIdat,
+    BeforeIend,
+    // anywhere after IHDR and before IEND, outside of the image data
+    Random,
+}
+
+// which chunks of a type `remove_chunks` takes out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence {
+    First,
+    // the chunk at this index in the chunk list, as print numbers them
+    At(usize),
+    All,
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(position: &str) -> std::result::Result<Self, Self::Err> {
+        match position {
+            "after-ihdr" => Ok(Position::AfterIhdr),
+            "before-idat" => Ok(Position::BeforeIdat),
+            "before-iend" => Ok(Position::BeforeIend),
+            "random" => Ok(Position::Random),
+            _ => Err(format!(
+                "Invalid position: {}. Expected after-ihdr, before-idat, before-iend or random",
+                position
+            )),
+        }
+    }
 }
 
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks }
+        Png {
+            chunks,
+            trailing: Vec::new(),
+        }
+    }
+
+    // Adds `chunk` before IEND, which has to stay the last chunk. Files from
+    // earlier versions can have chunks after IEND, those stay where they are.
+    pub fn insert_chunk(&mut self, chunk: Chunk) {
+        self.insert_chunk_at(chunk, Position::BeforeIend);
+    }
+
+    // a new chunk type for a payload, one the image does not have already as
+    // that would make the token ambiguous
+    pub fn unused_chunk_type(&self) -> ChunkType {
+        std::iter::repeat_with(new_chunk_type)
+            .find(|chunk_type| self.chunks.iter().all(|chunk| chunk.chunk_type != *chunk_type))
+            .unwrap()
     }
 
+    // The chunks following IEND, which earlier versions appended their
+    // payloads after. They are read so those payloads can still be decoded,
+    // but hide from anything that stops at IEND.
+    pub fn chunks_after_iend(&self) -> &[Chunk] {
+        match self.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *b"IEND") {
+            Some(iend) => &self.chunks[iend + 1..],
+            None => &[],
+        }
+    }
+
+    // the same as `insert_chunk`, under the name it had before chunks went
+    // before IEND
+    #[deprecated(note = "use insert_chunk, which places the chunk before IEND")]
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk);
+        self.insert_chunk(chunk);
     }
 
-    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
-        let chunk_type = ChunkType::from_str(chunk_type)?;
-        
-        let chunk = self
+    pub fn insert_chunk_at(&mut self, chunk: Chunk, position: Position) {
+        let find = |chunk_type: &[u8; 4]| self.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *chunk_type);
+
+        let index = match position {
+            Position::AfterIhdr => find(b"IHDR").map(|ihdr| ihdr + 1),
+            Position::BeforeIdat => find(b"IDAT").or_else(|| find(b"IEND")),
+            Position::BeforeIend => find(b"IEND"),
+            Position::Random => self.random_index(),
+        };
+
+        self.chunks.insert(index.unwrap_or(self.chunks.len()), chunk);
+    }
+
+    // A random place for a new chunk between IHDR and IEND. Consecutive image
+    // data chunks have to stay together, so the chunk never goes between two.
+    fn random_index(&self) -> Option<usize> {
+        let is_data = |index: usize| matches!(&self.chunks[index].chunk_type.bytes, b"IDAT" | b"fdAT");
+        let first = self.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *b"IHDR")? + 1;
+        let last = self
             .chunks
             .iter()
-            .position(|chunk| chunk.chunk_type == chunk_type)
-            .ok_or(PngError::ChunkNotFound)?;
+            .position(|chunk| chunk.chunk_type.bytes == *b"IEND")
+            .unwrap_or(self.chunks.len());
+
+        let places: Vec<usize> = (first..=last)
+            .filter(|&index| index == self.chunks.len() || !(is_data(index - 1) && is_data(index)))
+            .collect();
 
-        let chunk = self.chunks.remove(chunk);
+        places.choose(&mut rand::thread_rng()).copied()
+    }
+
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, PngError> {
+        let (_, chunk) = self.remove_chunks(chunk_type, Occurrence::First)?.remove(0);
         Ok(chunk)
     }
 
+    // Removes the chunks of `chunk_type` that `occurrence` picks, returning each
+    // with the index it had in the chunk list. Fails when there is none.
+    pub fn remove_chunks(&mut self, chunk_type: &str, occurrence: Occurrence) -> Result<Vec<(usize, Chunk)>, PngError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+
+        let mut indices: Vec<usize> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk.chunk_type == chunk_type)
+            .map(|(index, _)| index)
+            .collect();
+
+        match occurrence {
+            Occurrence::First => indices.truncate(1),
+            Occurrence::At(index) => indices.retain(|&found| found == index),
+            Occurrence::All => {}
+        }
+
+        if indices.is_empty() {
+            return Err(PngError::ChunkNotFound);
+        }
+
+        // from the back, so the indices still to remove stay valid
+        let mut removed: Vec<(usize, Chunk)> = indices.into_iter().rev().map(|index| (index, self.chunks.remove(index))).collect();
+        removed.reverse();
+
+        Ok(removed)
+    }
+
+    // keep only the chunks `keep` accepts, returning the others
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, mut keep: F) -> Vec<Chunk> {
+        let (kept, removed) = self.chunks.drain(..).partition(|chunk| keep(chunk));
+        self.chunks = kept;
+        removed
+    }
+
     fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
     }
 
-    fn chunks(&self) -> &[Chunk] {
-        &self.chunks
+    // the chunks in file order, `png[index]` or `chunk(index)` for a single one
+    pub fn chunks(&self) -> std::slice::Iter<'_, Chunk> {
+        self.chunks.iter()
+    }
+
+    pub fn chunk(&self, index: usize) -> Option<&Chunk> {
+        self.chunks.get(index)
     }
 
+    // the first chunk of the type, see `chunks_by_type` for types that repeat
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks_by_type(chunk_type).next()
+    }
+
+    // every chunk of the type, such as all tEXt chunks or all payloads
+    // sharing a chunk type
+    pub fn chunks_by_type(&self, chunk_type: &str) -> impl Iterator<Item = &Chunk> + '_ {
+        let chunk_type = chunk_type.as_bytes().to_vec();
+        self.chunks
+            .iter()
+            .filter(move |chunk| chunk.chunk_type.bytes[..] == chunk_type[..])
+    }
+
+    pub fn ihdr(&self) -> Result<Header> {
         let chunk = self
+            .chunks
+            .first()
+            .filter(|chunk| chunk.chunk_type.bytes == *b"IHDR")
+            .ok_or(PngError::MissingHeaderChunk)?;
+
+        Header::from_bytes(chunk.data())
+    }
+
+    // decompress and unfilter the image data of every IDAT chunk
+    pub fn pixels(&self) -> Result<Pixels> {
+        let header = self.ihdr()?;
+
+        let compressed: Vec<u8> = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type.bytes == *b"IDAT")
+            .flat_map(|chunk| chunk.data().iter().copied())
+            .collect();
+
+        Pixels::decode(header, &compressed)
+    }
+
+    // re-encode `pixels` into a single IDAT chunk taking the place of the existing ones
+    pub fn set_pixels(&mut self, pixels: &Pixels) -> Result<()> {
+        let position = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.bytes == *b"IDAT")
+            .ok_or(PngError::ChunkNotFound)?;
+
+        let data = pixels.encode()?;
+
+        self.chunks.retain(|chunk| chunk.chunk_type.bytes != *b"IDAT");
+        self.chunks
+            .insert(position, Chunk::new(ChunkType::IDAT, data));
+
+        Ok(())
+    }
+
+    pub fn palette(&self) -> Result<Palette> {
+        let plte = self.chunk_by_type("PLTE").ok_or(PngError::ChunkNotFound)?;
+        let trns = self.chunk_by_type("tRNS").map(|chunk| chunk.data());
+
+        Palette::from_chunks(plte.data(), trns)
+    }
+
+    // write `palette` into PLTE, and into tRNS when it has transparency
+    pub fn set_palette(&mut self, palette: &Palette) -> Result<()> {
+        let plte = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.bytes == *b"PLTE")
+            .ok_or(PngError::ChunkNotFound)?;
+
+        self.chunks[plte] = Chunk::new(ChunkType::PLTE, palette.plte_bytes());
+
+        let trns = self.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *b"tRNS");
+        let trns_chunk = Chunk::new(ChunkType::TRNS, palette.trns_bytes());
+
+        match trns {
+            Some(position) => self.chunks[position] = trns_chunk,
+            // tRNS must come after PLTE and before the image data
+            None if palette.has_transparency() => self.chunks.insert(plte + 1, trns_chunk),
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    // Sorts the palette by luminance, see `Palette::sort_by_luminance`, and
+    // returns `frames` with their indices updated to match.
+    pub fn sort_palette(&mut self, frames: &[Pixels]) -> Result<Vec<Pixels>> {
+        let header = self.ihdr()?;
+        let mut palette = self.palette()?;
+        let mapping = palette.sort_by_luminance(&header);
+
+        self.set_palette(&palette)?;
+        self.remap_palette_references(&mapping, palette.entries.len());
+
+        frames.iter().map(|frame| palette::remap(frame, &mapping)).collect()
+    }
+
+    // Moves what bKGD and hIST say of each palette entry to where `mapping`
+    // puts the entry, see `sort_palette`: the index of the background color,
+    // and the count of each entry for a palette of `entries` entries now.
+    fn remap_palette_references(&mut self, mapping: &[u8], entries: usize) {
+        for chunk in self.chunks.iter_mut() {
+            let data = match &chunk.chunk_type.bytes {
+                b"bKGD" => match chunk.data() {
+                    [index] => vec![mapping.get(*index as usize).copied().unwrap_or(*index)],
+                    _ => continue,
+                },
+                b"hIST" => {
+                    let mut histogram = vec![0u8; entries * 2];
+                    for (old, count) in chunk.data().chunks_exact(2).enumerate().take(mapping.len()) {
+                        let new = mapping[old] as usize * 2;
+                        histogram[new..new + 2].copy_from_slice(count);
+                    }
+                    histogram
+                }
+                _ => continue,
+            };
+
+            *chunk = Chunk::new(chunk.chunk_type, data);
+        }
+    }
+
+    // the keyword and text of the tEXt and iTXt chunks, leaving out the ones that can not be read
+    pub fn texts(&self) -> Vec<Text> {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| match &chunk.chunk_type.bytes {
+                b"tEXt" => Text::from_chunk(chunk).ok(),
+                b"iTXt" => InternationalText::from_chunk(chunk).ok().map(Text::from),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // replaces the text chunks with the keyword by one holding `text`, before the image data
+    pub fn set_text(&mut self, keyword: &str, text: &str) -> Result<()> {
+        let chunk = text::text_chunk(keyword, text)?;
+
+        self.remove_texts(keyword);
+        self.insert_chunk_at(chunk, Position::BeforeIdat);
+
+        Ok(())
+    }
+
+    // removes the tEXt, zTXt and iTXt chunks with the keyword, returning them
+    pub fn remove_texts(&mut self, keyword: &str) -> Vec<Chunk> {
+        self.retain_chunks(|chunk| text::keyword_of(chunk).as_deref() != Some(keyword))
+    }
+
+    // the EXIF data of the eXIf chunk
+    pub fn exif(&self) -> Option<&[u8]> {
+        self.chunk_by_type("eXIf").map(|chunk| chunk.data())
+    }
+
+    // replaces the eXIf chunk, or adds one before the image data, which it has to precede
+    pub fn set_exif(&mut self, data: Vec<u8>) -> Result<()> {
+        if !exif::is_exif(&data) {
+            return Err(exif::ExifError::InvalidHeader.into());
+        }
+
+        let chunk = Chunk::new(ChunkType::EXIF, data);
+
+        match self.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *b"eXIf") {
+            Some(index) => self.chunks[index] = chunk,
+            None => self.insert_chunk_at(chunk, Position::BeforeIdat),
+        }
+
+        Ok(())
+    }
+
+    // removes the eXIf chunks, returning them
+    pub fn strip_exif(&mut self) -> Vec<Chunk> {
+        self.retain_chunks(|chunk| chunk.chunk_type.bytes != *b"eXIf")
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.chunk_by_type("acTL").is_some()
+    }
+
+    // the position of every fcTL that starts a frame stored in fdAT chunks
+    fn fdat_frames(&self) -> Result<Vec<usize>> {
+        let first_idat = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type.bytes == *b"IDAT")
+            .ok_or(PngError::ChunkNotFound)?;
+
+        Ok((first_idat..self.chunks.len())
+            .filter(|&i| self.chunks[i].chunk_type.bytes == *b"fcTL")
+            .collect())
+    }
+
+    // The default image followed by every frame of an animation, in order. A
+    // still image has the default image as its only frame.
+    pub fn frames(&self) -> Result<Vec<Pixels>> {
+        let mut frames = vec![self.pixels()?];
+
+        if !self.is_animated() {
+            return Ok(frames);
+        }
+
+        let header = self.ihdr()?;
+
+        for position in self.fdat_frames()? {
+            let control = FrameControl::from_bytes(self.chunks[position].data())?;
+
+            let mut compressed = Vec::new();
+            for chunk in self.chunks[position + 1..]
+                .iter()
+                .take_while(|chunk| chunk.chunk_type.bytes == *b"fdAT")
+            {
+                compressed.extend_from_slice(apng::frame_data(chunk.data())?);
+            }
+
+            let frame_header = Header {
+                width: control.width,
+                height: control.height,
+                ..header
+            };
+            frames.push(Pixels::decode(frame_header, &compressed)?);
+        }
+
+        Ok(frames)
+    }
+
+    // Number of bytes the image data of every frame inflates to when `frames`
+    // decodes them, which can be far more than the file holds.
+    pub fn decoded_size(&self) -> Result<u64> {
+        let header = self.ihdr()?;
+        let mut size = header.raw_length()? as u64;
+
+        if !self.is_animated() {
+            return Ok(size);
+        }
+
+        for position in self.fdat_frames()? {
+            let control = FrameControl::from_bytes(self.chunks[position].data())?;
+            let frame_header = Header {
+                width: control.width,
+                height: control.height,
+                ..header
+            };
+            frame_header.check()?;
+            size = size.saturating_add(frame_header.raw_length()? as u64);
+        }
+
+        Ok(size)
+    }
+
+    // Re-encode `frames`, as returned by `frames`. Each animation frame ends up
+    // in a single fdAT, and the sequence numbers are rewritten to stay consecutive.
+    pub fn set_frames(&mut self, frames: &[Pixels]) -> Result<()> {
+        let fdat_frames = if self.is_animated() { self.fdat_frames()? } else { Vec::new() };
+
+        if frames.len() != fdat_frames.len() + 1 {
+            return Err(PngError::FrameCountMismatch.into());
+        }
+
+        let (first, others) = frames.split_at(1);
+        self.set_pixels(&first[0])?;
+
+        if fdat_frames.is_empty() {
+            return Ok(());
+        }
+
+        let mut encoded = others.iter();
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+
+        for (i, chunk) in self.chunks.drain(..).enumerate() {
+            if chunk.chunk_type.bytes == *b"fdAT" {
+                continue;
+            }
+
+            let starts_frame = fdat_frames.contains(&i);
+            chunks.push(chunk);
+
+            if starts_frame {
+                let frame = encoded.next().ok_or(PngError::FrameCountMismatch)?;
+
+                // the sequence number is filled in below
+                let mut data = vec![0; apng::SEQUENCE_SIZE];
+                data.extend(frame.encode()?);
+                chunks.push(Chunk::new(ChunkType::FDAT, data));
+            }
+        }
+
+        let mut sequence = 0;
+        for chunk in chunks.iter_mut() {
+            if chunk.chunk_type.bytes == *b"fcTL" || chunk.chunk_type.bytes == *b"fdAT" {
+                *chunk = Chunk::new(chunk.chunk_type, apng::with_sequence(chunk.data(), sequence));
+                sequence += 1;
+            }
+        }
+
+        self.chunks = chunks;
+
+        Ok(())
+    }
+
+    pub fn trailing(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    // where each chunk starts in the file, at its length field
+    pub fn chunk_offsets(&self) -> Vec<usize> {
+        self.chunks
+            .iter()
+            .scan(Png::STANDARD_HEADER.len(), |offset, chunk| {
+                let start = *offset;
+                *offset += 12 + chunk.data().len();
+                Some(start)
+            })
+            .collect()
+    }
+
+    // where the trailing bytes start in the file, right after IEND
+    pub fn trailing_offset(&self) -> usize {
+        Png::STANDARD_HEADER.len() + self.chunks.iter().map(|chunk| 12 + chunk.data().len()).sum::<usize>()
+    }
+
+    pub fn strip_trailing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailing)
+    }
+
+    // adds `bytes` after IEND, behind any trailing data already there
+    pub fn append_trailing(&mut self, bytes: &[u8]) {
+        self.trailing.extend_from_slice(bytes);
+    }
+
+    // everything about the chunks, their order and what follows IEND that the
+    // specification does not allow
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = self
             .chunks
             .iter()
-            .find(|chunk| &chunk.chunk_type.to_string() == chunk_type);
-        chunk
+            .zip(self.chunk_offsets())
+            .flat_map(|(chunk, offset)| validate::check_chunk(offset, chunk.chunk_type.bytes, chunk.data(), chunk.crc))
+            .collect();
+
+        let types: Vec<String> = self.chunks.iter().map(|chunk| chunk.chunk_type.to_string()).collect();
+        let ihdr = self
+            .chunks
+            .first()
+            .filter(|chunk| chunk.chunk_type.bytes == *b"IHDR")
+            .map(|chunk| chunk.data());
+        violations.extend(validate::check_order(&types, ihdr));
+
+        let after_iend = self.chunks_after_iend();
+        let offsets = self.chunk_offsets();
+        for (chunk, offset) in after_iend.iter().zip(&offsets[self.chunks.len() - after_iend.len()..]) {
+            violations.push(Violation::ChunkAfterIend {
+                offset: *offset,
+                chunk_type: chunk.chunk_type.to_string(),
+            });
+        }
+
+        if !self.trailing.is_empty() {
+            violations.push(Violation::TrailingData {
+                offset: self.trailing_offset(),
+                length: self.trailing.len(),
+            });
+        }
+
+        violations
     }
 
+    // Like `try_from`, but reads chunks whose stored CRC does not match their
+    // data instead of failing. The mismatches are returned as violations, so
+    // they can be reported.
+    pub fn try_from_ignoring_crc(value: &[u8]) -> Result<(Png, Vec<Violation>), PngError> {
+        let png = Png::parse(value, CrcCheck::Ignore)?;
+
+        let mismatches = png
+            .chunks
+            .iter()
+            .zip(png.chunk_offsets())
+            .filter_map(|(chunk, offset)| {
+                let crc_start = offset + 8 + chunk.data().len();
+                let stored = u32::from_be_bytes(value.get(crc_start..crc_start + 4)?.try_into().ok()?);
+
+                (stored != chunk.crc).then(|| Violation::InvalidCrc {
+                    offset,
+                    chunk_type: chunk.chunk_type.to_string(),
+                    stored,
+                    computed: chunk.crc,
+                })
+            })
+            .collect();
+
+        Ok((png, mismatches))
+    }
+
+    // Reads as many whole chunks as `value` holds, for files cut off or damaged
+    // part way. Also returns the offset of the first chunk that could not be
+    // read, when there is one. With `ignore_crc`, chunks with a CRC mismatch
+    // are read too.
+    pub fn salvage(value: &[u8], ignore_crc: bool) -> Result<(Png, Option<usize>), PngError> {
+        if value.get(0..8) != Some(&Png::STANDARD_HEADER[..]) {
+            return Err(PngError::InvalidHeader);
+        }
+
+        let mut chunks = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        let check = if ignore_crc { CrcCheck::Ignore } else { CrcCheck::Verify };
+
+        while offset < value.len() {
+            match read_chunk(value, offset, check) {
+                Ok((chunk, end)) => {
+                    chunks.push(chunk.into_chunk());
+                    offset = end;
+                }
+                Err(_) => return Ok((Png::from_chunks(chunks), Some(offset))),
+            }
+        }
+
+        Ok((Png::from_chunks(chunks), None))
+    }
+
+    // the size of the file `as_bytes` gives
+    pub fn size(&self) -> usize {
+        self.header().len() + self.chunks.iter().map(Chunk::size).sum::<usize>() + self.trailing.len()
+    }
+
+    // The file, with the data found after IEND kept at its end. The buffer is
+    // sized up front, so it is never reallocated and copied as it fills up.
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(self.header());
+        let mut bytes = Vec::with_capacity(self.size());
+        self.write_to(&mut bytes).expect("Writing to a Vec can not fail.");
+        bytes
+    }
+
+    // writes the file to `writer` chunk by chunk, without building it in memory
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.write_chunks_to(&mut writer)?;
+        writer.write_all(&self.trailing)
+    }
+
+    // like `write_to`, leaving out the bytes after IEND
+    pub fn write_chunks_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.header())?;
         for chunk in self.chunks.iter() {
-            bytes.extend_from_slice(&chunk.as_bytes());
+            chunk.write_to(&mut writer)?;
         }
-        bytes
+        Ok(())
+    }
+}
+
+// An ancillary, private and safe-to-copy chunk type: lowercase, lowercase,
+// uppercase as the reserved bit requires, then lowercase so that editors are
+// allowed to keep the chunk when they change the image. The token is all it
+// takes to find a payload, so the letters come from the operating system's
+// secure generator, and a type the specification defines is never returned.
+pub fn new_chunk_type() -> ChunkType {
+    new_chunk_type_with_prefix(None)
+}
+
+// the same, starting with `prefix` when one is given
+pub fn new_chunk_type_with_prefix(prefix: Option<Prefix>) -> ChunkType {
+    let mut rng = OsRng;
+
+    std::iter::repeat_with(|| {
+        let Prefix([first, second]) =
+            prefix.unwrap_or_else(|| Prefix([rng.gen_range(b'a'..=b'z'), rng.gen_range(b'a'..=b'z')]));
+        ChunkType::unchecked([first, second, rng.gen_range(b'A'..=b'Z'), rng.gen_range(b'a'..=b'z')])
+    })
+    .find(|chunk_type| !chunk_type.is_standard())
+    .unwrap()
+}
+
+// The chunk type a password stands for, with the same properties as the ones
+// `new_chunk_type` picks, so decode finds the payload without a token. A label
+// gives the same password other types, for several payloads in one image.
+// The type shows in the image, and deriving it is fast, so it lets password
+// guesses be checked without the work of decrypting: only strong passwords
+// should be used this way.
+pub fn derived_chunk_type(password: &str, label: Option<&str>) -> ChunkType {
+    let mut info = b"chunk type".to_vec();
+    if let Some(label) = label {
+        info.push(0);
+        info.extend(label.as_bytes());
+    }
+
+    let mut bytes = [0u8; 4];
+    crypto::expand_password(password, &info, &mut bytes);
+
+    let letter = |byte: u8, first: u8| first + byte % 26;
+    ChunkType::unchecked([
+        letter(bytes[0], b'a'),
+        letter(bytes[1], b'a'),
+        letter(bytes[2], b'A'),
+        letter(bytes[3], b'a'),
+    ])
+}
+
+// Payloads go in chunks of their own, named by their chunk type.
+impl Carrier for Png {
+    fn format(&self) -> &'static str {
+        "PNG"
+    }
+
+    fn capacity(&self) -> usize {
+        Chunk::MAX_LENGTH as usize
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let selector = self.unused_chunk_type().to_string();
+        self.embed_as(payload, &selector, position)?;
+
+        Ok(selector)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], selector: &str, position: Position) -> Result<()> {
+        let chunk_type = ChunkType::from_str(selector)?;
+        chunk_type.check_payload_type()?;
+        if self.chunks.iter().any(|chunk| chunk.chunk_type == chunk_type) {
+            return Err(CarrierError::SelectorTaken(selector.to_string()).into());
+        }
+
+        self.insert_chunk_at(Chunk::new(chunk_type, payload.to_vec()), position);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        let chunk = self
+            .chunk_by_type(selector)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        Ok(chunk.data().to_vec())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        if self.chunk_by_type(selector).is_none() {
+            return Err(CarrierError::NotFound(selector.to_string()).into());
+        }
+
+        Ok(self.remove_chunk(selector)?.data().to_vec())
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.chunks
+            .iter()
+            .filter(|chunk| !chunk.chunk_type.is_critical() && envelope::is_envelope(chunk.data()))
+            .map(|chunk| Slot {
+                selector: chunk.chunk_type.to_string(),
+                length: chunk.data().len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        Png::write_to(self, writer)
+    }
+
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        Png::strip_trailing(self)
+    }
+}
+
+// the chunk at `start` and the position right after it
+fn read_chunk(value: &[u8], start: usize, check: CrcCheck) -> Result<(ChunkRef<'_>, usize), PngError> {
+    let length_data: [u8; 4] = value
+        .get(start..start.saturating_add(4))
+        .and_then(|length| length.try_into().ok())
+        .ok_or(PngError::Truncated)?;
+    let length = u32::from_be_bytes(length_data);
+
+    let end = start.saturating_add(12).saturating_add(length as usize);
+
+    let bytes = value.get(start..end).ok_or(PngError::Truncated)?;
+    Ok((ChunkRef::parse(bytes, check)?, end))
+}
+
+// Reads chunks one after the other from an offset in a buffer, stopping after
+// IEND or at the first chunk that can not be read. Lets a PNG be read from the
+// middle of a larger file, without knowing where it ends. The chunks borrow
+// their data from the buffer, see `ChunkRef`.
+pub struct ChunkReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    check: CrcCheck,
+    done: bool,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub fn new(bytes: &'a [u8], offset: usize) -> Self {
+        ChunkReader {
+            bytes,
+            offset,
+            check: CrcCheck::Verify,
+            done: false,
+        }
+    }
+
+    // reads chunks with a CRC mismatch too, see `Chunk::from_bytes_ignoring_crc`
+    pub fn ignoring_crc(mut self) -> Self {
+        self.check = CrcCheck::Ignore;
+        self
+    }
+
+    // what is done with the CRC of each chunk, see `CrcCheck`
+    pub fn checking_crc(mut self, check: CrcCheck) -> Self {
+        self.check = check;
+        self
+    }
+
+    // where the next chunk starts, or the chunk that could not be read
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for ChunkReader<'a> {
+    // the offset of the chunk and the chunk
+    type Item = Result<(usize, ChunkRef<'a>), PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.bytes.len() {
+            return None;
+        }
+
+        match read_chunk(self.bytes, self.offset, self.check) {
+            Ok((chunk, end)) => {
+                let start = self.offset;
+                self.offset = end;
+                self.done = chunk.chunk_type.bytes == *b"IEND";
+
+                Some(Ok((start, chunk)))
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// The chunks from `start` up to IEND, or up to the end of `value` when there is
+// no IEND, and the position right after them.
+fn read_chunks(value: &[u8], start: usize, check: CrcCheck) -> Result<(Vec<Chunk>, usize), PngError> {
+    let mut reader = ChunkReader::new(value, start).checking_crc(check);
+
+    let chunks = reader
+        .by_ref()
+        .map(|result| result.map(|(_, chunk)| chunk.into_chunk()))
+        .collect::<Result<Vec<Chunk>, PngError>>()?;
+
+    Ok((chunks, reader.offset()))
+}
+
+impl Index<usize> for Png {
+    type Output = Chunk;
+
+    fn index(&self, index: usize) -> &Chunk {
+        &self.chunks[index]
     }
 }
 
+// the most `Png::from_reader` allocates for the data of a chunk before reading it
+const PREALLOCATED_DATA: usize = 16 * 1024 * 1024;
+
 impl TryFrom<&[u8]> for Png {
-    type Error = Error;
+    type Error = PngError;
+
+    fn try_from(value: &[u8]) -> Result<Self, PngError> {
+        Png::parse(value, CrcCheck::Verify)
+    }
+}
 
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let passed_header = &value[0..8];
+// How `Png::parse_with` reads a file. With `verify_crc` off, the CRC stored with
+// each chunk is recorded as it is instead of being checked against its data,
+// which saves going through every byte of the image data. Meant for large
+// batches of files from trusted storage; verification is on by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub verify_crc: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { verify_crc: true }
+    }
+}
+
+impl Png {
+    pub fn parse_with(value: &[u8], options: ParseOptions) -> Result<Png, PngError> {
+        Png::parse(value, if options.verify_crc { CrcCheck::Verify } else { CrcCheck::Trust })
+    }
+
+    fn parse(value: &[u8], check: CrcCheck) -> Result<Png, PngError> {
+        let passed_header = value.get(0..8).ok_or(PngError::InvalidHeader)?;
 
         if passed_header != Png::STANDARD_HEADER {
-            return Err(PngError::InvalidHeader.into());
+            return Err(PngError::InvalidHeader);
+        }
+
+        let (chunks, end) = read_chunks(value, Png::STANDARD_HEADER.len(), check)?;
+
+        Ok(Png::with_rest(chunks, &value[end..], check))
+    }
+
+    // Earlier versions appended their chunks after IEND. Bytes there made of
+    // nothing but well formed chunks are read as such, anything else is
+    // trailing data.
+    fn with_rest(mut chunks: Vec<Chunk>, rest: &[u8], check: CrcCheck) -> Png {
+        let mut end = 0;
+        if let Ok((appended, appended_end)) = read_chunks(rest, 0, check) {
+            if appended_end == rest.len() {
+                chunks.extend(appended);
+                end = appended_end;
+            }
         }
 
-        let mut chunks: Vec<Chunk> = Vec::new();
-        let mut chunk_start = 8;
+        Png {
+            chunks,
+            trailing: rest[end..].to_vec(),
+        }
+    }
 
-        while chunk_start < value.len() {
-            let next_chunk_length_end = chunk_start + 4;
+    // Like `try_from`, but reads the file from `reader` one chunk at a time
+    // instead of needing all of it in memory first. Only what follows IEND is
+    // read in one go.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png, PngError> {
+        Png::from_reader_with(reader, ParseOptions::default())
+    }
 
-            let length_data: [u8; 4] = value[chunk_start..next_chunk_length_end].try_into()?;
-            let length = u32::from_be_bytes(length_data);
+    // `from_reader` with the options of `parse_with`
+    pub fn from_reader_with<R: Read>(mut reader: R, options: ParseOptions) -> Result<Png, PngError> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|_| PngError::InvalidHeader)?;
 
-            let chunk_end = chunk_start + 12 + length as usize;
+        if header != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidHeader);
+        }
 
-            let chunk = Chunk::try_from(&value[chunk_start..chunk_end])?;
+        let mut chunks = Vec::new();
+        loop {
+            // the length and the type, or nothing when the file has no IEND
+            let mut frame = Vec::with_capacity(8);
+            if reader.by_ref().take(8).read_to_end(&mut frame)? == 0 {
+                break;
+            }
+            if frame.len() < 8 {
+                return Err(PngError::Truncated);
+            }
 
+            let length = u32::from_be_bytes(frame[..4].try_into().unwrap());
+            if length > Chunk::MAX_LENGTH {
+                return Err(ChunkError::InvalidLength(length).into());
+            }
+            let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&frame[4..]).unwrap())
+                .map_err(|_| ChunkError::InvalidChunkType(String::from_utf8_lossy(&frame[4..]).into_owned()))?;
+
+            // The data is read straight into the buffer the chunk keeps, and its
+            // CRC, when checked, computed from there, so it is never held
+            // twice. A length
+            // larger than what is left is only found out by reading it, so no
+            // more than `PREALLOCATED_DATA` is allocated up front.
+            let mut data = Vec::with_capacity((length as usize).min(PREALLOCATED_DATA));
+            if reader.by_ref().take(length as u64).read_to_end(&mut data)? != length as usize {
+                return Err(PngError::Truncated);
+            }
+
+            let mut stored_crc = [0; 4];
+            reader.read_exact(&mut stored_crc).map_err(|_| PngError::Truncated)?;
+
+            let stored_crc = u32::from_be_bytes(stored_crc);
+            let chunk = if options.verify_crc {
+                Chunk::new(chunk_type, data)
+            } else {
+                Chunk {
+                    chunk_type,
+                    length,
+                    crc: stored_crc,
+                    data,
+                }
+            };
+            if chunk.crc != stored_crc {
+                return Err(ChunkError::InvalidCrc(chunk.crc).into());
+            }
+            if !chunk.chunk_type.is_valid() {
+                return Err(ChunkError::InvalidChunkType(chunk.chunk_type.to_string()).into());
+            }
+
+            let is_end = chunk.chunk_type.bytes == *b"IEND";
             chunks.push(chunk);
 
-            chunk_start = chunk_end;
+            if is_end {
+                break;
+            }
         }
 
-        Ok(Png { chunks: chunks })
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+
+        let check = if options.verify_crc { CrcCheck::Verify } else { CrcCheck::Trust };
+
+        Ok(Png::with_rest(chunks, &rest, check))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
 pub enum PngError {
+    #[error("Invalid header")]
     InvalidHeader,
+    #[error("Chunk not found")]
     ChunkNotFound,
+    #[error("IHDR chunk not found at the start of the image")]
+    MissingHeaderChunk,
+    #[error("Number of frames does not match the animation")]
+    FrameCountMismatch,
+    #[error("File ends in the middle of a chunk")]
+    Truncated,
+    #[error(transparent)]
+    Chunk(#[from] ChunkError),
+    #[error(transparent)]
+    ChunkType(#[from] ChunkTypeError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
 }
 
-impl std::error::Error for PngError {}
-
 impl fmt::Display for Png {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self.as_bytes())
     }
 }
 
-impl fmt::Display for PngError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            PngError::InvalidHeader => write!(f, "Invalid header"),
-            PngError::ChunkNotFound => write!(f, "Chunk not found"),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,6 +1044,7 @@ mod tests {
     use crate::chunk_type::ChunkType;
     use std::convert::TryFrom;
 
+    #[allow(clippy::vec_init_then_push)]
     fn testing_chunks() -> Vec<Chunk> {
         let mut chunks = Vec::new();
 
@@ -229,6 +1150,21 @@ mod tests {
     }
 
     #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.insert_chunk(chunk_from_strings("FrSt", "I am another first chunk").unwrap());
+
+        let data: Vec<String> = png.chunks_by_type("FrSt").map(|chunk| chunk.data_as_string().unwrap()).collect();
+        assert_eq!(data, vec!["I am the first chunk", "I am another first chunk"]);
+        assert_eq!(png.chunks_by_type("NoNe").count(), 0);
+
+        assert_eq!(png[3].chunk_type().to_string(), "FrSt");
+        assert_eq!(png.chunk(0).unwrap().chunk_type().to_string(), "FrSt");
+        assert!(png.chunk(4).is_none());
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn test_append_chunk() {
         let mut png = testing_png();
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
@@ -238,6 +1174,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_remove_chunk() {
         let mut png = testing_png();
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
@@ -246,6 +1183,23 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunks() {
+        let chunk = |data: &str| chunk_from_strings("TeSt", data).unwrap();
+        let mut png = testing_png();
+        for data in ["one", "two", "three"] {
+            png.insert_chunk(chunk(data));
+        }
+
+        let removed = png.remove_chunks("TeSt", Occurrence::At(4)).unwrap();
+        assert_eq!(removed, vec![(4, chunk("two"))]);
+        assert!(matches!(png.remove_chunks("TeSt", Occurrence::At(0)), Err(PngError::ChunkNotFound)));
+
+        let removed = png.remove_chunks("TeSt", Occurrence::All).unwrap();
+        assert_eq!(removed, vec![(3, chunk("one")), (4, chunk("three"))]);
+        assert!(png.chunk_by_type("TeSt").is_none());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -253,6 +1207,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::iter_cloned_collect)]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
@@ -260,6 +1215,445 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_write_to() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+        assert_eq!(png.size(), bytes.len());
+        assert_eq!(png.as_bytes().capacity(), bytes.len());
+
+        let mut written = Vec::new();
+        png.write_chunks_to(&mut written).unwrap();
+        assert_eq!(written, PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+
+        let json = serde_json::to_value(&png).unwrap();
+        assert_eq!(json["chunks"][0]["chunk_type"], "IHDR");
+        assert_eq!(json["chunks"][0]["length"], 13);
+        assert_eq!(json["trailing"], "dHJhaWxpbmc=");
+
+        let png: Png = serde_json::from_value(json).unwrap();
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.ihdr().unwrap();
+        assert_eq!(header.width, 50);
+        assert_eq!(header.height, 50);
+        assert_eq!(header.bit_depth, 8);
+        assert_eq!(header.color_type, 6);
+    }
+
+    #[test]
+    fn test_missing_ihdr() {
+        let png = testing_png();
+        assert!(png.ihdr().is_err());
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"PK\x03\x04 appended archive");
+
+        let mut png = Png::try_from(bytes.as_ref()).unwrap();
+
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.trailing(), b"PK\x03\x04 appended archive");
+        assert_eq!(png.trailing_offset(), end);
+        assert_eq!(png.chunk_offsets()[0], 8);
+        assert_eq!(png.chunk_offsets()[1], 8 + 12 + 20);
+        assert_eq!(png.as_bytes(), bytes);
+
+        png.strip_trailing();
+        assert!(png.trailing().is_empty());
+        assert_eq!(png.as_bytes(), &bytes[..end]);
+    }
+
+    #[test]
+    fn test_chunks_after_iend() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        chunks.push(chunk_from_strings("abCD", "Written by an earlier version").unwrap());
+
+        let png = Png::try_from(Png::from_chunks(chunks).as_bytes().as_ref()).unwrap();
+
+        assert_eq!(png.chunks().len(), 5);
+        assert!(png.trailing().is_empty());
+        assert!(png.chunk_by_type("abCD").is_some());
+        assert_eq!(png.chunks_after_iend().len(), 1);
+    }
+
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+
+        let mut png = Png::from_chunks(chunks);
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+
+        assert_eq!(png[3].chunk_type.to_string(), "TeSt");
+        assert_eq!(png[4].chunk_type.to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_insert_chunk_with_chunks_after_iend() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        chunks.push(chunk_from_strings("abCD", "Written by an earlier version").unwrap());
+
+        let mut png = Png::try_from(Png::from_chunks(chunks).as_bytes().as_ref()).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+
+        let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "miDl", "LASt", "TeSt", "IEND", "abCD"]);
+        assert_eq!(png.extract("abCD").unwrap(), b"Written by an earlier version");
+    }
+
+    #[test]
+    fn test_new_chunk_type() {
+        for _ in 0..1000 {
+            let chunk_type = new_chunk_type();
+
+            assert!(chunk_type.is_valid());
+            assert!(!chunk_type.is_critical() && !chunk_type.is_public() && chunk_type.is_safe_to_copy());
+            assert!(!chunk_type.is_standard());
+        }
+
+        let png = testing_png();
+        let chunk_type = png.unused_chunk_type();
+        assert!(png.chunks().all(|chunk| chunk.chunk_type != chunk_type));
+    }
+
+    #[test]
+    fn test_derived_chunk_type() {
+        let chunk_type = derived_chunk_type("correct horse", None);
+
+        assert_eq!(chunk_type, derived_chunk_type("correct horse", None));
+        assert_ne!(chunk_type, derived_chunk_type("correct horse", Some("second")));
+        assert!(chunk_type.is_valid() && !chunk_type.is_public() && chunk_type.is_safe_to_copy());
+
+        let mut png = testing_png();
+        png.embed_as(b"payload", &chunk_type.to_string(), Position::BeforeIend).unwrap();
+        assert_eq!(png.extract(&chunk_type.to_string()).unwrap(), b"payload");
+        assert!(png.embed_as(b"payload", &chunk_type.to_string(), Position::BeforeIend).is_err());
+    }
+
+    #[test]
+    fn test_insert_chunk_at() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+
+        png.insert_chunk_at(chunk("abCd"), Position::AfterIhdr);
+        png.insert_chunk_at(chunk("efGh"), Position::BeforeIdat);
+        png.insert_chunk_at(chunk("ijKl"), Position::BeforeIend);
+
+        let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "abCd", "efGh", "IDAT", "ijKl", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk_at_random() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+
+        for _ in 0..50 {
+            let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IDAT"), chunk("IEND")]);
+            png.insert_chunk_at(chunk("abCd"), Position::Random);
+
+            let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+            let index = types.iter().position(|chunk_type| chunk_type == "abCd").unwrap();
+
+            assert!(index == 1 || index == 3);
+            assert_eq!(types.last().unwrap(), "IEND");
+        }
+    }
+
+    #[test]
+    fn test_retain_chunks() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("tIME"), chunk("IDAT"), chunk("abCD"), chunk("IEND")]);
+
+        let removed = png.retain_chunks(|chunk| chunk.chunk_type.is_critical() || chunk.chunk_type.is_safe_to_copy());
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_set_text() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+
+        png.set_text("Title", "Dice").unwrap();
+        png.set_text("Author", "Ann").unwrap();
+        png.set_text("Title", "Two dice").unwrap();
+
+        let texts: Vec<String> = png.texts().iter().map(Text::to_string).collect();
+        assert_eq!(texts, vec!["Author: Ann", "Title: Two dice"]);
+        assert_eq!(png[3].chunk_type.to_string(), "IDAT");
+
+        assert_eq!(png.remove_texts("Title").len(), 1);
+        assert_eq!(png.texts().len(), 1);
+    }
+
+    #[test]
+    fn test_set_exif() {
+        let chunk = |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new());
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+
+        assert!(png.set_exif(b"JFIF".to_vec()).is_err());
+
+        png.set_exif(b"MM\0*first".to_vec()).unwrap();
+        png.set_exif(b"MM\0*second".to_vec()).unwrap();
+
+        assert_eq!(png[1].chunk_type.to_string(), "eXIf");
+        assert_eq!(png.exif(), Some(&b"MM\0*second"[..]));
+
+        assert_eq!(png.strip_exif().len(), 1);
+        assert_eq!(png.exif(), None);
+    }
+
+    #[test]
+    fn test_salvage_truncated_png() {
+        let bytes = testing_png().as_bytes();
+        let cut = bytes.len() - 5;
+
+        let (png, truncated_at) = Png::salvage(&bytes[..cut], false).unwrap();
+
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_some());
+        assert_eq!(truncated_at, Some(png.trailing_offset()));
+
+        assert_eq!(Png::salvage(&bytes, false).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_ignoring_crc() {
+        let mut bytes = testing_png().as_bytes();
+        let offset = testing_png().chunk_offsets()[1];
+        // a flipped bit in the data of the second chunk
+        bytes[offset + 8] ^= 1;
+
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+
+        let (png, mismatches) = Png::try_from_ignoring_crc(&bytes).unwrap();
+
+        assert_eq!(png.chunks().len(), 3);
+        assert!(matches!(&mismatches[..], [Violation::InvalidCrc { offset: o, .. }] if *o == offset));
+    }
+
+    #[test]
+    fn test_parse_without_verifying_crc() {
+        let mut bytes = testing_png().as_bytes();
+        let offset = testing_png().chunk_offsets()[1];
+        bytes[offset + 8] ^= 1;
+
+        assert!(Png::parse_with(&bytes, ParseOptions::default()).is_err());
+
+        // the chunk keeps the CRC it was stored with
+        let png = Png::parse_with(&bytes, ParseOptions { verify_crc: false }).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunk(1).unwrap().crc, testing_png().chunk(1).unwrap().crc);
+        assert_ne!(png.chunk(1).unwrap().data(), testing_png().chunk(1).unwrap().data());
+
+        assert!(Png::from_reader(bytes.as_slice()).is_err());
+        let read = Png::from_reader_with(bytes.as_slice(), ParseOptions { verify_crc: false }).unwrap();
+        assert_eq!(read.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_truncated_png() {
+        assert!(Png::try_from(&PNG_FILE[..4]).is_err());
+        assert!(Png::try_from(&PNG_FILE[..PNG_FILE.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+
+        let mut bytes = Png::from_chunks(chunks).as_bytes();
+        bytes.extend_from_slice(b"PK\x03\x04 appended archive");
+
+        let png = Png::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.trailing(), b"PK\x03\x04 appended archive");
+        assert_eq!(png.as_bytes(), bytes);
+
+        let png = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.as_bytes(), Png::try_from(&PNG_FILE[..]).unwrap().as_bytes());
+
+        assert!(Png::from_reader(&PNG_FILE[..4]).is_err());
+        assert!(Png::from_reader(&PNG_FILE[..PNG_FILE.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert!(matches!(Png::try_from(&PNG_FILE[..4]), Err(PngError::InvalidHeader)));
+        assert!(matches!(Png::try_from(&PNG_FILE[..PNG_FILE.len() - 3]), Err(PngError::Truncated)));
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[20] ^= 1;
+        assert!(matches!(Png::try_from(bytes.as_ref()), Err(PngError::Chunk(ChunkError::InvalidCrc(_)))));
+
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(matches!(png.remove_chunk("ab"), Err(PngError::ChunkType(_))));
+        assert!(matches!(png.remove_chunk("abCd"), Err(PngError::ChunkNotFound)));
+    }
+
+    #[test]
+    fn test_damaged_input_does_not_panic() {
+        for end in 0..PNG_FILE.len() {
+            let bytes = &PNG_FILE[..end];
+            // a file cut between two chunks still reads, only without IEND
+            let _ = Png::try_from(bytes);
+            let _ = Png::from_reader(bytes);
+            let _ = Png::salvage(bytes, true);
+            let _ = validate::validate_bytes(bytes);
+        }
+
+        for index in 8..PNG_FILE.len() {
+            let mut bytes = PNG_FILE.to_vec();
+            bytes[index] ^= 0xff;
+            if let Err(e) = Png::try_from_ignoring_crc(&bytes) {
+                let _ = e.to_string();
+            }
+            let _ = Png::from_reader(bytes.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_set_pixels_roundtrip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut pixels = png.pixels().unwrap();
+        assert_eq!(pixels.data.len(), 50 * 50 * 4);
+
+        pixels.data[0] ^= 1;
+        png.set_pixels(&pixels).unwrap();
+
+        let reencoded = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reencoded.pixels().unwrap().data, pixels.data);
+        assert_eq!(reencoded.chunks().len(), png.chunks().len());
+    }
+
+    // PNG_FILE turned into a two frame animation, the second frame of 25x25
+    // pixels split over two fdAT chunks
+    fn testing_apng() -> Png {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.ihdr().unwrap();
+
+        let frame = Pixels::new(
+            Header { width: 25, height: 25, ..header },
+            (0..25 * 25 * 4).map(|i| (i % 251) as u8).collect(),
+        );
+        let compressed = frame.encode().unwrap();
+        let (head, tail) = compressed.split_at(compressed.len() / 2);
+
+        let control = |sequence: u32, size: u32| {
+            let mut data = sequence.to_be_bytes().to_vec();
+            data.extend_from_slice(&size.to_be_bytes());
+            data.extend_from_slice(&size.to_be_bytes());
+            data.extend_from_slice(&[0; 14]);
+            Chunk::new(ChunkType::from_str("fcTL").unwrap(), data)
+        };
+        let fdat = |sequence: u32, part: &[u8]| {
+            Chunk::new(ChunkType::from_str("fdAT").unwrap(), apng::with_sequence(&[&[0; 4], part].concat(), sequence))
+        };
+
+        let mut chunks = Vec::new();
+        for chunk in png.chunks.into_iter() {
+            let chunk_type = chunk.chunk_type.bytes;
+
+            if chunk_type == *b"IDAT" {
+                chunks.push(control(0, 50));
+            }
+            if chunk_type == *b"IEND" {
+                chunks.push(control(1, 25));
+                chunks.push(fdat(2, head));
+                chunks.push(fdat(3, tail));
+            }
+
+            chunks.push(chunk);
+
+            if chunk_type == *b"IHDR" {
+                chunks.push(Chunk::new(ChunkType::from_str("acTL").unwrap(), vec![0, 0, 0, 2, 0, 0, 0, 0]));
+            }
+        }
+
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = testing_apng();
+        let frames = png.frames().unwrap();
+
+        assert!(png.is_animated());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, png.pixels().unwrap().data);
+        assert_eq!(frames[1].header.width, 25);
+        assert_eq!(frames[1].data.len(), 25 * 25 * 4);
+    }
+
+    #[test]
+    fn test_set_frames_roundtrip() {
+        let mut png = testing_apng();
+        let mut frames = png.frames().unwrap();
+        frames[1].data[0] ^= 1;
+
+        png.set_frames(&frames).unwrap();
+
+        let reencoded = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reencoded.frames().unwrap()[1].data, frames[1].data);
+
+        // the two fdAT chunks were merged and the sequence renumbered
+        let sequences: Vec<u8> = reencoded
+            .chunks()
+            .filter(|chunk| chunk.chunk_type.bytes == *b"fcTL" || chunk.chunk_type.bytes == *b"fdAT")
+            .map(|chunk| chunk.data()[3])
+            .collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+        assert!(png.set_frames(&frames[..1]).is_err());
+    }
+
+    #[test]
+    fn test_sort_palette_remaps_background_and_histogram() {
+        // white, black and gray, with white as the background
+        let mut png = builder::PngBuilder::new(2, 1)
+            .color(3, 8)
+            .chunk(Chunk::new(ChunkType::PLTE, vec![255, 255, 255, 0, 0, 0, 128, 128, 128]))
+            .chunk(Chunk::new(ChunkType::BKGD, vec![0]))
+            .chunk(Chunk::new(ChunkType::HIST, vec![0, 5, 0, 7, 0, 9]))
+            .pixels(vec![0, 1])
+            .build()
+            .unwrap();
+
+        let frames = png.sort_palette(&png.frames().unwrap()).unwrap();
+
+        let palette = png.palette().unwrap();
+        let background = png.chunk_by_type("bKGD").unwrap().data()[0];
+        assert_eq!(palette.entries[background as usize], [255, 255, 255]);
+        assert_eq!(frames[0].data, vec![2, 0]);
+        // black, gray, white and the duplicate of white no pixel uses
+        assert_eq!(png.chunk_by_type("hIST").unwrap().data(), &[0, 7, 0, 9, 0, 5, 0, 0]);
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
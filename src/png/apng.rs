@@ -0,0 +1,89 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use anyhow::Result;
+
+// Animated PNGs (APNG) keep the default image in IDAT and add frames, each an
+// fcTL chunk describing the frame followed by fdAT chunks holding its image
+// data. fcTL and fdAT share one sequence number counter, stored in their first
+// four bytes, which must increase by one from chunk to chunk.
+
+pub const SEQUENCE_SIZE: usize = 4;
+pub const FRAME_CONTROL_SIZE: usize = 26;
+
+#[derive(Debug)]
+pub enum ApngError {
+    InvalidFrameControl,
+    InvalidFrameData,
+}
+
+impl std::error::Error for ApngError {}
+
+impl fmt::Display for ApngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApngError::InvalidFrameControl => write!(f, "Invalid fcTL chunk"),
+            ApngError::InvalidFrameData => write!(f, "fdAT chunk is too short to hold a sequence number"),
+        }
+    }
+}
+
+// The parts of an fcTL chunk needed to decode the frame it describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FrameControl {
+    pub fn from_bytes(data: &[u8]) -> Result<FrameControl> {
+        if data.len() != FRAME_CONTROL_SIZE {
+            return Err(ApngError::InvalidFrameControl.into());
+        }
+
+        Ok(FrameControl {
+            sequence: u32::from_be_bytes(data[0..4].try_into()?),
+            width: u32::from_be_bytes(data[4..8].try_into()?),
+            height: u32::from_be_bytes(data[8..12].try_into()?),
+        })
+    }
+}
+
+// the image data of an fdAT chunk, without its sequence number
+pub fn frame_data(fdat: &[u8]) -> Result<&[u8]> {
+    fdat.get(SEQUENCE_SIZE..)
+        .ok_or_else(|| ApngError::InvalidFrameData.into())
+}
+
+// `data` of an fcTL or fdAT chunk with its sequence number replaced
+pub fn with_sequence(data: &[u8], sequence: u32) -> Vec<u8> {
+    let mut bytes = sequence.to_be_bytes().to_vec();
+    bytes.extend_from_slice(data.get(SEQUENCE_SIZE..).unwrap_or(&[]));
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_control_from_bytes() {
+        let mut data = vec![0, 0, 0, 3, 0, 0, 0, 20, 0, 0, 0, 10];
+        data.extend_from_slice(&[0; 14]);
+
+        let control = FrameControl::from_bytes(&data).unwrap();
+
+        assert_eq!(control.sequence, 3);
+        assert_eq!(control.width, 20);
+        assert_eq!(control.height, 10);
+        assert!(FrameControl::from_bytes(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn test_with_sequence() {
+        assert_eq!(with_sequence(&[0, 0, 0, 1, 9, 9], 7), vec![0, 0, 0, 7, 9, 9]);
+        assert_eq!(frame_data(&[0, 0, 0, 1, 9, 9]).unwrap(), &[9, 9]);
+        assert!(frame_data(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use super::pixels::{Header, Pixels};
+use super::Png;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+use std::convert::TryFrom;
+
+use anyhow::Result;
+
+// Builds an image from scratch: the signature, IHDR, the chunks given, a
+// single IDAT holding the pixels and IEND. Without pixels the image is all
+// zeros, black or transparent depending on the color type.
+//
+//     let png = PngBuilder::new(4, 4).color(2, 8).chunk(text).build()?;
+pub struct PngBuilder {
+    header: Header,
+    chunks: Vec<Chunk>,
+    pixels: Option<Vec<u8>>,
+}
+
+impl PngBuilder {
+    // an 8 bit grayscale image
+    pub fn new(width: u32, height: u32) -> Self {
+        PngBuilder {
+            header: Header {
+                width,
+                height,
+                bit_depth: 8,
+                color_type: 0,
+                interlace: 0,
+            },
+            chunks: Vec::new(),
+            pixels: None,
+        }
+    }
+
+    pub fn color(mut self, color_type: u8, bit_depth: u8) -> Self {
+        self.header.color_type = color_type;
+        self.header.bit_depth = bit_depth;
+        self
+    }
+
+    // the scanlines back to back, without filter bytes, as `Pixels` holds them
+    pub fn pixels(mut self, data: Vec<u8>) -> Self {
+        self.pixels = Some(data);
+        self
+    }
+
+    // adds a chunk between IHDR and IDAT, such as PLTE or tEXt
+    pub fn chunk(mut self, chunk: Chunk) -> Self {
+        self.chunks.push(chunk);
+        self
+    }
+
+    pub fn build(self) -> Result<Png> {
+        let length = self.header.stride()? * self.header.height as usize;
+        let data = self.pixels.unwrap_or_else(|| vec![0; length]);
+        let idat = Pixels::new(self.header, data).encode()?;
+
+        let mut chunks = vec![Chunk::new(ChunkType::try_from(*b"IHDR")?, self.header.to_bytes().to_vec())];
+        chunks.extend(self.chunks);
+        chunks.push(Chunk::new(ChunkType::try_from(*b"IDAT")?, idat));
+        chunks.push(Chunk::new(ChunkType::try_from(*b"IEND")?, Vec::new()));
+
+        Ok(Png::from_chunks(chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_build_png() {
+        let text = Chunk::builder(ChunkType::from_str("tEXt").unwrap()).data(b"Title\0Dice".to_vec()).build();
+        let png = PngBuilder::new(2, 2)
+            .color(2, 8)
+            .pixels((0..12).collect())
+            .chunk(text)
+            .build()
+            .unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(png.ihdr().unwrap().color_type, 2);
+        assert_eq!(png.pixels().unwrap().data, (0..12).collect::<Vec<u8>>());
+        assert!(png.validate().is_empty());
+    }
+
+    #[test]
+    fn test_build_invalid_png() {
+        assert!(PngBuilder::new(2, 2).pixels(vec![0; 3]).build().is_err());
+        assert!(PngBuilder::new(2, 2).color(5, 8).build().is_err());
+    }
+}
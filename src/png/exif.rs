@@ -0,0 +1,291 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use anyhow::Result;
+
+// EXIF metadata as the eXIf chunk stores it: a TIFF header giving the byte
+// order, followed by image file directories (IFDs) of tagged entries. IFD0
+// describes the image and points to the Exif IFD, with the camera settings,
+// and to the GPS IFD, with the location the picture was taken at.
+//
+// Only what is needed to list the entries is read: values are shown, never
+// changed, so stripping the GPS data means replacing or removing the chunk.
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+
+// how many values of an entry are shown before the rest is left out
+const SHOWN_VALUES: usize = 16;
+
+#[derive(Debug)]
+pub enum ExifError {
+    InvalidHeader,
+    Truncated(usize),
+}
+
+impl std::error::Error for ExifError {}
+
+impl fmt::Display for ExifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExifError::InvalidHeader => write!(f, "The EXIF data does not start with a TIFF header, II*\\0 or MM\\0*"),
+            ExifError::Truncated(offset) => write!(f, "The EXIF data is cut off at offset {}", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ifd {
+    Image,
+    Exif,
+    Gps,
+}
+
+impl fmt::Display for Ifd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ifd::Image => "IFD0",
+            Ifd::Exif => "Exif",
+            Ifd::Gps => "GPS",
+        };
+
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub ifd: Ifd,
+    pub tag: u16,
+    pub value: String,
+}
+
+impl Entry {
+    pub fn name(&self) -> Option<&'static str> {
+        tag_name(self.ifd, self.tag)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "ifd": self.ifd.to_string(),
+            "tag": self.tag,
+            "name": self.name(),
+            "value": self.value,
+        })
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = format!("{:#06x}", self.tag);
+        write!(f, "{:<4}  {}  {:<18}  {}", self.ifd, tag, self.name().unwrap_or("unknown"), self.value)
+    }
+}
+
+fn tag_name(ifd: Ifd, tag: u16) -> Option<&'static str> {
+    let name = match (ifd, tag) {
+        (Ifd::Gps, 0x0000) => "GPSVersionID",
+        (Ifd::Gps, 0x0001) => "GPSLatitudeRef",
+        (Ifd::Gps, 0x0002) => "GPSLatitude",
+        (Ifd::Gps, 0x0003) => "GPSLongitudeRef",
+        (Ifd::Gps, 0x0004) => "GPSLongitude",
+        (Ifd::Gps, 0x0005) => "GPSAltitudeRef",
+        (Ifd::Gps, 0x0006) => "GPSAltitude",
+        (Ifd::Gps, 0x0007) => "GPSTimeStamp",
+        (Ifd::Gps, 0x001d) => "GPSDateStamp",
+        (Ifd::Gps, _) => return None,
+        (_, 0x010e) => "ImageDescription",
+        (_, 0x010f) => "Make",
+        (_, 0x0110) => "Model",
+        (_, 0x0112) => "Orientation",
+        (_, 0x011a) => "XResolution",
+        (_, 0x011b) => "YResolution",
+        (_, 0x0128) => "ResolutionUnit",
+        (_, 0x0131) => "Software",
+        (_, 0x0132) => "DateTime",
+        (_, 0x013b) => "Artist",
+        (_, 0x8298) => "Copyright",
+        (_, EXIF_IFD) => "ExifIFD",
+        (_, GPS_IFD) => "GPSInfo",
+        (_, 0x829a) => "ExposureTime",
+        (_, 0x829d) => "FNumber",
+        (_, 0x8827) => "ISOSpeedRatings",
+        (_, 0x9003) => "DateTimeOriginal",
+        (_, 0x9004) => "DateTimeDigitized",
+        (_, 0x920a) => "FocalLength",
+        (_, 0xa002) => "PixelXDimension",
+        (_, 0xa003) => "PixelYDimension",
+        (_, 0xa434) => "LensModel",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+pub fn is_exif(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, length: usize) -> Result<&'a [u8]> {
+        Ok(self
+            .data
+            .get(offset..offset.saturating_add(length))
+            .ok_or(ExifError::Truncated(offset))?)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64> {
+        let bytes = self.bytes(offset, 8)?.try_into().unwrap();
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    fn ifd(&self, offset: usize, ifd: Ifd, entries: &mut Vec<Entry>) -> Result<()> {
+        let count = self.u16(offset)? as usize;
+
+        for index in 0..count {
+            let entry = offset + 2 + index * 12;
+            let tag = self.u16(entry)?;
+            let value = self.value(entry)?;
+
+            entries.push(Entry { ifd, tag, value });
+
+            // the sub-IFDs are only linked from IFD0
+            if ifd == Ifd::Image && (tag == EXIF_IFD || tag == GPS_IFD) {
+                let sub_ifd = if tag == EXIF_IFD { Ifd::Exif } else { Ifd::Gps };
+                self.ifd(self.u32(entry + 8)? as usize, sub_ifd, entries)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the value of the 12 byte entry at `entry`, stored in the entry itself
+    // when it fits in 4 bytes and at the offset found there otherwise
+    fn value(&self, entry: usize) -> Result<String> {
+        let kind = self.u16(entry + 2)?;
+        let count = self.u32(entry + 4)? as usize;
+
+        let size: usize = match kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return Ok(format!("unknown type {}", kind)),
+        };
+
+        let length = size.saturating_mul(count);
+        let start = if length <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+        let bytes = self.bytes(start, length)?;
+
+        if kind == 2 {
+            let text = bytes.split(|&byte| byte == 0).next().unwrap_or_default();
+            return Ok(String::from_utf8_lossy(text).into_owned());
+        }
+
+        if kind == 7 {
+            return Ok(format!("{} bytes", count));
+        }
+
+        let values: Vec<String> = (0..count.min(SHOWN_VALUES))
+            .map(|index| {
+                let offset = start + index * size;
+                Ok(match kind {
+                    1 => bytes[index].to_string(),
+                    6 => (bytes[index] as i8).to_string(),
+                    3 => self.u16(offset)?.to_string(),
+                    8 => (self.u16(offset)? as i16).to_string(),
+                    4 => self.u32(offset)?.to_string(),
+                    9 => (self.u32(offset)? as i32).to_string(),
+                    11 => f32::from_bits(self.u32(offset)?).to_string(),
+                    5 => format!("{}/{}", self.u32(offset)?, self.u32(offset + 4)?),
+                    10 => format!("{}/{}", self.u32(offset)? as i32, self.u32(offset + 4)? as i32),
+                    _ => f64::from_bits(self.u64(offset)?).to_string(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let mut value = values.join(", ");
+        if count > SHOWN_VALUES {
+            value.push_str(", ...");
+        }
+
+        Ok(value)
+    }
+}
+
+// the entries of IFD0 and of the Exif and GPS IFDs it links to
+pub fn entries(data: &[u8]) -> Result<Vec<Entry>> {
+    if !is_exif(data) {
+        return Err(ExifError::InvalidHeader.into());
+    }
+
+    let reader = Reader {
+        data,
+        big_endian: data.starts_with(b"MM"),
+    };
+
+    let mut entries = Vec::new();
+    reader.ifd(reader.u32(4)? as usize, Ifd::Image, &mut entries)?;
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a little endian TIFF header, an IFD0 with Make and a GPS link, and a GPS IFD with the latitude
+    fn exif() -> Vec<u8> {
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+
+        data.extend(2u16.to_le_bytes());
+        data.extend([0x0f, 0x01, 2, 0, 4, 0, 0, 0]);
+        data.extend(b"Acme");
+        data.extend([0x25, 0x88, 4, 0, 1, 0, 0, 0]);
+        data.extend(38u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+
+        data.extend(1u16.to_le_bytes());
+        data.extend([0x02, 0x00, 5, 0, 1, 0, 0, 0]);
+        data.extend(56u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(52u32.to_le_bytes());
+        data.extend(1u32.to_le_bytes());
+
+        data
+    }
+
+    #[test]
+    fn test_entries() {
+        let entries = entries(&exif()).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name(), Some("Make"));
+        assert_eq!(entries[0].value, "Acme");
+        assert_eq!(entries[2].ifd, Ifd::Gps);
+        assert_eq!(entries[2].name(), Some("GPSLatitude"));
+        assert_eq!(entries[2].value, "52/1");
+    }
+
+    #[test]
+    fn test_invalid_exif() {
+        assert!(entries(b"JFIF").is_err());
+        assert!(entries(&exif()[..40]).is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use super::pixels::Header;
+use super::{Png, PngError, Position};
+use crate::carrier::Slot;
+use crate::chunk::{Chunk, ChunkError, CrcCheck};
+use crate::chunk_type::ChunkType;
+use crate::envelope;
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use anyhow::Result;
+use rand::seq::SliceRandom;
+
+// The chunks of a PNG file without their data: where each one starts, its
+// type, length and stored CRC. Only the 8 bytes in front of a chunk and the 4
+// of its CRC are read, the data is skipped over, so listing the chunks of a
+// large image reads a few bytes per chunk instead of the whole file. The data
+// of a chunk is read when it is asked for, one chunk at a time, and its CRC is
+// checked then.
+//
+// Like `Png::from_reader`, the index stops after IEND; what follows is taken
+// for chunks an earlier version appended when it is made of nothing but whole
+// chunks, and for trailing data otherwise. Their CRCs are not checked here.
+//
+// Payloads are found the same way: the ancillary chunks holding one are told
+// apart by the first bytes of their data, and only the chunk asked for is
+// read in whole, so decoding takes about as much memory as the payload too.
+//
+// A chunk can also be added without reading the others in: the file is copied
+// over as it is, a buffer at a time, with the new chunk written at its place
+// on the way, so adding a payload to an image of any size takes about as much
+// memory as the payload.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    // where the chunk starts in the file, at its length
+    pub offset: u64,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub crc: u32,
+}
+
+impl Entry {
+    // the offset right after the chunk's CRC
+    pub fn end(&self) -> u64 {
+        self.offset + 12 + self.length as u64
+    }
+}
+
+pub struct ChunkIndex<R> {
+    reader: R,
+    entries: Vec<Entry>,
+    // where the data after IEND starts, and the size of the file
+    trailing_offset: u64,
+    file_length: u64,
+}
+
+// The chunk at the reader's position, with its data skipped, or nothing at the
+// end of the file.
+fn read_entry<R: Read + Seek>(reader: &mut R, offset: u64, file_length: u64) -> Result<Option<Entry>, PngError> {
+    if offset == file_length {
+        return Ok(None);
+    }
+
+    let mut frame = [0; 8];
+    reader.read_exact(&mut frame).map_err(|_| PngError::Truncated)?;
+
+    let length = u32::from_be_bytes(frame[..4].try_into().unwrap());
+    if length > Chunk::MAX_LENGTH {
+        return Err(ChunkError::InvalidLength(length).into());
+    }
+
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&frame[4..]).unwrap())
+        .map_err(|_| ChunkError::InvalidChunkType(String::from_utf8_lossy(&frame[4..]).into_owned()))?;
+    if !chunk_type.is_valid() {
+        return Err(ChunkError::InvalidChunkType(chunk_type.to_string()).into());
+    }
+
+    let entry_end = offset + 12 + length as u64;
+    if entry_end > file_length {
+        return Err(PngError::Truncated);
+    }
+
+    let mut crc = [0; 4];
+    reader.seek(SeekFrom::Start(entry_end - 4))?;
+    reader.read_exact(&mut crc)?;
+
+    Ok(Some(Entry {
+        offset,
+        chunk_type,
+        length,
+        crc: u32::from_be_bytes(crc),
+    }))
+}
+
+impl<R: Read + Seek> ChunkIndex<R> {
+    pub fn read(mut reader: R) -> Result<Self, PngError> {
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(|_| PngError::InvalidHeader)?;
+        if header != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidHeader);
+        }
+
+        let mut entries = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len() as u64;
+        while let Some(entry) = read_entry(&mut reader, offset, file_length)? {
+            entries.push(entry);
+            offset = entry.end();
+
+            if entry.chunk_type == ChunkType::IEND {
+                break;
+            }
+        }
+
+        // chunks appended after IEND, when nothing else is there
+        let mut appended = Vec::new();
+        let mut end = offset;
+        while let Ok(Some(entry)) = read_entry(&mut reader, end, file_length) {
+            appended.push(entry);
+            end = entry.end();
+        }
+
+        let trailing_offset = if end == file_length {
+            entries.extend(appended);
+            file_length
+        } else {
+            offset
+        };
+
+        Ok(ChunkIndex {
+            reader,
+            entries,
+            trailing_offset,
+            file_length,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn trailing_offset(&self) -> u64 {
+        self.trailing_offset
+    }
+
+    pub fn trailing_length(&self) -> u64 {
+        self.file_length - self.trailing_offset
+    }
+
+    pub fn file_length(&self) -> u64 {
+        self.file_length
+    }
+
+    // the chunk of the `index`th entry, read from the file
+    pub fn chunk(&mut self, index: usize, check: CrcCheck) -> Result<Chunk, PngError> {
+        let entry = self.entries[index];
+
+        let mut bytes = vec![0; entry.length as usize + 12];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut bytes)?;
+
+        Ok(Chunk::parse(&bytes, check)?)
+    }
+
+    // the first `length` bytes of the data of the `index`th entry, or all of
+    // it when it is shorter, with its CRC not checked
+    pub fn data_prefix(&mut self, index: usize, length: usize) -> Result<Vec<u8>, PngError> {
+        let entry = self.entries[index];
+
+        let mut bytes = vec![0; length.min(entry.length as usize)];
+        self.reader.seek(SeekFrom::Start(entry.offset + 8))?;
+        self.reader.read_exact(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    // the index of the first entry of type `chunk_type`
+    pub fn position(&self, chunk_type: ChunkType) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.chunk_type == chunk_type)
+    }
+
+    // the ancillary chunks holding a PNGCrypt payload, as `Carrier::list` gives
+    // them for a `Png`
+    pub fn slots(&mut self) -> Result<Vec<Slot>, PngError> {
+        let mut slots = Vec::new();
+
+        for index in 0..self.entries.len() {
+            let entry = self.entries[index];
+            if entry.chunk_type.is_critical() {
+                continue;
+            }
+            if !envelope::is_envelope(&self.data_prefix(index, envelope::MAGIC.len())?) {
+                continue;
+            }
+
+            slots.push(Slot {
+                selector: entry.chunk_type.to_string(),
+                length: entry.length as usize,
+            });
+        }
+
+        Ok(slots)
+    }
+
+    pub fn trailing(&mut self) -> Result<Vec<u8>, PngError> {
+        let mut bytes = Vec::new();
+        self.reader.seek(SeekFrom::Start(self.trailing_offset))?;
+        self.reader.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    pub fn has_chunk(&self, chunk_type: ChunkType) -> bool {
+        self.entries.iter().any(|entry| entry.chunk_type == chunk_type)
+    }
+
+    // where the chunks end, which is where the data after IEND starts
+    fn chunks_end(&self) -> u64 {
+        self.entries.last().map(Entry::end).unwrap_or(Png::STANDARD_HEADER.len() as u64)
+    }
+
+    // The offset at which a chunk added at `position` goes, the place
+    // `Png::insert_chunk_at` gives it.
+    pub fn insertion_offset(&self, position: Position) -> u64 {
+        let find = |chunk_type: ChunkType| self.entries.iter().find(|entry| entry.chunk_type == chunk_type);
+
+        let offset = match position {
+            Position::AfterIhdr => find(ChunkType::IHDR).map(Entry::end),
+            Position::BeforeIdat => find(ChunkType::IDAT).or_else(|| find(ChunkType::IEND)).map(|entry| entry.offset),
+            Position::BeforeIend => find(ChunkType::IEND).map(|entry| entry.offset),
+            Position::Random => self.random_offset(),
+        };
+
+        offset.unwrap_or_else(|| self.chunks_end())
+    }
+
+    // see `Png::random_index`
+    fn random_offset(&self) -> Option<u64> {
+        let is_data = |index: usize| matches!(&self.entries[index].chunk_type.bytes, b"IDAT" | b"fdAT");
+        let first = self.entries.iter().position(|entry| entry.chunk_type == ChunkType::IHDR)? + 1;
+        let last = self
+            .entries
+            .iter()
+            .position(|entry| entry.chunk_type == ChunkType::IEND)
+            .unwrap_or(self.entries.len());
+
+        let places: Vec<usize> = (first..=last)
+            .filter(|&index| index == self.entries.len() || !(is_data(index - 1) && is_data(index)))
+            .collect();
+
+        places.choose(&mut rand::thread_rng()).map(|&index| match self.entries.get(index) {
+            Some(entry) => entry.offset,
+            None => self.chunks_end(),
+        })
+    }
+
+    // Writes the file to `writer` with `chunk` added at `offset`, one of the
+    // offsets `insertion_offset` gives. The data after IEND is left out unless
+    // `keep_trailing` is set.
+    pub fn write_with_chunk<W: Write>(
+        &mut self,
+        chunk: &Chunk,
+        offset: u64,
+        keep_trailing: bool,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let end = if keep_trailing { self.file_length } else { self.trailing_offset };
+
+        self.reader.seek(SeekFrom::Start(0))?;
+        io::copy(&mut self.reader.by_ref().take(offset), &mut writer)?;
+        chunk.write_to(&mut writer)?;
+        io::copy(&mut self.reader.by_ref().take(end - offset), &mut writer)?;
+
+        Ok(())
+    }
+
+    // the header of the image, reading only the IHDR chunk
+    pub fn ihdr(&mut self) -> Result<Header> {
+        match self.entries.first() {
+            Some(entry) if entry.chunk_type == ChunkType::IHDR => {
+                Header::from_bytes(self.chunk(0, CrcCheck::Verify)?.data())
+            }
+            _ => Err(PngError::MissingHeaderChunk.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+        let ihdr = [0, 0, 0, 2, 0, 0, 0, 3, 8, 2, 0, 0, 0];
+
+        Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("ruSt", b"hidden"),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_index_entries() {
+        let png = testing_png();
+        let mut index = ChunkIndex::read(Cursor::new(png.as_bytes())).unwrap();
+
+        let offsets: Vec<u64> = index.entries().iter().map(|entry| entry.offset).collect();
+        assert_eq!(offsets, vec![8, 33, 51, 66]);
+        for (entry, chunk) in index.entries().iter().zip(png.chunks()) {
+            assert_eq!((entry.chunk_type, entry.length, entry.crc), (chunk.chunk_type, chunk.length, chunk.crc));
+        }
+
+        assert_eq!(&index.chunk(1, CrcCheck::Verify).unwrap(), png.chunk(1).unwrap());
+        assert_eq!(index.ihdr().unwrap().width, 2);
+        assert_eq!(index.trailing_length(), 0);
+    }
+
+    #[test]
+    fn test_index_slots() {
+        let mut png = testing_png();
+        let sealed = envelope::seal_with_rounds(b"Message", None, None, 10).unwrap();
+        png.insert_chunk(Chunk::new(ChunkType::from_str("pcAb").unwrap(), sealed.clone()));
+
+        let mut index = ChunkIndex::read(Cursor::new(png.as_bytes())).unwrap();
+        let slots = index.slots().unwrap();
+        assert_eq!(slots.iter().map(|slot| slot.selector.as_str()).collect::<Vec<_>>(), vec!["pcAb"]);
+        assert_eq!(slots[0].length, sealed.len());
+
+        let position = index.position(ChunkType::from_str("pcAb").unwrap()).unwrap();
+        assert_eq!(index.data_prefix(position, 4).unwrap(), &sealed[..4]);
+        assert_eq!(index.data_prefix(1, 100).unwrap(), b"hidden");
+    }
+
+    #[test]
+    fn test_index_trailing_and_damage() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.extend(b"appended");
+
+        let mut index = ChunkIndex::read(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(index.entries().len(), 4);
+        assert_eq!(index.trailing_offset(), 78);
+        assert_eq!(index.trailing().unwrap(), b"appended");
+
+        // a damaged chunk is only found out when its data is read
+        bytes[45] ^= 1;
+        let mut index = ChunkIndex::read(Cursor::new(bytes.clone())).unwrap();
+        assert!(index.chunk(1, CrcCheck::Verify).is_err());
+        assert_eq!(index.chunk(1, CrcCheck::Ignore).unwrap().data(), b"hidddn");
+
+        assert!(matches!(ChunkIndex::read(Cursor::new(&bytes[..60])), Err(PngError::Truncated)));
+    }
+
+    #[test]
+    fn test_write_with_chunk() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.extend(b"appended");
+        let mut index = ChunkIndex::read(Cursor::new(bytes.clone())).unwrap();
+        let chunk = Chunk::new(ChunkType::from_str("pcAb").unwrap(), b"payload".to_vec());
+
+        for position in [Position::AfterIhdr, Position::BeforeIdat, Position::BeforeIend, Position::Random] {
+            let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+            expected.insert_chunk_at(chunk.clone(), position);
+
+            let mut written = Vec::new();
+            index.write_with_chunk(&chunk, index.insertion_offset(position), true, &mut written).unwrap();
+
+            let written = Png::try_from(written.as_slice()).unwrap();
+            assert_eq!(written.trailing(), b"appended");
+            assert_eq!(written.chunks().len(), 5);
+            if position != Position::Random {
+                assert_eq!(written.as_bytes(), expected.as_bytes());
+            }
+        }
+
+        let mut written = Vec::new();
+        index.write_with_chunk(&chunk, index.insertion_offset(Position::BeforeIend), false, &mut written).unwrap();
+        assert!(Png::try_from(written.as_slice()).unwrap().trailing().is_empty());
+    }
+}
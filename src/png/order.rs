@@ -0,0 +1,124 @@
+use super::Png;
+
+use std::fmt;
+
+use anyhow::Result;
+
+// Where the specification places the ancillary chunks, and a rewrite of the
+// chunk list in that order: IHDR, the ancillary chunks that have to precede
+// PLTE, PLTE, the ancillary chunks that have to precede IDAT, the IDAT chunks
+// and IEND. Chunks the specification places anywhere stay on the side of the
+// image data they were found on, in the order they were found in, so the
+// same set of chunks always comes out in the same order.
+
+// ancillary chunks that have to come before PLTE and IDAT
+pub const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+
+// ancillary chunks that have to come after PLTE, when there is one, and before IDAT
+pub const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+
+// ancillary chunks that have to come before IDAT
+pub const BEFORE_IDAT: [&str; 6] = ["pHYs", "sPLT", "oFFs", "pCAL", "sCAL", "acTL"];
+
+#[derive(Debug)]
+pub enum OrderError {
+    Animated,
+}
+
+impl std::error::Error for OrderError {}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::Animated => write!(
+                f,
+                "Animated images can not be reordered, their frame chunks are placed around the image data on purpose"
+            ),
+        }
+    }
+}
+
+fn rank(chunk_type: &str, before_idat: bool) -> u8 {
+    match chunk_type {
+        "IHDR" => 0,
+        _ if BEFORE_PLTE.contains(&chunk_type) => 1,
+        "PLTE" => 2,
+        _ if AFTER_PLTE.contains(&chunk_type) || BEFORE_IDAT.contains(&chunk_type) || chunk_type == "eXIf" => 3,
+        "IDAT" => 4,
+        "IEND" => 6,
+        _ if before_idat => 3,
+        _ => 5,
+    }
+}
+
+// puts the chunks in canonical order, returning how many of them moved
+pub fn normalize(png: &mut Png) -> Result<usize> {
+    if png.is_animated() {
+        return Err(OrderError::Animated.into());
+    }
+
+    let idat = png.chunks.iter().position(|chunk| chunk.chunk_type.bytes == *b"IDAT");
+    let before: Vec<[u8; 4]> = png.chunks.iter().map(|chunk| chunk.chunk_type.bytes).collect();
+
+    let mut ranked: Vec<(u8, _)> = png
+        .chunks
+        .drain(..)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let before_idat = idat.map(|idat| index < idat).unwrap_or(true);
+            (rank(&chunk.chunk_type.to_string(), before_idat), chunk)
+        })
+        .collect();
+
+    // stable, so chunks of the same rank keep their order
+    ranked.sort_by_key(|(rank, _)| *rank);
+    png.chunks = ranked.into_iter().map(|(_, chunk)| chunk).collect();
+
+    let moved = before
+        .iter()
+        .zip(png.chunks.iter())
+        .filter(|(before, after)| **before != after.chunk_type.bytes)
+        .count();
+
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn png(chunk_types: &[&str]) -> Png {
+        Png::from_chunks(
+            chunk_types
+                .iter()
+                .map(|chunk_type| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new()))
+                .collect(),
+        )
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut png = png(&["IHDR", "tEXt", "PLTE", "gAMA", "IDAT", "pHYs", "IDAT", "ruSt", "tRNS", "IEND"]);
+
+        assert_eq!(normalize(&mut png).unwrap(), 6);
+        assert_eq!(
+            chunk_types(&png),
+            vec!["IHDR", "gAMA", "PLTE", "tEXt", "pHYs", "tRNS", "IDAT", "IDAT", "ruSt", "IEND"]
+        );
+
+        assert_eq!(normalize(&mut png).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_normalize_animated() {
+        let mut png = png(&["IHDR", "acTL", "IDAT", "IEND"]);
+        assert!(normalize(&mut png).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use super::pixels::{Header, Pixels};
+
+use std::fmt;
+
+use anyhow::Result;
+
+pub const MAX_ENTRIES: usize = 256;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    InvalidLength(usize),
+    IndexOutOfRange(u8),
+}
+
+impl std::error::Error for PaletteError {}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::InvalidLength(len) => write!(f, "Invalid PLTE chunk length: {}", len),
+            PaletteError::IndexOutOfRange(index) => {
+                write!(f, "Pixel refers to palette entry {} which does not exist", index)
+            }
+        }
+    }
+}
+
+// The colors of an indexed image, from PLTE, with the alpha of each entry from
+// tRNS (fully opaque when tRNS does not cover it).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub entries: Vec<[u8; 3]>,
+    pub alpha: Vec<u8>,
+}
+
+impl Palette {
+    pub fn from_chunks(plte: &[u8], trns: Option<&[u8]>) -> Result<Palette> {
+        if plte.is_empty() || !plte.len().is_multiple_of(3) || plte.len() / 3 > MAX_ENTRIES {
+            return Err(PaletteError::InvalidLength(plte.len()).into());
+        }
+
+        let entries: Vec<[u8; 3]> = plte.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect();
+
+        let trns = trns.unwrap_or(&[]);
+        let alpha = (0..entries.len())
+            .map(|i| trns.get(i).copied().unwrap_or(u8::MAX))
+            .collect();
+
+        Ok(Palette { entries, alpha })
+    }
+
+    pub fn plte_bytes(&self) -> Vec<u8> {
+        self.entries.iter().flatten().copied().collect()
+    }
+
+    // tRNS data, without the trailing opaque entries it can leave out
+    pub fn trns_bytes(&self) -> Vec<u8> {
+        let len = self.alpha.iter().rposition(|&alpha| alpha != u8::MAX).map_or(0, |i| i + 1);
+        self.alpha[..len].to_vec()
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.alpha.iter().any(|&alpha| alpha != u8::MAX)
+    }
+
+    fn luminance(&self, index: usize) -> u32 {
+        let [r, g, b] = self.entries[index];
+        299 * r as u32 + 587 * g as u32 + 114 * b as u32
+    }
+
+    // Reorders the entries by luminance so that indices differing only in their
+    // lowest bit hold similar colors, and pads the palette to an even length so
+    // every index has such a neighbour. Returns the new index of every old one.
+    pub fn sort_by_luminance(&mut self, header: &Header) -> Vec<u8> {
+        let mut order: Vec<usize> = (0..self.entries.len()).collect();
+        order.sort_by_key(|&i| (self.luminance(i), self.alpha[i]));
+
+        let mut mapping = vec![0u8; self.entries.len()];
+        for (new, &old) in order.iter().enumerate() {
+            mapping[old] = new as u8;
+        }
+
+        self.entries = order.iter().map(|&i| self.entries[i]).collect();
+        self.alpha = order.iter().map(|&i| self.alpha[i]).collect();
+
+        // the duplicate is the same color, so flipping onto it changes nothing
+        let max_entries = std::cmp::min(MAX_ENTRIES, 1 << header.bit_depth);
+        if !self.entries.len().is_multiple_of(2) && self.entries.len() < max_entries {
+            self.entries.push(self.entries[self.entries.len() - 1]);
+            self.alpha.push(self.alpha[self.alpha.len() - 1]);
+        }
+
+        mapping
+    }
+
+    // the indexed image as 8 bit RGBA, or RGB without transparency
+    pub fn expand(&self, pixels: &Pixels) -> Result<Pixels> {
+        let unpacked = pixels.unpack()?;
+        let transparent = self.has_transparency();
+
+        let mut data = Vec::with_capacity(unpacked.data.len() * 4);
+
+        for &index in unpacked.data.iter() {
+            let entry = self
+                .entries
+                .get(index as usize)
+                .ok_or(PaletteError::IndexOutOfRange(index))?;
+
+            data.extend_from_slice(entry);
+            if transparent {
+                data.push(self.alpha[index as usize]);
+            }
+        }
+
+        let header = Header {
+            bit_depth: 8,
+            color_type: if transparent { 6 } else { 2 },
+            ..pixels.header
+        };
+
+        Ok(Pixels::new(header, data))
+    }
+}
+
+// point every index of an indexed image at its entry in a reordered palette
+pub fn remap(pixels: &Pixels, mapping: &[u8]) -> Result<Pixels> {
+    let mut unpacked = pixels.unpack()?;
+
+    for index in unpacked.data.iter_mut() {
+        *index = *mapping
+            .get(*index as usize)
+            .ok_or(PaletteError::IndexOutOfRange(*index))?;
+    }
+
+    unpacked.repack(pixels.header.bit_depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_header() -> Header {
+        Header {
+            width: 3,
+            height: 1,
+            bit_depth: 8,
+            color_type: 3,
+            interlace: 0,
+        }
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        let palette = Palette::from_chunks(&[255, 255, 255, 0, 0, 0], Some(&[0])).unwrap();
+
+        assert_eq!(palette.entries, vec![[255, 255, 255], [0, 0, 0]]);
+        assert_eq!(palette.alpha, vec![0, 255]);
+        assert_eq!(palette.trns_bytes(), vec![0]);
+        assert!(Palette::from_chunks(&[1, 2], None).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_luminance() {
+        let mut palette = Palette::from_chunks(&[255, 255, 255, 0, 0, 0, 128, 128, 128], None).unwrap();
+        let mapping = palette.sort_by_luminance(&testing_header());
+
+        assert_eq!(mapping, vec![2, 0, 1]);
+        assert_eq!(
+            palette.entries,
+            vec![[0, 0, 0], [128, 128, 128], [255, 255, 255], [255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn test_remap_keeps_colors() {
+        let mut palette = Palette::from_chunks(&[255, 255, 255, 0, 0, 0, 128, 128, 128], None).unwrap();
+        let pixels = Pixels::new(testing_header(), vec![0, 1, 2]);
+        let before = palette.expand(&pixels).unwrap();
+
+        let mapping = palette.sort_by_luminance(&testing_header());
+        let remapped = remap(&pixels, &mapping).unwrap();
+
+        assert_eq!(remapped.data, vec![2, 0, 1]);
+        assert_eq!(palette.expand(&remapped).unwrap().data, before.data);
+    }
+}
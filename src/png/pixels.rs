@@ -0,0 +1,489 @@
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+pub const IHDR_SIZE: usize = 13;
+
+// the largest width and height the PNG specification allows
+pub const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlace: u8,
+}
+
+impl Header {
+    pub fn from_bytes(data: &[u8]) -> Result<Header> {
+        if data.len() != IHDR_SIZE {
+            return Err(PixelError::InvalidHeader.into());
+        }
+
+        let header = Header {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlace: data[12],
+        };
+
+        header.check()?;
+
+        Ok(header)
+    }
+
+    // Whether the header describes an image the PNG specification allows: at
+    // least one pixel each way, and a bit depth its color type has.
+    pub fn check(&self) -> Result<()> {
+        let dimensions = 1..=MAX_DIMENSION;
+        if !dimensions.contains(&self.width) || !dimensions.contains(&self.height) {
+            return Err(PixelError::InvalidDimensions(self.width, self.height).into());
+        }
+
+        let bit_depths: &[u8] = match self.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(PixelError::UnsupportedColorType(self.color_type).into()),
+        };
+        if !bit_depths.contains(&self.bit_depth) {
+            return Err(PixelError::InvalidBitDepth(self.bit_depth, self.color_type).into());
+        }
+
+        Ok(())
+    }
+
+    // the data of an IHDR chunk, with the only compression and filter methods there are
+    pub fn to_bytes(&self) -> [u8; IHDR_SIZE] {
+        let mut bytes = [0; IHDR_SIZE];
+        bytes[0..4].copy_from_slice(&self.width.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.height.to_be_bytes());
+        bytes[8] = self.bit_depth;
+        bytes[9] = self.color_type;
+        bytes[12] = self.interlace;
+        bytes
+    }
+
+    pub fn channels(&self) -> Result<usize> {
+        match self.color_type {
+            0 | 3 => Ok(1),
+            2 => Ok(3),
+            4 => Ok(2),
+            6 => Ok(4),
+            _ => Err(PixelError::UnsupportedColorType(self.color_type).into()),
+        }
+    }
+
+    pub fn color_type_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "Grayscale",
+            2 => "RGB",
+            3 => "Indexed",
+            4 => "Grayscale + alpha",
+            6 => "RGBA",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn interlace_name(&self) -> &'static str {
+        match self.interlace {
+            0 => "None",
+            1 => "Adam7",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        self.color_type == 3
+    }
+
+    // number of bytes in a scanline, without the leading filter byte
+    pub fn stride(&self) -> Result<usize> {
+        let bits = (self.width as usize)
+            .checked_mul(self.channels()? * self.bit_depth as usize)
+            .ok_or(PixelError::TooLarge(self.width, self.height))?;
+        Ok(bits.div_ceil(8))
+    }
+
+    // number of bytes of the decompressed image data: every scanline with its filter byte
+    pub fn raw_length(&self) -> Result<usize> {
+        let length = (self.height as usize).checked_mul(self.stride()? + 1);
+        Ok(length.ok_or(PixelError::TooLarge(self.width, self.height))?)
+    }
+
+    // distance in bytes to the corresponding byte of the previous pixel, as used by the filters
+    pub fn filter_distance(&self) -> Result<usize> {
+        let bits = self.channels()? * self.bit_depth as usize;
+        Ok(std::cmp::max(1, bits / 8))
+    }
+}
+
+#[derive(Debug)]
+pub enum PixelError {
+    InvalidHeader,
+    InvalidDimensions(u32, u32),
+    InvalidBitDepth(u8, u8),
+    TooLarge(u32, u32),
+    UnsupportedColorType(u8),
+    UnsupportedInterlace,
+    InvalidFilter(u8),
+    InvalidDataLength(usize),
+}
+
+impl std::error::Error for PixelError {}
+
+impl fmt::Display for PixelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PixelError::InvalidHeader => write!(f, "Invalid IHDR chunk"),
+            PixelError::InvalidDimensions(width, height) => write!(
+                f,
+                "Invalid image size: {}x{}. Expected a width and height between 1 and {}",
+                width, height, MAX_DIMENSION
+            ),
+            PixelError::InvalidBitDepth(bit_depth, color_type) => {
+                write!(f, "Invalid bit depth {} for color type {}", bit_depth, color_type)
+            }
+            PixelError::TooLarge(width, height) => write!(f, "The image is too large to decode: {}x{}", width, height),
+            PixelError::UnsupportedColorType(color_type) => {
+                write!(f, "Unsupported color type: {}", color_type)
+            }
+            PixelError::UnsupportedInterlace => write!(f, "Interlaced images are not supported"),
+            PixelError::InvalidFilter(filter) => write!(f, "Invalid scanline filter: {}", filter),
+            PixelError::InvalidDataLength(len) => {
+                write!(f, "Decompressed image data has an unexpected length: {}", len)
+            }
+        }
+    }
+}
+
+// Unfiltered image data. `data` holds the scanlines back to back without their
+// filter bytes; the original filter of each line is kept so that re-encoding
+// produces a stream similar to the one we read.
+#[derive(Debug, Clone)]
+pub struct Pixels {
+    pub header: Header,
+    pub data: Vec<u8>,
+    filters: Vec<u8>,
+}
+
+impl Pixels {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        let filters = vec![0; header.height as usize];
+
+        Pixels {
+            header,
+            data,
+            filters,
+        }
+    }
+
+    pub fn decode(header: Header, compressed: &[u8]) -> Result<Pixels> {
+        // frames take their size from fcTL rather than IHDR
+        header.check()?;
+        if header.interlace != 0 {
+            return Err(PixelError::UnsupportedInterlace.into());
+        }
+
+        let stride = header.stride()?;
+        let distance = header.filter_distance()?;
+        let height = header.height as usize;
+        let length = header.raw_length()?;
+
+        // a byte past the expected length is enough to tell the data is too long
+        let mut raw = Vec::new();
+        ZlibDecoder::new(compressed).take(length as u64 + 1).read_to_end(&mut raw)?;
+
+        if raw.len() != length {
+            return Err(PixelError::InvalidDataLength(raw.len()).into());
+        }
+
+        let mut data = vec![0u8; height * stride];
+        let mut filters = Vec::with_capacity(height);
+
+        for row in 0..height {
+            let line = &raw[row * (stride + 1)..(row + 1) * (stride + 1)];
+            let filter = line[0];
+
+            let (previous, current) = data.split_at_mut(row * stride);
+            let previous = if row == 0 { None } else { Some(&previous[(row - 1) * stride..]) };
+
+            unfilter(filter, &line[1..], previous, &mut current[..stride], distance)?;
+            filters.push(filter);
+        }
+
+        Ok(Pixels {
+            header,
+            data,
+            filters,
+        })
+    }
+
+    // one byte per sample for images packing several samples in a byte
+    pub fn unpack(&self) -> Result<Pixels> {
+        let depth = self.header.bit_depth as usize;
+        if depth >= 8 {
+            return Ok(self.clone());
+        }
+
+        let stride = self.header.stride()?;
+        let samples = self.header.width as usize * self.header.channels()?;
+        let mask = (1u8 << depth) - 1;
+
+        let data = self
+            .data
+            .chunks_exact(stride)
+            .flat_map(|line| {
+                (0..samples).map(move |sample| {
+                    let bit = sample * depth;
+                    (line[bit / 8] >> (8 - depth - bit % 8)) & mask
+                })
+            })
+            .collect();
+
+        Ok(Pixels {
+            header: Header { bit_depth: 8, ..self.header },
+            data,
+            filters: self.filters.clone(),
+        })
+    }
+
+    // the inverse of `unpack`, packing samples back into `bit_depth` bits each
+    pub fn repack(&self, bit_depth: u8) -> Result<Pixels> {
+        let header = Header { bit_depth, ..self.header };
+        let depth = bit_depth as usize;
+        let stride = header.stride()?;
+        let samples = header.width as usize * header.channels()?;
+        let mask = ((1u16 << depth) - 1) as u8;
+
+        let length = stride
+            .checked_mul(header.height as usize)
+            .ok_or(PixelError::TooLarge(header.width, header.height))?;
+        let mut data = vec![0u8; length];
+
+        for (line, out) in self.data.chunks_exact(samples).zip(data.chunks_exact_mut(stride)) {
+            for (sample, &value) in line.iter().enumerate() {
+                let bit = sample * depth;
+                out[bit / 8] |= (value & mask) << (8 - depth - bit % 8);
+            }
+        }
+
+        Ok(Pixels {
+            header,
+            data,
+            filters: self.filters.clone(),
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let stride = self.header.stride()?;
+        let distance = self.header.filter_distance()?;
+        let height = self.header.height as usize;
+
+        if Some(self.data.len()) != height.checked_mul(stride) {
+            return Err(PixelError::InvalidDataLength(self.data.len()).into());
+        }
+
+        let mut raw = Vec::with_capacity(self.header.raw_length()?);
+
+        for row in 0..height {
+            let line = &self.data[row * stride..(row + 1) * stride];
+            let previous = if row == 0 { None } else { Some(&self.data[(row - 1) * stride..row * stride]) };
+
+            raw.push(self.filters[row]);
+            raw.extend(filter(self.filters[row], line, previous, distance)?);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw)?;
+
+        Ok(encoder.finish()?)
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// the value predicted for a byte from its left (a), upper (b) and upper-left (c) neighbours
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> Result<u8> {
+    match filter {
+        0 => Ok(0),
+        1 => Ok(a),
+        2 => Ok(b),
+        3 => Ok(((a as u16 + b as u16) / 2) as u8),
+        4 => Ok(paeth(a, b, c)),
+        _ => Err(PixelError::InvalidFilter(filter).into()),
+    }
+}
+
+fn unfilter(filter: u8, line: &[u8], previous: Option<&[u8]>, out: &mut [u8], distance: usize) -> Result<()> {
+    for i in 0..line.len() {
+        let a = if i >= distance { out[i - distance] } else { 0 };
+        let b = previous.map_or(0, |previous| previous[i]);
+        let c = if i >= distance { previous.map_or(0, |previous| previous[i - distance]) } else { 0 };
+
+        out[i] = line[i].wrapping_add(predict(filter, a, b, c)?);
+    }
+
+    Ok(())
+}
+
+fn filter(filter: u8, line: &[u8], previous: Option<&[u8]>, distance: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(line.len());
+
+    for i in 0..line.len() {
+        let a = if i >= distance { line[i - distance] } else { 0 };
+        let b = previous.map_or(0, |previous| previous[i]);
+        let c = if i >= distance { previous.map_or(0, |previous| previous[i - distance]) } else { 0 };
+
+        out.push(line[i].wrapping_sub(predict(filter, a, b, c)?));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_header() -> Header {
+        Header {
+            width: 7,
+            height: 5,
+            bit_depth: 8,
+            color_type: 6,
+            interlace: 0,
+        }
+    }
+
+    fn testing_data() -> Vec<u8> {
+        (0..7 * 5 * 4).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_header_from_bytes() {
+        let bytes = [0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0];
+        let header = Header::from_bytes(&bytes).unwrap();
+
+        assert_eq!(header.width, 50);
+        assert_eq!(header.height, 40);
+        assert_eq!(header.channels().unwrap(), 4);
+        assert_eq!(header.stride().unwrap(), 200);
+        assert_eq!(header.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(Header::from_bytes(&[0, 0, 0, 50]).is_err());
+        assert!(Header::from_bytes(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 5, 0, 0, 0]).is_err());
+        assert!(Header::from_bytes(&[0, 0, 0, 0, 0, 0, 0, 40, 8, 6, 0, 0, 0]).is_err());
+        assert!(Header::from_bytes(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 8, 6, 0, 0, 0]).is_err());
+        assert!(Header::from_bytes(&[0, 0, 0, 50, 0, 0, 0, 40, 0, 0, 0, 0, 0]).is_err());
+        assert!(Header::from_bytes(&[0, 0, 0, 50, 0, 0, 0, 40, 4, 2, 0, 0, 0]).is_err());
+        assert!(Header::from_bytes(&[0, 0, 0, 50, 0, 0, 0, 40, 16, 3, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_images() {
+        let header = Header {
+            width: MAX_DIMENSION,
+            height: MAX_DIMENSION,
+            bit_depth: 16,
+            ..testing_header()
+        };
+        let compressed = Pixels::new(testing_header(), testing_data()).encode().unwrap();
+
+        assert!(header.raw_length().is_err());
+        assert!(Pixels::decode(header, &compressed).is_err());
+        assert!(Pixels::decode(Header { width: u32::MAX, ..testing_header() }, &compressed).is_err());
+    }
+
+    #[test]
+    fn test_stride_of_packed_pixels() {
+        let header = Header {
+            bit_depth: 1,
+            color_type: 0,
+            ..testing_header()
+        };
+
+        assert_eq!(header.stride().unwrap(), 1);
+        assert_eq!(header.filter_distance().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_filters_roundtrip() {
+        let header = testing_header();
+        let data = testing_data();
+
+        for filter_type in 0..5 {
+            let mut pixels = Pixels::new(header, data.clone());
+            pixels.filters = vec![filter_type; 5];
+
+            let compressed = pixels.encode().unwrap();
+            let decoded = Pixels::decode(header, &compressed).unwrap();
+
+            assert_eq!(decoded.data, data);
+            assert_eq!(decoded.filters, vec![filter_type; 5]);
+        }
+    }
+
+    #[test]
+    fn test_unpack_repack() {
+        let header = Header {
+            width: 5,
+            height: 2,
+            bit_depth: 2,
+            color_type: 3,
+            interlace: 0,
+        };
+        let pixels = Pixels::new(header, vec![0b00_01_10_11, 0b01_00_00_00, 0b11_10_01_00, 0b10_00_00_00]);
+
+        let unpacked = pixels.unpack().unwrap();
+        assert_eq!(unpacked.header.bit_depth, 8);
+        assert_eq!(unpacked.data, vec![0, 1, 2, 3, 1, 3, 2, 1, 0, 2]);
+
+        assert_eq!(unpacked.repack(2).unwrap().data, pixels.data);
+    }
+
+    #[test]
+    fn test_decode_rejects_interlaced() {
+        let header = Header {
+            interlace: 1,
+            ..testing_header()
+        };
+        let compressed = Pixels::new(testing_header(), testing_data()).encode().unwrap();
+
+        assert!(Pixels::decode(header, &compressed).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_wrong_length() {
+        let header = Header {
+            height: 6,
+            ..testing_header()
+        };
+        let compressed = Pixels::new(testing_header(), testing_data()).encode().unwrap();
+
+        assert!(Pixels::decode(header, &compressed).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use super::{Png, PngError};
+use crate::chunk::{self, Chunk};
+use crate::chunk_type::ChunkType;
+
+use std::convert::TryInto;
+use std::fmt;
+
+use anyhow::Result;
+
+// Reads a PNG whose chunks were damaged in transit, fixing what can be fixed.
+//
+// A chunk keeps its stored length when the data it gives ends at a matching
+// CRC or right before another intact chunk. Otherwise its data is taken to end
+// at the first position where the stored CRC matches, or right before the next
+// intact chunk. Every chunk is written back with the CRC of its data.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    Length { offset: usize, chunk_type: String, stored: u32, actual: u32 },
+    Crc { offset: usize, chunk_type: String, stored: u32, computed: u32 },
+}
+
+impl fmt::Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::Length {
+                offset,
+                chunk_type,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "{} chunk at offset {}: length {} corrected to {}",
+                chunk_type, offset, stored, actual
+            ),
+            Repair::Crc {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "{} chunk at offset {}: CRC {:#010x} corrected to {:#010x}",
+                chunk_type, offset, stored, computed
+            ),
+        }
+    }
+}
+
+pub struct Repaired {
+    pub png: Png,
+    pub repairs: Vec<Repair>,
+    // where reading had to stop, everything from there on is kept as trailing data
+    pub unrecoverable: Option<usize>,
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(position..position + 4)?.try_into().ok()?))
+}
+
+// whether an undamaged chunk starts at `position`, or the file ends there
+fn is_chunk_at(bytes: &[u8], position: usize) -> bool {
+    if position == bytes.len() {
+        return true;
+    }
+
+    let length = match read_u32(bytes, position) {
+        Some(length) if length <= Chunk::MAX_LENGTH => length as usize,
+        _ => return false,
+    };
+    let chunk_type = match bytes.get(position + 4..position + 8) {
+        Some(chunk_type) if chunk_type.iter().all(u8::is_ascii_alphabetic) => chunk_type,
+        _ => return false,
+    };
+
+    let end = position + 8 + length;
+    match (bytes.get(position + 8..end), read_u32(bytes, end)) {
+        (Some(data), Some(stored)) => chunk::crc(chunk_type, data) == stored,
+        _ => false,
+    }
+}
+
+// the first position from `start` on where the CRC stored right after the data matches it
+fn crc_boundary(bytes: &[u8], chunk_type: &[u8], start: usize) -> Option<usize> {
+    let mut digest = chunk::CRC.digest();
+    digest.update(chunk_type);
+
+    for end in start..bytes.len().saturating_sub(3) {
+        if read_u32(bytes, end) == Some(digest.clone().finalize()) {
+            return Some(end);
+        }
+        digest.update(&bytes[end..end + 1]);
+    }
+
+    None
+}
+
+// where the data of the chunk at `offset` ends, see the top of the file
+fn data_end(bytes: &[u8], offset: usize, chunk_type: &[u8], stored_length: u32) -> Option<usize> {
+    let start = offset + 8;
+    let declared = start + stored_length as usize;
+
+    if stored_length <= Chunk::MAX_LENGTH && declared + 4 <= bytes.len() {
+        let crc_matches = read_u32(bytes, declared) == Some(chunk::crc(chunk_type, &bytes[start..declared]));
+
+        if crc_matches || is_chunk_at(bytes, declared + 4) {
+            return Some(declared);
+        }
+    }
+
+    crc_boundary(bytes, chunk_type, start)
+        .or_else(|| (start + 4..=bytes.len()).find(|&next| is_chunk_at(bytes, next)).map(|next| next - 4))
+}
+
+pub fn repair(bytes: &[u8]) -> Result<Repaired> {
+    if bytes.get(..Png::STANDARD_HEADER.len()) != Some(&Png::STANDARD_HEADER[..]) {
+        return Err(PngError::InvalidHeader.into());
+    }
+
+    let mut chunks = Vec::new();
+    let mut repairs = Vec::new();
+    let mut unrecoverable = None;
+    let mut after_iend = false;
+    let mut offset = Png::STANDARD_HEADER.len();
+
+    while offset < bytes.len() {
+        // chunks after IEND are only kept when intact, as `Png::try_from` does
+        if after_iend && !is_chunk_at(bytes, offset) {
+            break;
+        }
+
+        let header = bytes
+            .get(offset..offset + 8)
+            .filter(|header| header[4..].iter().all(u8::is_ascii_alphabetic));
+        let end = header.and_then(|header| data_end(bytes, offset, &header[4..], read_u32(header, 0)?));
+
+        let (header, end) = match (header, end) {
+            (Some(header), Some(end)) => (header, end),
+            _ => {
+                unrecoverable = Some(offset);
+                break;
+            }
+        };
+
+        let chunk_type = ChunkType::unchecked([header[4], header[5], header[6], header[7]]);
+        let name = chunk_type.to_string();
+        let data = bytes[offset + 8..end].to_vec();
+
+        let stored_length = read_u32(header, 0).unwrap_or_default();
+        if stored_length as usize != data.len() {
+            repairs.push(Repair::Length {
+                offset,
+                chunk_type: name.clone(),
+                stored: stored_length,
+                actual: data.len() as u32,
+            });
+        }
+
+        let stored_crc = read_u32(bytes, end).unwrap_or_default();
+        let chunk = Chunk::new(chunk_type, data);
+        if stored_crc != chunk.crc {
+            repairs.push(Repair::Crc {
+                offset,
+                chunk_type: name.clone(),
+                stored: stored_crc,
+                computed: chunk.crc,
+            });
+        }
+
+        chunks.push(chunk);
+        after_iend |= name == "IEND";
+        offset = end + 4;
+    }
+
+    Ok(Repaired {
+        png: Png {
+            chunks,
+            trailing: bytes.get(offset..).unwrap_or_default().to_vec(),
+        },
+        repairs,
+        unrecoverable,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0A long enough comment"),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_intact_file() {
+        let bytes = testing_bytes();
+        let repaired = repair(&bytes).unwrap();
+
+        assert!(repaired.repairs.is_empty());
+        assert_eq!(repaired.unrecoverable, None);
+        assert_eq!(repaired.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_repair_crc() {
+        let original = testing_bytes();
+        let mut bytes = original.clone();
+        // a flipped bit in the tEXt data
+        bytes[33 + 10] ^= 1;
+
+        let repaired = repair(&bytes).unwrap();
+
+        assert!(matches!(&repaired.repairs[..], [Repair::Crc { offset: 33, .. }]));
+        assert_eq!(repaired.png.chunks().len(), 4);
+        assert!(repaired.png.validate().is_empty());
+    }
+
+    #[test]
+    fn test_repair_length() {
+        let original = testing_bytes();
+        let mut bytes = original.clone();
+        // tEXt starts at 33, its length field goes from 29 to 200
+        bytes[33 + 3] = 200;
+
+        let repaired = repair(&bytes).unwrap();
+
+        assert_eq!(
+            repaired.repairs,
+            vec![Repair::Length {
+                offset: 33,
+                chunk_type: "tEXt".to_string(),
+                stored: 200,
+                actual: 29,
+            }]
+        );
+        assert_eq!(repaired.png.as_bytes(), original);
+    }
+
+    #[test]
+    fn test_repair_length_and_crc() {
+        let original = testing_bytes();
+        let mut bytes = original.clone();
+        bytes[33 + 3] = 5;
+        bytes[33 + 10] ^= 1;
+
+        let repaired = repair(&bytes).unwrap();
+
+        assert_eq!(repaired.repairs.len(), 2);
+        assert_eq!(repaired.png[1].data().len(), 29);
+        assert!(repaired.png.validate().is_empty());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+// Textual metadata as the specification stores it in tEXt chunks: a keyword,
+// a NUL separator and the text, both in Latin-1. Standard tools show these,
+// so a message written this way is visible to anyone looking.
+//
+// iTXt chunks hold UTF-8 text instead, optionally zlib compressed, along with
+// the language it is written in and the keyword translated to that language:
+//
+//   keyword NUL compression-flag compression-method language NUL translated-keyword NUL text
+
+pub const MAX_KEYWORD_LENGTH: usize = 79;
+
+// the keywords the specification defines, which tools know how to show
+pub const STANDARD_KEYWORDS: [&str; 10] = [
+    "Title",
+    "Author",
+    "Description",
+    "Copyright",
+    "Creation Time",
+    "Software",
+    "Disclaimer",
+    "Warning",
+    "Source",
+    "Comment",
+];
+
+#[derive(Debug)]
+pub enum TextError {
+    InvalidKeyword(String),
+    NotLatin1(char),
+    MissingSeparator,
+    InvalidLanguage(String),
+    UnknownCompression(u8),
+    InvalidUtf8,
+}
+
+impl std::error::Error for TextError {}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextError::InvalidKeyword(keyword) => write!(
+                f,
+                "Invalid keyword {:?}: it has to be 1 to {} printable Latin-1 characters, without leading, trailing or consecutive spaces",
+                keyword, MAX_KEYWORD_LENGTH
+            ),
+            TextError::NotLatin1(char) => write!(f, "{:?} can not be written in Latin-1", char),
+            TextError::MissingSeparator => write!(f, "The text chunk has no NUL after its keyword"),
+            TextError::InvalidLanguage(language) => write!(
+                f,
+                "Invalid language tag {:?}: it has to be hyphen separated words of 1 to 8 ASCII letters or digits, like en-GB",
+                language
+            ),
+            TextError::UnknownCompression(method) => write!(f, "Unknown compression method {}", method),
+            TextError::InvalidUtf8 => write!(f, "The international text chunk does not hold UTF-8"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.keyword, self.text)
+    }
+}
+
+// every byte is the Latin-1 character of the same code
+pub fn latin1_decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+pub fn latin1_encode(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|char| u8::try_from(char as u32).map_err(|_| TextError::NotLatin1(char).into()))
+        .collect()
+}
+
+pub fn is_valid_keyword(keyword: &str) -> bool {
+    let printable = keyword
+        .chars()
+        .all(|char| (' '..='~').contains(&char) || ('\u{a1}'..='\u{ff}').contains(&char));
+
+    (1..=MAX_KEYWORD_LENGTH).contains(&keyword.chars().count())
+        && printable
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ")
+}
+
+// an iTXt chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalText {
+    pub keyword: String,
+    pub compressed: bool,
+    // an RFC 3066 language tag such as "en-GB", empty when the language is not given
+    pub language: String,
+    pub translated_keyword: String,
+    pub text: String,
+}
+
+impl fmt::Display for InternationalText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.keyword)?;
+
+        if !self.language.is_empty() || !self.translated_keyword.is_empty() {
+            write!(f, " ({}", self.language)?;
+            if !self.translated_keyword.is_empty() {
+                write!(f, ", {}", self.translated_keyword)?;
+            }
+            write!(f, ")")?;
+        }
+
+        write!(f, ": {}", self.text)
+    }
+}
+
+pub fn is_valid_language(language: &str) -> bool {
+    language.is_empty()
+        || language
+            .split('-')
+            .all(|word| (1..=8).contains(&word.len()) && word.chars().all(|char| char.is_ascii_alphanumeric()))
+}
+
+// the fields before the text of an iTXt chunk are NUL terminated
+fn split_nul(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let separator = data.iter().position(|&byte| byte == 0).ok_or(TextError::MissingSeparator)?;
+    Ok((&data[..separator], &data[separator + 1..]))
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String> {
+    Ok(String::from_utf8(bytes).map_err(|_| TextError::InvalidUtf8)?)
+}
+
+impl InternationalText {
+    pub fn new(keyword: &str, language: &str, translated_keyword: &str, text: &str, compressed: bool) -> Result<InternationalText> {
+        if !is_valid_keyword(keyword) {
+            return Err(TextError::InvalidKeyword(keyword.to_string()).into());
+        }
+
+        if !is_valid_language(language) {
+            return Err(TextError::InvalidLanguage(language.to_string()).into());
+        }
+
+        Ok(InternationalText {
+            keyword: keyword.to_string(),
+            compressed,
+            language: language.to_string(),
+            translated_keyword: translated_keyword.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Result<InternationalText> {
+        InternationalText::from_data(chunk.data())
+    }
+
+    // reads the data of an iTXt chunk
+    pub fn from_data(data: &[u8]) -> Result<InternationalText> {
+        let (keyword, rest) = split_nul(data)?;
+        let (flags, rest) = rest.split_at(rest.len().min(2));
+
+        let compressed = match *flags {
+            [0, _] => false,
+            [_, 0] => true,
+            [_, method] => return Err(TextError::UnknownCompression(method).into()),
+            _ => return Err(TextError::MissingSeparator.into()),
+        };
+
+        let (language, rest) = split_nul(rest)?;
+        let (translated_keyword, text) = split_nul(rest)?;
+
+        let text = if compressed {
+            let mut raw = Vec::new();
+            ZlibDecoder::new(text).read_to_end(&mut raw)?;
+            raw
+        } else {
+            text.to_vec()
+        };
+
+        Ok(InternationalText {
+            keyword: latin1_decode(keyword),
+            compressed,
+            language: latin1_decode(language),
+            translated_keyword: utf8(translated_keyword.to_vec())?,
+            text: utf8(text)?,
+        })
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = latin1_encode(&self.keyword)?;
+        data.extend([0, self.compressed as u8, 0]);
+        data.extend(self.language.as_bytes());
+        data.push(0);
+        data.extend(self.translated_keyword.as_bytes());
+        data.push(0);
+
+        if self.compressed {
+            let mut encoder = ZlibEncoder::new(data, Compression::best());
+            encoder.write_all(self.text.as_bytes())?;
+            data = encoder.finish()?;
+        } else {
+            data.extend(self.text.as_bytes());
+        }
+
+        Ok(Chunk::new(ChunkType::ITXT, data))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "keyword": self.keyword,
+            "text": self.text,
+            "language": self.language,
+            "translated_keyword": self.translated_keyword,
+            "compressed": self.compressed,
+        })
+    }
+}
+
+// the keyword and text, without the language
+impl From<InternationalText> for Text {
+    fn from(text: InternationalText) -> Text {
+        Text {
+            keyword: text.keyword,
+            text: text.text,
+        }
+    }
+}
+
+// the keyword of a tEXt, zTXt or iTXt chunk, which all start with it
+pub fn keyword_of(chunk: &Chunk) -> Option<String> {
+    if !matches!(&chunk.chunk_type.bytes, b"tEXt" | b"zTXt" | b"iTXt") {
+        return None;
+    }
+
+    let (keyword, _) = split_nul(chunk.data()).ok()?;
+    Some(latin1_decode(keyword))
+}
+
+// a tEXt chunk when the text can be written in Latin-1, an iTXt chunk otherwise
+pub fn text_chunk(keyword: &str, text: &str) -> Result<Chunk> {
+    if latin1_encode(text).is_ok() {
+        Text::new(keyword, text)?.to_chunk()
+    } else {
+        InternationalText::new(keyword, "", "", text, false)?.to_chunk()
+    }
+}
+
+impl Text {
+    pub fn new(keyword: &str, text: &str) -> Result<Text> {
+        if !is_valid_keyword(keyword) {
+            return Err(TextError::InvalidKeyword(keyword.to_string()).into());
+        }
+
+        Ok(Text {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+        })
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Result<Text> {
+        Text::from_data(chunk.data())
+    }
+
+    // reads the data of a tEXt chunk
+    pub fn from_data(data: &[u8]) -> Result<Text> {
+        let separator = data.iter().position(|&byte| byte == 0).ok_or(TextError::MissingSeparator)?;
+
+        Ok(Text {
+            keyword: latin1_decode(&data[..separator]),
+            text: latin1_decode(&data[separator + 1..]),
+        })
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = latin1_encode(&self.keyword)?;
+        data.push(0);
+        data.extend(latin1_encode(&self.text)?);
+
+        Ok(Chunk::new(ChunkType::TEXT, data))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "keyword": self.keyword,
+            "text": self.text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_chunk() {
+        let text = Text::new("Comment", "Café at noon").unwrap();
+        let chunk = text.to_chunk().unwrap();
+
+        assert_eq!(chunk.data(), b"Comment\0Caf\xe9 at noon");
+        assert_eq!(Text::from_chunk(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        assert!(Text::new("", "text").is_err());
+        assert!(Text::new(" Comment", "text").is_err());
+        assert!(Text::new("Two  spaces", "text").is_err());
+        assert!(Text::new(&"k".repeat(80), "text").is_err());
+
+        assert!(Text::new("Comment", "日本").unwrap().to_chunk().is_err());
+    }
+
+    #[test]
+    fn test_international_text_chunk() {
+        for compressed in [false, true] {
+            let text = InternationalText::new("Title", "ja", "タイトル", "日本の海", compressed).unwrap();
+            let chunk = text.to_chunk().unwrap();
+
+            assert!(chunk.data().starts_with(b"Title\0"));
+            assert_eq!(InternationalText::from_chunk(&chunk).unwrap(), text);
+        }
+
+        let chunk = Chunk::new(ChunkType::ITXT, b"Title\0\0\0\0\0Dice".to_vec());
+        assert_eq!(InternationalText::from_chunk(&chunk).unwrap().text, "Dice");
+
+        assert!(InternationalText::new("Title", "en_GB", "", "text", false).is_err());
+    }
+
+    #[test]
+    fn test_text_chunk_type() {
+        let latin1 = text_chunk("Author", "Zoë").unwrap();
+        let utf8 = text_chunk("Author", "李雷").unwrap();
+
+        assert_eq!(latin1.chunk_type.to_string(), "tEXt");
+        assert_eq!(utf8.chunk_type.to_string(), "iTXt");
+        assert_eq!(keyword_of(&utf8), Some("Author".to_string()));
+    }
+}
@@ -0,0 +1,330 @@
+use super::pixels::Header;
+use super::Png;
+use crate::chunk::{self, Chunk};
+use crate::chunk_type::ChunkType;
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+use rayon::prelude::*;
+
+// Checks a PNG against the structure the specification requires. Violations
+// are collected rather than returned as errors, so a verifier can list all of
+// them at once.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    InvalidSignature,
+    // the file ends in the middle of the chunk starting at this offset
+    Truncated { offset: usize },
+    LengthOutOfBounds { offset: usize, length: u64 },
+    InvalidChunkType { offset: usize, chunk_type: String },
+    InvalidCrc { offset: usize, chunk_type: String, stored: u32, computed: u32 },
+    FirstChunkNotIhdr,
+    InvalidHeader,
+    LastChunkNotIend,
+    DuplicateChunk(String),
+    MissingIdat,
+    IdatNotContiguous,
+    MissingPalette,
+    TrailingData { offset: usize, length: usize },
+    // a well formed chunk after IEND, as earlier versions wrote their payloads
+    ChunkAfterIend { offset: usize, chunk_type: String },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::InvalidSignature => write!(f, "The file does not start with the PNG signature"),
+            Violation::Truncated { offset } => write!(f, "The file ends in the middle of the chunk at offset {}", offset),
+            Violation::LengthOutOfBounds { offset, length } => write!(
+                f,
+                "The chunk at offset {} has a length of {}, above the limit of {}",
+                offset,
+                length,
+                Chunk::MAX_LENGTH
+            ),
+            Violation::InvalidChunkType { offset, chunk_type } => {
+                write!(f, "The chunk at offset {} has an invalid type {:?}", offset, chunk_type)
+            }
+            Violation::InvalidCrc {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "The {} chunk at offset {} has CRC {:#010x}, its data gives {:#010x}",
+                chunk_type, offset, stored, computed
+            ),
+            Violation::FirstChunkNotIhdr => write!(f, "The first chunk is not IHDR"),
+            Violation::InvalidHeader => write!(f, "The IHDR chunk does not describe a valid image"),
+            Violation::LastChunkNotIend => write!(f, "The last chunk is not IEND"),
+            Violation::DuplicateChunk(chunk_type) => write!(f, "There is more than one {} chunk", chunk_type),
+            Violation::MissingIdat => write!(f, "There is no IDAT chunk"),
+            Violation::IdatNotContiguous => write!(f, "The IDAT chunks are not consecutive"),
+            Violation::MissingPalette => write!(f, "The image is indexed but has no PLTE chunk"),
+            Violation::TrailingData { offset, length } => {
+                write!(f, "{} bytes of data follow IEND at offset {}", length, offset)
+            }
+            Violation::ChunkAfterIend { offset, chunk_type } => {
+                write!(f, "A {} chunk follows IEND at offset {}", chunk_type, offset)
+            }
+        }
+    }
+}
+
+// problems with a single chunk: its length, type and CRC
+pub fn check_chunk(offset: usize, chunk_type: [u8; 4], data: &[u8], stored: u32) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let name = String::from_utf8_lossy(&chunk_type).to_string();
+
+    if data.len() as u64 > Chunk::MAX_LENGTH as u64 {
+        violations.push(Violation::LengthOutOfBounds {
+            offset,
+            length: data.len() as u64,
+        });
+    }
+
+    let valid_type = ChunkType::try_from(chunk_type)
+        .map(|chunk_type| chunk_type.is_valid())
+        .unwrap_or(false);
+    if !valid_type {
+        violations.push(Violation::InvalidChunkType {
+            offset,
+            chunk_type: name.clone(),
+        });
+    }
+
+    let computed = chunk::crc(&chunk_type, data);
+    if computed != stored {
+        violations.push(Violation::InvalidCrc {
+            offset,
+            chunk_type: name,
+            stored,
+            computed,
+        });
+    }
+
+    violations
+}
+
+// problems with the order of the chunks; `ihdr` is the data of the first chunk when it is IHDR
+pub fn check_order(types: &[String], ihdr: Option<&[u8]>) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if types.first().map(String::as_str) != Some("IHDR") {
+        violations.push(Violation::FirstChunkNotIhdr);
+    }
+
+    let header = ihdr.and_then(|data| Header::from_bytes(data).ok());
+    if ihdr.is_some() && header.is_none() {
+        violations.push(Violation::InvalidHeader);
+    }
+
+    if types.last().map(String::as_str) != Some("IEND") {
+        violations.push(Violation::LastChunkNotIend);
+    }
+
+    for unique in ["IHDR", "PLTE", "IEND"] {
+        if types.iter().filter(|chunk_type| *chunk_type == unique).count() > 1 {
+            violations.push(Violation::DuplicateChunk(unique.to_string()));
+        }
+    }
+
+    let idats: Vec<usize> = (0..types.len()).filter(|&i| types[i] == "IDAT").collect();
+    match (idats.first(), idats.last()) {
+        (None, _) => violations.push(Violation::MissingIdat),
+        (Some(first), Some(last)) if last - first + 1 != idats.len() => violations.push(Violation::IdatNotContiguous),
+        _ => {}
+    }
+
+    if header.map(|header| header.is_indexed()).unwrap_or(false) && !types.iter().any(|chunk_type| chunk_type == "PLTE") {
+        violations.push(Violation::MissingPalette);
+    }
+
+    violations
+}
+
+// Checks the bytes of a file, which unlike `Png::validate` also catches
+// problems that keep it from being parsed at all. The chunks up to IEND are
+// found first, from their lengths alone; their CRCs, which take going through
+// all of their data, are then checked in parallel, see `check_chunks`.
+pub fn validate_bytes(bytes: &[u8]) -> Vec<Violation> {
+    if bytes.get(..Png::STANDARD_HEADER.len()) != Some(&Png::STANDARD_HEADER[..]) {
+        return vec![Violation::InvalidSignature];
+    }
+
+    let mut types = Vec::new();
+    let mut ihdr = None;
+    let mut chunks = Vec::new();
+    let mut stopped = None;
+    let mut offset = Png::STANDARD_HEADER.len();
+
+    while offset < bytes.len() {
+        let (chunk, length) = match frame(bytes, offset) {
+            Ok(frame) => frame,
+            Err(violation) => {
+                stopped = Some(violation);
+                break;
+            }
+        };
+
+        let chunk_type = String::from_utf8_lossy(&chunk[4..8]).to_string();
+        if types.is_empty() && chunk_type == "IHDR" {
+            ihdr = Some(&chunk[8..8 + length]);
+        }
+        let is_end = chunk_type == "IEND";
+        types.push(chunk_type);
+        chunks.push((offset, chunk));
+
+        offset += 12 + length;
+        if is_end {
+            break;
+        }
+    }
+
+    let mut violations: Vec<Violation> = check_chunks(&chunks).into_iter().flatten().collect();
+    violations.extend(stopped);
+
+    // Earlier versions appended their chunks after IEND. Those count as
+    // chunks and are reported one by one, anything there that is not a well
+    // formed chunk as trailing data.
+    if types.last().map(String::as_str) == Some("IEND") {
+        while offset < bytes.len() {
+            match frame(bytes, offset) {
+                Ok((chunk, _)) if check_framed(offset, chunk).is_empty() => {
+                    let chunk_type = String::from_utf8_lossy(&chunk[4..8]).to_string();
+                    violations.push(Violation::ChunkAfterIend {
+                        offset,
+                        chunk_type: chunk_type.clone(),
+                    });
+                    types.push(chunk_type);
+                    offset += chunk.len();
+                }
+                _ => {
+                    violations.push(Violation::TrailingData {
+                        offset,
+                        length: bytes.len() - offset,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+
+    violations.extend(check_order(&types, ihdr));
+    violations
+}
+
+// the chunk starting at `offset` and the length of its data, when it is all there
+fn frame(bytes: &[u8], offset: usize) -> Result<(&[u8], usize), Violation> {
+    let length = bytes
+        .get(offset..offset + 8)
+        .map(|fields| u32::from_be_bytes(fields[..4].try_into().unwrap()) as usize)
+        .ok_or(Violation::Truncated { offset })?;
+
+    match bytes.get(offset..offset + 12 + length) {
+        Some(chunk) => Ok((chunk, length)),
+        None if length > Chunk::MAX_LENGTH as usize => Err(Violation::LengthOutOfBounds {
+            offset,
+            length: length as u64,
+        }),
+        None => Err(Violation::Truncated { offset }),
+    }
+}
+
+// `check_chunk` for the chunk starting at `offset`, all of its bytes given
+fn check_framed(offset: usize, chunk: &[u8]) -> Vec<Violation> {
+    let chunk_type = [chunk[4], chunk[5], chunk[6], chunk[7]];
+    let stored = u32::from_be_bytes(chunk[chunk.len() - 4..].try_into().unwrap());
+
+    check_chunk(offset, chunk_type, &chunk[8..chunk.len() - 4], stored)
+}
+
+// `check_framed` for every chunk in parallel across the available cores, the
+// results in the order of the chunks
+fn check_chunks(chunks: &[(usize, &[u8])]) -> Vec<Vec<Violation>> {
+    chunks.par_iter().map(|&(offset, chunk)| check_framed(offset, chunk)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("IDAT", &[1, 2, 3]),
+            chunk("IEND", &[]),
+        ]
+    }
+
+    #[test]
+    fn test_valid_png() {
+        let png = Png::from_chunks(testing_chunks());
+
+        assert!(png.validate().is_empty());
+        assert!(validate_bytes(&png.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_order_violations() {
+        let mut chunks = testing_chunks();
+        chunks.swap(0, 1);
+        chunks.push(chunk("IDAT", &[4]));
+
+        let violations = Png::from_chunks(chunks).validate();
+
+        assert!(violations.contains(&Violation::FirstChunkNotIhdr));
+        assert!(violations.contains(&Violation::LastChunkNotIend));
+        assert!(violations.contains(&Violation::IdatNotContiguous));
+    }
+
+    #[test]
+    fn test_bad_crc_and_truncation() {
+        let mut bytes = Png::from_chunks(testing_chunks()).as_bytes();
+
+        // a flipped bit in the IDAT data
+        bytes[8 + 25 + 8] ^= 1;
+        let violations = validate_bytes(&bytes);
+        assert!(matches!(&violations[..], [Violation::InvalidCrc { offset: 33, .. }]));
+
+        let violations = validate_bytes(&bytes[..bytes.len() - 2]);
+        assert!(violations.contains(&Violation::Truncated { offset: bytes.len() - 12 }));
+        assert!(violations.contains(&Violation::LastChunkNotIend));
+
+        assert_eq!(validate_bytes(b"GIF89a"), vec![Violation::InvalidSignature]);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = Png::from_chunks(testing_chunks()).as_bytes();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"appended");
+
+        assert_eq!(validate_bytes(&bytes), vec![Violation::TrailingData { offset: end, length: 8 }]);
+    }
+
+    #[test]
+    fn test_chunk_after_iend() {
+        let mut chunks = testing_chunks();
+        chunks.push(Chunk::new(ChunkType::try_from(*b"tEXt").unwrap(), b"Comment\0hidden".to_vec()));
+        let png = Png::from_chunks(chunks);
+        let offset = *png.chunk_offsets().last().unwrap();
+
+        let after_iend = Violation::ChunkAfterIend {
+            offset,
+            chunk_type: "tEXt".to_string(),
+        };
+        for violations in [validate_bytes(&png.as_bytes()), png.validate()] {
+            assert!(violations.contains(&after_iend));
+            assert!(violations.contains(&Violation::LastChunkNotIend));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+// Progress bars for what takes long enough to wonder about: reading large
+// files, hiding a message in the pixel data and scanning directories. They
+// are drawn on stderr, and only when stdout is a terminal and JSON was not
+// asked for, so output meant for other programs is left as it is. Bars that
+// are not drawn still count, which costs next to nothing.
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// called once the arguments are read
+pub fn init(json: bool) {
+    ENABLED.store(!json && std::io::stdout().is_terminal(), Ordering::Relaxed);
+}
+
+fn target() -> ProgressDrawTarget {
+    if ENABLED.load(Ordering::Relaxed) {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    }
+}
+
+// the bytes of a file of `length` bytes read so far
+pub fn bytes(length: u64, message: String) -> ProgressBar {
+    let style = ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} ({eta})")
+        .expect("The template is valid.")
+        .progress_chars("=> ");
+
+    ProgressBar::with_draw_target(Some(length), target()).with_style(style).with_message(message)
+}
+
+// the files of a batch of `count` done so far, the current one as the message
+pub fn files(count: u64) -> ProgressBar {
+    let style = ProgressStyle::with_template("[{bar:30}] {pos}/{len} files {wide_msg}")
+        .expect("The template is valid.")
+        .progress_chars("=> ");
+
+    ProgressBar::with_draw_target(Some(count), target()).with_style(style)
+}
+
+// bars drawn one above the other, as the bytes of the file being read under
+// the files of a batch
+pub fn stack() -> MultiProgress {
+    MultiProgress::with_draw_target(target())
+}
+
+// the step of a task of unknown length being worked on
+pub fn spinner(message: &'static str) -> ProgressBar {
+    let bar = ProgressBar::with_draw_target(None, target())
+        .with_style(ProgressStyle::with_template("{spinner} {msg} {elapsed}").expect("The template is valid."))
+        .with_message(message);
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    bar
+}
@@ -0,0 +1,184 @@
+use super::png::pixels::Pixels;
+
+// Measures how far embedding moved the stego image from its cover.
+//
+// PSNR is computed over every sample. SSIM is the mean over 8x8 windows, taken
+// every 4 pixels, of each channel separately.
+
+const WINDOW: usize = 8;
+const STEP: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quality {
+    // in dB, infinite when the images are identical
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+// the samples of the image as numbers, along with the largest value they can take
+fn samples(pixels: &Pixels) -> (Vec<f64>, f64) {
+    if pixels.header.bit_depth == 16 {
+        let values = pixels
+            .data
+            .chunks_exact(2)
+            .map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as f64)
+            .collect();
+
+        (values, u16::MAX as f64)
+    } else {
+        (pixels.data.iter().map(|&sample| sample as f64).collect(), u8::MAX as f64)
+    }
+}
+
+pub fn psnr(original: &Pixels, modified: &Pixels) -> f64 {
+    let (a, max) = samples(original);
+    let (b, _) = samples(modified);
+
+    let mse = a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum::<f64>() / a.len() as f64;
+
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+
+    10.0 * (max * max / mse).log10()
+}
+
+fn window_ssim(a: &[f64], b: &[f64], max: f64) -> f64 {
+    let c1 = (0.01 * max).powi(2);
+    let c2 = (0.03 * max).powi(2);
+    let n = a.len() as f64;
+
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+
+    let var_a = a.iter().map(|a| (a - mean_a).powi(2)).sum::<f64>() / n;
+    let var_b = b.iter().map(|b| (b - mean_b).powi(2)).sum::<f64>() / n;
+    let covariance = a.iter().zip(b).map(|(a, b)| (a - mean_a) * (b - mean_b)).sum::<f64>() / n;
+
+    ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+        / ((mean_a.powi(2) + mean_b.powi(2) + c1) * (var_a + var_b + c2))
+}
+
+// window start positions along a side, the whole side when it is shorter than a window
+fn window_starts(size: usize) -> Vec<usize> {
+    if size <= WINDOW {
+        return vec![0];
+    }
+
+    (0..=size - WINDOW).step_by(STEP).collect()
+}
+
+pub fn ssim(original: &Pixels, modified: &Pixels) -> f64 {
+    let (a, max) = samples(original);
+    let (b, _) = samples(modified);
+
+    let width = original.header.width as usize;
+    let height = original.header.height as usize;
+    let channels = a.len() / (width * height).max(1);
+
+    let mut total = 0.0;
+    let mut count = 0;
+
+    for channel in 0..channels {
+        for &top in &window_starts(height) {
+            for &left in &window_starts(width) {
+                let indices: Vec<usize> = (top..(top + WINDOW).min(height))
+                    .flat_map(|y| (left..(left + WINDOW).min(width)).map(move |x| (y * width + x) * channels + channel))
+                    .collect();
+
+                let window_a: Vec<f64> = indices.iter().map(|&i| a[i]).collect();
+                let window_b: Vec<f64> = indices.iter().map(|&i| b[i]).collect();
+
+                total += window_ssim(&window_a, &window_b, max);
+                count += 1;
+            }
+        }
+    }
+
+    if count == 0 {
+        return 1.0;
+    }
+
+    total / count as f64
+}
+
+pub fn compare(original: &Pixels, modified: &Pixels) -> Quality {
+    Quality {
+        psnr: psnr(original, modified),
+        ssim: ssim(original, modified),
+    }
+}
+
+// the quality of the most degraded frame of an animation
+pub fn compare_frames(original: &[Pixels], modified: &[Pixels]) -> Quality {
+    original
+        .iter()
+        .zip(modified)
+        .map(|(original, modified)| compare(original, modified))
+        .fold(
+            Quality {
+                psnr: f64::INFINITY,
+                ssim: 1.0,
+            },
+            |worst, quality| Quality {
+                psnr: worst.psnr.min(quality.psnr),
+                ssim: worst.ssim.min(quality.ssim),
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::pixels::Header;
+
+    fn testing_pixels() -> Pixels {
+        let header = Header {
+            width: 16,
+            height: 16,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+
+        Pixels::new(header, (0..16 * 16 * 3).map(|i| (i * 31 % 256) as u8).collect())
+    }
+
+    #[test]
+    fn test_identical_images() {
+        let pixels = testing_pixels();
+        let quality = compare(&pixels, &pixels);
+
+        assert!(quality.psnr.is_infinite());
+        assert!((quality.ssim - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flipped_low_bits() {
+        let original = testing_pixels();
+        let mut modified = original.clone();
+        for sample in modified.data.iter_mut() {
+            *sample ^= 1;
+        }
+
+        let quality = compare(&original, &modified);
+
+        // every sample off by one gives an MSE of 1
+        assert!((quality.psnr - 20.0 * 255f64.log10()).abs() < 1e-9);
+        assert!(quality.ssim > 0.99 && quality.ssim < 1.0);
+    }
+
+    #[test]
+    fn test_heavy_noise_scores_lower() {
+        let original = testing_pixels();
+        let mut light = original.clone();
+        let mut heavy = original.clone();
+        light.data[0] ^= 1;
+        for sample in heavy.data.iter_mut() {
+            *sample = sample.wrapping_add(64);
+        }
+
+        assert!(psnr(&original, &heavy) < psnr(&original, &light));
+        assert!(ssim(&original, &heavy) < ssim(&original, &light));
+    }
+}
@@ -0,0 +1,287 @@
+use super::inspect::{self, Content};
+use super::png::pixels::Pixels;
+use super::png::validate::{self, Violation};
+use super::png::Png;
+use super::scan::{self, Finding};
+use super::steganalysis::{self, Analysis};
+
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+// A forensic report on one file: whether its structure is sound, the chunks it
+// is made of, what the scan turns up and the steganalysis of its pixel data.
+//
+// The report is signed off with the SHA-256 of its own contents, so a copy
+// filed with a case can be checked for changes made after it was written.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkEntry {
+    // position of the chunk length field in the file
+    pub offset: usize,
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+    pub entropy: f64,
+    pub content: Content,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub file: String,
+    pub size: usize,
+    pub sha256: String,
+    // seconds since the Unix epoch
+    pub created: u64,
+    pub examiner: Option<String>,
+    // problems with the structure of the file, empty when it is sound
+    pub structure: Vec<String>,
+    pub chunks: Vec<ChunkEntry>,
+    pub findings: Vec<Finding>,
+    // missing when the pixel data can not be decoded
+    pub analysis: Option<Analysis>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn chunk_entries(png: &Png) -> Vec<ChunkEntry> {
+    png.chunks()
+        .zip(png.chunk_offsets())
+        .map(|(chunk, offset)| ChunkEntry {
+            offset,
+            chunk_type: chunk.chunk_type.to_string(),
+            length: chunk.length,
+            crc: chunk.crc,
+            entropy: inspect::entropy(chunk.data()),
+            content: inspect::classify(chunk.data()),
+        })
+        .collect()
+}
+
+// the pixel data of the default image, one sample per byte
+fn analysis_pixels(png: &Png) -> Option<Pixels> {
+    let pixels = png.pixels().ok()?;
+
+    if pixels.header.is_indexed() {
+        pixels.unpack().ok()
+    } else {
+        Some(pixels)
+    }
+}
+
+impl Report {
+    // `file` is only used to name the file in the report
+    pub fn new(file: &str, bytes: &[u8], examiner: Option<String>) -> Report {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let mut report = Report {
+            file: file.to_string(),
+            size: bytes.len(),
+            sha256: hex(&Sha256::digest(bytes)),
+            created,
+            examiner,
+            structure: Vec::new(),
+            chunks: Vec::new(),
+            findings: Vec::new(),
+            analysis: None,
+        };
+
+        report.structure = validate::validate_bytes(bytes).iter().map(Violation::to_string).collect();
+
+        match Png::try_from(bytes) {
+            Err(e) => report.structure.push(format!("Not a valid PNG: {}", e)),
+            Ok(png) => {
+                report.chunks = chunk_entries(&png);
+                report.findings = scan::scan_png(&png);
+                report.analysis = analysis_pixels(&png).map(|pixels| steganalysis::analyze(&pixels));
+            }
+        }
+
+        report
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        !self.findings.is_empty() || self.analysis.map(|analysis| analysis.is_suspicious()).unwrap_or(false)
+    }
+
+    fn body(&self) -> serde_json::Value {
+        serde_json::json!({
+            "file": self.file,
+            "size": self.size,
+            "sha256": self.sha256,
+            "created": self.created,
+            "examiner": self.examiner,
+            "structure": self.structure,
+            "chunks": self.chunks.iter().map(|chunk| serde_json::json!({
+                "offset": chunk.offset,
+                "chunk_type": chunk.chunk_type,
+                "length": chunk.length,
+                "crc": chunk.crc,
+                "entropy": chunk.entropy,
+                "content": chunk.content.to_string(),
+            })).collect::<Vec<_>>(),
+            "findings": self.findings.iter().map(Finding::to_json).collect::<Vec<_>>(),
+            "analysis": self.analysis.map(|analysis| serde_json::json!({
+                "chi_square": analysis.chi_square,
+                "rs": analysis.rs,
+                "suspicious": analysis.is_suspicious(),
+            })),
+            "suspicious": self.is_suspicious(),
+        })
+    }
+
+    // SHA-256 of the report contents, the sign-off
+    pub fn digest(&self) -> String {
+        hex(&Sha256::digest(self.body().to_string().as_bytes()))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = self.body();
+        json["report_sha256"] = serde_json::Value::from(self.digest());
+        json
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>PNGCrypt report: {}</title>\n", escape(&self.file)));
+        html.push_str("</head>\n<body>\n");
+        html.push_str(&format!("<h1>{}</h1>\n", escape(&self.file)));
+
+        html.push_str("<table>\n");
+        for (name, value) in [
+            ("Size", format!("{} bytes", self.size)),
+            ("SHA-256", self.sha256.clone()),
+            ("Created", format!("{} (Unix time)", self.created)),
+            ("Examiner", self.examiner.clone().unwrap_or_else(|| "-".to_string())),
+            ("Verdict", if self.is_suspicious() { "suspicious" } else { "clean" }.to_string()),
+        ] {
+            html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, escape(&value)));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Structure</h2>\n");
+        html.push_str(&list(&self.structure, "The structure is sound."));
+
+        html.push_str("<h2>Chunks</h2>\n<table>\n");
+        html.push_str("<tr><th>Offset</th><th>Type</th><th>Length</th><th>CRC</th><th>Entropy</th><th>Content</th></tr>\n");
+        for chunk in self.chunks.iter() {
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:#010x}</td><td>{:.3}</td><td>{}</td></tr>\n",
+                chunk.offset,
+                escape(&chunk.chunk_type),
+                chunk.length,
+                chunk.crc,
+                chunk.entropy,
+                chunk.content
+            ));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Findings</h2>\n");
+        let findings: Vec<String> = self.findings.iter().map(Finding::to_string).collect();
+        html.push_str(&list(&findings, "Nothing found."));
+
+        html.push_str("<h2>Steganalysis</h2>\n");
+        match self.analysis {
+            Some(analysis) => html.push_str(&format!(
+                "<p>Chi-square: {:.2}%. RS analysis: {:.2}% of the samples carry a payload.</p>\n",
+                analysis.chi_square * 100.0,
+                analysis.rs * 100.0
+            )),
+            None => html.push_str("<p>The pixel data could not be decoded.</p>\n"),
+        }
+
+        html.push_str(&format!("<p>Report SHA-256: {}</p>\n", self.digest()));
+        html.push_str("</body>\n</html>\n");
+
+        html
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn list(items: &[String], empty: &str) -> String {
+    if items.is_empty() {
+        return format!("<p>{}</p>\n", empty);
+    }
+
+    let items: String = items.iter().map(|item| format!("<li>{}</li>\n", escape(item))).collect();
+    format!("<ul>\n{}</ul>\n", items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::pixels::Header;
+    use std::str::FromStr;
+
+    fn testing_bytes(extra: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+        let header = Header {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: 0,
+            interlace: 0,
+        };
+        let pixels = Pixels::new(header, (0..16).map(|i| i * 16).collect());
+
+        let mut chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 4, 0, 0, 0, 4, 8, 0, 0, 0, 0]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), pixels.encode().unwrap()),
+        ];
+        for (chunk_type, data) in extra {
+            chunks.push(Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data));
+        }
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_clean_report() {
+        let report = Report::new("clean.png", &testing_bytes(Vec::new()), Some("J. Doe".to_string()));
+
+        assert!(report.structure.is_empty());
+        assert_eq!(report.chunks.len(), 3);
+        assert_eq!(report.chunks[0].offset, 8);
+        assert_eq!(report.chunks[1].offset, 8 + 12 + 13);
+        assert!(report.findings.is_empty());
+        assert!(report.analysis.is_some());
+        assert_eq!(report.to_json()["report_sha256"], report.digest());
+    }
+
+    #[test]
+    fn test_report_flags_findings() {
+        let report = Report::new("odd.png", &testing_bytes(vec![("ruSt", b"hi".to_vec())]), None);
+
+        assert!(report.is_suspicious());
+        assert!(report.structure.is_empty());
+
+        let report = Report::new("odd.png", &testing_bytes(vec![("ruSt", b"hi".to_vec()), ("IDAT", Vec::new())]), None);
+        assert_eq!(report.structure, vec![Violation::IdatNotContiguous.to_string()]);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let report = Report::new("broken.png", b"not a png", None);
+
+        assert_eq!(report.structure.len(), 2);
+        assert!(report.chunks.is_empty());
+        assert!(report.to_html().contains("Not a valid PNG"));
+    }
+}
@@ -0,0 +1,141 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use anyhow::Result;
+
+// The Resource Interchange File Format WebP and WAV files use: "RIFF", the
+// size of what follows as a little endian u32, a four character form type
+// such as "WEBP", then chunks. Each chunk is a four character code, its data
+// length as a little endian u32 and the data, padded to an even length.
+
+const HEADER_LENGTH: usize = 12;
+
+#[derive(Debug)]
+pub enum RiffError {
+    InvalidHeader,
+    Truncated(usize),
+}
+
+impl std::error::Error for RiffError {}
+
+impl fmt::Display for RiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiffError::InvalidHeader => write!(f, "The file does not start with a RIFF header"),
+            RiffError::Truncated(offset) => write!(f, "The RIFF file is cut off in the chunk at offset {}", offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiffChunk {
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl RiffChunk {
+    pub fn id(&self) -> String {
+        String::from_utf8_lossy(&self.id).into_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Riff {
+    pub form: [u8; 4],
+    pub chunks: Vec<RiffChunk>,
+    // bytes found after the size the header gives
+    pub trailing: Vec<u8>,
+}
+
+impl Riff {
+    pub fn is_riff(bytes: &[u8], form: &[u8; 4]) -> bool {
+        bytes.starts_with(b"RIFF") && bytes.get(8..HEADER_LENGTH) == Some(form)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Riff> {
+        if !bytes.starts_with(b"RIFF") || bytes.len() < HEADER_LENGTH {
+            return Err(RiffError::InvalidHeader.into());
+        }
+
+        let size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        let end = 8usize.saturating_add(size);
+        let body = bytes.get(..end).ok_or(RiffError::Truncated(bytes.len()))?;
+
+        let mut chunks = Vec::new();
+        let mut offset = HEADER_LENGTH;
+
+        while offset < body.len() {
+            let header = body.get(offset..offset + 8).ok_or(RiffError::Truncated(offset))?;
+            let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+            let data = body
+                .get(offset + 8..(offset + 8).saturating_add(length))
+                .ok_or(RiffError::Truncated(offset))?;
+
+            chunks.push(RiffChunk {
+                id: header[..4].try_into().unwrap(),
+                data: data.to_vec(),
+            });
+            offset += 8 + length + length % 2;
+        }
+
+        Ok(Riff {
+            form: bytes[8..HEADER_LENGTH].try_into().unwrap(),
+            chunks,
+            trailing: bytes[end..].to_vec(),
+        })
+    }
+
+    pub fn chunk(&self, id: &[u8]) -> Option<&RiffChunk> {
+        self.chunks.iter().find(|chunk| chunk.id == id)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut body = self.form.to_vec();
+
+        for chunk in self.chunks.iter() {
+            body.extend(chunk.id);
+            body.extend((chunk.data.len() as u32).to_le_bytes());
+            body.extend(&chunk.data);
+            if chunk.data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes.extend(&self.trailing);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_riff_round_trip() {
+        let riff = Riff {
+            form: *b"TEST",
+            chunks: vec![
+                RiffChunk {
+                    id: *b"odd ",
+                    data: vec![1, 2, 3],
+                },
+                RiffChunk {
+                    id: *b"even",
+                    data: vec![4, 5],
+                },
+            ],
+            trailing: b"appended".to_vec(),
+        };
+
+        let bytes = riff.as_bytes();
+        assert_eq!(&bytes[4..8], &26u32.to_le_bytes());
+        assert!(Riff::is_riff(&bytes, b"TEST"));
+        assert_eq!(Riff::parse(&bytes).unwrap(), riff);
+
+        assert!(Riff::parse(&bytes[..20]).is_err());
+        assert!(Riff::parse(b"RIFX").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use super::crypto;
+use super::png::pixels::Pixels;
+use super::png::Png;
+
+use anyhow::Result;
+
+// Produces a clean copy of an image: every chunk that is not needed to display
+// it is dropped along with any data after IEND, and the lowest bit of every
+// sample is replaced with a random one before the image data is re-encoded, so
+// nothing hidden with LSB embedding survives.
+//
+// Sub-byte grayscale images are only re-encoded. Their lowest bit carries too
+// much of the image to be randomized without visibly damaging it. Indexed
+// images of any depth have their palette sorted first, as LSB mode does.
+
+// chunks needed to display the image and its animation
+const KEPT_CHUNKS: [&str; 8] = ["IHDR", "PLTE", "tRNS", "IDAT", "IEND", "acTL", "fcTL", "fdAT"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub removed_chunks: Vec<String>,
+    pub trailing: usize,
+    // whether the lowest bits were replaced
+    pub randomized: bool,
+}
+
+// replace the lowest bit of the low byte of every sample with a random one
+fn randomize_low_bits(pixels: &mut Pixels) {
+    let sample_size = std::cmp::max(1, pixels.header.bit_depth as usize / 8);
+
+    let mut noise = vec![0u8; pixels.data.len() / sample_size];
+    crypto::random_bytes(&mut noise);
+
+    for (sample, random) in pixels.data.chunks_exact_mut(sample_size).zip(noise) {
+        sample[sample_size - 1] = (sample[sample_size - 1] & !1) | (random & 1);
+    }
+}
+
+pub fn sanitize(png: &mut Png) -> Result<Summary> {
+    let removed_chunks = png
+        .retain_chunks(|chunk| KEPT_CHUNKS.contains(&chunk.chunk_type.to_string().as_str()))
+        .iter()
+        .map(|chunk| chunk.chunk_type.to_string())
+        .collect();
+
+    let trailing = png.strip_trailing().len();
+
+    let header = png.ihdr()?;
+    let mut frames = png.frames()?;
+    let randomized = header.bit_depth >= 8 || header.is_indexed();
+
+    if randomized {
+        // sorted by luminance, flipping the lowest bit of an index lands on a similar color
+        if header.is_indexed() {
+            frames = png.sort_palette(&frames)?;
+        }
+
+        if header.bit_depth < 8 {
+            frames = frames
+                .iter()
+                .map(|frame| {
+                    let mut unpacked = frame.unpack()?;
+                    randomize_low_bits(&mut unpacked);
+                    unpacked.repack(header.bit_depth)
+                })
+                .collect::<Result<_>>()?;
+        } else {
+            frames.iter_mut().for_each(randomize_low_bits);
+        }
+    }
+
+    png.set_frames(&frames)?;
+
+    Ok(Summary {
+        removed_chunks,
+        trailing,
+        randomized,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::envelope;
+    use crate::lsb::{self, LsbOptions};
+    use crate::png::pixels::Header;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let header = Header {
+            width: 32,
+            height: 32,
+            bit_depth: 8,
+            color_type: 2,
+            interlace: 0,
+        };
+        let mut pixels = Pixels::new(header, (0..32 * 32 * 3).map(|i| (i * 7 % 256) as u8).collect());
+
+        let payload = envelope::seal(b"Hidden", None, None).unwrap();
+        lsb::embed(&mut pixels, &payload, &LsbOptions::default()).unwrap();
+
+        let chunk = |chunk_type: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data);
+
+        let mut bytes = Png::from_chunks(vec![
+            chunk("IHDR", vec![0, 0, 0, 32, 0, 0, 0, 32, 8, 2, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0hello".to_vec()),
+            chunk("IDAT", pixels.encode().unwrap()),
+            chunk("abCD", envelope::seal(b"Hidden", None, None).unwrap()),
+            chunk("IEND", Vec::new()),
+        ])
+        .as_bytes();
+        bytes.extend_from_slice(b"appended");
+
+        Png::try_from(bytes.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut png = testing_png();
+        assert!(lsb::extract_frames(&png.frames().unwrap(), None).is_ok());
+
+        let summary = sanitize(&mut png).unwrap();
+
+        assert_eq!(summary.removed_chunks, vec!["tEXt".to_string(), "abCD".to_string()]);
+        assert_eq!(summary.trailing, 8);
+        assert!(summary.randomized);
+
+        let types: Vec<String> = png.chunks().map(|chunk| chunk.chunk_type.to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert!(png.trailing().is_empty());
+        assert!(lsb::extract_frames(&png.frames().unwrap(), None).is_err());
+    }
+}
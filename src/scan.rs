@@ -0,0 +1,390 @@
+use super::chunk_type::ChunkType;
+use super::contents;
+use super::detect::{self, Detection};
+use super::envelope;
+use super::inspect::{self, Content};
+use super::lsb;
+use super::png::{ParseOptions, Png, PngError};
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Result;
+
+// Looks for data hidden in PNG files: chunks the PNG specification does not
+// define, PNGCrypt envelopes stored in chunks, and envelopes embedded in the
+// pixel data without a password (those are read back in plain order). Data
+// matching the fingerprint of another tool is attributed to it.
+
+// what a chunk of a standard type holds, as the registry in chunk_type gives it
+fn description(chunk_type: &str) -> Option<&'static str> {
+    ChunkType::from_str(chunk_type).ok().and_then(|chunk_type| chunk_type.description())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    UnknownChunk {
+        chunk_type: String,
+        size: usize,
+        critical: bool,
+        content: Content,
+    },
+    ChunkEnvelope {
+        chunk_type: String,
+        size: usize,
+        encrypted: bool,
+    },
+    PixelEnvelope {
+        size: usize,
+        encrypted: bool,
+    },
+    Signature(Detection),
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encryption = |encrypted: bool| if encrypted { "encrypted" } else { "plain" };
+
+        match self {
+            Finding::UnknownChunk {
+                chunk_type,
+                size,
+                critical,
+                content,
+            } => write!(
+                f,
+                "non-standard {} chunk {} ({} bytes, {})",
+                if *critical { "critical" } else { "ancillary" },
+                chunk_type,
+                size,
+                content
+            ),
+            Finding::ChunkEnvelope {
+                chunk_type,
+                size,
+                encrypted,
+            } => write!(
+                f,
+                "PNGCrypt envelope in {} ({} bytes, {})",
+                match description(chunk_type) {
+                    Some(description) => format!("{} chunk {}", description, chunk_type),
+                    None => format!("chunk {}", chunk_type),
+                },
+                size,
+                encryption(*encrypted)
+            ),
+            Finding::PixelEnvelope { size, encrypted } => write!(
+                f,
+                "PNGCrypt envelope in the pixel data ({} bytes, {})",
+                size,
+                encryption(*encrypted)
+            ),
+            Finding::Signature(detection) => write!(f, "{}: {}", detection.tool, detection.detail),
+        }
+    }
+}
+
+impl Finding {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Finding::UnknownChunk {
+                chunk_type,
+                size,
+                critical,
+                content,
+            } => serde_json::json!({
+                "kind": "unknown_chunk",
+                "chunk_type": chunk_type,
+                "size": size,
+                "critical": critical,
+                "content": content.to_string(),
+            }),
+            Finding::ChunkEnvelope {
+                chunk_type,
+                size,
+                encrypted,
+            } => serde_json::json!({
+                "kind": "chunk_envelope",
+                "chunk_type": chunk_type,
+                "description": description(chunk_type),
+                "size": size,
+                "encrypted": encrypted,
+            }),
+            Finding::PixelEnvelope { size, encrypted } => serde_json::json!({
+                "kind": "pixel_envelope",
+                "size": size,
+                "encrypted": encrypted,
+            }),
+            Finding::Signature(detection) => serde_json::json!({
+                "kind": "signature",
+                "tool": detection.tool,
+                "detail": detection.detail,
+            }),
+        }
+    }
+}
+
+pub struct FileReport {
+    pub path: PathBuf,
+    pub size: u64,
+    pub findings: Vec<Finding>,
+    // set when the file could not be read or parsed
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    pub fn is_flagged(&self) -> bool {
+        !self.findings.is_empty() || self.error.is_some()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.path.to_string_lossy(),
+            "size": self.size,
+            "findings": self.findings.iter().map(Finding::to_json).collect::<Vec<_>>(),
+            "error": self.error,
+        })
+    }
+}
+
+pub fn scan_png(png: &Png) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type.to_string();
+        let size = chunk.data().len();
+
+        if envelope::is_envelope(chunk.data()) {
+            findings.push(Finding::ChunkEnvelope {
+                chunk_type,
+                size,
+                encrypted: envelope::is_encrypted(chunk.data()),
+            });
+        } else if let Some(detection) = detect::known_chunk(&chunk_type) {
+            findings.push(Finding::Signature(detection));
+        } else if !chunk.chunk_type.is_standard() {
+            findings.push(Finding::UnknownChunk {
+                chunk_type,
+                size,
+                critical: chunk.chunk_type.is_critical(),
+                content: inspect::classify(chunk.data()),
+            });
+        }
+    }
+
+    if let Ok(frames) = png.frames() {
+        if let Ok(payload) = lsb::extract_frames(&frames, None) {
+            findings.push(Finding::PixelEnvelope {
+                size: payload.len(),
+                encrypted: envelope::is_encrypted(&payload),
+            });
+        }
+
+        findings.extend(detect::lsb_signatures(&frames[0]).into_iter().map(Finding::Signature));
+    }
+
+    findings.extend(detect::chunks_after_iend(png).map(Finding::Signature));
+    findings.extend(detect::trailing_data(png).map(Finding::Signature));
+
+    findings
+}
+
+pub fn scan_bytes(bytes: &[u8], options: ParseOptions) -> Result<Vec<Finding>> {
+    Ok(scan_png(&Png::parse_with(bytes, options)?))
+}
+
+// The image in the file and the size of the file. Files too small to be
+// mapped, see `contents`, are read a chunk at a time, so their bytes are not
+// held alongside the chunks read from them. Mapped files are read as a
+// stream from their mapping too, so `on_read` follows both.
+fn read_png(
+    path: &Path,
+    options: ParseOptions,
+    on_read: impl FnMut(usize),
+) -> io::Result<(u64, Result<Png, PngError>)> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    if size >= contents::MAP_THRESHOLD {
+        let bytes = contents::read(file)?;
+        let reader = Counting { inner: &*bytes, on_read };
+        return Ok((size, Png::from_reader_with(reader, options)));
+    }
+
+    let reader = Counting { inner: BufReader::new(file), on_read };
+    Ok((size, Png::from_reader_with(reader, options)))
+}
+
+// a reader telling `on_read` how many bytes each read gave
+struct Counting<R, F> {
+    inner: R,
+    on_read: F,
+}
+
+impl<R: Read, F: FnMut(usize)> Read for Counting<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        (self.on_read)(read);
+
+        Ok(read)
+    }
+}
+
+pub fn scan_file(path: &Path, options: ParseOptions) -> FileReport {
+    scan_file_with(path, options, |_| {})
+}
+
+// Like `scan_file`, telling `on_read` how many bytes of the file each read
+// gave, as for a progress bar.
+pub fn scan_file_with(path: &Path, options: ParseOptions, on_read: impl FnMut(usize)) -> FileReport {
+    let (size, findings, error) = match read_png(path, options, on_read) {
+        Err(e) => (0, Vec::new(), Some(e.to_string())),
+        Ok((size, Ok(png))) => (size, scan_png(&png), None),
+        Ok((size, Err(e))) => (size, Vec::new(), Some(format!("Not a valid PNG: {}", e))),
+    };
+
+    FileReport {
+        path: path.to_path_buf(),
+        size,
+        findings,
+        error,
+    }
+}
+
+// whether the file starts with the PNG signature
+fn has_png_signature(path: &Path) -> bool {
+    let mut signature = [0u8; 8];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .map(|_| signature == Png::STANDARD_HEADER)
+        .unwrap_or(false)
+}
+
+// PNG files at `root`, going into subdirectories when `recursive` is set.
+// Files are recognized by their signature rather than their extension.
+pub fn find_pngs(root: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(root)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+
+    for entry in entries {
+        if entry.is_dir() {
+            if recursive {
+                files.extend(find_pngs(&entry, true)?);
+            }
+        } else if has_png_signature(&entry) {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::pixels::{Header, Pixels, MAX_DIMENSION};
+    use std::str::FromStr;
+
+    fn testing_png(chunks: Vec<(&str, Vec<u8>)>) -> Png {
+        let mut all = vec![("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0])];
+        all.extend(chunks);
+        all.push(("IEND", Vec::new()));
+
+        Png::from_chunks(
+            all.into_iter()
+                .map(|(chunk_type, data)| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_scan_clean_png() {
+        let png = testing_png(vec![("tEXt", b"Title\0Dice".to_vec())]);
+
+        assert!(scan_png(&png).is_empty());
+    }
+
+    #[test]
+    fn test_scan_attributes_known_chunks() {
+        let png = testing_png(vec![("ruSt", b"message".to_vec())]);
+
+        assert!(matches!(&scan_png(&png)[..], [Finding::Signature(detection)] if detection.tool == "pngme"));
+    }
+
+    #[test]
+    fn test_counting_reader() {
+        let bytes = testing_png(vec![("tEXt", b"Title\0Dice".to_vec())]).as_bytes();
+        let mut read = 0;
+
+        let reader = Counting { inner: bytes.as_slice(), on_read: |count| read += count };
+        Png::from_reader(reader).unwrap();
+        assert_eq!(read, bytes.len());
+    }
+
+    #[test]
+    fn test_scan_hostile_headers() {
+        let header = Header {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: 6,
+            interlace: 0,
+        };
+        let idat = Pixels::new(header, vec![0; 64]).encode().unwrap();
+        let claims = [(u32::MAX, u32::MAX, 8), (MAX_DIMENSION, MAX_DIMENSION, 16), (0, 4, 8), (4, 4, 0)];
+
+        for (width, height, bit_depth) in claims {
+            let ihdr = Header {
+                width,
+                height,
+                bit_depth,
+                ..header
+            };
+            let png = Png::from_chunks(vec![
+                Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr.to_bytes().to_vec()),
+                Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat.clone()),
+                Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+            ]);
+
+            assert!(scan_png(&png).is_empty());
+            assert!(scan_bytes(&png.as_bytes(), ParseOptions::default()).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_scan_finds_chunks() {
+        let sealed = envelope::seal_with_rounds(b"Message", Some("hunter2"), None, 10).unwrap();
+        let png = testing_png(vec![("abCD", b"opaque".to_vec()), ("tEXt", sealed.clone())]);
+
+        assert_eq!(
+            scan_png(&png),
+            vec![
+                Finding::UnknownChunk {
+                    chunk_type: "abCD".to_string(),
+                    size: 6,
+                    critical: false,
+                    content: Content::Text,
+                },
+                Finding::ChunkEnvelope {
+                    chunk_type: "tEXt".to_string(),
+                    size: sealed.len(),
+                    encrypted: true,
+                },
+            ]
+        );
+    }
+}
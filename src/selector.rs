@@ -0,0 +1,119 @@
+use std::fmt;
+
+// A pattern naming chunk types, or the tokens other carriers use in their
+// place, for commands that pick chunks by name. `?` stands for any one
+// character and `*` for any run of them, so "ru??" and "*St" both match ruSt.
+// Matching can ignore case, since the case of a token is easily forgotten.
+
+#[derive(Debug)]
+pub enum SelectorError {
+    NotFound(String),
+    Ambiguous(String, Vec<String>),
+}
+
+impl std::error::Error for SelectorError {}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectorError::NotFound(pattern) => write!(f, "Nothing matches {}", pattern),
+            SelectorError::Ambiguous(pattern, names) => {
+                write!(f, "{} matches several chunks: {}. Be more specific", pattern, names.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    pub pattern: String,
+    pub case_insensitive: bool,
+}
+
+impl Selector {
+    pub fn new(pattern: &str, case_insensitive: bool) -> Selector {
+        Selector {
+            pattern: pattern.to_string(),
+            case_insensitive,
+        }
+    }
+
+    // a pattern that only matches itself, which can be used as a name as it is
+    pub fn is_exact(&self) -> bool {
+        !self.case_insensitive && !self.pattern.contains(['?', '*'])
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+
+        self.matches_from(&pattern, &name)
+    }
+
+    fn matches_from(&self, pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                self.matches_from(&pattern[1..], name) || (!name.is_empty() && self.matches_from(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => self.matches_from(&pattern[1..], &name[1..]),
+            (Some(expected), Some(actual)) => {
+                let equal = if self.case_insensitive {
+                    expected.eq_ignore_ascii_case(actual)
+                } else {
+                    expected == actual
+                };
+
+                equal && self.matches_from(&pattern[1..], &name[1..])
+            }
+            _ => false,
+        }
+    }
+
+    // the one name among `names` the pattern matches, names repeating count once
+    pub fn find_one(&self, names: &[String]) -> Result<String, SelectorError> {
+        let mut found: Vec<String> = names.iter().filter(|name| self.matches(name)).cloned().collect();
+        found.sort();
+        found.dedup();
+
+        match found.len() {
+            0 => Err(SelectorError::NotFound(self.pattern.clone())),
+            1 => Ok(found.remove(0)),
+            _ => Err(SelectorError::Ambiguous(self.pattern.clone(), found)),
+        }
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector_matches() {
+        assert!(Selector::new("ruSt", false).matches("ruSt"));
+        assert!(!Selector::new("rust", false).matches("ruSt"));
+        assert!(Selector::new("rust", true).matches("ruSt"));
+
+        assert!(Selector::new("ru??", false).matches("ruSt"));
+        assert!(Selector::new("*St", false).matches("ruSt"));
+        assert!(Selector::new("*", false).matches("ruSt"));
+        assert!(!Selector::new("ru?", false).matches("ruSt"));
+        assert!(!Selector::new("*tt", false).matches("ruSt"));
+        assert!(Selector::new("*ST", true).matches("ruSt"));
+    }
+
+    #[test]
+    fn test_selector_find_one() {
+        let names = vec!["ruSt".to_string(), "abCd".to_string(), "abCe".to_string()];
+
+        assert_eq!(Selector::new("RU??", true).find_one(&names).unwrap(), "ruSt");
+        assert!(matches!(Selector::new("ab??", false).find_one(&names), Err(SelectorError::Ambiguous(_, found)) if found.len() == 2));
+        assert!(matches!(Selector::new("zz??", false).find_one(&names), Err(SelectorError::NotFound(_))));
+    }
+}
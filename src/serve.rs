@@ -0,0 +1,384 @@
+use std::io::{Cursor, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use pngcrypt::carrier;
+use pngcrypt::contents::MemoryLimit;
+use pngcrypt::envelope::{self, EnvelopeError};
+use pngcrypt::multipart::{self, Part};
+use pngcrypt::png::{ParseOptions, Png, Position};
+use pngcrypt::scan;
+use pngcrypt::selector::Selector;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{info, warn};
+
+// An HTTP server doing what encode, decode and scan do, on images uploaded as
+// multipart/form-data, for services that can not run the command themselves:
+//
+//     POST /encode  image, message, password and position: the image itself,
+//                   with the message hidden and its chunk type in the
+//                   X-PNGCrypt-Token header, as the body is not JSON
+//     POST /decode  image, token and password: the message under the token,
+//                   or every message that opens without a token, as JSON
+//     POST /scan    image: what scan finds in it, as JSON
+//
+// Failures are answered with {"error": ...}: 400 and up for what was
+// uploaded, 500 for what went wrong on the server. A request that panics is
+// answered with 500 too, and the worker goes on with the next one.
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+// a request that can not be answered, with the status telling why
+struct Failure {
+    status: u16,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: u16, message: impl Into<String>) -> Failure {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+// anything not blamed on the upload, see `invalid`, went wrong on the server
+impl From<anyhow::Error> for Failure {
+    fn from(error: anyhow::Error) -> Failure {
+        Failure::new(500, format!("{:#}", error))
+    }
+}
+
+// the upload can not be read or does not hold what it should
+fn invalid(error: impl Into<anyhow::Error>) -> Failure {
+    Failure::new(400, format!("{:#}", error.into()))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn json(status: u16, value: serde_json::Value) -> Reply {
+    Response::from_data(value.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn media_type(format: &str) -> &'static str {
+    match format {
+        "PNG" => "image/png",
+        "JPEG" => "image/jpeg",
+        "GIF" => "image/gif",
+        "WebP" => "image/webp",
+        "BMP" => "image/bmp",
+        "TIFF" => "image/tiff",
+        "ICO" => "image/vnd.microsoft.icon",
+        "WAV" => "audio/wav",
+        "PDF" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+// the fields of an uploaded form
+struct Form {
+    parts: Vec<Part>,
+}
+
+impl Form {
+    fn read(request: &mut Request, max_upload: MemoryLimit) -> Result<Form, Failure> {
+        let too_large = || Failure::new(413, format!("Uploads are limited to {} bytes", max_upload.bytes));
+        if let Some(length) = request.body_length() {
+            max_upload.check(length as u64).map_err(|_| too_large())?;
+        }
+
+        let content_type = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Content-Type"))
+            .map(|header| header.value.to_string())
+            .unwrap_or_default();
+        let boundary = multipart::boundary(&content_type).map_err(|error| Failure::new(415, error.to_string()))?;
+
+        // one byte more than the limit tells a body without a length is over it
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(max_upload.bytes.saturating_add(1))
+            .read_to_end(&mut body)
+            .context("Unable to read the upload")?;
+        max_upload.check(body.len() as u64).map_err(|_| too_large())?;
+
+        let parts = multipart::parse(&body, &boundary).context("Unable to read the form").map_err(invalid)?;
+        Ok(Form { parts })
+    }
+
+    fn bytes(&self, name: &str) -> Option<&[u8]> {
+        self.parts.iter().find(|part| part.name == name).map(|part| part.data.as_slice())
+    }
+
+    fn text(&self, name: &str) -> Result<Option<&str>, Failure> {
+        let not_text = || Failure::new(400, format!("The {} field is not UTF-8", name));
+        self.bytes(name).map(|bytes| std::str::from_utf8(bytes).map_err(|_| not_text())).transpose()
+    }
+
+    fn required(&self, name: &str) -> Result<&[u8], Failure> {
+        self.bytes(name).ok_or_else(|| Failure::new(400, format!("The form has no {} field", name)))
+    }
+}
+
+fn encode(form: &Form, kdf_rounds: u32) -> Result<Reply, Failure> {
+    let message = form.text("message")?.ok_or_else(|| Failure::new(400, "The form has no message field"))?;
+    let password = form.text("password")?.filter(|password| !password.is_empty());
+    let position = match form.text("position")? {
+        Some(position) => Position::from_str(position).map_err(|error| Failure::new(400, error))?,
+        None => Position::BeforeIend,
+    };
+
+    let mut carrier = carrier::open(form.required("image")?).map_err(invalid)?;
+    let payload = envelope::seal_with_rounds(message.as_bytes(), password, None, kdf_rounds).map_err(invalid)?;
+    let token = carrier.embed(&payload, position).map_err(invalid)?;
+    info!("Hid {} bytes in a {} image under {}", message.len(), carrier.format(), token);
+
+    Ok(Response::from_data(carrier.to_bytes())
+        .with_header(header("Content-Type", media_type(carrier.format())))
+        .with_header(header("X-PNGCrypt-Token", &token)))
+}
+
+fn decode(form: &Form) -> Result<Reply, Failure> {
+    let password = form.text("password")?.filter(|password| !password.is_empty());
+    let carrier = carrier::open(form.required("image")?).map_err(invalid)?;
+    let open = |data: &[u8]| -> Result<String> {
+        Ok(String::from_utf8(envelope::open(data, password)?)?)
+    };
+    // a payload asking for more KDF rounds than allowed is refused as a whole
+    let too_many_rounds = |error: &anyhow::Error| matches!(error.downcast_ref(), Some(EnvelopeError::TooManyRounds(_)));
+
+    if let Some(token) = form.text("token")?.filter(|token| !token.is_empty()) {
+        let names: Vec<String> = carrier.list().into_iter().map(|slot| slot.selector).collect();
+        let selector = Selector::new(token, false);
+        let chunk_type = match selector.is_exact() {
+            true => token.to_string(),
+            false => selector.find_one(&names).map_err(|error| Failure::new(404, error.to_string()))?,
+        };
+
+        let data = carrier.extract(&chunk_type).map_err(|error| Failure::new(404, error.to_string()))?;
+        let message = open(&data).map_err(invalid)?;
+        return Ok(json(200, serde_json::json!({ "chunk_type": chunk_type, "message": message })));
+    }
+
+    let mut messages = Vec::new();
+    for slot in carrier.list() {
+        let Ok(data) = carrier.extract(&slot.selector) else { continue };
+
+        match open(&data) {
+            Ok(message) => messages.push(serde_json::json!({ "chunk_type": slot.selector, "message": message })),
+            Err(error) if too_many_rounds(&error) => return Err(invalid(error)),
+            Err(_) => {}
+        }
+    }
+    if messages.is_empty() {
+        return Err(Failure::new(404, "No chunk holds a payload that opens with this password"));
+    }
+
+    Ok(json(200, serde_json::json!({ "messages": messages })))
+}
+
+fn scan(form: &Form, max_decoded: MemoryLimit) -> Result<Reply, Failure> {
+    let image = form.required("image")?;
+    if !image.starts_with(&Png::STANDARD_HEADER) {
+        return Err(Failure::new(415, "Only PNG images can be scanned"));
+    }
+
+    let png = Png::parse_with(image, ParseOptions::default()).map_err(invalid)?;
+    // an image whose size can not be worked out is not decoded by the scan either
+    if let Ok(size) = png.decoded_size() {
+        let too_large = format!("Scans decode at most {} bytes of image data", max_decoded.bytes);
+        max_decoded.check(size).map_err(|_| Failure::new(413, too_large))?;
+    }
+
+    let findings = scan::scan_png(&png);
+    let findings: Vec<serde_json::Value> = findings.iter().map(scan::Finding::to_json).collect();
+
+    Ok(json(200, serde_json::json!({ "flagged": !findings.is_empty(), "findings": findings })))
+}
+
+fn respond(
+    request: &mut Request,
+    max_upload: MemoryLimit,
+    max_decoded: MemoryLimit,
+    kdf_rounds: u32,
+) -> Result<Reply, Failure> {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    if !["/encode", "/decode", "/scan"].contains(&path.as_str()) {
+        return Err(Failure::new(404, format!("No endpoint at {}", path)));
+    }
+    if *request.method() != Method::Post {
+        return Err(Failure::new(405, format!("{} only answers POST requests", path)));
+    }
+
+    let form = Form::read(request, max_upload)?;
+    match path.as_str() {
+        "/encode" => encode(&form, kdf_rounds),
+        "/decode" => decode(&form),
+        _ => scan(&form, max_decoded),
+    }
+}
+
+fn handle(mut request: Request, max_upload: MemoryLimit, max_decoded: MemoryLimit, kdf_rounds: u32) {
+    let (method, url) = (request.method().clone(), request.url().to_string());
+
+    // the panic itself is reported on stderr by the panic hook
+    let answered = panic::catch_unwind(AssertUnwindSafe(|| respond(&mut request, max_upload, max_decoded, kdf_rounds)))
+        .unwrap_or_else(|_| Err(Failure::new(500, "The server failed while answering the request")));
+
+    let reply = match answered {
+        Ok(reply) => reply,
+        Err(failure) => {
+            warn!("{} {}: {}", method, url, failure.message);
+            json(failure.status, serde_json::json!({ "error": failure.message }))
+        }
+    };
+    info!("{} {} {}", method, url, reply.status_code().0);
+
+    if let Err(error) = request.respond(reply) {
+        warn!("Unable to answer {} {}: {}", method, url, error);
+    }
+}
+
+// Answers requests on `address` with `threads` of them at a time, until the
+// process is stopped.
+pub fn run(
+    address: &str,
+    threads: usize,
+    max_upload: MemoryLimit,
+    max_decoded: MemoryLimit,
+    kdf_rounds: u32,
+) -> Result<()> {
+    if threads == 0 {
+        bail!("The server needs at least one thread");
+    }
+
+    let server = Server::http(address).map_err(|error| anyhow!("Unable to listen on {}: {}", address, error))?;
+    let server = Arc::new(server);
+    info!("Listening on http://{}", server.server_addr());
+
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(request, max_upload, max_decoded, kdf_rounds);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().map_err(|_| anyhow!("A server thread panicked"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngcrypt::chunk::Chunk;
+    use pngcrypt::png::builder::PngBuilder;
+    use pngcrypt::png::pixels::Header;
+
+    fn field(name: &str, data: &[u8]) -> Part {
+        Part {
+            name: name.to_string(),
+            filename: None,
+            content_type: None,
+            data: data.to_vec(),
+        }
+    }
+
+    // an image holding a payload that asks for `u32::MAX` KDF rounds, and its token
+    fn hostile_image() -> (Vec<u8>, String) {
+        let mut payload = envelope::seal_with_rounds(b"Message", Some("hunter2"), None, 10).unwrap();
+        payload[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut carrier = carrier::open(&PngBuilder::new(2, 2).build().unwrap().as_bytes()).unwrap();
+        let token = carrier.embed(&payload, Position::BeforeIend).unwrap();
+
+        (carrier.to_bytes(), token)
+    }
+
+    #[test]
+    fn test_decode_refuses_too_many_rounds() {
+        let (image, token) = hostile_image();
+
+        let form = Form {
+            parts: vec![field("image", &image), field("token", token.as_bytes()), field("password", b"hunter2")],
+        };
+        assert_eq!(decode(&form).err().map(|failure| failure.status), Some(400));
+
+        let form = Form {
+            parts: vec![field("image", &image), field("password", b"hunter2")],
+        };
+        assert_eq!(decode(&form).err().map(|failure| failure.status), Some(400));
+    }
+
+    // a 2x2 image whose IHDR claims `width` x `height` pixels
+    fn claiming(width: u32, height: u32) -> Vec<u8> {
+        let png = PngBuilder::new(2, 2).build().unwrap();
+        let header = Header {
+            width,
+            height,
+            ..png.ihdr().unwrap()
+        };
+
+        let chunks = png
+            .chunks()
+            .map(|chunk| match chunk.chunk_type.bytes == *b"IHDR" {
+                true => Chunk::new(chunk.chunk_type, header.to_bytes().to_vec()),
+                false => chunk.clone(),
+            })
+            .collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_scan_limits_decoded_size() {
+        let limit = MemoryLimit { bytes: 1 << 20 };
+        let scanned = |image: Vec<u8>| {
+            let form = Form {
+                parts: vec![field("image", &image)],
+            };
+            scan(&form, limit).map(|_| 200).unwrap_or_else(|failure| failure.status)
+        };
+
+        assert_eq!(scanned(claiming(2, 2)), 200);
+        // 4096x4096 pixels inflate to 64 MiB, whatever the upload weighs
+        assert_eq!(scanned(claiming(4096, 4096)), 413);
+        // an impossible size is not decoded at all
+        assert_eq!(scanned(claiming(u32::MAX, u32::MAX)), 200);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let image = PngBuilder::new(2, 2).build().unwrap().as_bytes();
+        let form = Form {
+            parts: vec![field("image", &image), field("message", b"Message")],
+        };
+        let encoded = encode(&form, 10).ok().unwrap().into_reader().into_inner();
+
+        let form = Form {
+            parts: vec![field("image", &encoded)],
+        };
+        let decoded = decode(&form).ok().unwrap().into_reader().into_inner();
+        assert!(String::from_utf8(decoded).unwrap().contains("\"message\":\"Message\""));
+
+        let form = Form {
+            parts: vec![field("message", b"Message")],
+        };
+        assert_eq!(encode(&form, 10).err().map(|failure| failure.status), Some(400));
+    }
+}
@@ -0,0 +1,178 @@
+use super::envelope;
+use super::png::Png;
+
+// Sums up the chunks of an image, to judge how much an embedded payload
+// stands out: how many chunks of each type there are, how the bytes split
+// between critical and ancillary chunks and how much of the file PNGCrypt
+// payloads take up. Sizes include the 12 bytes of length, type and CRC around
+// the data of each chunk, unless they say otherwise.
+
+// how many of the largest chunks are listed
+const LARGEST: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeStats {
+    pub chunk_type: String,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSize {
+    pub offset: usize,
+    pub chunk_type: String,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub file_size: usize,
+    // in the order the types first appear
+    pub types: Vec<TypeStats>,
+    pub critical_bytes: usize,
+    pub ancillary_bytes: usize,
+    // compressed image data, without the chunk framing
+    pub idat_bytes: usize,
+    // ancillary chunks holding a PNGCrypt envelope
+    pub payload_chunks: usize,
+    pub payload_bytes: usize,
+    pub trailing_bytes: usize,
+    // data length, largest first
+    pub largest: Vec<ChunkSize>,
+}
+
+impl Stats {
+    // share of the file taken up by PNGCrypt payloads, in percent
+    pub fn payload_share(&self) -> f64 {
+        if self.file_size == 0 {
+            return 0.0;
+        }
+
+        self.payload_bytes as f64 * 100.0 / self.file_size as f64
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "file_size": self.file_size,
+            "types": self.types.iter().map(|types| serde_json::json!({
+                "chunk_type": types.chunk_type,
+                "count": types.count,
+                "bytes": types.bytes,
+            })).collect::<Vec<_>>(),
+            "critical_bytes": self.critical_bytes,
+            "ancillary_bytes": self.ancillary_bytes,
+            "idat_bytes": self.idat_bytes,
+            "payload_chunks": self.payload_chunks,
+            "payload_bytes": self.payload_bytes,
+            "payload_share": self.payload_share(),
+            "trailing_bytes": self.trailing_bytes,
+            "largest": self.largest.iter().map(|chunk| serde_json::json!({
+                "offset": chunk.offset,
+                "chunk_type": chunk.chunk_type,
+                "length": chunk.length,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+pub fn stats(png: &Png) -> Stats {
+    let mut stats = Stats {
+        file_size: png.trailing_offset() + png.trailing().len(),
+        types: Vec::new(),
+        critical_bytes: 0,
+        ancillary_bytes: 0,
+        idat_bytes: 0,
+        payload_chunks: 0,
+        payload_bytes: 0,
+        trailing_bytes: png.trailing().len(),
+        largest: Vec::new(),
+    };
+
+    for (chunk, offset) in png.chunks().zip(png.chunk_offsets()) {
+        let chunk_type = chunk.chunk_type.to_string();
+        let size = 12 + chunk.data().len();
+
+        match stats.types.iter_mut().find(|types| types.chunk_type == chunk_type) {
+            Some(types) => {
+                types.count += 1;
+                types.bytes += size;
+            }
+            None => stats.types.push(TypeStats {
+                chunk_type: chunk_type.clone(),
+                count: 1,
+                bytes: size,
+            }),
+        }
+
+        if chunk.chunk_type.is_critical() {
+            stats.critical_bytes += size;
+        } else {
+            stats.ancillary_bytes += size;
+
+            if envelope::is_envelope(chunk.data()) {
+                stats.payload_chunks += 1;
+                stats.payload_bytes += size;
+            }
+        }
+
+        if chunk.chunk_type.bytes == *b"IDAT" {
+            stats.idat_bytes += chunk.data().len();
+        }
+
+        stats.largest.push(ChunkSize {
+            offset,
+            chunk_type,
+            length: chunk.data().len(),
+        });
+    }
+
+    // stable, so chunks of the same size stay in file order
+    stats.largest.sort_by_key(|chunk| std::cmp::Reverse(chunk.length));
+    stats.largest.truncate(LARGEST);
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    #[test]
+    fn test_stats() {
+        let payload = envelope::seal(b"secret", None, None).unwrap();
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Title\0Dice".to_vec()),
+            chunk("IDAT", vec![1; 40]),
+            chunk("IDAT", vec![2; 20]),
+            chunk("ruSt", payload.clone()),
+            chunk("IEND", Vec::new()),
+        ]);
+
+        let stats = stats(&png);
+
+        assert_eq!(stats.file_size, png.as_bytes().len());
+        assert_eq!(
+            stats.types[2],
+            TypeStats {
+                chunk_type: "IDAT".to_string(),
+                count: 2,
+                bytes: 84,
+            }
+        );
+        assert_eq!(stats.critical_bytes, 25 + 84 + 12);
+        assert_eq!(stats.ancillary_bytes, 22 + 12 + payload.len());
+        assert_eq!(stats.idat_bytes, 60);
+        assert_eq!(stats.payload_chunks, 1);
+        assert_eq!(stats.payload_bytes, 12 + payload.len());
+        assert_eq!(stats.largest[0].length, 40);
+        assert_eq!(stats.largest.len(), 5);
+    }
+}
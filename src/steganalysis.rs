@@ -0,0 +1,358 @@
+use super::png::pixels::Pixels;
+
+// Statistical tests for LSB embedding in the pixel data.
+//
+// The chi-square attack (Westfeld and Pfitzmann) relies on embedding evening
+// out the counts of each pair of values 2k and 2k + 1, which differ in natural
+// images. RS analysis (Fridrich, Goljan and Du) measures how flipping low bits
+// changes the smoothness of small groups of samples, which embedding makes
+// asymmetric, and estimates from it the share of samples that carry a payload.
+//
+// Both work on the low byte of each sample, one channel at a time for RS.
+
+// above this chi-square probability the value pairs look evened out
+pub const CHI_SQUARE_THRESHOLD: f64 = 0.95;
+// above this estimated share of payload samples an image is reported as carrying a payload
+pub const RS_THRESHOLD: f64 = 0.05;
+
+const RS_GROUP: usize = 4;
+const RS_MASK: [i16; RS_GROUP] = [0, 1, 1, 0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Analysis {
+    // probability that the value pairs were evened out by embedding
+    pub chi_square: f64,
+    // estimated share of samples carrying a payload
+    pub rs: f64,
+}
+
+impl Analysis {
+    // Smooth images have evenly spread histograms of their own, so the
+    // chi-square test alone raises too many false alarms to decide on.
+    pub fn is_suspicious(&self) -> bool {
+        self.rs > RS_THRESHOLD
+    }
+}
+
+// the low byte of every sample, grouped by channel
+fn channel_samples(pixels: &Pixels) -> Vec<Vec<u8>> {
+    let channels = pixels.header.channels().unwrap_or(1);
+    let sample_size = std::cmp::max(1, pixels.header.bit_depth as usize / 8);
+
+    let samples: Vec<u8> = pixels
+        .data
+        .chunks_exact(sample_size)
+        .map(|sample| sample[sample_size - 1])
+        .collect();
+
+    (0..channels)
+        .map(|channel| samples.iter().skip(channel).step_by(channels).copied().collect())
+        .collect()
+}
+
+// natural logarithm of the gamma function, Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |acc, (i, c)| acc + c / (x + 1.0 + i as f64));
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// regularized lower incomplete gamma function P(a, x)
+fn gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let ln_prefix = -x + a * x.ln() - ln_gamma(a);
+
+    if x < a + 1.0 {
+        // series expansion
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut n = a;
+
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-12 {
+                break;
+            }
+        }
+
+        (sum.ln() + ln_prefix).exp().min(1.0)
+    } else {
+        // continued fraction for the upper function, Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-12 {
+                break;
+            }
+        }
+
+        1.0 - (ln_prefix.exp() * h)
+    }
+}
+
+// Probability that the samples had their lowest bits replaced by random data.
+// Close to 1 when every pair of values 2k, 2k + 1 occurs about equally often.
+pub fn chi_square(pixels: &Pixels) -> f64 {
+    let mut histogram = [0u64; 256];
+
+    for channel in channel_samples(pixels) {
+        for value in channel {
+            histogram[value as usize] += 1;
+        }
+    }
+
+    let mut statistic = 0.0;
+    let mut pairs = 0;
+
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+
+        // pairs that barely occur say nothing and would inflate the statistic
+        if expected < 5.0 {
+            continue;
+        }
+
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+
+    if pairs < 2 {
+        return 0.0;
+    }
+
+    1.0 - gamma_p((pairs - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+// how far apart neighbouring samples of a group are
+fn smoothness(group: &[i16]) -> i16 {
+    group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum()
+}
+
+// flipping of the lowest bit: 0 <-> 1, 2 <-> 3, ...
+fn flip(value: i16) -> i16 {
+    value ^ 1
+}
+
+// shifted flipping: -1 <-> 0, 1 <-> 2, ...
+fn shifted_flip(value: i16) -> i16 {
+    flip(value + 1) - 1
+}
+
+// share of regular and singular groups when `flipping` is applied following `mask`
+fn regular_singular(samples: &[i16], mask: &[i16; RS_GROUP], negative: bool) -> (f64, f64) {
+    let mut regular = 0;
+    let mut singular = 0;
+    let mut groups = 0;
+
+    for group in samples.chunks_exact(RS_GROUP) {
+        let flipped: Vec<i16> = group
+            .iter()
+            .zip(mask)
+            .map(|(&value, &m)| match (m, negative) {
+                (0, _) => value,
+                (_, false) => flip(value),
+                (_, true) => shifted_flip(value),
+            })
+            .collect();
+
+        let before = smoothness(group);
+        let after = smoothness(&flipped);
+
+        if after > before {
+            regular += 1;
+        } else if after < before {
+            singular += 1;
+        }
+        groups += 1;
+    }
+
+    if groups == 0 {
+        return (0.0, 0.0);
+    }
+
+    (regular as f64 / groups as f64, singular as f64 / groups as f64)
+}
+
+fn rs_channel(samples: &[u8]) -> Option<f64> {
+    let samples: Vec<i16> = samples.iter().map(|&value| value as i16).collect();
+    let inverted: Vec<i16> = samples.iter().map(|&value| flip(value)).collect();
+
+    let (r_m, s_m) = regular_singular(&samples, &RS_MASK, false);
+    let (r_neg, s_neg) = regular_singular(&samples, &RS_MASK, true);
+    let (r_m1, s_m1) = regular_singular(&inverted, &RS_MASK, false);
+    let (r_neg1, s_neg1) = regular_singular(&inverted, &RS_MASK, true);
+
+    let d0 = r_m - s_m;
+    let d1 = r_m1 - s_m1;
+    let d_neg0 = r_neg - s_neg;
+    let d_neg1 = r_neg1 - s_neg1;
+
+    // Flipping the lowest bits makes no difference at all while the shifted
+    // flipping still does: the lowest bits are pure noise. The estimate below
+    // breaks down there, as every sample carries a payload.
+    if d0.abs() < 0.02 && d1.abs() < 0.02 && d_neg0 > 0.05 {
+        return Some(1.0);
+    }
+
+    // 2 (d1 + d0) x^2 + (d-0 - d-1 - d1 - 3 d0) x + d0 - d-0 = 0
+    let a = 2.0 * (d1 + d0);
+    let b = d_neg0 - d_neg1 - d1 - 3.0 * d0;
+    let c = d0 - d_neg0;
+
+    let x = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let roots = [
+            (-b + discriminant.sqrt()) / (2.0 * a),
+            (-b - discriminant.sqrt()) / (2.0 * a),
+        ];
+        if roots[0].abs() < roots[1].abs() {
+            roots[0]
+        } else {
+            roots[1]
+        }
+    };
+
+    Some((x / (x - 0.5)).clamp(0.0, 1.0))
+}
+
+// Estimated share of samples whose lowest bit carries a payload, averaged over
+// the channels.
+pub fn rs(pixels: &Pixels) -> f64 {
+    let estimates: Vec<f64> = channel_samples(pixels)
+        .iter()
+        .filter_map(|samples| rs_channel(samples))
+        .collect();
+
+    if estimates.is_empty() {
+        return 0.0;
+    }
+
+    estimates.iter().sum::<f64>() / estimates.len() as f64
+}
+
+pub fn analyze(pixels: &Pixels) -> Analysis {
+    Analysis {
+        chi_square: chi_square(pixels),
+        rs: rs(pixels),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::pixels::Header;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    // smooth waves with some noise, standing in for a photograph
+    fn testing_pixels() -> Pixels {
+        let header = Header {
+            width: 128,
+            height: 128,
+            bit_depth: 8,
+            color_type: 0,
+            interlace: 0,
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+
+        let data = (0..128 * 128)
+            .map(|i| {
+                let (x, y) = ((i % 128) as f64, (i / 128) as f64);
+                let value = (x / 9.0).sin() * 60.0 + (y / 13.0).cos() * 50.0 + 128.0;
+                (value as i32 + rng.gen_range(-2..=2)).clamp(0, 255) as u8
+            })
+            .collect();
+
+        Pixels::new(header, data)
+    }
+
+    // replace the lowest bit of every `step`th sample with a random one
+    fn randomize_low_bits(pixels: &mut Pixels, step: usize) {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        for sample in pixels.data.iter_mut().step_by(step) {
+            *sample = (*sample & !1) | rng.gen_range(0..=1);
+        }
+    }
+    #[test]
+    fn test_gamma_p() {
+        // P(1, x) = 1 - e^-x
+        assert!((gamma_p(1.0, 2.0) - (1.0 - (-2.0f64).exp())).abs() < 1e-9);
+        assert!((gamma_p(3.0, 0.5) - 0.01438767796).abs() < 1e-9);
+        assert!((gamma_p(3.0, 10.0) - 0.99723060428).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_chi_square() {
+        // an image with only even values has nothing in common with embedding
+        let mut pixels = testing_pixels();
+        for sample in pixels.data.iter_mut() {
+            *sample &= !1;
+        }
+        assert!(chi_square(&pixels) < 0.05);
+
+        randomize_low_bits(&mut pixels, 1);
+        assert!(chi_square(&pixels) > CHI_SQUARE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_rs_clean_image() {
+        assert!(rs(&testing_pixels()) < RS_THRESHOLD);
+    }
+
+    #[test]
+    fn test_rs_estimates_payload() {
+        let mut half = testing_pixels();
+        randomize_low_bits(&mut half, 2);
+        assert!((rs(&half) - 0.5).abs() < 0.1);
+
+        let mut full = testing_pixels();
+        randomize_low_bits(&mut full, 1);
+        assert!(rs(&full) > 0.9);
+        assert!(analyze(&full).is_suspicious());
+    }
+}
@@ -0,0 +1,81 @@
+use super::envelope;
+use super::png::Png;
+
+use std::fmt;
+
+// Tells whether the PNGCrypt payload chunks of an image are likely to outlive
+// editing. The case of the fourth letter of a chunk type is its safe-to-copy
+// bit: editors that change the image data have to drop chunks with an
+// uppercase fourth letter, as those may depend on the data they changed, while
+// they may copy the others as they are.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadChunk {
+    pub chunk_type: String,
+    pub length: usize,
+    pub safe_to_copy: bool,
+}
+
+impl PayloadChunk {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "chunk_type": self.chunk_type,
+            "length": self.length,
+            "safe_to_copy": self.safe_to_copy,
+        })
+    }
+}
+
+impl fmt::Display for PayloadChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.safe_to_copy {
+            write!(f, "{} ({} bytes): safe to copy, editors may keep it", self.chunk_type, self.length)
+        } else {
+            write!(
+                f,
+                "{} ({} bytes): unsafe to copy, editors drop it when they change the image",
+                self.chunk_type, self.length
+            )
+        }
+    }
+}
+
+// the ancillary chunks holding a PNGCrypt envelope
+pub fn payload_chunks(png: &Png) -> Vec<PayloadChunk> {
+    png.chunks()
+        .filter(|chunk| !chunk.chunk_type.is_critical() && envelope::is_envelope(chunk.data()))
+        .map(|chunk| PayloadChunk {
+            chunk_type: chunk.chunk_type.to_string(),
+            length: chunk.data().len(),
+            safe_to_copy: chunk.chunk_type.is_safe_to_copy(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_payload_chunks() {
+        let payload = || envelope::seal(b"secret", None, None).unwrap();
+        let chunk = |chunk_type: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data);
+
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", vec![0; 13]),
+            chunk("tEXt", b"Title\0Dice".to_vec()),
+            chunk("abCd", payload()),
+            chunk("abCD", payload()),
+            chunk("IEND", Vec::new()),
+        ]);
+
+        let payloads = payload_chunks(&png);
+
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads[0].safe_to_copy);
+        assert!(!payloads[1].safe_to_copy);
+    }
+}
@@ -0,0 +1,383 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::png::{self, Position};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+
+use anyhow::Result;
+use rand::Rng;
+
+// A TIFF file is a header giving the byte order and the offset of the first
+// image file directory (IFD), followed by data anywhere in the file. Each IFD
+// is a count, 12 byte entries sorted by tag and the offset of the next IFD,
+// so multi-page documents are a chain of them. An entry holds a tag, a type,
+// a value count and the value itself when it fits in 4 bytes, or its offset.
+//
+// Payloads go in entries with a private tag from the reusable range, 65000 to
+// 65535, of type UNDEFINED. Their value is the identifier "PNGCrypt", the four
+// letter token decode asks for and the payload. An IFD can not grow where it
+// is, so the IFD getting the entry is rewritten at the end of the file and
+// the offset pointing to it updated; everything else stays where it was.
+
+const HEADERS: [&[u8]; 2] = [b"II*\0", b"MM\0*"];
+
+const IDENTIFIER: &[u8] = b"PNGCrypt";
+const PRIVATE_TAGS: RangeInclusive<u16> = 65000..=u16::MAX;
+const UNDEFINED: u16 = 7;
+const ENTRY_LENGTH: usize = 12;
+
+// more than any real document has, to stop on IFD chains that loop
+const MAX_IFDS: usize = 4096;
+
+#[derive(Debug)]
+pub enum TiffError {
+    InvalidHeader,
+    Truncated(usize),
+    TooManyIfds,
+}
+
+impl std::error::Error for TiffError {}
+
+impl fmt::Display for TiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiffError::InvalidHeader => write!(f, "The file does not start with a TIFF header, II*\\0 or MM\\0*"),
+            TiffError::Truncated(offset) => write!(f, "The TIFF file is cut off at offset {}", offset),
+            TiffError::TooManyIfds => write!(f, "The chain of image file directories does not end"),
+        }
+    }
+}
+
+// an image file directory and where the offset pointing to it is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ifd {
+    pub offset: usize,
+    pub pointer: usize,
+}
+
+struct Record {
+    ifd: Ifd,
+    tag: u16,
+    token: String,
+    value: Range<usize>,
+}
+
+pub struct Tiff {
+    bytes: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Tiff {
+    pub fn is_tiff(bytes: &[u8]) -> bool {
+        HEADERS.iter().any(|header| bytes.starts_with(header))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Tiff> {
+        if !Tiff::is_tiff(bytes) {
+            return Err(TiffError::InvalidHeader.into());
+        }
+
+        let tiff = Tiff {
+            bytes: bytes.to_vec(),
+            big_endian: bytes.starts_with(b"MM"),
+        };
+
+        for ifd in tiff.ifds()? {
+            tiff.entries(ifd)?;
+        }
+
+        Ok(tiff)
+    }
+
+    fn get(&self, offset: usize, length: usize) -> Result<&[u8]> {
+        Ok(self
+            .bytes
+            .get(offset..offset.saturating_add(length))
+            .ok_or(TiffError::Truncated(offset))?)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        let bytes = self.get(offset, 2)?.try_into().unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.get(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() }
+    }
+
+    fn u32_bytes(&self, value: usize) -> [u8; 4] {
+        if self.big_endian { (value as u32).to_be_bytes() } else { (value as u32).to_le_bytes() }
+    }
+
+    // the IFDs of the file, in the order of the chain
+    pub fn ifds(&self) -> Result<Vec<Ifd>> {
+        let mut ifds = Vec::new();
+        let mut pointer = 4;
+
+        loop {
+            let offset = self.u32(pointer)? as usize;
+            if offset == 0 {
+                return Ok(ifds);
+            }
+
+            if ifds.len() == MAX_IFDS {
+                return Err(TiffError::TooManyIfds.into());
+            }
+
+            ifds.push(Ifd { offset, pointer });
+            pointer = offset + 2 + self.u16(offset)? as usize * ENTRY_LENGTH;
+        }
+    }
+
+    // the raw 12 byte entries of an IFD
+    fn entries(&self, ifd: Ifd) -> Result<Vec<&[u8]>> {
+        let count = self.u16(ifd.offset)? as usize;
+        let entries = self.get(ifd.offset + 2, count * ENTRY_LENGTH + 4)?;
+
+        Ok(entries[..count * ENTRY_LENGTH].chunks(ENTRY_LENGTH).collect())
+    }
+
+    fn tag(&self, entry: &[u8]) -> u16 {
+        let bytes = entry[..2].try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    // the payload entries of every IFD
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+
+        for ifd in self.ifds().unwrap_or_default() {
+            for index in 0..self.entries(ifd).map(|entries| entries.len()).unwrap_or(0) {
+                let entry = ifd.offset + 2 + index * ENTRY_LENGTH;
+                if let Ok(Some(record)) = self.record(ifd, entry) {
+                    records.push(record);
+                }
+            }
+        }
+
+        records
+    }
+
+    fn record(&self, ifd: Ifd, entry: usize) -> Result<Option<Record>> {
+        let tag = self.u16(entry)?;
+        let count = self.u32(entry + 4)? as usize;
+
+        if !PRIVATE_TAGS.contains(&tag) || self.u16(entry + 2)? != UNDEFINED || count < IDENTIFIER.len() + 4 {
+            return Ok(None);
+        }
+
+        let start = self.u32(entry + 8)? as usize;
+        let value = self.get(start, count)?;
+        if !value.starts_with(IDENTIFIER) {
+            return Ok(None);
+        }
+
+        Ok(Some(Record {
+            ifd,
+            tag,
+            token: String::from_utf8_lossy(&value[IDENTIFIER.len()..IDENTIFIER.len() + 4]).into_owned(),
+            value: start..start + count,
+        }))
+    }
+
+    // values start on a word boundary
+    fn align(&mut self) {
+        if self.bytes.len() % 2 == 1 {
+            self.bytes.push(0);
+        }
+    }
+
+    fn find(&self, selector: &str) -> Result<Record> {
+        self.records()
+            .into_iter()
+            .find(|record| record.token == selector)
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Carrier for Tiff {
+    fn format(&self) -> &'static str {
+        "TIFF"
+    }
+
+    // offsets are u32
+    fn capacity(&self) -> usize {
+        (u32::MAX as usize).saturating_sub(self.bytes.len() + IDENTIFIER.len() + 4)
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let records = self.records();
+        let token = std::iter::repeat_with(|| png::new_chunk_type().to_string())
+            .find(|token| records.iter().all(|record| record.token != *token))
+            .unwrap();
+        self.embed_as(payload, &token, position)?;
+
+        Ok(token)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.records().iter().any(|record| record.token == token) {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let ifds = self.ifds()?;
+        let ifd = match position {
+            Position::AfterIhdr | Position::BeforeIdat => ifds.first(),
+            Position::BeforeIend => ifds.last(),
+            Position::Random => ifds.get(rand::thread_rng().gen_range(0..ifds.len().max(1))),
+        }
+        .copied()
+        .ok_or(TiffError::InvalidHeader)?;
+
+        let mut entries: Vec<Vec<u8>> = self.entries(ifd)?.into_iter().map(<[u8]>::to_vec).collect();
+        let next = self.u32(ifd.offset + 2 + entries.len() * ENTRY_LENGTH)? as usize;
+
+        let used: Vec<u16> = entries.iter().map(|entry| self.tag(entry)).collect();
+        let tag = std::iter::repeat_with(|| rand::thread_rng().gen_range(PRIVATE_TAGS))
+            .find(|tag| !used.contains(tag))
+            .unwrap();
+
+        self.align();
+        let value_offset = self.bytes.len();
+        self.bytes.extend(IDENTIFIER);
+        self.bytes.extend(token.as_bytes());
+        self.bytes.extend(payload);
+
+        let mut entry = self.u16_bytes(tag).to_vec();
+        entry.extend(self.u16_bytes(UNDEFINED));
+        entry.extend(self.u32_bytes(IDENTIFIER.len() + 4 + payload.len()));
+        entry.extend(self.u32_bytes(value_offset));
+        entries.push(entry);
+        entries.sort_by_key(|entry| self.tag(entry));
+
+        // the IFD with the new entry replaces the old one in the chain
+        self.align();
+        let ifd_offset = self.bytes.len();
+        let mut directory = self.u16_bytes(entries.len() as u16).to_vec();
+        directory.extend(entries.concat());
+        directory.extend(self.u32_bytes(next));
+        self.bytes.extend(directory);
+
+        let pointer = self.u32_bytes(ifd_offset);
+        self.bytes[ifd.pointer..ifd.pointer + 4].copy_from_slice(&pointer);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        let record = self.find(selector)?;
+        Ok(self.bytes[record.value.start + IDENTIFIER.len() + 4..record.value.end].to_vec())
+    }
+
+    // The entry is taken out of its IFD where it is, and the value cleared, or
+    // cut off when it ends the file.
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let payload = self.extract(selector)?;
+        let record = self.find(selector)?;
+
+        let entries: Vec<Vec<u8>> = self
+            .entries(record.ifd)?
+            .into_iter()
+            .filter(|entry| self.tag(entry) != record.tag)
+            .map(<[u8]>::to_vec)
+            .collect();
+        let next = self.u32(record.ifd.offset + 2 + (entries.len() + 1) * ENTRY_LENGTH)? as usize;
+
+        let mut directory = self.u16_bytes(entries.len() as u16).to_vec();
+        directory.extend(entries.concat());
+        directory.extend(self.u32_bytes(next));
+        directory.extend([0; ENTRY_LENGTH]);
+
+        let start = record.ifd.offset;
+        self.bytes[start..start + directory.len()].copy_from_slice(&directory);
+
+        if record.value.end == self.bytes.len() {
+            self.bytes.truncate(record.value.start);
+        } else {
+            self.bytes[record.value].fill(0);
+        }
+
+        Ok(payload)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.records()
+            .into_iter()
+            .map(|record| Slot {
+                selector: record.token,
+                length: record.value.len() - IDENTIFIER.len() - 4,
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a big endian header and two IFDs of one entry each, the image width
+    fn testing_tiff() -> Vec<u8> {
+        let mut bytes = b"MM\0*".to_vec();
+        bytes.extend(8u32.to_be_bytes());
+
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend([0x01, 0x00, 0, 3, 0, 0, 0, 1, 0, 16, 0, 0]);
+        bytes.extend(26u32.to_be_bytes());
+
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend([0x01, 0x00, 0, 3, 0, 0, 0, 1, 0, 32, 0, 0]);
+        bytes.extend(0u32.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_ifds() {
+        let tiff = Tiff::parse(&testing_tiff()).unwrap();
+
+        assert_eq!(
+            tiff.ifds().unwrap(),
+            vec![Ifd { offset: 8, pointer: 4 }, Ifd { offset: 26, pointer: 22 }]
+        );
+
+        assert!(Tiff::parse(&testing_tiff()[..30]).is_err());
+        assert!(Tiff::parse(b"BM").is_err());
+    }
+
+    #[test]
+    fn test_tiff_carrier() {
+        let mut tiff = Tiff::parse(&testing_tiff()).unwrap();
+
+        let first = tiff.embed(b"first page", Position::AfterIhdr).unwrap();
+        let last = tiff.embed(b"last page", Position::BeforeIend).unwrap();
+
+        let mut tiff = Tiff::parse(&tiff.to_bytes()).unwrap();
+        let ifds = tiff.ifds().unwrap();
+        assert_eq!(ifds.len(), 2);
+        assert_eq!(tiff.entries(ifds[0]).unwrap().len(), 2);
+        assert_eq!(tiff.u16(ifds[1].offset + 10).unwrap(), 32);
+        assert_eq!(tiff.list().len(), 2);
+        assert_eq!(tiff.extract(&first).unwrap(), b"first page");
+        assert_eq!(tiff.extract(&last).unwrap(), b"last page");
+
+        tiff.remove(&first).unwrap();
+        let tiff = Tiff::parse(&tiff.to_bytes()).unwrap();
+        assert_eq!(tiff.entries(tiff.ifds().unwrap()[0]).unwrap().len(), 1);
+        assert!(tiff.extract(&first).is_err());
+        assert_eq!(tiff.extract(&last).unwrap(), b"last page");
+    }
+}
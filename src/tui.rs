@@ -0,0 +1,256 @@
+use pngcrypt::chunk::Chunk;
+use pngcrypt::envelope;
+use pngcrypt::inspect;
+use pngcrypt::png::text::{InternationalText, Text};
+use pngcrypt::png::{Occurrence, Png};
+
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+// A terminal browser for the chunks of an image: the list of chunks on the
+// left, and the properties of the selected one on the right, with its text
+// for tEXt and iTXt chunks, a hex view of the start of its data and, once
+// decoded, the message it holds. Chunks can be removed, extracted to a file
+// and decoded from there; removals only reach the file when it is written.
+
+// the bytes of a chunk the hex view shows
+const PREVIEW_SIZE: usize = 512;
+
+const HELP: &str = "↑↓ move  d decode  e extract  x remove  w write  q quit";
+
+// What the browser does to files, which the command running it provides:
+// `extract` writes the data of the chunk at an index somewhere and `save`
+// writes the image, each returning what it did.
+pub struct Actions<'a> {
+    pub extract: &'a mut dyn FnMut(usize, &Chunk) -> Result<String>,
+    pub save: &'a mut dyn FnMut(&Png) -> Result<String>,
+}
+
+struct Browser<'a> {
+    png: &'a mut Png,
+    password: Option<String>,
+    list: ListState,
+    // the message of the selected chunk, or why it could not be decoded
+    decoded: Option<String>,
+    status: String,
+    // chunks were removed since the image was last written
+    changed: bool,
+    // a key to press again to confirm what the first press asked
+    pending: Option<char>,
+}
+
+impl Browser<'_> {
+    fn selected(&self) -> Option<(usize, &Chunk)> {
+        let index = self.list.selected()?;
+        self.png.chunk(index).map(|chunk| (index, chunk))
+    }
+
+    fn select(&mut self, index: usize) {
+        let last = self.png.chunks().len().saturating_sub(1);
+        self.list.select(Some(index.min(last)));
+        self.decoded = None;
+    }
+
+    fn decode(&mut self) {
+        let Some((_, chunk)) = self.selected() else { return };
+
+        let decoded = match envelope::open(chunk.data(), self.password.as_deref()) {
+            Ok(message) => String::from_utf8_lossy(&message).into_owned(),
+            Err(error) if envelope::is_envelope(chunk.data()) => format!("Can not decode: {}", error),
+            Err(_) => "No PNGCrypt payload in this chunk".to_string(),
+        };
+        self.decoded = Some(decoded);
+    }
+
+    fn remove(&mut self) {
+        let Some((index, chunk)) = self.selected() else { return };
+        let chunk_type = chunk.chunk_type.to_string();
+
+        // the image does not open without its critical chunks
+        if chunk.chunk_type.is_critical() && self.pending != Some('x') {
+            self.status = format!("{} is a critical chunk, press x again to remove it anyway", chunk_type);
+            self.pending = Some('x');
+            return;
+        }
+
+        if self.png.remove_chunks(&chunk_type, Occurrence::At(index)).is_ok() {
+            self.status = format!("Removed the {} chunk, press w to write the image", chunk_type);
+            self.changed = true;
+            self.select(index);
+        }
+    }
+
+    // handles a key, telling whether to quit
+    fn handle(&mut self, key: KeyCode, actions: &mut Actions) -> bool {
+        let pending = self.pending.take();
+        let index = self.list.selected().unwrap_or(0);
+
+        match key {
+            KeyCode::Up | KeyCode::Char('k') => self.select(index.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(index + 1),
+            KeyCode::Home => self.select(0),
+            KeyCode::End => self.select(usize::MAX),
+            KeyCode::Char('d') | KeyCode::Enter => self.decode(),
+            KeyCode::Char('x') | KeyCode::Delete => {
+                self.pending = pending;
+                self.remove();
+            }
+            KeyCode::Char('e') => {
+                if let Some((index, chunk)) = self.selected() {
+                    self.status = (actions.extract)(index, chunk).unwrap_or_else(|error| format!("{:#}", error));
+                }
+            }
+            KeyCode::Char('w') => match (actions.save)(self.png) {
+                Ok(status) => {
+                    self.status = status;
+                    self.changed = false;
+                }
+                Err(error) => self.status = format!("{:#}", error),
+            },
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if !self.changed || pending == Some('q') {
+                    return true;
+                }
+
+                self.status = "Removed chunks are not written yet, press q again to quit without them".to_string();
+                self.pending = Some('q');
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn details(&self) -> Vec<Line<'static>> {
+        let Some((index, chunk)) = self.selected() else {
+            return vec![Line::from("No chunks left")];
+        };
+        let chunk_type = chunk.chunk_type;
+        let flag = |set: bool, yes: &str, no: &str| if set { yes.to_string() } else { no.to_string() };
+
+        let mut lines = vec![
+            Line::from(format!("Chunk {} of {}", index, self.png.chunks().len())),
+            Line::from(format!("Type:        {}", chunk_type)),
+            Line::from(format!("Offset:      {}", self.png.chunk_offsets()[index])),
+            Line::from(format!("Length:      {} bytes", chunk.data().len())),
+            Line::from(format!("CRC:         {:#010x}", chunk.crc)),
+            Line::from(format!(
+                "Properties:  {}, {}, {}",
+                flag(chunk_type.is_critical(), "critical", "ancillary"),
+                flag(chunk_type.is_public(), "public", "private"),
+                flag(chunk_type.is_safe_to_copy(), "safe to copy", "unsafe to copy")
+            )),
+            Line::from(format!("Holds:       {}", chunk_type.description().unwrap_or("non-standard data"))),
+            Line::from(format!(
+                "Content:     {}, entropy {:.2} bits per byte",
+                inspect::classify(chunk.data()),
+                inspect::entropy(chunk.data())
+            )),
+        ];
+
+        if envelope::is_envelope(chunk.data()) {
+            let encryption = flag(envelope::is_encrypted(chunk.data()), "encrypted", "plain");
+            lines.push(Line::from(format!("Payload:     PNGCrypt envelope, {}", encryption)));
+        }
+
+        let text = match &chunk_type.bytes {
+            b"tEXt" => Text::from_chunk(chunk).ok().map(|text| text.to_string()),
+            b"iTXt" => InternationalText::from_chunk(chunk).ok().map(|text| text.to_string()),
+            _ => None,
+        };
+        if let Some(text) = text {
+            lines.push(Line::from(""));
+            lines.push(Line::from(format!("Text: {}", text)));
+        }
+
+        if let Some(decoded) = &self.decoded {
+            lines.push(Line::from(""));
+            lines.push(Line::styled(format!("Message: {}", decoded), Style::default().fg(Color::Green)));
+        }
+
+        let preview = &chunk.data()[..chunk.data().len().min(PREVIEW_SIZE)];
+        if !preview.is_empty() {
+            lines.push(Line::from(""));
+            lines.extend(inspect::hexdump(preview).lines().map(|line| Line::from(line.to_string())));
+        }
+
+        lines
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(1), Constraint::Length(1)])
+            .split(frame.area());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(24), Constraint::Min(40)])
+            .split(rows[0]);
+
+        let items: Vec<ListItem> = self
+            .png
+            .chunks()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let style = if envelope::is_envelope(chunk.data()) {
+                    Style::default().fg(Color::Yellow)
+                } else if chunk.chunk_type.is_critical() {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                };
+
+                ListItem::new(format!("{:>4} {} {:>8}", index, chunk.chunk_type, chunk.data().len())).style(style)
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Chunks"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, columns[0], &mut self.list);
+
+        let details = Paragraph::new(self.details())
+            .block(Block::default().borders(Borders::ALL).title("Details"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(details, columns[1]);
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), rows[1]);
+        frame.render_widget(Paragraph::new(HELP).style(Style::default().fg(Color::DarkGray)), rows[2]);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, actions: &mut Actions) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && self.handle(key.code, actions) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// Runs the browser on `png` until it is quit. Decoding opens payloads with
+// `password`.
+pub fn browse(png: &mut Png, password: Option<String>, mut actions: Actions) -> Result<()> {
+    let mut browser = Browser {
+        png,
+        password,
+        list: ListState::default().with_selected(Some(0)),
+        decoded: None,
+        status: String::new(),
+        changed: false,
+        pending: None,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = browser.run(&mut terminal, &mut actions);
+    ratatui::restore();
+
+    result
+}
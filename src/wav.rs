@@ -0,0 +1,362 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::chunk_type::ChunkType;
+use super::envelope;
+use super::lsb::{self, Seed};
+use super::png::{self, Position};
+use super::riff::{Riff, RiffChunk};
+
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Result;
+use rand::Rng;
+
+// A WAV file is a RIFF file of form type "WAVE": a "fmt " chunk describing the
+// samples, a "data" chunk holding them and optional chunks such as "LIST"
+// with the title and artist. Players skip the chunks they do not know.
+//
+// Payloads go either in a chunk of their own whose four character code is the
+// token, like the chunk types of PNG payloads, or in the lowest bit of the PCM
+// samples. In the latter case the first 32 samples hold the payload length,
+// big endian, and the payload follows one bit per sample, most significant bit
+// first. With a password the samples are visited in an order derived from it,
+// as in the pixel mode of PNG images.
+
+pub const FORM: &[u8; 4] = b"WAVE";
+
+const PCM: u16 = 1;
+const EXTENSIBLE: u16 = 0xfffe;
+const LENGTH_BITS: usize = 32;
+
+#[derive(Debug)]
+pub enum WavError {
+    MissingChunk(&'static str),
+    NotPcm(u16),
+    PayloadTooLarge { needed: usize, available: usize },
+    NoPayload,
+}
+
+impl std::error::Error for WavError {}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::MissingChunk(id) => write!(f, "The WAV file has no {:?} chunk", id),
+            WavError::NotPcm(format) => write!(f, "Only PCM samples can hide data, the file has format {:#06x}", format),
+            WavError::PayloadTooLarge { needed, available } => write!(
+                f,
+                "Payload too large: {} bytes needed but the samples can only hide {} bytes",
+                needed, available
+            ),
+            WavError::NoPayload => write!(f, "No hidden payload found in the samples"),
+        }
+    }
+}
+
+// the sample format the "fmt " chunk gives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+impl Format {
+    pub fn is_pcm(&self) -> bool {
+        self.format == PCM || self.format == EXTENSIBLE
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        (self.bits_per_sample as usize).div_ceil(8).max(1)
+    }
+}
+
+pub struct Wav {
+    riff: Riff,
+}
+
+impl Wav {
+    pub fn parse(bytes: &[u8]) -> Result<Wav> {
+        let riff = Riff::parse(bytes)?;
+
+        if &riff.form != FORM {
+            return Err(CarrierError::UnknownFormat.into());
+        }
+
+        let wav = Wav { riff };
+        wav.format()?;
+        wav.samples()?;
+
+        Ok(wav)
+    }
+
+    pub fn chunks(&self) -> &[RiffChunk] {
+        &self.riff.chunks
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.riff.as_bytes()
+    }
+
+    pub fn format(&self) -> Result<Format> {
+        let data = &self.riff.chunk(b"fmt ").ok_or(WavError::MissingChunk("fmt "))?.data;
+        let field = |offset: usize| data.get(offset..offset + 2).ok_or(WavError::MissingChunk("fmt "));
+
+        Ok(Format {
+            format: u16::from_le_bytes(field(0)?.try_into().unwrap()),
+            channels: u16::from_le_bytes(field(2)?.try_into().unwrap()),
+            sample_rate: u32::from_le_bytes(data.get(4..8).ok_or(WavError::MissingChunk("fmt "))?.try_into().unwrap()),
+            bits_per_sample: u16::from_le_bytes(field(14)?.try_into().unwrap()),
+        })
+    }
+
+    fn data_index(&self) -> Result<usize> {
+        Ok(self
+            .riff
+            .chunks
+            .iter()
+            .position(|chunk| &chunk.id == b"data")
+            .ok_or(WavError::MissingChunk("data"))?)
+    }
+
+    pub fn samples(&self) -> Result<&[u8]> {
+        Ok(&self.riff.chunks[self.data_index()?].data)
+    }
+
+    pub fn sample_count(&self) -> Result<usize> {
+        Ok(self.samples()?.len() / self.format()?.bytes_per_sample())
+    }
+
+    // number of payload bytes the lowest bits of the samples can hide
+    pub fn lsb_capacity(&self) -> Result<usize> {
+        Ok(self.sample_count()?.saturating_sub(LENGTH_BITS) / 8)
+    }
+
+    // indices into the sample data of the bytes whose lowest bit carries the
+    // payload: the first, least significant, byte of every sample
+    fn carrier_indices(&self, seed: Option<&Seed>) -> Result<Vec<usize>> {
+        let format = self.format()?;
+        if !format.is_pcm() {
+            return Err(WavError::NotPcm(format.format).into());
+        }
+
+        let size = format.bytes_per_sample();
+        let mut indices: Vec<usize> = (0..self.sample_count()?).map(|sample| sample * size).collect();
+
+        if let Some(seed) = seed {
+            lsb::shuffle(&mut indices, seed);
+        }
+
+        Ok(indices)
+    }
+
+    pub fn embed_lsb(&mut self, payload: &[u8], seed: Option<&Seed>) -> Result<()> {
+        let available = self.lsb_capacity()?;
+        if payload.len() > available {
+            return Err(WavError::PayloadTooLarge {
+                needed: payload.len(),
+                available,
+            }
+            .into());
+        }
+
+        let indices = self.carrier_indices(seed)?;
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(payload);
+
+        let index = self.data_index()?;
+        let samples = &mut self.riff.chunks[index].data;
+        for (bit, &sample) in indices.iter().enumerate().take(bytes.len() * 8) {
+            let value = (bytes[bit / 8] >> (7 - bit % 8)) & 1;
+            samples[sample] = (samples[sample] & !1) | value;
+        }
+
+        Ok(())
+    }
+
+    pub fn extract_lsb(&self, seed: Option<&Seed>) -> Result<Vec<u8>> {
+        let indices = self.carrier_indices(seed)?;
+        let samples = self.samples()?;
+
+        let read = |from: usize, len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|byte| {
+                    (0..8).fold(0, |value, bit| value << 1 | (samples[indices[from + byte * 8 + bit]] & 1))
+                })
+                .collect()
+        };
+
+        if indices.len() < LENGTH_BITS {
+            return Err(WavError::NoPayload.into());
+        }
+
+        let length = u32::from_be_bytes(read(0, 4).try_into().unwrap()) as usize;
+        if length > self.lsb_capacity()? {
+            return Err(WavError::NoPayload.into());
+        }
+
+        Ok(read(LENGTH_BITS, length))
+    }
+}
+
+impl Carrier for Wav {
+    fn format(&self) -> &'static str {
+        "WAV"
+    }
+
+    fn capacity(&self) -> usize {
+        // the chunk length and the RIFF size are both u32
+        u32::MAX as usize - self.as_bytes().len()
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let id = std::iter::repeat_with(png::new_chunk_type)
+            .map(|chunk_type| chunk_type.to_string())
+            .find(|id| self.riff.chunk(id.as_bytes()).is_none())
+            .unwrap();
+        self.embed_as(payload, &id, position)?;
+
+        Ok(id)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.riff.chunk(token.as_bytes()).is_some() {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let chunks = &self.riff.chunks;
+        let first = chunks.iter().position(|chunk| &chunk.id == b"fmt ").map(|index| index + 1).unwrap_or(0);
+        let index = match position {
+            Position::AfterIhdr => first,
+            Position::BeforeIdat => self.data_index()?,
+            Position::BeforeIend => chunks.len(),
+            Position::Random => rand::thread_rng().gen_range(first..=chunks.len()),
+        };
+
+        let chunk = RiffChunk {
+            id: ChunkType::from_str(token)?.bytes,
+            data: payload.to_vec(),
+        };
+        self.riff.chunks.insert(index, chunk);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        let chunk = self
+            .riff
+            .chunk(selector.as_bytes())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        Ok(chunk.data.clone())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let index = self
+            .riff
+            .chunks
+            .iter()
+            .position(|chunk| chunk.id == selector.as_bytes())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        Ok(self.riff.chunks.remove(index).data)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.riff
+            .chunks
+            .iter()
+            .filter(|chunk| envelope::is_envelope(&chunk.data))
+            .map(|chunk| Slot {
+                selector: chunk.id(),
+                length: chunk.data.len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.riff.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one second of 16 bit mono PCM at 1000 Hz, a ramp
+    fn testing_wav() -> Vec<u8> {
+        let mut format = PCM.to_le_bytes().to_vec();
+        format.extend(1u16.to_le_bytes());
+        format.extend(1000u32.to_le_bytes());
+        format.extend(2000u32.to_le_bytes());
+        format.extend(2u16.to_le_bytes());
+        format.extend(16u16.to_le_bytes());
+
+        let samples = (0..1000i16).flat_map(|sample| (sample * 30).to_le_bytes()).collect();
+
+        Riff {
+            form: *FORM,
+            chunks: vec![
+                RiffChunk {
+                    id: *b"fmt ",
+                    data: format,
+                },
+                RiffChunk {
+                    id: *b"data",
+                    data: samples,
+                },
+            ],
+            trailing: Vec::new(),
+        }
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_wav_format() {
+        let wav = Wav::parse(&testing_wav()).unwrap();
+        let format = wav.format().unwrap();
+
+        assert_eq!(format.sample_rate, 1000);
+        assert_eq!(format.bits_per_sample, 16);
+        assert_eq!(wav.sample_count().unwrap(), 1000);
+        assert_eq!(wav.lsb_capacity().unwrap(), 121);
+    }
+
+    #[test]
+    fn test_wav_lsb() {
+        let mut wav = Wav::parse(&testing_wav()).unwrap();
+        let seed = lsb::seed_from_password("hunter2");
+
+        wav.embed_lsb(b"hidden in the noise", Some(&seed)).unwrap();
+
+        let wav = Wav::parse(&wav.as_bytes()).unwrap();
+        assert_eq!(wav.as_bytes().len(), testing_wav().len());
+        assert_eq!(wav.extract_lsb(Some(&seed)).unwrap(), b"hidden in the noise");
+        assert!(wav.extract_lsb(None).map(|payload| payload != b"hidden in the noise").unwrap_or(true));
+
+        let mut wav = Wav::parse(&testing_wav()).unwrap();
+        assert!(wav.embed_lsb(&[0; 122], None).is_err());
+    }
+
+    #[test]
+    fn test_wav_carrier() {
+        let mut wav = Wav::parse(&testing_wav()).unwrap();
+        let payload = envelope::seal(b"secret", None, None).unwrap();
+
+        let token = wav.embed(&payload, Position::BeforeIdat).unwrap();
+
+        let mut wav = Wav::parse(&wav.as_bytes()).unwrap();
+        assert_eq!(wav.chunks()[1].id(), token);
+        assert_eq!(wav.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+
+        assert_eq!(wav.remove(&token).unwrap(), payload);
+        assert_eq!(wav.as_bytes(), testing_wav());
+    }
+}
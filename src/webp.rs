@@ -0,0 +1,209 @@
+use super::carrier::{self, Carrier, CarrierError, Slot};
+use super::chunk_type::ChunkType;
+use super::envelope;
+use super::png::{self, Position};
+use super::riff::{Riff, RiffChunk};
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use rand::Rng;
+
+// A WebP file is a RIFF file of form type "WEBP". Simple files hold a single
+// "VP8 " (lossy) or "VP8L" (lossless) chunk, extended files start with a
+// "VP8X" chunk and may add color profiles, animation frames and metadata.
+//
+// Payloads go in a chunk of their own whose four character code is the token
+// decode asks for, like the chunk types of PNG payloads. Decoders skip chunks
+// they do not know, but only look for them after the image in simple files,
+// so that is where payloads go there whatever the position.
+
+pub const FORM: &[u8; 4] = b"WEBP";
+
+// the chunks of an extended file that have to come first, in this order
+const LEADING_CHUNKS: [&[u8; 4]; 3] = [b"VP8X", b"ICCP", b"ANIM"];
+const IMAGE_CHUNKS: [&[u8; 4]; 4] = [b"ALPH", b"VP8 ", b"VP8L", b"ANMF"];
+
+pub struct WebP {
+    riff: Riff,
+}
+
+impl WebP {
+    pub fn parse(bytes: &[u8]) -> Result<WebP> {
+        let riff = Riff::parse(bytes)?;
+
+        if &riff.form != FORM {
+            return Err(CarrierError::UnknownFormat.into());
+        }
+
+        Ok(WebP { riff })
+    }
+
+    pub fn chunks(&self) -> &[RiffChunk] {
+        &self.riff.chunks
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.riff.as_bytes()
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.riff.chunks.first().map(|chunk| &chunk.id == b"VP8X").unwrap_or(false)
+    }
+
+    fn is_image(chunk: &RiffChunk) -> bool {
+        IMAGE_CHUNKS.contains(&&chunk.id)
+    }
+
+    // the first index a payload can go at
+    fn first_index(&self) -> usize {
+        let chunks = &self.riff.chunks;
+
+        if self.is_extended() {
+            chunks.iter().take_while(|chunk| LEADING_CHUNKS.contains(&&chunk.id)).count()
+        } else {
+            chunks.iter().position(WebP::is_image).map(|index| index + 1).unwrap_or(chunks.len())
+        }
+    }
+}
+
+impl Carrier for WebP {
+    fn format(&self) -> &'static str {
+        "WebP"
+    }
+
+    fn capacity(&self) -> usize {
+        // the chunk length and the RIFF size are both u32
+        u32::MAX as usize - self.as_bytes().len()
+    }
+
+    fn embed(&mut self, payload: &[u8], position: Position) -> Result<String> {
+        let id = std::iter::repeat_with(png::new_chunk_type)
+            .map(|chunk_type| chunk_type.to_string())
+            .find(|id| self.riff.chunk(id.as_bytes()).is_none())
+            .unwrap();
+        self.embed_as(payload, &id, position)?;
+
+        Ok(id)
+    }
+
+    fn embed_as(&mut self, payload: &[u8], token: &str, position: Position) -> Result<()> {
+        carrier::check_selector(token)?;
+        if self.riff.chunk(token.as_bytes()).is_some() {
+            return Err(CarrierError::SelectorTaken(token.to_string()).into());
+        }
+
+        let chunks = &self.riff.chunks;
+        let first = self.first_index();
+        let index = match position {
+            Position::AfterIhdr => first,
+            Position::BeforeIdat => chunks.iter().position(WebP::is_image).unwrap_or(chunks.len()).max(first),
+            Position::BeforeIend => chunks.len(),
+            Position::Random => rand::thread_rng().gen_range(first..=chunks.len()),
+        };
+
+        let chunk = RiffChunk {
+            id: ChunkType::from_str(token)?.bytes,
+            data: payload.to_vec(),
+        };
+        self.riff.chunks.insert(index, chunk);
+
+        Ok(())
+    }
+
+    fn extract(&self, selector: &str) -> Result<Vec<u8>> {
+        let chunk = self
+            .riff
+            .chunk(selector.as_bytes())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        Ok(chunk.data.clone())
+    }
+
+    fn remove(&mut self, selector: &str) -> Result<Vec<u8>> {
+        let index = self
+            .riff
+            .chunks
+            .iter()
+            .position(|chunk| chunk.id == selector.as_bytes())
+            .ok_or_else(|| CarrierError::NotFound(selector.to_string()))?;
+
+        Ok(self.riff.chunks.remove(index).data)
+    }
+
+    fn list(&self) -> Vec<Slot> {
+        self.riff
+            .chunks
+            .iter()
+            .filter(|chunk| envelope::is_envelope(&chunk.data))
+            .map(|chunk| Slot {
+                selector: chunk.id(),
+                length: chunk.data.len(),
+            })
+            .collect()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    fn strip_trailing(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.riff.trailing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> RiffChunk {
+        RiffChunk {
+            id: *id,
+            data: data.to_vec(),
+        }
+    }
+
+    fn testing_webp(chunks: Vec<RiffChunk>) -> Vec<u8> {
+        Riff {
+            form: *FORM,
+            chunks,
+            trailing: Vec::new(),
+        }
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_simple_webp_carrier() {
+        let bytes = testing_webp(vec![chunk(b"VP8L", b"lossless")]);
+        let mut webp = WebP::parse(&bytes).unwrap();
+        let payload = envelope::seal(b"secret", None, None).unwrap();
+
+        // payloads only go after the image in simple files
+        let token = webp.embed(&payload, Position::AfterIhdr).unwrap();
+
+        let mut webp = WebP::parse(&webp.as_bytes()).unwrap();
+        assert_eq!(&webp.chunks()[0].id, b"VP8L");
+        assert_eq!(webp.chunks()[1].id(), token);
+        assert_eq!(webp.list(), vec![Slot { selector: token.clone(), length: payload.len() }]);
+        assert_eq!(webp.extract(&token).unwrap(), payload);
+
+        assert_eq!(webp.remove(&token).unwrap(), payload);
+        assert_eq!(webp.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_extended_webp_carrier() {
+        let bytes = testing_webp(vec![chunk(b"VP8X", &[0; 10]), chunk(b"ICCP", b"profile"), chunk(b"VP8 ", b"lossy")]);
+        let mut webp = WebP::parse(&bytes).unwrap();
+
+        webp.embed(b"first", Position::AfterIhdr).unwrap();
+        webp.embed(b"second", Position::BeforeIend).unwrap();
+
+        let ids: Vec<String> = webp.chunks().iter().map(RiffChunk::id).collect();
+        assert_eq!(&ids[..2], ["VP8X", "ICCP"]);
+        assert_eq!(ids[3], "VP8 ");
+        assert_eq!(webp.chunks()[4].data, b"second");
+
+        assert!(WebP::parse(&testing_webp(Vec::new())[..10]).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use super::args::{Args, Command};
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, ErrorKind};
+use dialoguer::{Completion, Confirm, Input, Password, Select};
+
+// Asks for what a command needs when it is not on the command line: run
+// without a subcommand, pngcrypt walks through encoding, decoding or removing
+// a message, and a command missing its message or chunk type asks for it.
+// Questions are only asked in a terminal; elsewhere, such as in scripts, the
+// missing argument is an error as before.
+
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+// Completes the path typed so far with Tab: to the name of the one entry of
+// its directory starting with it, or to as much as all those entries share.
+struct PathCompletion;
+
+impl Completion for PathCompletion {
+    fn get(&self, input: &str) -> Option<String> {
+        let (directory, start) = match input.rfind('/') {
+            Some(slash) => (&input[..=slash], &input[slash + 1..]),
+            None => ("", input),
+        };
+
+        let names: Vec<String> = fs::read_dir(if directory.is_empty() { "." } else { directory })
+            .ok()?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let mut name = entry.file_name().into_string().ok()?;
+                if entry.path().is_dir() {
+                    name.push('/');
+                }
+                Some(name)
+            })
+            .filter(|name| name.starts_with(start) && (!name.starts_with('.') || start.starts_with('.')))
+            .collect();
+
+        let first = names.first()?;
+        let shared = names.iter().fold(first.len(), |shared, name| {
+            first
+                .char_indices()
+                .zip(name.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((index, a), _)| index + a.len_utf8())
+                .min(shared)
+        });
+
+        Some(format!("{}{}", directory, &first[..shared]))
+    }
+}
+
+fn ask_path(prompt: &str) -> Result<PathBuf> {
+    let path: String = Input::new()
+        .with_prompt(prompt)
+        .completion_with(&PathCompletion)
+        .validate_with(|path: &String| match Path::new(path).is_file() {
+            true => Ok(()),
+            false => Err("No file there"),
+        })
+        .interact_text()?;
+
+    Ok(PathBuf::from(path))
+}
+
+fn ask_password(prompt: &str) -> Result<Option<String>> {
+    let password = Password::new().with_prompt(prompt).allow_empty_password(true).interact()?;
+
+    Ok(Some(password).filter(|password| !password.is_empty()))
+}
+
+// the message to hide, typed without being shown when asked to
+pub fn message() -> Result<String> {
+    if !is_interactive() {
+        bail!("A message is required, pass it with -m");
+    }
+
+    let hidden = Confirm::new()
+        .with_prompt("Hide the message while typing it?")
+        .default(false)
+        .interact()?;
+
+    let message = if hidden {
+        Password::new().with_prompt("Message").interact()?
+    } else {
+        Input::new().with_prompt("Message").interact_text()?
+    };
+
+    Ok(message)
+}
+
+// the chunk type, or token, a message was stored under
+pub fn chunk_type() -> Result<String> {
+    if !is_interactive() {
+        bail!("A chunk type is required, pass the token with -c or use --auto");
+    }
+
+    Ok(Input::new().with_prompt("Token printed by encode").interact_text()?)
+}
+
+// Walks through an operation and returns the command it comes down to, as it
+// would have been typed. Nothing is written before it is confirmed.
+pub fn run() -> Result<Command> {
+    if !is_interactive() {
+        Args::command()
+            .error(ErrorKind::MissingSubcommand, "A subcommand is required outside of a terminal")
+            .exit();
+    }
+
+    let operations = ["Encode a message", "Decode a message", "Remove a message"];
+    let operation = Select::new()
+        .with_prompt("What do you want to do?")
+        .items(&operations)
+        .default(0)
+        .interact()?;
+
+    let path = ask_path("Image")?;
+    let mut arguments: Vec<OsString> = vec!["pngcrypt".into()];
+
+    match operation {
+        0 => {
+            let message = message()?;
+            let password = ask_password("Password to encrypt it with (leave empty for none)")?;
+            let output: String = Input::new()
+                .with_prompt("Write the image to (leave empty for the default name)")
+                .allow_empty(true)
+                .interact_text()?;
+
+            arguments.extend(["encode".into(), path.clone().into(), "--message".into(), message.into()]);
+            if let Some(password) = password {
+                arguments.extend(["--password".into(), password.into()]);
+            }
+            if !output.is_empty() {
+                arguments.extend(["--output-file".into(), output.into()]);
+            }
+        }
+        _ => {
+            let chunk_type = chunk_type()?;
+            let password = ask_password("Password it was encrypted with (leave empty for none)")?;
+
+            let subcommand = if operation == 1 { "decode" } else { "remove" };
+            arguments.extend([subcommand.into(), path.clone().into(), "--chunk-type".into(), chunk_type.into()]);
+            if let Some(password) = password {
+                arguments.extend(["--password".into(), password.into()]);
+            }
+        }
+    }
+
+    // decoding only reads the image
+    if operation != 1 {
+        let question = format!(
+            "{} {} {}?",
+            operations[operation],
+            if operation == 0 { "into" } else { "from" },
+            path.display()
+        );
+        let confirmed = Confirm::new().with_prompt(question).default(true).interact()?;
+        if !confirmed {
+            bail!("Nothing was written");
+        }
+    }
+
+    Args::try_parse_configured(arguments)?.command.context("No operation was chosen")
+}
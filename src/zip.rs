@@ -0,0 +1,192 @@
+use super::chunk::CRC;
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+// A ZIP archive is a local header and the data of every file, a central
+// directory listing the files with the offset of their local header, and an
+// end of central directory record giving the offset of the directory. Readers
+// start from that record at the end of the file, so an archive can follow
+// other data, such as a PNG image, as long as the offsets count from the
+// start of the file. The result opens both as an image and as an archive.
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+
+const VERSION: u16 = 20;
+// the file names are UTF-8
+const FLAGS: u16 = 0x0800;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+// 1980-01-01, the earliest date a ZIP archive can hold
+const DATE: u16 = 0x0021;
+
+#[derive(Debug)]
+pub enum ZipError {
+    MissingDirectory,
+    Truncated(usize),
+    UnsupportedMethod(u16),
+    InvalidCrc(String),
+}
+
+impl std::error::Error for ZipError {}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZipError::MissingDirectory => write!(f, "No ZIP archive found, the end of central directory record is missing"),
+            ZipError::Truncated(offset) => write!(f, "The ZIP archive is cut off at offset {}", offset),
+            ZipError::UnsupportedMethod(method) => write!(f, "Unsupported compression method {}", method),
+            ZipError::InvalidCrc(name) => write!(f, "The CRC of {} does not match its data", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// An archive holding a single deflated file, whose offsets assume it starts
+// `start` bytes into the file it is appended to.
+pub fn archive(name: &str, data: &[u8], start: usize) -> Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    // the part of the local header the central directory repeats
+    let mut common = Vec::new();
+    common.extend(VERSION.to_le_bytes());
+    common.extend(FLAGS.to_le_bytes());
+    common.extend(DEFLATED.to_le_bytes());
+    common.extend(0u16.to_le_bytes());
+    common.extend(DATE.to_le_bytes());
+    common.extend(CRC.checksum(data).to_le_bytes());
+    common.extend((compressed.len() as u32).to_le_bytes());
+    common.extend((data.len() as u32).to_le_bytes());
+    common.extend((name.len() as u16).to_le_bytes());
+    common.extend(0u16.to_le_bytes());
+
+    let mut bytes = LOCAL_HEADER.to_le_bytes().to_vec();
+    bytes.extend(&common);
+    bytes.extend(name.as_bytes());
+    bytes.extend(&compressed);
+
+    let directory = start + bytes.len();
+    bytes.extend(CENTRAL_HEADER.to_le_bytes());
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend(&common);
+    // comment length, disk, internal and external attributes
+    bytes.extend([0; 10]);
+    bytes.extend((start as u32).to_le_bytes());
+    bytes.extend(name.as_bytes());
+    let directory_length = start + bytes.len() - directory;
+
+    bytes.extend(END_OF_DIRECTORY.to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend((directory_length as u32).to_le_bytes());
+    bytes.extend((directory as u32).to_le_bytes());
+    bytes.extend(0u16.to_le_bytes());
+
+    Ok(bytes)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+    let field = bytes.get(offset..offset + 2).ok_or(ZipError::Truncated(offset))?;
+    Ok(u16::from_le_bytes(field.try_into().unwrap()))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    let field = bytes.get(offset..offset + 4).ok_or(ZipError::Truncated(offset))?;
+    Ok(u32::from_le_bytes(field.try_into().unwrap()))
+}
+
+// the files of the archive ending `bytes`, stored or deflated
+pub fn entries(bytes: &[u8]) -> Result<Vec<Entry>> {
+    let end = bytes
+        .windows(4)
+        .rposition(|window| window == END_OF_DIRECTORY.to_le_bytes())
+        .ok_or(ZipError::MissingDirectory)?;
+
+    let count = u16_at(bytes, end + 10)? as usize;
+    let mut offset = u32_at(bytes, end + 16)? as usize;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        if u32_at(bytes, offset)? != CENTRAL_HEADER {
+            return Err(ZipError::Truncated(offset).into());
+        }
+
+        let method = u16_at(bytes, offset + 10)?;
+        let crc = u32_at(bytes, offset + 16)?;
+        let compressed_length = u32_at(bytes, offset + 20)? as usize;
+        let name_length = u16_at(bytes, offset + 28)? as usize;
+        let extra_length = u16_at(bytes, offset + 30)? as usize;
+        let comment_length = u16_at(bytes, offset + 32)? as usize;
+        let local = u32_at(bytes, offset + 42)? as usize;
+
+        let name = bytes.get(offset + 46..offset + 46 + name_length).ok_or(ZipError::Truncated(offset))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        let start = local + 30 + u16_at(bytes, local + 26)? as usize + u16_at(bytes, local + 28)? as usize;
+        let raw = bytes.get(start..start + compressed_length).ok_or(ZipError::Truncated(start))?;
+
+        let data = match method {
+            STORED => raw.to_vec(),
+            DEFLATED => {
+                let mut data = Vec::new();
+                DeflateDecoder::new(raw).read_to_end(&mut data)?;
+                data
+            }
+            _ => return Err(ZipError::UnsupportedMethod(method).into()),
+        };
+
+        if CRC.checksum(&data) != crc {
+            return Err(ZipError::InvalidCrc(name).into());
+        }
+
+        entries.push(Entry { name, data });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_appended_archive() {
+        let mut bytes = b"an image".to_vec();
+        bytes.extend(archive("message.txt", b"Meet at noon", bytes.len()).unwrap());
+
+        let entries = entries(&bytes).unwrap();
+        assert_eq!(
+            entries,
+            vec![Entry {
+                name: "message.txt".to_string(),
+                data: b"Meet at noon".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_archive() {
+        assert!(entries(b"no archive here").is_err());
+
+        let mut bytes = archive("message.txt", b"Meet at noon", 0).unwrap();
+        bytes[41] ^= 1;
+        assert!(entries(&bytes).is_err());
+    }
+}